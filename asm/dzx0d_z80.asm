@@ -0,0 +1,87 @@
+; Decompressor for the experimental dual-stream variant of the ZX0 format, for the Z80 (96 bytes).
+;
+; The compressed data is produced by Compressor::compress_dual. Literals and offset LSBs are copied
+; straight from the data stream in HL, while the control bits are read from a separate stream in
+; IX, so no bits have to be shifted in between the bytes that are copied.
+;
+; Input:  HL = compressed data
+;         DE = destination
+; Output: HL = end of the data stream
+;         DE = end of decompressed data
+; Uses:   AF, BC, IX
+;
+; The bit buffer in A holds the remaining bits of the current 8-bit group, followed by a sentinel
+; bit. The buffer becomes zero when the sentinel bit is shifted out, which is when the next group
+; is read from the control stream.
+
+dzx0d:
+        ld      c, (hl)                 ; length of the data stream
+        inc     hl
+        ld      b, (hl)
+        inc     hl
+        push    hl                      ; data stream
+        add     hl, bc                  ; control stream follows the data stream
+        push    hl
+        pop     ix
+        pop     hl
+        ld      bc, $ffff               ; preserve default offset 1
+        push    bc
+        ld      a, $80                  ; empty bit buffer
+dzx0d_literals:
+        call    dzx0d_elias             ; obtain length
+        ldir                            ; copy literals
+        call    dzx0d_bit               ; copy from last offset or new offset?
+        jr      c, dzx0d_new_offset
+        call    dzx0d_elias             ; obtain length
+dzx0d_copy:
+        ex      (sp), hl                ; preserve data stream, restore offset
+        push    hl                      ; preserve offset
+        add     hl, de                  ; calculate destination - offset
+        ldir                            ; copy from offset
+        pop     hl                      ; restore offset
+        ex      (sp), hl                ; preserve offset, restore data stream
+        call    dzx0d_bit               ; copy literals or from new offset?
+        jr      nc, dzx0d_literals
+dzx0d_new_offset:
+        pop     bc                      ; discard last offset
+        ld      c, $fe                  ; prepare negative offset MSB
+        call    dzx0d_msb               ; obtain offset MSB
+        inc     c
+        ret     z                       ; check end marker
+        ld      b, c
+        ld      c, (hl)                 ; obtain offset LSB, which is stored negated
+        inc     hl
+        push    bc                      ; preserve new offset
+        call    dzx0d_elias             ; obtain length - 1
+        inc     bc
+        jr      dzx0d_copy
+
+; Read an interlaced Elias Gamma code into BC
+dzx0d_elias:
+        ld      bc, 1
+dzx0d_elias_loop:
+        call    dzx0d_bit
+        ret     c
+        call    dzx0d_bit
+        rl      c
+        rl      b
+        jr      dzx0d_elias_loop
+
+; Read an interlaced Elias Gamma code into C, inverting every bit but the leading one, which
+; makes it the negative offset MSB minus one
+dzx0d_msb:
+        call    dzx0d_bit
+        ret     c
+        call    dzx0d_bit
+        ccf
+        rl      c
+        jr      dzx0d_msb
+
+; Read the next bit from the control stream into the carry flag
+dzx0d_bit:
+        add     a, a
+        ret     nz
+        ld      a, (ix+0)               ; load another group of 8 bits
+        inc     ix
+        rla
+        ret
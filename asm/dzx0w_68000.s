@@ -0,0 +1,87 @@
+; Decompressor for the experimental 16-bit word variant of the ZX0 format, for the Motorola 68000.
+;
+; The compressed data is produced by Compressor::compress_words. Every read and write is a whole
+; word, so both the compressed and the decompressed data must start at an even address.
+;
+; Input:  a0 = compressed data
+;         a1 = destination
+; Output: a0 = end of compressed data
+;         a1 = end of decompressed data
+; Uses:   d0-d3, a2
+;
+; The bit buffer in d0 holds the remaining bits of the current 16-bit group, followed by a
+; sentinel bit. The buffer becomes zero when the sentinel bit is shifted out, which is when the
+; next group is read from the stream.
+
+dzx0w:
+        move.l  #$80000000,d0           ; Empty bit buffer, read a group for the first bit
+        moveq   #2,d2                   ; Last offset in bytes
+
+dzx0w_literals:
+        bsr.s   dzx0w_gamma             ; Number of literal words
+dzx0w_literal_loop:
+        move.w  (a0)+,(a1)+
+        subq.l  #1,d1
+        bne.s   dzx0w_literal_loop
+
+        bsr.s   dzx0w_bit               ; Copy from last offset or from new offset?
+        bcs.s   dzx0w_new_offset
+
+        bsr.s   dzx0w_gamma             ; Number of words to copy from last offset
+
+dzx0w_copy:
+        move.l  a1,a2
+        sub.l   d2,a2
+dzx0w_copy_loop:
+        move.w  (a2)+,(a1)+
+        subq.l  #1,d1
+        bne.s   dzx0w_copy_loop
+
+        bsr.s   dzx0w_bit               ; Literals or copy from new offset?
+        bcc.s   dzx0w_literals
+
+dzx0w_new_offset:
+        bsr.s   dzx0w_gamma             ; Offset MSB
+        cmp.w   #256,d1                 ; End marker?
+        beq.s   dzx0w_done
+
+        subq.l  #1,d1
+        moveq   #7-1,d3
+dzx0w_lsb_loop:
+        bsr.s   dzx0w_bit               ; Offset LSB, 7 bits
+        addx.l  d1,d1
+        dbra    d3,dzx0w_lsb_loop
+
+        addq.l  #1,d1                   ; Offset in words
+        add.l   d1,d1                   ; Offset in bytes
+        move.l  d1,d2
+
+        bsr.s   dzx0w_gamma             ; Number of words to copy, minus one
+        addq.l  #1,d1
+        bra.s   dzx0w_copy
+
+dzx0w_done:
+        rts
+
+; Read an interlaced Elias Gamma code into d1
+dzx0w_gamma:
+        moveq   #1,d1
+dzx0w_gamma_loop:
+        bsr.s   dzx0w_bit
+        bcs.s   dzx0w_gamma_done
+        bsr.s   dzx0w_bit
+        addx.l  d1,d1
+        bra.s   dzx0w_gamma_loop
+dzx0w_gamma_done:
+        rts
+
+; Read the next bit into the carry and extend flags
+dzx0w_bit:
+        add.l   d0,d0
+        bne.s   dzx0w_bit_done
+        move.w  (a0)+,d0                ; The buffer is empty, so the upper word is zero
+        swap    d0
+        move.w  #$8000,d0               ; Sentinel bit
+        add.l   d0,d0
+dzx0w_bit_done:
+        rts
@@ -0,0 +1,118 @@
+//! Criterion benchmarks that measure the parsing and encoding phases of the compressor separately,
+//! across a few representative kinds of input data and compression modes.
+//!
+//! Run with `cargo bench --bench phases`, optionally followed by a filter such as `-- encode/quick`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use zx0::Compressor;
+
+const CORPUS_SIZE: usize = 4096;
+
+/// Build the input corpora. All of them are deterministic so that results can be compared between
+/// runs.
+fn corpora() -> Vec<(&'static str, Vec<u8>)> {
+    let mut rng = StdRng::seed_from_u64(0x5a58);
+
+    // A sprite sheet of 16x16 pixel 4bpp sprites, consisting of runs of a few colors with the
+    // occasional repeated sprite
+    let mut graphics = Vec::with_capacity(CORPUS_SIZE);
+    while graphics.len() < CORPUS_SIZE {
+        if !graphics.is_empty() && rng.gen_bool(0.25) {
+            let start = rng.gen_range(0..graphics.len() / 128) * 128;
+            graphics.extend_from_within(start..start + 128);
+        } else {
+            let end = graphics.len() + 128;
+            while graphics.len() < end {
+                let color = rng.gen_range(0..4u8) * 0x11;
+                let run = rng.gen_range(1..12).min(end - graphics.len());
+                graphics.resize(graphics.len() + run, color);
+            }
+        }
+    }
+    graphics.truncate(CORPUS_SIZE);
+
+    let mut random = vec![0; CORPUS_SIZE];
+    rng.fill(&mut random[..]);
+
+    // Sentences of words from a small vocabulary, in which the common words repeat often like they
+    // do in source code and documentation
+    const WORDS: [&str; 16] = [
+        "the", "data", "is", "compressed", "offset", "of", "a", "match", "length", "and", "literal",
+        "bits", "to", "output", "buffer", "stream"
+    ];
+
+    let mut text = Vec::with_capacity(CORPUS_SIZE);
+    while text.len() < CORPUS_SIZE {
+        for i in 0..rng.gen_range(4..12) {
+            if i > 0 {
+                text.push(b' ');
+            }
+
+            // Favor the words at the start of the vocabulary
+            let index = rng.gen_range(0..WORDS.len()).min(rng.gen_range(0..WORDS.len()));
+            text.extend_from_slice(WORDS[index].as_bytes());
+        }
+
+        text.extend_from_slice(b".\n");
+    }
+    text.truncate(CORPUS_SIZE);
+
+    vec![
+        ("text", text),
+        ("graphics", graphics),
+        ("random", random)
+    ]
+}
+
+fn modes() -> Vec<(&'static str, Compressor<'static>)> {
+    let mut quick = Compressor::new();
+    quick.quick_mode(true);
+
+    let mut backwards = Compressor::new();
+    backwards.backwards_mode(true);
+
+    let mut classic = Compressor::new();
+    classic.classic_mode(true);
+
+    vec![
+        ("default", Compressor::new()),
+        ("quick", quick),
+        ("backwards", backwards),
+        ("classic", classic)
+    ]
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    group.sample_size(10);
+
+    for (corpus, input) in corpora() {
+        for (mode, mut compressor) in modes() {
+            group.bench_with_input(BenchmarkId::new(mode, corpus), &input, |b, input| {
+                b.iter(|| compressor.parse(input))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+
+    for (corpus, input) in corpora() {
+        for (mode, mut compressor) in modes() {
+            let parse = compressor.parse(&input);
+
+            group.bench_with_input(BenchmarkId::new(mode, corpus), &input, |b, input| {
+                b.iter(|| compressor.encode(&parse, input))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, parse, encode);
+criterion_main!(benches);
@@ -0,0 +1,342 @@
+//! This example demonstrates in-place decompression on a simulated 8-bit machine with a 64K
+//! address space, which is what the ZX0 format is designed for.
+//!
+//! A loading screen and a level are compressed and placed in memory according to the delta value
+//! that the compressor reports, so that the compressed data overlaps the area that it decompresses
+//! to. They are then decompressed forwards and backwards, once on their own and once using data
+//! that is already resident in memory as a prefix or suffix. The decompressor below reads and
+//! writes the simulated memory one byte at a time like a Z80 routine would, and checks that it
+//! never overwrites compressed data that it hasn't read yet.
+//!
+//! Run it with `cargo run --example in_place`.
+
+use std::ops::Range;
+
+use zx0::Compressor;
+
+const MEMORY_SIZE: usize = 0x10000;
+
+// Memory that was never written by the loader or the decompressor keeps this value
+const UNUSED: u8 = 0xee;
+
+/// The decompressor state. The compressed data that hasn't been read yet is tracked as a range, so
+/// that every write can be checked against it.
+struct Decompressor<'a> {
+    memory: &'a mut [u8],
+    unread: Range<usize>,
+    destination: usize,
+    backwards_mode: bool,
+    bit_mask: u8,
+    bit_value: u8,
+    backtrack: Option<bool>
+}
+
+impl Decompressor<'_> {
+    // Forwards the compressed data is read from the start, and backwards from the end
+    fn read_byte(&mut self) -> u8 {
+        if self.backwards_mode {
+            self.unread.end -= 1;
+            self.memory[self.unread.end]
+        } else {
+            self.unread.start += 1;
+            self.memory[self.unread.start - 1]
+        }
+    }
+
+    fn read_bit(&mut self) -> bool {
+        if let Some(bit) = self.backtrack.take() {
+            return bit;
+        }
+
+        if self.bit_mask == 0 {
+            self.bit_mask = 128;
+            self.bit_value = self.read_byte();
+        }
+
+        let bit = self.bit_value & self.bit_mask != 0;
+        self.bit_mask >>= 1;
+        bit
+    }
+
+    fn read_interlaced_elias_gamma(&mut self, inverted: bool) -> usize {
+        let mut value = 1;
+
+        while self.read_bit() == self.backwards_mode {
+            value = (value << 1) | (self.read_bit() ^ inverted) as usize;
+        }
+
+        value
+    }
+
+    // Backwards the destination is the address directly after the next byte to be written
+    fn write_byte(&mut self, value: u8) {
+        let address = if self.backwards_mode { self.destination - 1 } else { self.destination };
+
+        assert!(
+            !self.unread.contains(&address),
+            "decompressed data overwrites unread compressed data at {:#06x}",
+            address
+        );
+
+        self.memory[address] = value;
+
+        if self.backwards_mode {
+            self.destination -= 1;
+        } else {
+            self.destination += 1;
+        }
+    }
+
+    fn copy(&mut self, offset: usize, length: usize) {
+        for _ in 0..length {
+            let value = if self.backwards_mode {
+                self.memory[self.destination - 1 + offset]
+            } else {
+                self.memory[self.destination - offset]
+            };
+
+            self.write_byte(value);
+        }
+    }
+}
+
+/// Decompress the compressed data in the given range of memory to the destination, which is the
+/// start of the decompressed data or, when decompressing backwards, its end. Returns the other end
+/// of the decompressed data.
+fn decompress(memory: &mut [u8], compressed: Range<usize>, destination: usize, backwards_mode: bool) -> usize {
+    let mut decompressor = Decompressor {
+        memory,
+        unread: compressed,
+        destination,
+        backwards_mode,
+        bit_mask: 0,
+        bit_value: 0,
+        backtrack: None
+    };
+
+    let invert_mode = !backwards_mode;
+    let mut last_offset = 1;
+
+    // The stream starts with literals
+    let mut literals = true;
+
+    loop {
+        if literals {
+            for _ in 0..decompressor.read_interlaced_elias_gamma(false) {
+                let value = decompressor.read_byte();
+                decompressor.write_byte(value);
+            }
+
+            if !decompressor.read_bit() {
+                // Copy from last offset
+                let length = decompressor.read_interlaced_elias_gamma(false);
+                decompressor.copy(last_offset, length);
+
+                literals = !decompressor.read_bit();
+                continue;
+            }
+        }
+
+        // Copy from new offset
+        let msb = decompressor.read_interlaced_elias_gamma(invert_mode);
+
+        if msb == 256 {
+            assert!(decompressor.unread.is_empty(), "end marker found before the end of the compressed data");
+            return decompressor.destination;
+        }
+
+        let lsb = decompressor.read_byte() as usize;
+
+        last_offset = if backwards_mode {
+            msb * 128 - 127 + (lsb >> 1)
+        } else {
+            msb * 128 - (lsb >> 1)
+        };
+
+        // The first bit of the length is stored in the lowest bit of the offset LSB
+        decompressor.backtrack = Some(lsb & 1 != 0);
+
+        let length = decompressor.read_interlaced_elias_gamma(false) + 1;
+        decompressor.copy(last_offset, length);
+
+        literals = !decompressor.read_bit();
+    }
+}
+
+// A deterministic pseudo-random number generator, so that every run uses the same data
+struct Random(u32);
+
+impl Random {
+    fn next(&mut self) -> u8 {
+        self.0 = self.0.wrapping_mul(1103515245).wrapping_add(12345);
+        (self.0 >> 16) as u8
+    }
+}
+
+// A loading screen in the layout of the ZX Spectrum: a bitmap of 6144 bytes with a dithered
+// gradient and some noise, followed by 768 bytes of colour attributes in horizontal bands
+fn loading_screen() -> Vec<u8> {
+    let mut random = Random(1);
+    let mut screen = Vec::with_capacity(6912);
+
+    for address in 0..6144 {
+        let row = (address >> 8 & 7) | (address >> 2 & 0x38) | (address >> 5 & 0xc0);
+        let column = address & 31;
+
+        let pattern = if row % 2 == 0 { 0xaa } else { 0x55 };
+        let gradient = if column * 8 < row { 0xff } else { pattern };
+
+        screen.push(if random.next() < 16 { random.next() } else { gradient });
+    }
+
+    for row in 0..24 {
+        screen.extend(std::iter::repeat_n(0x40 | (row / 3) as u8, 32));
+    }
+
+    screen
+}
+
+// A tile set and a level map that is built from those tiles, so that the level compresses much
+// better when the tile set is already resident in memory
+fn tiles_and_level() -> (Vec<u8>, Vec<u8>) {
+    let mut random = Random(2);
+    let tiles = (0..32 * 8).map(|_| random.next()).collect::<Vec<_>>();
+
+    let mut level = Vec::new();
+
+    while level.len() < 4096 {
+        let tile = (random.next() % 32) as usize;
+        level.extend_from_slice(&tiles[8 * tile..8 * tile + 8]);
+
+        if random.next() < 64 {
+            level.push(random.next());
+        }
+    }
+
+    (tiles, level)
+}
+
+/// Where the decompressed data ends up, and what is resident in memory next to it.
+struct Scenario<'a> {
+    name: &'a str,
+    data: &'a [u8],
+    address: usize,
+    backwards_mode: bool,
+
+    // Data that is resident directly before the decompressed data, or directly after it when
+    // decompressing backwards
+    resident: &'a [u8]
+}
+
+fn run(scenario: &Scenario) {
+    let Scenario { name, data, address, backwards_mode, resident } = *scenario;
+    let end = address + data.len();
+
+    // Compress the data together with the resident part, which is skipped. Backwards compression
+    // works on reversed input and produces reversed output.
+    let result = if backwards_mode {
+        let mut input = [data, resident].concat();
+        input.reverse();
+
+        let mut result = Compressor::new().backwards_mode(true).skip(resident.len()).compress(&input);
+        result.output.reverse();
+        result
+    } else {
+        Compressor::new().skip(resident.len()).compress(&[resident, data].concat())
+    };
+
+    let delta = result.delta;
+    let compressed_len = result.output.len();
+
+    // Place the compressed data so that it overlaps the end of the decompressed data, or the start
+    // when decompressing backwards, leaving a gap of delta bytes
+    let compressed = if backwards_mode {
+        address - delta..address - delta + compressed_len
+    } else {
+        end + delta - compressed_len..end + delta
+    };
+
+    let mut memory = vec![UNUSED; MEMORY_SIZE];
+    memory[compressed.clone()].copy_from_slice(&result.output);
+
+    if backwards_mode {
+        memory[end..end + resident.len()].copy_from_slice(resident);
+    } else {
+        memory[address - resident.len()..address].copy_from_slice(resident);
+    }
+
+    let destination = if backwards_mode { end } else { address };
+    let other_end = decompress(&mut memory, compressed.clone(), destination, backwards_mode);
+
+    assert_eq!(other_end, if backwards_mode { address } else { end });
+    assert_eq!(&memory[address..end], data);
+
+    // The resident data must be left intact, and nothing outside of the decompressed data, the
+    // compressed data and the resident data may have been touched
+    let resident_range = if backwards_mode { end..end + resident.len() } else { address - resident.len()..address };
+    let used = address.min(compressed.start).min(resident_range.start)..end.max(compressed.end).max(resident_range.end);
+
+    assert_eq!(&memory[resident_range], resident);
+    assert!(memory[..used.start].iter().chain(&memory[used.end..]).all(|&value| value == UNUSED));
+
+    println!(
+        "{:<30} {:>5} -> {:>5} bytes at {:#06x}..{:#06x}, compressed data at {:#06x}..{:#06x} (delta {})",
+        name,
+        data.len(),
+        compressed_len,
+        address,
+        end,
+        compressed.start,
+        compressed.end,
+        delta
+    );
+}
+
+fn main() {
+    let screen = loading_screen();
+    let (tiles, level) = tiles_and_level();
+
+    let scenarios = [
+        Scenario {
+            name: "screen, forwards",
+            data: &screen,
+            address: 0x4000,
+            backwards_mode: false,
+            resident: &[]
+        },
+        Scenario {
+            name: "screen, backwards",
+            data: &screen,
+            address: 0x4000,
+            backwards_mode: true,
+            resident: &[]
+        },
+        Scenario {
+            name: "level after tiles",
+            data: &level,
+            address: 0x8000 + tiles.len(),
+            backwards_mode: false,
+            resident: &tiles
+        },
+        Scenario {
+            name: "level before tiles, backwards",
+            data: &level,
+            address: 0xc000,
+            backwards_mode: true,
+            resident: &tiles
+        },
+        Scenario {
+            name: "level without tiles",
+            data: &level,
+            address: 0xc000,
+            backwards_mode: false,
+            resident: &[]
+        }
+    ];
+
+    for scenario in &scenarios {
+        run(scenario);
+    }
+
+    println!("All scenarios decompressed correctly");
+}
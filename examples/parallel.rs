@@ -0,0 +1,100 @@
+//! This example compresses all files in a directory in parallel using a batch, while a separate
+//! thread displays the aggregated progress. When all files are done a summary table is shown.
+//!
+//! Run it with `cargo run --release --example parallel [DIRECTORY] [THREADS]`. The directory
+//! defaults to `src` and the number of threads defaults to the available parallelism.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use zx0::batch::{Batch, Config, Outcome};
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path).unwrap().map(|entry| entry.unwrap().path()).collect::<Vec<_>>();
+        entries.sort();
+
+        for entry in entries {
+            collect_files(&entry, files);
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let directory = args.next().unwrap_or_else(|| "src".to_string());
+    let threads = args.next().map_or(0, |threads| threads.parse().expect("invalid number of threads"));
+
+    let mut files = Vec::new();
+    collect_files(Path::new(&directory), &mut files);
+
+    let start = Instant::now();
+    let mut batch = Batch::new(threads);
+    let mut sizes = Vec::new();
+
+    for file in &files {
+        let input = std::fs::read(file).unwrap();
+        sizes.push(input.len());
+
+        batch.submit(file.display().to_string(), input, Config::default());
+    }
+
+    // Poll the shared progress of all workers until the results have been collected
+    let handle = batch.handle();
+    let done = Arc::new(AtomicBool::new(false));
+
+    let display = thread::spawn({
+        let done = done.clone();
+
+        move || {
+            while !done.load(Ordering::Relaxed) {
+                eprint!("\rCompressing {} files: {:5.1} %", files.len(), handle.progress() * 100.0);
+                std::io::stderr().flush().unwrap();
+
+                thread::sleep(Duration::from_millis(100));
+            }
+
+            eprintln!("\rCompressing {} files: done   ", files.len());
+        }
+    });
+
+    let results = batch.results().collect::<Vec<_>>();
+
+    done.store(true, Ordering::Relaxed);
+    display.join().unwrap();
+
+    let width = results.iter().map(|job| job.name.len()).max().unwrap_or(0).max(4);
+    let mut total_output = 0;
+
+    println!();
+    println!("{:<width$} {:>10} {:>10} {:>7}", "File", "Input", "Output", "Ratio");
+
+    for job in &results {
+        let input = sizes[job.index];
+
+        match &job.outcome {
+            Outcome::Compressed(result) => {
+                total_output += result.output.len();
+
+                let ratio = input as f32 / result.output.len().max(1) as f32;
+                println!("{:<width$} {:>10} {:>10} {:>7.3}", job.name, input, result.output.len(), ratio);
+            }
+
+            Outcome::Cancelled => println!("{:<width$} {:>10} {:>10}", job.name, input, "cancelled"),
+            Outcome::Failed(message) => println!("{:<width$} {:>10} {:>10} ({})", job.name, input, "failed", message)
+        }
+    }
+
+    let total_input = sizes.iter().sum::<usize>();
+    let ratio = total_input as f32 / total_output.max(1) as f32;
+
+    println!("{:<width$} {:>10} {:>10} {:>7.3}", "Total", total_input, total_output, ratio);
+    println!();
+    println!("Compressed in {:.3} s", start.elapsed().as_secs_f32());
+}
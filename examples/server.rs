@@ -0,0 +1,245 @@
+//! This example offers compression as a small HTTP service, so that build pipelines written in
+//! other languages can compress their assets without binding the library. Every connection is
+//! handled on its own thread, which is possible because the compressor is thread-safe. The number
+//! of connections that are handled at the same time is limited, and further connections are turned
+//! away with `503 Service Unavailable` until one of them is done.
+//!
+//! The input is sent as the body of a `POST /compress` request, and the compression settings are
+//! passed in the query string, for example:
+//!
+//! ```text
+//! curl --data-binary @title.scr -o title.scr.zx0 -D - 'http://localhost:8008/compress?backwards&skip=128'
+//! ```
+//!
+//! The settings are `quick`, `backwards`, `classic` and `extreme`, which are flags, and `skip` and
+//! `effort`, which take a number. Backwards compression works on the data in its natural order, so
+//! the input does not have to be reversed first. The response contains the compressed data, and
+//! reports the input and output sizes and the delta in the `X-ZX0-Input-Size`, `X-ZX0-Output-Size`
+//! and `X-ZX0-Delta` headers.
+//!
+//! Run it with `cargo run --release --example server [ADDRESS]`. The address defaults to
+//! `127.0.0.1:8008`.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use zx0::Compressor;
+
+// Larger inputs are rejected before any memory is allocated for them
+const MAX_INPUT_LEN: usize = 16 * 1024 * 1024;
+
+// Requests with more or longer header lines than this are rejected
+const MAX_HEADER_LINES: usize = 100;
+const MAX_HEADER_LINE_LEN: usize = 8192;
+
+// Every connection may hold an input of the maximum length, so their number is limited as well
+const MAX_CONNECTIONS: usize = 16;
+
+// Clients that stop sending or receiving data for this long are disconnected
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// The number of connections that are being handled
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// A connection slot, which is released when the connection is done, even if handling it panics.
+struct Slot;
+
+impl Slot {
+    fn acquire() -> Option<Self> {
+        CONNECTIONS.fetch_update(Ordering::AcqRel, Ordering::Acquire, |connections| {
+            (connections < MAX_CONNECTIONS).then_some(connections + 1)
+        }).ok().map(|_| Slot)
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        CONNECTIONS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// A response that consists of a status and a body, along with any extra headers.
+struct Response {
+    status: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>
+}
+
+impl Response {
+    fn error(status: &'static str, message: impl Into<String>) -> Self {
+        let mut body = message.into().into_bytes();
+        body.push(b'\n');
+
+        Self {
+            status,
+            headers: vec![("Content-Type", "text/plain; charset=utf-8".to_string())],
+            body
+        }
+    }
+
+    fn write_to(&self, stream: &mut TcpStream) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n", self.status, self.body.len());
+
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        head.push_str("\r\n");
+
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
+
+// Read a single header line without its line ending, refusing lines that are too long
+fn read_line(reader: &mut BufReader<TcpStream>) -> Result<String, Response> {
+    let mut line = Vec::new();
+
+    reader.by_ref().take(MAX_HEADER_LINE_LEN as u64).read_until(b'\n', &mut line)
+        .map_err(|err| Response::error("400 Bad Request", format!("could not read request: {}", err)))?;
+
+    if !line.ends_with(b"\n") {
+        return Err(Response::error("400 Bad Request", "request header is incomplete or too long"));
+    }
+
+    let line = String::from_utf8(line).map_err(|_| Response::error("400 Bad Request", "request header is not valid UTF-8"))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+// Configure a compressor from the query string of the request
+fn configure(compressor: &mut Compressor, query: &str) -> Result<(), Response> {
+    for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+        let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+
+        let number = || value.parse::<usize>()
+            .map_err(|_| Response::error("400 Bad Request", format!("expected a number for {}", name)));
+
+        match name {
+            "quick" => { compressor.quick_mode(true); },
+            "backwards" => { compressor.backwards_mode(true); },
+            "classic" => { compressor.classic_mode(true); },
+            "extreme" => { compressor.extreme_mode(true); },
+            "skip" => { compressor.skip(number()?); },
+            "effort" => { compressor.effort(number()?.min(u8::MAX as usize) as u8); },
+            _ => return Err(Response::error("400 Bad Request", format!("unknown setting {}", name)))
+        }
+    }
+
+    Ok(())
+}
+
+fn handle(stream: &TcpStream) -> Result<Response, Response> {
+    let mut reader = BufReader::new(stream.try_clone().map_err(|err| Response::error("500 Internal Server Error", err.to_string()))?);
+
+    let request_line = read_line(&mut reader)?;
+    let mut parts = request_line.split(' ');
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut content_len = None;
+    let mut complete = false;
+
+    for _ in 0..MAX_HEADER_LINES {
+        let line = read_line(&mut reader)?;
+
+        if line.is_empty() {
+            complete = true;
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_len = Some(value.trim().parse::<usize>()
+                    .map_err(|_| Response::error("400 Bad Request", "invalid content length"))?);
+            }
+        }
+    }
+
+    if !complete {
+        return Err(Response::error("431 Request Header Fields Too Large", format!("the request may have at most {} header lines", MAX_HEADER_LINES)));
+    }
+
+    if path != "/compress" {
+        return Err(Response::error("404 Not Found", "only /compress is available"));
+    }
+
+    if method != "POST" {
+        return Err(Response::error("405 Method Not Allowed", "use POST to send the data to compress"));
+    }
+
+    let mut compressor = Compressor::new();
+    compressor.natural_order(true).max_input_len(MAX_INPUT_LEN);
+    configure(&mut compressor, query)?;
+
+    let content_len = content_len.ok_or_else(|| Response::error("411 Length Required", "the request has no content length"))?;
+
+    if content_len > MAX_INPUT_LEN {
+        return Err(Response::error("413 Content Too Large", format!("the input may be at most {} bytes", MAX_INPUT_LEN)));
+    }
+
+    let mut input = vec![0; content_len];
+
+    reader.read_exact(&mut input)
+        .map_err(|err| Response::error("400 Bad Request", format!("could not read request body: {}", err)))?;
+
+    let result = compressor.try_compress(&input).map_err(|err| Response::error("422 Unprocessable Content", err.to_string()))?;
+
+    Ok(Response {
+        status: "200 OK",
+        headers: vec![
+            ("Content-Type", "application/octet-stream".to_string()),
+            ("X-ZX0-Input-Size", input.len().to_string()),
+            ("X-ZX0-Output-Size", result.output.len().to_string()),
+            ("X-ZX0-Delta", result.delta.to_string())
+        ],
+        body: result.output
+    })
+}
+
+fn main() -> io::Result<()> {
+    let address = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:8008".to_string());
+    let listener = TcpListener::bind(&address)?;
+
+    eprintln!("Listening on http://{}/compress", listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Could not accept connection: {}", err);
+                continue;
+            }
+        };
+
+        // Without timeouts, idle clients would keep their connection slot forever
+        if let Err(err) = stream.set_read_timeout(Some(TIMEOUT)).and_then(|()| stream.set_write_timeout(Some(TIMEOUT))) {
+            eprintln!("Could not set connection timeouts: {}", err);
+            continue;
+        }
+
+        let Some(slot) = Slot::acquire() else {
+            let response = Response::error("503 Service Unavailable", "too many connections, try again later");
+
+            if let Err(err) = response.write_to(&mut stream) {
+                eprintln!("Could not send response: {}", err);
+            }
+
+            continue;
+        };
+
+        thread::spawn(move || {
+            let _slot = slot;
+            let response = handle(&stream).unwrap_or_else(|response| response);
+
+            if let Err(err) = response.write_to(&mut stream) {
+                eprintln!("Could not send response: {}", err);
+            }
+        });
+    }
+
+    Ok(())
+}
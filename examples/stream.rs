@@ -0,0 +1,28 @@
+//! This example compresses standard input to standard output without holding the entire input in
+//! memory, and reports the resulting sizes and the peak memory usage on standard error.
+//!
+//! Run it with `cargo run --release --example stream < INPUT > OUTPUT.zx0`. Passing `--quick`
+//! uses the smaller window of the quick mode, which is considerably faster.
+
+use std::io::{self, BufWriter};
+
+use zx0::Compressor;
+
+fn main() -> io::Result<()> {
+    let quick_mode = std::env::args().skip(1).any(|arg| arg == "--quick");
+
+    let result = Compressor::new()
+        .quick_mode(quick_mode)
+        .compress_stream(io::stdin().lock(), BufWriter::new(io::stdout().lock()))?;
+
+    eprintln!(
+        "{} -> {} bytes (ratio {:.3}, delta {}), peak memory {} KiB",
+        result.input_len,
+        result.output_len,
+        result.input_len as f64 / result.output_len as f64,
+        result.delta,
+        result.peak_memory / 1024
+    );
+
+    Ok(())
+}
@@ -0,0 +1,199 @@
+//! This example contains a fuzzer that feeds the reference compressor and the Rust-based
+//! compressor with structured data that is known to be hard to get right, instead of uniform
+//! random data. Every input is built from segments such as long runs, repetitions with a period
+//! around 128 bytes and matches that straddle the edge of the offset window. The outputs of both
+//! compressors are compared, and the output of the Rust-based compressor is decompressed again to
+//! verify that it round-trips.
+//!
+//! Every input is generated from its own seed, which is printed before the input is compressed.
+//! Pass a seed as the first argument to start from a specific input, for example to reproduce a
+//! failure.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const MAX_OFFSET_ZX0: usize = 32640;
+const MAX_OFFSET_ZX7: usize = 2176;
+
+// Generate an input by concatenating randomly chosen segments
+fn generate(rng: &mut StdRng, window: usize) -> Vec<u8> {
+    // Occasionally produce an input that consists of a single repeated byte
+    if rng.gen_ratio(1, 20) {
+        return vec![rng.gen(); rng.gen_range(1..=3 * window)];
+    }
+
+    let len = rng.gen_range(1..=2 * window + 256);
+    let mut input = Vec::with_capacity(len + 1024);
+
+    while input.len() < len {
+        match rng.gen_range(0..4) {
+            // A long run of a single byte
+            0 => {
+                let value = rng.gen::<u8>();
+                input.extend(std::iter::repeat_n(value, rng.gen_range(1..=1024)));
+            }
+
+            // A repetition with a period just below, at or just above 128 bytes, with the
+            // occasional mutation to break up the matches
+            1 => {
+                let period = rng.gen_range(127..=129);
+                let pattern = (0..period).map(|_| rng.gen()).collect::<Vec<u8>>();
+
+                for i in 0..rng.gen_range(1..=8) * period {
+                    let value = if rng.gen_ratio(1, 64) { rng.gen() } else { pattern[i % period] };
+                    input.push(value);
+                }
+            }
+
+            // A copy of earlier data from right around the edge of the offset window
+            2 if input.len() + 2 > window => {
+                let offset = rng.gen_range(window - 2..=(window + 2).min(input.len()));
+                let start = input.len() - offset;
+
+                for i in 0..rng.gen_range(1..=64) {
+                    input.push(input[start + i]);
+                }
+            }
+
+            // A short stretch of noise
+            _ => {
+                for _ in 0..rng.gen_range(1..=32) {
+                    input.push(rng.gen());
+                }
+            }
+        }
+    }
+
+    input.truncate(len);
+    input
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    position: usize,
+    bit_mask: u8,
+    bit_value: u8,
+    backtrack: Option<bool>
+}
+
+impl Decoder<'_> {
+    fn read_byte(&mut self) -> u8 {
+        let value = self.input[self.position];
+        self.position += 1;
+        value
+    }
+
+    fn read_bit(&mut self) -> bool {
+        if let Some(bit) = self.backtrack.take() {
+            return bit;
+        }
+
+        if self.bit_mask == 0 {
+            self.bit_mask = 128;
+            self.bit_value = self.read_byte();
+        }
+
+        let bit = self.bit_value & self.bit_mask != 0;
+        self.bit_mask >>= 1;
+        bit
+    }
+
+    fn read_interlaced_elias_gamma(&mut self, inverted: bool) -> usize {
+        let mut value = 1;
+
+        while !self.read_bit() {
+            value = (value << 1) | (self.read_bit() ^ inverted) as usize;
+        }
+
+        value
+    }
+}
+
+// Decompress a forward stream in either the classic or the current format
+fn decompress(input: &[u8], classic_mode: bool) -> Vec<u8> {
+    let mut decoder = Decoder {
+        input,
+        position: 0,
+        bit_mask: 0,
+        bit_value: 0,
+        backtrack: None
+    };
+
+    let mut output = Vec::new();
+    let mut last_offset = 1;
+    let mut new_offset = false;
+
+    loop {
+        if new_offset {
+            let msb = decoder.read_interlaced_elias_gamma(!classic_mode);
+
+            if msb == 256 {
+                assert_eq!(decoder.position, input.len(), "trailing data after end marker");
+                return output;
+            }
+
+            let lsb = decoder.read_byte() as usize;
+            last_offset = msb * 128 - (lsb >> 1);
+            decoder.backtrack = Some(lsb & 1 != 0);
+        } else {
+            let length = decoder.read_interlaced_elias_gamma(false);
+
+            for _ in 0..length {
+                let value = decoder.read_byte();
+                output.push(value);
+            }
+
+            if decoder.read_bit() {
+                new_offset = true;
+                continue;
+            }
+        }
+
+        let length = decoder.read_interlaced_elias_gamma(false) + new_offset as usize;
+
+        for _ in 0..length {
+            output.push(output[output.len() - last_offset]);
+        }
+
+        new_offset = decoder.read_bit();
+    }
+}
+
+fn main() {
+    let mut seed = std::env::args().nth(1).map_or_else(rand::random, |seed| seed.parse().expect("invalid seed"));
+
+    loop {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let quick_mode = rng.gen_ratio(1, 2);
+        let classic_mode = rng.gen_ratio(1, 4);
+        let input = generate(&mut rng, if quick_mode { MAX_OFFSET_ZX7 } else { MAX_OFFSET_ZX0 });
+
+        println!("Seed {}: {} bytes, quick mode {}, classic mode {}", seed, input.len(), quick_mode, classic_mode);
+
+        let reference = reference::Compressor::new()
+            .quick_mode(quick_mode)
+            .classic_mode(classic_mode)
+            .compress(&input);
+
+        let result = zx0::Compressor::new()
+            .quick_mode(quick_mode)
+            .classic_mode(classic_mode)
+            .compress(&input);
+
+        unsafe { reference::reset() };
+
+        if result.output != reference.output || result.delta != reference.delta {
+            println!("Reference: {:?} (delta {})", reference.output, reference.delta);
+            println!("Output:    {:?} (delta {})", result.output, result.delta);
+
+            panic!("Output and reference don't match for seed {}!", seed);
+        }
+
+        if decompress(&result.output, classic_mode) != input {
+            panic!("Output does not decompress to the input for seed {}!", seed);
+        }
+
+        seed = seed.wrapping_add(1);
+    }
+}
@@ -27,7 +27,7 @@ fn benchmark<C, T>(closure: C) where C: Fn() -> T {
 }
 
 fn main() {
-    let input = std::fs::read("src/lib.rs").unwrap();
+    let input = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/../src/lib.rs")).unwrap();
 
     benchmark(|| reference::Compressor::new().compress(&input));
     benchmark(|| zx0::Compressor::new().compress(&input));
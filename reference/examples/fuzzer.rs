@@ -1,5 +1,8 @@
 //! This example contains a fuzzer that feeds the reference compressor and the Rust-based
 //! compressor with random data and compares the results.
+//!
+//! When running it with `-p reference --features debug-dump`, the state of the optimizer is dumped to
+//! `fuzzer.dump` for the first input whose output doesn't match.
 
 use rand::{thread_rng, Rng};
 
@@ -17,6 +20,12 @@ fn main() {
             println!("Reference: {:?}", reference.output);
             println!("Output:    {:?}", result.output);
 
+            #[cfg(feature = "debug-dump")]
+            {
+                zx0::Compressor::new().debug_dump("fuzzer.dump", 64).compress(&input);
+                println!("Wrote the state of the optimizer to fuzzer.dump");
+            }
+
             panic!("Output and reference don't match!");
         }
     }
@@ -112,6 +112,6 @@ mod tests {
 
         let result = Compressor::new().compress(&input);
 
-        assert!(result.output.len() > 0);
+        assert!(!result.output.is_empty());
     }
 }
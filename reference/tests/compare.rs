@@ -0,0 +1,114 @@
+//! Compare the output of the Rust-based compressor with the original C implementation. These
+//! tests are part of the reference crate, so that only they require a C toolchain. Run them with
+//! `cargo test -p reference`, or as part of `cargo test --workspace`.
+
+use zx0::Compressor;
+
+// The source of the compressor makes for a convenient input of a decent size
+const INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../src/lib.rs");
+
+#[test]
+fn defaults() {
+    let input = std::fs::read(INPUT).unwrap();
+
+    let reference = reference::Compressor::new().compress(&input);
+    let result = Compressor::new().compress(&input);
+
+    assert_eq!(result.output, reference.output);
+    assert_eq!(result.delta, reference.delta);
+}
+
+#[test]
+fn defaults_with_prefix() {
+    let input = std::fs::read(INPUT).unwrap();
+
+    // This may take a minute on a debug build
+    for skip in (0..input.len()).step_by(512) {
+        let reference = reference::Compressor::new().skip(skip).compress(&input);
+        unsafe { reference::reset(); }
+
+        let result = Compressor::new().skip(skip).compress(&input);
+
+        assert_eq!(result.output, reference.output);
+        assert_eq!(result.delta, reference.delta);
+    }
+}
+
+#[test]
+fn backwards_mode() {
+    let input = std::fs::read(INPUT).unwrap();
+
+    let reference = reference::Compressor::new().backwards_mode(true).compress(&input);
+    unsafe { reference::reset(); }
+
+    let result = Compressor::new().backwards_mode(true).compress(&input);
+
+    assert_eq!(result.output, reference.output);
+    assert_eq!(result.delta, reference.delta);
+}
+
+#[test]
+fn backwards_mode_with_suffix() {
+    let input = std::fs::read(INPUT).unwrap();
+
+    // This may take a minute on a debug build
+    for skip in (0..input.len()).step_by(512) {
+        let reference = reference::Compressor::new().backwards_mode(true).skip(skip).compress(&input);
+        unsafe { reference::reset(); }
+
+        let result = Compressor::new().backwards_mode(true).skip(skip).compress(&input);
+
+        assert_eq!(result.output, reference.output);
+        assert_eq!(result.delta, reference.delta);
+    }
+}
+
+#[test]
+fn quick_mode() {
+    let input = std::fs::read(INPUT).unwrap();
+
+    let reference = reference::Compressor::new().quick_mode(true).compress(&input);
+    unsafe { reference::reset(); }
+
+    let result = Compressor::new().quick_mode(true).compress(&input);
+
+    assert_eq!(result.output, reference.output);
+    assert_eq!(result.delta, reference.delta);
+}
+
+#[test]
+fn classic_mode() {
+    let input = std::fs::read(INPUT).unwrap();
+
+    let reference = reference::Compressor::new().classic_mode(true).compress(&input);
+    unsafe { reference::reset(); }
+
+    let result = Compressor::new().classic_mode(true).compress(&input);
+
+    assert_eq!(result.output, reference.output);
+    assert_eq!(result.delta, reference.delta);
+}
+
+#[test]
+fn effort() {
+    let input = std::fs::read(INPUT).unwrap();
+
+    let reference = reference::Compressor::new().compress(&input);
+    unsafe { reference::reset(); }
+
+    let result = Compressor::new().effort(8).compress(&input);
+
+    assert_eq!(result.output, reference.output);
+    assert_eq!(result.delta, reference.delta);
+}
+
+#[test]
+fn extreme_mode() {
+    let input = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/../src/live.rs")).unwrap();
+
+    let reference = reference::Compressor::new().compress(&input);
+    unsafe { reference::reset(); }
+
+    let result = Compressor::new().extreme_mode(true).compress(&input);
+    assert!(result.output.len() <= reference.output.len());
+}
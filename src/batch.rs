@@ -0,0 +1,380 @@
+//! A job queue for compressing many inputs in parallel.
+//!
+//! A [`Batch`] owns a pool of worker threads that pick up submitted jobs in order. Results are
+//! delivered in submission order through [`Batch::results`], regardless of the order in which
+//! the jobs finish. The aggregated progress of the batch can be queried and the batch can be
+//! cancelled from any thread using a [`BatchHandle`].
+//!
+//! ```
+//! use zx0::batch::{Batch, Config, Outcome};
+//!
+//! let mut batch = Batch::new(2);
+//!
+//! batch.submit("first", vec![1; 100], Config::default());
+//! batch.submit("second", vec![2; 100], Config { quick_mode: true, ..Config::default() });
+//!
+//! for job in batch.results() {
+//!     if let Outcome::Compressed(result) = job.outcome {
+//!         println!("{}: {} bytes", job.name, result.output.len());
+//!     }
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::{CompressionResult, Compressor, MAX_EFFORT};
+
+/// The compression settings for a single job. These correspond to the settings of a
+/// [`Compressor`], and have the same defaults.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The number of prefix/suffix bytes to skip, see [`Compressor::skip`].
+    pub skip: usize,
+
+    /// Whether quick mode is enabled, see [`Compressor::quick_mode`].
+    pub quick_mode: bool,
+
+    /// Whether backwards mode is enabled, see [`Compressor::backwards_mode`]. Like the
+    /// [`Compressor`], the batch does not reverse the input or output.
+    pub backwards_mode: bool,
+
+    /// Whether classic mode is enabled, see [`Compressor::classic_mode`].
+    pub classic_mode: bool,
+
+    /// The compression effort, see [`Compressor::effort`].
+    pub effort: u8
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            skip: 0,
+            quick_mode: false,
+            backwards_mode: false,
+            classic_mode: false,
+            effort: MAX_EFFORT
+        }
+    }
+}
+
+/// The outcome of a single job.
+pub enum Outcome {
+    /// The input was compressed successfully.
+    Compressed(CompressionResult),
+
+    /// The batch was cancelled before the job was started.
+    Cancelled,
+
+    /// The input could not be compressed, for example because the whole input was skipped, or the
+    /// compressor panicked. This contains the error or panic message.
+    Failed(String)
+}
+
+/// The result of a single job, as returned by [`Batch::results`].
+pub struct JobResult {
+    /// The position of the job in the order of submission, starting at zero.
+    pub index: usize,
+
+    /// The name that the job was submitted with.
+    pub name: String,
+
+    /// The outcome of the job.
+    pub outcome: Outcome
+}
+
+struct Job {
+    index: usize,
+    name: String,
+    input: Vec<u8>,
+    config: Config
+}
+
+#[derive(Default)]
+struct State {
+    cancelled: AtomicBool,
+
+    // The input length and progress of every submitted job
+    progress: Mutex<Vec<(usize, f32)>>
+}
+
+/// A handle to a [`Batch`] that can be used to monitor or cancel it from other threads.
+#[derive(Clone)]
+pub struct BatchHandle {
+    state: Arc<State>
+}
+
+impl BatchHandle {
+    /// Cancel the batch. Jobs that have not been started yet will not be started anymore, and
+    /// are reported as [`Outcome::Cancelled`]. Jobs that are already running are completed.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Return whether the batch was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Return the aggregated progress of all submitted jobs, between `0.0` and `1.0`. Every job
+    /// is weighted by the length of its input, and cancelled jobs count as completed.
+    pub fn progress(&self) -> f32 {
+        let progress = self.state.progress.lock().unwrap();
+        let total = progress.iter().map(|&(len, _)| len).sum::<usize>();
+
+        if total == 0 {
+            return if progress.is_empty() { 0.0 } else { 1.0 };
+        }
+
+        progress.iter().map(|&(len, progress)| len as f32 * progress).sum::<f32>() / total as f32
+    }
+}
+
+/// A queue of compression jobs that are processed by a pool of worker threads.
+///
+/// Dropping a batch before all of its results have been received cancels it, and waits for the
+/// running jobs to complete.
+pub struct Batch {
+    handle: BatchHandle,
+    sender: Option<Sender<Job>>,
+    receiver: Receiver<JobResult>,
+    workers: Vec<JoinHandle<()>>,
+    submitted: usize,
+    received: usize
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "compressor panicked".to_string()
+    }
+}
+
+fn run_job(job: &Job, state: &State) -> Outcome {
+    if state.cancelled.load(Ordering::Relaxed) {
+        return Outcome::Cancelled;
+    }
+
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        Compressor::new()
+            .skip(job.config.skip)
+            .quick_mode(job.config.quick_mode)
+            .backwards_mode(job.config.backwards_mode)
+            .classic_mode(job.config.classic_mode)
+            .effort(job.config.effort)
+            .progress_callback(|progress| state.progress.lock().unwrap()[job.index].1 = progress)
+            .try_compress(&job.input)
+    }));
+
+    match outcome {
+        Ok(Ok(result)) => Outcome::Compressed(result),
+        Ok(Err(err)) => Outcome::Failed(err.to_string()),
+        Err(payload) => Outcome::Failed(panic_message(payload))
+    }
+}
+
+impl Batch {
+    /// Create a new batch that processes its jobs using the given number of worker threads. If
+    /// the number of threads is zero, the available parallelism of the system is used instead.
+    pub fn new(threads: usize) -> Self {
+        let threads = if threads == 0 {
+            thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1)
+        } else {
+            threads
+        };
+
+        let state = Arc::new(State::default());
+        let (sender, jobs) = mpsc::channel::<Job>();
+        let (results, receiver) = mpsc::channel();
+        let jobs = Arc::new(Mutex::new(jobs));
+
+        let workers = (0..threads).map(|_| {
+            let state = state.clone();
+            let jobs = jobs.clone();
+            let results = results.clone();
+
+            thread::spawn(move || loop {
+                // The lock is released as soon as a job has been taken off the queue
+                let job = match jobs.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => break
+                };
+
+                let outcome = run_job(&job, &state);
+                state.progress.lock().unwrap()[job.index].1 = 1.0;
+
+                let result = JobResult {
+                    index: job.index,
+                    name: job.name,
+                    outcome
+                };
+
+                if results.send(result).is_err() {
+                    break;
+                }
+            })
+        }).collect();
+
+        Self {
+            handle: BatchHandle { state },
+            sender: Some(sender),
+            receiver,
+            workers,
+            submitted: 0,
+            received: 0
+        }
+    }
+
+    /// Submit a job to the batch, which will be picked up by the next available worker thread.
+    /// Returns the index of the job, which is also reported in its [`JobResult`].
+    pub fn submit(&mut self, name: impl Into<String>, input: Vec<u8>, config: Config) -> usize {
+        let index = self.submitted;
+        self.submitted += 1;
+
+        self.handle.state.progress.lock().unwrap().push((input.len(), 0.0));
+
+        let job = Job {
+            index,
+            name: name.into(),
+            input,
+            config
+        };
+
+        if let Some(sender) = &self.sender {
+            // The workers only stop after the sender is dropped, so this can't fail
+            sender.send(job).unwrap();
+        }
+
+        index
+    }
+
+    /// Return a handle that can be used to monitor the progress of the batch, or to cancel it.
+    pub fn handle(&self) -> BatchHandle {
+        self.handle.clone()
+    }
+
+    /// Close the batch for new jobs, and return an iterator over the results of all submitted
+    /// jobs in the order in which they were submitted. The iterator blocks until the next result
+    /// is available.
+    pub fn results(mut self) -> Results {
+        self.sender = None;
+
+        Results {
+            batch: self,
+            pending: BTreeMap::new()
+        }
+    }
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        if self.received < self.submitted {
+            self.handle.cancel();
+        }
+
+        self.sender = None;
+
+        // Drain the results, so that no worker is blocked while being joined
+        while self.receiver.try_recv().is_ok() {}
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// An iterator over the results of a [`Batch`] in submission order, as returned by
+/// [`Batch::results`].
+pub struct Results {
+    batch: Batch,
+    pending: BTreeMap<usize, JobResult>
+}
+
+impl Iterator for Results {
+    type Item = JobResult;
+
+    fn next(&mut self) -> Option<JobResult> {
+        let next = self.batch.received;
+
+        if next == self.batch.submitted {
+            return None;
+        }
+
+        // Buffer results that arrive out of order until it is their turn
+        while !self.pending.contains_key(&next) {
+            let result = self.batch.receiver.recv().ok()?;
+            self.pending.insert(result.index, result);
+        }
+
+        self.batch.received += 1;
+        self.pending.remove(&next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Batch, Config, Outcome};
+    use crate::Compressor;
+
+    #[test]
+    fn ordered_results() {
+        let input = std::fs::read("src/lib.rs").unwrap();
+        let inputs = input.chunks(1000).map(|chunk| chunk.to_vec()).collect::<Vec<_>>();
+
+        let mut batch = Batch::new(3);
+        let handle = batch.handle();
+
+        for (i, input) in inputs.iter().enumerate() {
+            batch.submit(format!("chunk {}", i), input.clone(), Config { quick_mode: i % 2 == 1, ..Config::default() });
+        }
+
+        // An input that can't be compressed fails without taking the batch down
+        batch.submit("empty", Vec::new(), Config::default());
+
+        let results = batch.results().collect::<Vec<_>>();
+        assert_eq!(results.len(), inputs.len() + 1);
+
+        for (i, (job, input)) in results.iter().zip(&inputs).enumerate() {
+            assert_eq!(job.index, i);
+            assert_eq!(job.name, format!("chunk {}", i));
+
+            let expected = Compressor::new().quick_mode(i % 2 == 1).compress(input);
+
+            match &job.outcome {
+                Outcome::Compressed(result) => assert_eq!(result.output, expected.output),
+                _ => panic!("job {} was not compressed", i)
+            }
+        }
+
+        assert!(matches!(results[inputs.len()].outcome, Outcome::Failed(_)));
+        assert_eq!(handle.progress(), 1.0);
+        assert!(!handle.is_cancelled());
+    }
+
+    #[test]
+    fn cancel() {
+        let mut batch = Batch::new(1);
+        let handle = batch.handle();
+
+        for i in 0..64 {
+            batch.submit(i.to_string(), vec![i as u8; 2000], Config::default());
+        }
+
+        handle.cancel();
+
+        let results = batch.results().collect::<Vec<_>>();
+        assert_eq!(results.len(), 64);
+
+        // At most the job that was already running when the batch was cancelled is completed
+        let compressed = results.iter().filter(|job| matches!(job.outcome, Outcome::Compressed(_))).count();
+        assert!(compressed <= 1);
+        assert!(handle.is_cancelled());
+    }
+}
@@ -0,0 +1,199 @@
+//! The bit writer that the encoders of the regular format share, which interleaves bit groups with
+//! the regular output bytes.
+
+/// The bytes that a [`BitWriter`] writes to.
+pub trait Output {
+    /// Append a byte.
+    fn push(&mut self, value: u8);
+
+    /// Return the number of bytes that are in the output.
+    fn position(&self) -> usize;
+
+    /// Return the byte at the given index, which must be in the output, for modification.
+    fn byte_mut(&mut self, index: usize) -> &mut u8;
+}
+
+impl Output for Vec<u8> {
+    fn push(&mut self, value: u8) {
+        Vec::push(self, value);
+    }
+
+    fn position(&self) -> usize {
+        self.len()
+    }
+
+    fn byte_mut(&mut self, index: usize) -> &mut u8 {
+        &mut self[index]
+    }
+}
+
+/// A slice of a known size that is filled from its start.
+pub struct SliceOutput<'a> {
+    slice: &'a mut [u8],
+    position: usize
+}
+
+impl<'a> SliceOutput<'a> {
+    pub fn new(slice: &'a mut [u8]) -> Self {
+        Self { slice, position: 0 }
+    }
+}
+
+impl Output for SliceOutput<'_> {
+    fn push(&mut self, value: u8) {
+        self.slice[self.position] = value;
+        self.position += 1;
+    }
+
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn byte_mut(&mut self, index: usize) -> &mut u8 {
+        &mut self.slice[index]
+    }
+}
+
+/// Writes the bytes and bits of the compressed data, and keeps track of the delta.
+pub struct BitWriter<O> {
+    output: O,
+
+    /// Whether the next bit goes into the lowest bit of the previously written byte.
+    pub backtrack: bool,
+
+    bits: usize,
+    bit_free: u32,
+    bit_index: usize,
+    diff: i64,
+    max_diff: i64
+}
+
+impl<O: Output> BitWriter<O> {
+    /// Create a writer whose first bit is backtracked into the byte before the output, which is
+    /// where the omitted indicator bit of the first literal run goes. The difference between the
+    /// decompressed and the compressed data starts out at `diff`.
+    pub fn new(output: O, diff: i64) -> Self {
+        Self {
+            output,
+            backtrack: true,
+            bits: 0,
+            bit_free: 0,
+            bit_index: 0,
+            diff,
+            max_diff: 0
+        }
+    }
+
+    /// Return the output.
+    pub fn output(&self) -> &O {
+        &self.output
+    }
+
+    /// Return the number of bits that have been written.
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+
+    /// Return the largest difference between the decompressed and the compressed data so far, or
+    /// zero if the compressed data was never behind.
+    pub fn max_diff(&self) -> i64 {
+        self.max_diff
+    }
+
+    /// Keep track of how far the decompressed data gets ahead of the compressed data, which
+    /// determines the delta.
+    pub fn read_bytes(&mut self, n: usize) {
+        self.diff += n as i64;
+        self.max_diff = self.max_diff.max(self.diff);
+    }
+
+    pub fn write_byte(&mut self, value: u8) {
+        self.output.push(value);
+        self.diff -= 1;
+    }
+
+    /// Write the lowest `count` bits of `value`, most significant bit first.
+    ///
+    /// Bits are packed into bit groups of one byte each that are interleaved with the regular
+    /// output bytes. Rather than handling every bit individually, as many bits as fit in the
+    /// current group are merged into it at once, so a complete Elias Gamma code is usually written
+    /// with one or two byte operations.
+    pub fn write_bits(&mut self, value: u64, mut count: u32) {
+        if self.backtrack {
+            // The first bit goes into the lowest bit of the previously written byte
+            count -= 1;
+
+            if (value >> count) & 1 != 0 {
+                let index = self.output.position() - 1;
+                *self.output.byte_mut(index) |= 1;
+            }
+
+            self.backtrack = false;
+        }
+
+        self.bits += count as usize;
+
+        while count > 0 {
+            if self.bit_free == 0 {
+                self.bit_free = 8;
+                self.bit_index = self.output.position();
+                self.write_byte(0);
+            }
+
+            let n = count.min(self.bit_free);
+            count -= n;
+            self.bit_free -= n;
+
+            let chunk = (value >> count) & ((1 << n) - 1);
+            *self.output.byte_mut(self.bit_index) |= (chunk as u8) << self.bit_free;
+        }
+    }
+
+    pub fn write_bit(&mut self, value: u8) {
+        self.write_bits(value as u64, 1);
+    }
+
+    pub fn write_interlaced_elias_gamma(
+        &mut self,
+        value: usize, // usize because the only values we will be writing are derived from it
+        backwards_mode: bool,
+        invert_mode: bool
+    ) {
+        let control = if backwards_mode { 1 } else { 0 };
+        let invert = if invert_mode { 1 } else { 0 };
+
+        // Assemble the complete code in a single word. Every value bit below the most significant
+        // one is preceded by a control bit, followed by a final terminating bit.
+        let value = value as u64;
+        let length = u64::BITS - value.leading_zeros() - 1;
+        let mut code = 0;
+
+        for i in (0..length).rev() {
+            code = (code << 2) | (control << 1) | (((value >> i) & 1) ^ invert);
+        }
+
+        code = (code << 1) | (control ^ 1);
+
+        self.write_bits(code, 2 * length + 1);
+    }
+}
+
+impl BitWriter<Vec<u8>> {
+    /// Remove the bytes that can no longer change from the start of the output and return them,
+    /// which is everything but the bit group that is being filled and the byte that the next bit
+    /// may be backtracked into.
+    pub fn drain_complete(&mut self) -> std::vec::Drain<'_, u8> {
+        let mut end = self.output.len();
+
+        if self.bit_free > 0 {
+            end = end.min(self.bit_index);
+        }
+
+        if self.backtrack {
+            end = end.saturating_sub(1);
+        }
+
+        self.bit_index = self.bit_index.saturating_sub(end);
+        self.output.drain(..end)
+    }
+}
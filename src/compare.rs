@@ -0,0 +1,142 @@
+//! The mode comparison, which compresses a file with every combination of the quick, backwards and
+//! classic settings, and shows how large the output is for each of them.
+
+use zx0::{Compressor, Error};
+
+/// A combination of settings that a file is compressed with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mode {
+    /// Whether quick mode is enabled.
+    pub quick_mode: bool,
+
+    /// Whether backwards mode is enabled.
+    pub backwards_mode: bool,
+
+    /// Whether the classic (V1) file format is used.
+    pub classic_mode: bool
+}
+
+impl Mode {
+    const fn new(quick_mode: bool, backwards_mode: bool, classic_mode: bool) -> Self {
+        Self { quick_mode, backwards_mode, classic_mode }
+    }
+
+    /// Return a short description of the mode.
+    pub fn name(&self) -> String {
+        format!(
+            "{} {} {}",
+            if self.quick_mode { "quick" } else { "optimal" },
+            if self.backwards_mode { "backwards" } else { "forwards" },
+            if self.classic_mode { "classic" } else { "v2" }
+        )
+    }
+
+    /// Return the command line flags that select the mode.
+    pub fn flags(&self) -> String {
+        let flags = [(self.quick_mode, "-q"), (self.backwards_mode, "-b"), (self.classic_mode, "-c")]
+            .iter()
+            .filter(|&&(enabled, _)| enabled)
+            .map(|&(_, flag)| flag)
+            .collect::<Vec<_>>();
+
+        if flags.is_empty() { "-".to_string() } else { flags.join(" ") }
+    }
+}
+
+/// The modes that are compared, starting with the default one. Both file formats are identical
+/// when compressing backwards, so the classic format is only compared when compressing forwards.
+pub const MODES: [Mode; 6] = [
+    Mode::new(false, false, false),
+    Mode::new(false, false, true),
+    Mode::new(false, true, false),
+    Mode::new(true, false, false),
+    Mode::new(true, false, true),
+    Mode::new(true, true, false)
+];
+
+/// The outcome of compressing a file with one of the modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Row {
+    /// The mode that the file was compressed with.
+    pub mode: Mode,
+
+    /// The size of the compressed data.
+    pub output_size: usize,
+
+    /// The minimum gap between the compressed and decompressed data for in-place decompression.
+    pub delta: usize
+}
+
+/// Compress the input with every mode in [`MODES`]. All other settings, such as the effort and
+/// the number of bytes to skip, are taken from the compressor, which is left in the last mode with
+/// its natural order enabled.
+///
+/// Returns an error if any of the modes fails to compress the input.
+pub fn compare_modes(compressor: &mut Compressor, input: &[u8]) -> Result<Vec<Row>, Error> {
+    // The input is not reversed up front, so the compressor has to take care of that
+    compressor.natural_order(true);
+
+    MODES.iter().map(|&mode| {
+        let result = compressor
+            .quick_mode(mode.quick_mode)
+            .backwards_mode(mode.backwards_mode)
+            .classic_mode(mode.classic_mode)
+            .try_compress(input)?;
+
+        Ok(Row { mode, output_size: result.output.len(), delta: result.delta })
+    }).collect()
+}
+
+/// Format the outcome of [`compare_modes`] as a table, in which the smallest outputs are marked.
+pub fn format_comparison(input_size: usize, rows: &[Row]) -> String {
+    let smallest = rows.iter().map(|row| row.output_size).min();
+
+    let mut result = format!("{:<26} {:<8} {:>8} {:>6} {:>7}\n", "Mode", "Flags", "Size", "Delta", "Ratio");
+
+    for row in rows {
+        let marker = if Some(row.output_size) == smallest { "  smallest" } else { "" };
+
+        result.push_str(&format!(
+            "{:<26} {:<8} {:>8} {:>6} {:>7.3}{}\n",
+            row.mode.name(),
+            row.mode.flags(),
+            row.output_size,
+            row.delta,
+            input_size as f32 / row.output_size.max(1) as f32,
+            marker
+        ));
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use zx0::Compressor;
+
+    use super::{compare_modes, format_comparison, MODES};
+
+    #[test]
+    fn comparison() {
+        let text = std::fs::read("src/main.rs").unwrap();
+        let input = &text[..4000];
+
+        let rows = compare_modes(&mut Compressor::new(), input).unwrap();
+
+        assert_eq!(rows.iter().map(|row| row.mode).collect::<Vec<_>>(), MODES);
+        assert_eq!(rows[0].output_size, Compressor::new().compress(input).output.len());
+        assert_eq!(rows[5].output_size, Compressor::new().quick_mode(true).backwards_mode(true).natural_order(true).compress(input).output.len());
+
+        // Quick mode never beats the optimal parse
+        assert!(rows[3].output_size >= rows[0].output_size);
+
+        let table = format_comparison(input.len(), &rows);
+        let lines = table.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "Mode                       Flags        Size  Delta   Ratio");
+        assert!(lines[1].starts_with("optimal forwards v2        -       "));
+        assert!(lines[6].starts_with("quick backwards v2         -q -b   "));
+        assert!(lines.iter().any(|line| line.ends_with("  smallest")));
+        assert_eq!(lines.len(), 7);
+    }
+}
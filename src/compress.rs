@@ -1,151 +1,63 @@
 use crate::INITIAL_OFFSET;
-
-pub struct Block {
-    pub bits: u32,
-    pub index: isize,
-    pub offset: usize
-}
-
-struct Context<'a> {
-    backtrack: bool,
-    bit_mask: u8,
-    bit_index: usize,
-    input_index: usize,
-    output: &'a mut Vec<u8>,
-    output_index: usize,
-    diff: isize
+use crate::bits::{BitWriter, Output, SliceOutput};
+use crate::compressor::{Token, TokenKind};
+use crate::optimize::Chain;
+use crate::segments::Symbols;
+
+/// Return the exact number of bytes that encoding the chain will produce.
+pub fn output_size(chain: &Chain) -> usize {
+    ((chain.bits + 25) / 8) as usize
 }
 
-impl Context<'_> {
-    fn read_bytes(
-        &mut self,
-        n: usize,
-        delta: &mut usize
-    ) {
-        self.input_index += n;
-        self.diff += n as isize;
-
-        // Since delta starts with zero and is only written to here, it can be proven that it will
-        // always be a non-negative number. Therefore its type is usize instead of diff's isize
-        // (since it can become negative).
-        if (*delta as isize) < self.diff {
-            *delta = self.diff as usize;
-        }
-    }
-
-    fn write_byte(
-        &mut self,
-        value: u8,
-    ) {
-        self.output[self.output_index] = value;
-        self.output_index += 1;
-        self.diff -= 1;
-    }
-
-    fn write_bit(
-        &mut self,
-        value: u8
-    ) {
-        if self.backtrack {
-            if value != 0 {
-                self.output[self.output_index - 1] |= 1;
-            }
-
-            self.backtrack = false;
-        } else {
-            if self.bit_mask == 0 {
-                self.bit_mask = 128;
-                self.bit_index = self.output_index;
-                self.write_byte(0);
-            }
-
-            if value != 0 {
-                self.output[self.bit_index] |= self.bit_mask as u8;
-            }
-
-            self.bit_mask >>= 1;
-        }
-    }
+/// Encode an optimized chain of blocks.
+///
+/// The compressed data is written to the output slice, which must be exactly
+/// [`output_size`] bytes long for the chain. Returns the delta value. Every encoded literal run or
+/// match is passed to the token callback.
+pub fn compress<I: Symbols<u8> + ?Sized>(
+    chain: &Chain,
+    input: &I,
+    backwards_mode: bool,
+    invert_mode: bool,
+    output: &mut [u8],
+    progress_callback: &mut dyn FnMut(f32),
+    token_callback: &mut dyn FnMut(Token)
+) -> usize {
+    let skip = chain.skip;
+    let output_size = output_size(chain);
+    assert_eq!(output.len(), output_size, "output length differs from the encoded size");
 
-    fn write_interlaced_elias_gamma(
-        &mut self,
-        value: usize, // usize because the only values we will be writing are derived from it
-        backwards_mode: bool,
-        invert_mode: bool
-    ) {
-        let mut i = 2;
+    // Bits are merged into the output, so it has to start out cleared
+    output.fill(0);
 
-        while i <= value {
-            i <<= 1;
-        }
+    // Initialize data
+    let mut last_offset = INITIAL_OFFSET;
+    let mut input_index = skip;
 
-        i >>= 1;
+    // Note: the difference is normally a negative number, unless optimize() has a compression
+    // ratio <1.
+    let mut context = BitWriter::new(SliceOutput::new(output), output_size as i64 - input.len() as i64 + skip as i64);
 
-        loop {
-            i >>= 1;
-            if i == 0 {
-                break;
-            }
+    // Generate output
+    let mut previous_block = chain.allocator.get(chain.head);
+    let mut tokens = 0usize;
 
-            self.write_bit(
-                if backwards_mode { 1 } else { 0 }
-            );
-
-            self.write_bit(
-                if invert_mode {
-                    if value & i != 0 {
-                        0
-                    } else {
-                        1
-                    }
-                } else if value & i != 0 {
-                    1
-                } else {
-                    0
-                }
-            );
+    while previous_block.next_index() != 0 {
+        if tokens.is_multiple_of(128) {
+            progress_callback((input_index - skip) as f32 / (input.len() - skip) as f32);
         }
 
-        self.write_bit(
-            if !backwards_mode { 1 } else { 0 }
-        );
-    }
-}
-
-pub fn compress(
-    chain: Vec<Block>,
-    input: &[u8],
-    skip: usize,
-    backwards_mode: bool,
-    invert_mode: bool,
-    delta: &mut usize
-) -> Vec<u8> {
-    // Calculate and allocate output buffer
-    let output_size = ((chain[0].bits + 25) / 8) as usize;
-    let mut output = vec![0; output_size];
-
-    // Un-reverse optimal sequence
-    let chain = chain.into_iter().rev().collect::<Vec<_>>();
+        tokens += 1;
 
-    // Initialize data
-    let mut last_offset = INITIAL_OFFSET as usize;
-
-    let mut context = Context {
-        backtrack: true,
-        bit_mask: 0,
-        bit_index: 0,
-        input_index: skip,
-        output: &mut output,
-        output_index: 0,
-        // Note: this is normally a negative number, unless optimize() has a compression ratio <1.
-        diff: output_size as isize - input.len() as isize + skip as isize
-    };
-
-    // Generate output
-    for (previous_block, current_block) in chain.windows(2).map(|s| (&s[0], &s[1])) {
+        let current_block = chain.allocator.get(previous_block.next_index());
         let length = (current_block.index - previous_block.index) as usize;
+        let offset = current_block.offset as usize;
 
-        if current_block.offset == 0 {
+        let input_position = input_index;
+        let output_position = context.output().position();
+        let bits = context.bits();
+
+        let kind = if offset == 0 {
             // Copy literals indicator
             context.write_bit(0);
 
@@ -154,38 +66,61 @@ pub fn compress(
 
             // Copy literals values
             for _ in 0..length {
-                let byte = input[context.input_index];
-                context.write_byte(byte);
-                context.read_bytes(1, delta);
+                context.write_byte(input.at(input_index));
+                context.read_bytes(1);
+                input_index += 1;
             }
-        } else if current_block.offset == last_offset {
+
+            TokenKind::Literals
+        } else if offset == last_offset {
             // Copy from last offset indicator
             context.write_bit(0);
 
             // Copy from last offset length
             context.write_interlaced_elias_gamma(length, backwards_mode, false);
-            context.read_bytes(length, delta);
+            context.read_bytes(length);
+            input_index += length;
+
+            TokenKind::LastOffset
         } else {
             // Copy from new offset indicator
             context.write_bit(1);
 
             // Copy from new offset MSB
-            context.write_interlaced_elias_gamma((current_block.offset - 1) / 128 + 1, backwards_mode, invert_mode);
+            context.write_interlaced_elias_gamma((offset - 1) / 128 + 1, backwards_mode, invert_mode);
 
             // Copy from new offset LSB
             if backwards_mode {
-                context.write_byte((((current_block.offset - 1) % 128) << 1) as u8);
+                context.write_byte((((offset - 1) % 128) << 1) as u8);
             } else {
-                context.write_byte(((127 - (current_block.offset - 1) % 128) << 1) as u8);
+                context.write_byte(((127 - (offset - 1) % 128) << 1) as u8);
             }
 
             // Copy from new offset length */
             context.backtrack = true;
             context.write_interlaced_elias_gamma(length - 1, backwards_mode, false);
-            context.read_bytes(length, delta);
-
-            last_offset = current_block.offset;
-        }
+            context.read_bytes(length);
+            input_index += length;
+
+            last_offset = offset;
+
+            TokenKind::NewOffset
+        };
+
+        token_callback(Token {
+            kind,
+            offset,
+            length,
+            bits: context.bits() - bits + match kind {
+                TokenKind::Literals => 8 * length,
+                TokenKind::LastOffset => 0,
+                TokenKind::NewOffset => 8
+            },
+            input_position,
+            output_position
+        });
+
+        previous_block = current_block;
     }
 
     // End marker
@@ -193,5 +128,8 @@ pub fn compress(
     context.write_interlaced_elias_gamma(256, backwards_mode, invert_mode);
 
     // Done
-    output
+    progress_callback(1.0);
+
+    // The delta only ever grows from zero, so it is never negative
+    context.max_diff() as usize
 }
@@ -1,16 +1,39 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+#[cfg(feature = "debug-dump")]
+use std::path::PathBuf;
+use std::sync::Arc;
+
 use crate::{
+    Error,
+    INITIAL_OFFSET,
+    LiveStats,
+    MAX_EFFORT,
     MAX_OFFSET_ZX0,
     MAX_OFFSET_ZX7
 };
 
-use crate::compress::{Block, compress};
-use crate::optimize::optimize;
+use crate::compress::{compress, output_size};
+#[cfg(feature = "debug-dump")]
+use crate::dump::Dump;
+use crate::optimize::{Chain, Scratch, Settings, UNREACHABLE_BITS, optimize};
+#[cfg(feature = "allocator-api2")]
+use crate::optimize::ScratchAllocator;
+use crate::parallel::optimize_chunks;
+use crate::progress::ProgressExt;
+use crate::segments::{Reversed, Segments, Symbols};
+use crate::{dual, stream, word};
 
 /// A struct containing a vector representing the compressed data, as well as metadata related to
 /// the compression operation.
-pub struct CompressionResult {
+///
+/// The compressed data is stored in a `Vec<u8>` by default. With the `bytes` feature, `into_bytes`
+/// turns the result into one that stores it in a `bytes::Bytes` buffer instead.
+pub struct CompressionResult<O = Vec<u8>> {
     /// A vector containing the compressed output data.
-    pub output: Vec<u8>,
+    pub output: O,
 
     /// This value represents the minimum gap that should be maintained between the compressed
     /// data's end address and the uncompressed data's end address when decompressing in-place.
@@ -20,11 +43,182 @@ pub struct CompressionResult {
     /// Please refer to the original C implementation's
     /// [readme](https://github.com/einar-saukas/ZX0#compressing-with-prefix) for an in-depth
     /// explanation.
-    pub delta: usize
+    pub delta: usize,
+
+    /// Statistics about the compression operation.
+    pub stats: CompressionStats,
+
+    /// The tokens that make up the compressed data, in the order in which they were encoded. This
+    /// is only populated when token recording was enabled using
+    /// [`record_tokens`](Compressor::record_tokens), and is empty otherwise.
+    pub tokens: Vec<Token>
+}
+
+impl CompressionResult {
+    /// Write the compressed data to a file, replacing the file if it already exists.
+    ///
+    /// The data is first written to a temporary file in the same directory, which is then renamed
+    /// to the destination. This makes sure that the destination either contains the old data or
+    /// the complete compressed data, and never a partially written file, even when the process is
+    /// interrupted. The temporary file is removed again if anything goes wrong.
+    ///
+    /// The data is written as it is stored in [`output`](CompressionResult::output). When using
+    /// the backwards mode, the output should be reversed first if the input was reversed before
+    /// compressing it, which is not needed when [`natural_order`](Compressor::natural_order) is
+    /// enabled.
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+
+        let file_name = path.file_name().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file path", path.display()))
+        })?;
+
+        let mut temp_name = file_name.to_os_string();
+        temp_name.push(format!(".{}.tmp", std::process::id()));
+        let temp_path = path.with_file_name(temp_name);
+
+        let result = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+            .and_then(|mut file| {
+                self.write_to_writer(&mut file)?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&temp_path, path));
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+
+        result
+    }
+
+    /// Write the compressed data to a writer, such as an open file or a network stream. The data
+    /// is written as it is stored in [`output`](CompressionResult::output).
+    pub fn write_to_writer<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.output)?;
+        writer.flush()
+    }
+
+    /// Convert the result to one that stores the compressed data in a [`Bytes`](bytes::Bytes)
+    /// buffer, keeping the delta, statistics and tokens. The buffer takes over the allocation of
+    /// the compressed data, so nothing is copied.
+    #[cfg(feature = "bytes")]
+    pub fn into_bytes(self) -> CompressionResult<bytes::Bytes> {
+        CompressionResult {
+            output: self.output.into(),
+            delta: self.delta,
+            stats: self.stats,
+            tokens: self.tokens
+        }
+    }
 }
 
+/// The outcome of compressing a stream using [`compress_stream`](Compressor::compress_stream).
+#[derive(Clone, Debug)]
+pub struct StreamResult {
+    /// The number of input bytes that were compressed, not counting the skipped prefix.
+    pub input_len: u64,
+
+    /// The number of bytes of compressed data that were written.
+    pub output_len: u64,
+
+    /// The minimum gap between the end of the compressed data and the end of the uncompressed data
+    /// when decompressing in-place, like [`CompressionResult::delta`].
+    pub delta: usize,
+
+    /// The peak number of bytes that were used by the compressor's working data structures,
+    /// including the buffered input and output.
+    pub peak_memory: usize
+}
+
+/// Statistics gathered during a compression operation, mostly useful for diagnostics and tuning.
+#[derive(Clone, Debug, Default)]
+pub struct CompressionStats {
+    /// The number of blocks that were allocated in the optimizer's block arena.
+    pub arena_blocks: usize,
+
+    /// The number of blocks that the arena was initially sized for. This is estimated from the
+    /// input length, the number of skipped bytes, and the window size.
+    pub estimated_arena_blocks: usize,
+
+    /// The peak number of bytes that were used by the compressor's working data structures,
+    /// including the block arena and the compressed output buffer. This does not include the
+    /// input data.
+    pub peak_memory: usize
+}
+
+/// The kind of a [`Token`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    /// A run of literal bytes that are copied from the compressed data.
+    Literals,
+
+    /// A copy from the most recently used offset.
+    LastOffset,
+
+    /// A copy from a newly encoded offset.
+    NewOffset
+}
+
+/// A single literal run or match as it was written to the compressed data.
+///
+/// Positions are relative to the data as it is compressed, so when using the backwards mode they
+/// refer to the reversed input and output, even when the compressor reverses both itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    /// The kind of the token.
+    pub kind: TokenKind,
+
+    /// The offset that is copied from, or `0` for literals.
+    pub offset: usize,
+
+    /// The number of bytes that the token produces when decompressing.
+    pub length: usize,
+
+    /// The number of bits that the token occupies in the compressed data, including literal
+    /// bytes and the offset LSB byte.
+    pub bits: usize,
+
+    /// The position in the input of the first byte that the token produces.
+    pub input_position: usize,
+
+    /// The number of bytes of compressed data that were written before the token.
+    pub output_position: usize
+}
+
+/// The optimal parse of an input slice, as produced by [`Compressor::parse`].
+///
+/// A parse describes how the input is split into literals and matches, and can be encoded into
+/// compressed data using [`Compressor::encode`].
+pub struct Parse {
+    chain: Chain,
+    input_len: usize
+}
+
+impl Parse {
+    /// Return the exact number of bytes of compressed data that encoding this parse will produce.
+    pub fn output_len(&self) -> usize {
+        output_size(&self.chain)
+    }
+}
+
+/// The approximate time it takes to encode a single input byte, expressed as the number of offsets
+/// that the optimizer can evaluate in the same amount of time.
+const ENCODE_COST: f32 = 16.0;
+
+/// The largest number of bits that a token is charged on top of its encoded size when searching
+/// for a parse within the token budget. Merging two tokens rarely costs more than this.
+const MAX_TOKEN_COST: u32 = 1 << 14;
+
 pub type ProgressCallback<'a> = Box<dyn FnMut(f32) + 'a>;
 
+pub type TokenCallback<'a> = Box<dyn FnMut(&Token) + 'a>;
+
+// Runs the optimizer on an input with the given settings and scratch allocations
+type Parser<'a> = dyn FnMut(Settings, &mut Scratch, &mut dyn FnMut(f32)) -> Chain + 'a;
+
 /// This struct provides a means of initializing and performing a ZX0 compression operation by
 /// leveraging the builder pattern.
 ///
@@ -35,6 +229,10 @@ pub type ProgressCallback<'a> = Box<dyn FnMut(f32) + 'a>;
 /// - Quick mode disabled
 /// - Backwards mode disabled
 /// - Classic mode disabled
+/// - Maximum effort
+/// - No limit on the length of literal runs
+/// - No limit on the number of tokens
+/// - Chunk-parallel mode disabled
 ///
 /// After constructing a [`Compressor`] instance the method [`compress`](Compressor::compress) is available to compress
 /// `u8` slices. The [`Compressor`] can be resued again afterwards.
@@ -44,9 +242,25 @@ pub type ProgressCallback<'a> = Box<dyn FnMut(f32) + 'a>;
 pub struct Compressor<'a> {
     skip: usize,
     quick_mode: bool,
+    max_offset: usize,
     backwards_mode: bool,
+    natural_order: bool,
     classic_mode: bool,
-    progress_callback: ProgressCallback<'a>
+    effort: u8,
+    extreme_mode: bool,
+    max_literal_run: usize,
+    max_tokens: Option<usize>,
+    chunk_len: usize,
+    chain_chunks: bool,
+    max_input_len: Option<usize>,
+    record_tokens: bool,
+    live_stats: Option<Arc<LiveStats>>,
+    progress_callback: ProgressCallback<'a>,
+    token_callback: TokenCallback<'a>,
+    #[cfg(feature = "debug-dump")]
+    debug_dump: Option<(PathBuf, usize)>,
+    #[cfg(feature = "allocator-api2")]
+    scratch_allocator: Option<ScratchAllocator>
 }
 
 impl<'a> Compressor<'a> {
@@ -56,13 +270,33 @@ impl<'a> Compressor<'a> {
     /// - Quick mode disabled
     /// - Backwards mode disabled
     /// - Classic mode disabled
+    /// - Maximum effort
+    /// - No limit on the length of literal runs
+    /// - No limit on the number of tokens
+    /// - Chunk-parallel mode disabled
     pub fn new() -> Self {
         Self {
             skip: 0,
             quick_mode: false,
+            max_offset: MAX_OFFSET_ZX0,
             backwards_mode: false,
+            natural_order: false,
             classic_mode: false,
-            progress_callback: Box::new(|_| ())
+            effort: MAX_EFFORT,
+            extreme_mode: false,
+            max_literal_run: usize::MAX,
+            max_tokens: None,
+            chunk_len: 0,
+            chain_chunks: true,
+            max_input_len: None,
+            record_tokens: false,
+            live_stats: None,
+            progress_callback: Box::new(|_| ()),
+            token_callback: Box::new(|_| ()),
+            #[cfg(feature = "debug-dump")]
+            debug_dump: None,
+            #[cfg(feature = "allocator-api2")]
+            scratch_allocator: None
         }
     }
 
@@ -77,12 +311,124 @@ impl<'a> Compressor<'a> {
         self
     }
 
+    /// Limit the offsets that matches may use to at most `max_offset`, which is clamped to the
+    /// range from `1` to the regular maximum of `32640`.
+    ///
+    /// This is meant for decompressors that only keep a restricted window of the decompressed data
+    /// around, such as routines that decompress to a ring buffer. The output is still regular ZX0
+    /// data. Like the quick mode, which limits the offsets to `2176`, a smaller window makes
+    /// compression faster and the compression ratio worse. When both are used, the smaller limit
+    /// applies.
+    pub fn max_offset(&mut self, max_offset: usize) -> &mut Self {
+        self.max_offset = max_offset.clamp(1, MAX_OFFSET_ZX0);
+        self
+    }
+
+    /// Change the compression effort, ranging from `0` to `8`. Higher values are clamped to `8`.
+    ///
+    /// At the maximum effort level the compressor performs a full optimal parse, which produces
+    /// output that is identical to the reference implementation. Lower levels limit the number of
+    /// new offsets that are considered as match candidates for every input position to `4^effort`
+    /// (so level `0` only considers a single candidate), trading compression ratio for speed.
+    ///
+    /// In contrast to quick mode this does not change the maximum offset, so the output can still
+    /// be decompressed by any standard ZX0 decompressor. Note that the speedup mostly applies to
+    /// repetitive input, since random data produces very few match candidates to begin with.
+    pub fn effort(&mut self, effort: u8) -> &mut Self {
+        self.effort = effort.min(MAX_EFFORT);
+        self
+    }
+
+    /// Change the value for the extreme mode setting. When enabled, the optimizer also considers
+    /// continuing with literals after an earlier match than the most recent one with the same
+    /// offset, which keeps that offset available for a cheap repeat match later on. This finds
+    /// parses that the reference implementation misses, so the output is never larger and
+    /// sometimes a few bytes smaller, at the cost of slower compression.
+    ///
+    /// The output can still be decompressed by any standard ZX0 decompressor, but it is no longer
+    /// identical to the output of the reference implementation.
+    pub fn extreme_mode(&mut self, extreme_mode: bool) -> &mut Self {
+        self.extreme_mode = extreme_mode;
+        self
+    }
+
+    /// Limit the length of every literal run to at most `max_literal_run` bytes, which is clamped
+    /// to a minimum of `1`. By default the length of literal runs is unlimited.
+    ///
+    /// This is meant for streaming decompressors that buffer a literal run before copying it, in a
+    /// buffer of a fixed size. The optimizer finds the cheapest parse in which no literal run
+    /// exceeds the limit, by ending longer runs at a match, so the output is still regular ZX0
+    /// data. Input that contains a longer stretch of bytes that can't be matched at all can't be
+    /// parsed within the limit, in which case the fallible methods return
+    /// [`Error::LiteralRunTooLong`].
+    ///
+    /// The chunk-parallel mode is not used while literal runs are limited, and
+    /// [`compress_stream`](Compressor::compress_stream) does not support the limit. For
+    /// [`compress_words`](Compressor::compress_words) the limit is counted in words.
+    pub fn max_literal_run(&mut self, max_literal_run: usize) -> &mut Self {
+        self.max_literal_run = max_literal_run.max(1);
+        self
+    }
+
+    /// Limit the total number of tokens, which are the literal runs and matches that make up the
+    /// compressed data, to at most `max_tokens`. By default the number of tokens is unlimited.
+    ///
+    /// This is meant for decompressors that have to finish within a known time, such as
+    /// interrupt-driven loaders that decode a single token per frame. When the optimal parse has
+    /// too many tokens, the optimizer charges a penalty of a number of bits for every token, and
+    /// searches for the smallest penalty at which the parse fits the budget. This trades
+    /// compression ratio for fewer tokens, and the output is still regular ZX0 data. The search
+    /// parses the input up to 16 times, so compression is that much slower when the budget is
+    /// exceeded. If no penalty gets the parse within the budget, the fallible methods return
+    /// [`Error::TokenBudgetExceeded`].
+    ///
+    /// Like [`max_literal_run`](Compressor::max_literal_run), the budget disables the
+    /// chunk-parallel mode and is not supported by
+    /// [`compress_stream`](Compressor::compress_stream). For the methods that take several blocks
+    /// or ranges the budget applies to each of them.
+    pub fn max_tokens(&mut self, max_tokens: usize) -> &mut Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Enable the chunk-parallel mode by setting the length of the chunks, or disable it by
+    /// setting it to `0`, which is the default.
+    ///
+    /// In this mode the input is split into chunks of `chunk_len` bytes, which are parsed on as
+    /// many threads as there are processor cores. The parses are joined afterwards, so the result
+    /// is still a single stream that any standard ZX0 decompressor accepts. Every chunk can refer
+    /// back to the data before it, but its parse can't take the chunks around it into account, so
+    /// the output is usually slightly larger than that of a regular parse. The larger the chunks,
+    /// the less is lost, as long as there are enough chunks to keep every core busy.
+    ///
+    /// The mode applies to every method that compresses or parses a byte slice. The live stats and
+    /// the debug dump are not updated while the chunks are being parsed, and the working memory of
+    /// the threads is always allocated from the global heap.
+    pub fn chunk_len(&mut self, chunk_len: usize) -> &mut Self {
+        self.chunk_len = chunk_len;
+        self
+    }
+
+    /// Change whether chunks in the chunk-parallel mode may refer back to the chunks before them,
+    /// which is enabled by default. Disabling it makes every chunk but the first start out without
+    /// a prefix dictionary, which costs compression ratio but speeds up the first positions of
+    /// every chunk. See [`chunk_len`](Compressor::chunk_len).
+    pub fn chain_chunks(&mut self, chain_chunks: bool) -> &mut Self {
+        self.chain_chunks = chain_chunks;
+        self
+    }
+
     /// Change the value for the backwards compression mode setting. This will cause the ZX0
     /// compressor to create compressed data that should be decompressed back-to-front. This can be
     /// useful in situations where in-place decompression is desired, and the end of the compressed
     /// data overlaps with the end of the region that the uncompressed data should be positioned
     /// in.
     ///
+    /// Like the original implementation, the backwards mode expects the input to be reversed
+    /// before compressing it, and produces output that has to be reversed before it is stored, so
+    /// that the prefix/suffix bytes that are skipped are at the start of the reversed input. Enable
+    /// [`natural_order`](Compressor::natural_order) to let the compressor take care of both.
+    ///
     /// Please refer to the original C implementation's
     /// [readme](https://github.com/einar-saukas/ZX0#compressing-backwards) for an in-depth
     /// explanation.
@@ -91,6 +437,24 @@ impl<'a> Compressor<'a> {
         self
     }
 
+    /// Change whether the backwards mode works on data in its natural order, which is disabled by
+    /// default for compatibility with the original implementation.
+    ///
+    /// When enabled, the input is passed to the compressor as it is stored in memory, with the
+    /// skipped suffix bytes at its end, and the compressed data is returned in the order in which
+    /// it is stored as well. The compressor reverses the input while reading it, without copying
+    /// it, and reverses the output in place. This applies to every method that compresses, parses
+    /// or encodes a byte slice, and has no effect unless the backwards mode is enabled.
+    ///
+    /// The blocks that are passed to [`compress_chained`](Compressor::compress_chained) are then
+    /// also expected in their natural order, in which each block uses the start of the block after
+    /// it as its suffix dictionary. Recorded tokens keep referring to positions in the reversed
+    /// input and output.
+    pub fn natural_order(&mut self, natural_order: bool) -> &mut Self {
+        self.natural_order = natural_order;
+        self
+    }
+
     /// Change the value for the classic compression mode setting. Enabling this will cause the ZX0
     /// compressor to output compressed data in its legacy V1 file format. This can be useful when
     /// compressing for one of the platforms that only provides a V1 decompression routine.
@@ -99,16 +463,89 @@ impl<'a> Compressor<'a> {
         self
     }
 
+    /// Change the value for the token recording setting. When enabled, the
+    /// [`tokens`](CompressionResult::tokens) field of the [`CompressionResult`] will contain a
+    /// description of every literal run and match in the compressed data. This is mostly useful
+    /// for analyzing and visualizing compressed data.
+    pub fn record_tokens(&mut self, record_tokens: bool) -> &mut Self {
+        self.record_tokens = record_tokens;
+        self
+    }
+
+    /// Set the maximum length of the input, including any skipped prefix/suffix bytes. Larger
+    /// inputs are rejected with [`Error::InputTooLarge`] before anything is allocated, since the
+    /// memory that the compressor needs grows with the input length. For the methods that take
+    /// several blocks or ranges the limit applies to each of them. By default there is no limit.
+    ///
+    /// This is useful when compressing input from untrusted sources. The infallible methods panic
+    /// when the limit is exceeded, so such input should be compressed using the `try_` methods.
+    /// Streams are not limited, because their memory usage doesn't depend on their length.
+    pub fn max_input_len(&mut self, max_input_len: usize) -> &mut Self {
+        self.max_input_len = Some(max_input_len);
+        self
+    }
+
     /// Set a progress callback. When providing a closure to this function, that closure will be
     /// called repeatedly during compression. The closure will be called with a progress value
-    /// between `0.0` and `1.0`. Note that due to the nature of the compression algorithm, this
-    /// value is not increasing linearly with time, and thus should be interpreted as a rough
-    /// estimate.
+    /// between `0.0` and `1.0`, covering both the optimization and the encoding phase. The final
+    /// call always reports exactly `1.0`. Note that due to the nature of the compression
+    /// algorithm, this value is not increasing linearly with time, and thus should be interpreted
+    /// as a rough estimate.
     pub fn progress_callback<C: FnMut(f32) + 'a>(&mut self, progress_callback: C) -> &mut Self {
         self.progress_callback = Box::new(progress_callback);
         self
     }
 
+    /// Set a token callback. The closure will be called during the encoding phase for every literal
+    /// run and match as soon as it has been written, in the order of the compressed data. The
+    /// [`output_position`](Token::output_position) of each token tells where in the compressed
+    /// data it starts. Unlike [`record_tokens`](Compressor::record_tokens) this doesn't keep the
+    /// tokens around, so the closure can collect statistics or pass them on as it goes.
+    pub fn on_token<C: FnMut(&Token) + 'a>(&mut self, token_callback: C) -> &mut Self {
+        self.token_callback = Box::new(token_callback);
+        self
+    }
+
+    /// Dump the state of the optimizer to a text file at the given path, taking a snapshot after
+    /// every `interval` positions and after the last one. This is meant for diagnosing parses
+    /// that turn out different than expected, and requires the `debug-dump` feature. The format
+    /// is described in the source of the `dump` module.
+    ///
+    /// Every parse overwrites the file, so when compressing several blocks or ranges it holds the
+    /// dump of the last one. Streams and the word format are not dumped.
+    ///
+    /// # Panics
+    ///
+    /// Compression panics if the dump can't be written.
+    #[cfg(feature = "debug-dump")]
+    pub fn debug_dump<P: AsRef<Path>>(&mut self, path: P, interval: usize) -> &mut Self {
+        self.debug_dump = Some((path.as_ref().to_path_buf(), interval));
+        self
+    }
+
+    /// Allocate the working memory of the compressor from the given allocator instead of the
+    /// global heap. This covers the block arena of the optimizer and its other working data, which
+    /// is by far the largest part of the memory that compression uses, and is freed again once
+    /// the compressed data has been encoded. The compressed data itself is still allocated from
+    /// the global heap, since it outlives the compression operation. Requires the
+    /// `allocator-api2` feature.
+    ///
+    /// The allocator has to implement the `Allocator` trait of the `allocator-api2` crate, which
+    /// mirrors the unstable allocator API of the standard library. Allocators that are borrowed,
+    /// such as a bump arena, can be passed as a `'static` reference.
+    #[cfg(feature = "allocator-api2")]
+    pub fn scratch_allocator<A: allocator_api2::alloc::Allocator + 'static>(&mut self, allocator: A) -> &mut Self {
+        self.scratch_allocator = Some(ScratchAllocator::new(allocator));
+        self
+    }
+
+    /// Set the counters that are updated during compression, so that other threads can observe
+    /// the progress of the compressor. See [`LiveStats`] for details.
+    pub fn live_stats(&mut self, live_stats: Arc<LiveStats>) -> &mut Self {
+        self.live_stats = Some(live_stats);
+        self
+    }
+
     /// Set the number of prefix/suffix bytes to skip during compression. This will cause the
     /// compressor to create a dictionary based on data that will already be in memory before the
     /// compressed data during decompression. Of course, for this to work the prefix (or suffix in
@@ -131,49 +568,788 @@ impl<'a> Compressor<'a> {
     /// The [`Compressor`] does not have to be discarded after calling this method. It does not
     /// contain any state (only the configuration) and thus can be reused again for compressing
     /// additional data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no data left to compress after skipping the prefix/suffix bytes. See
+    /// [`try_compress`](Compressor::try_compress) for a variant that returns an error instead.
     pub fn compress(&mut self, input: &[u8]) -> CompressionResult {
-        let chain = {
-            let (allocator, mut optimal) = optimize(
+        unwrap(self.try_compress(input))
+    }
+
+    /// Compress the provided slice like [`compress`](Compressor::compress) does, but return an
+    /// error instead of panicking if the input can't be compressed.
+    pub fn try_compress(&mut self, input: &[u8]) -> Result<CompressionResult, Error> {
+        self.check_input_len(input.len())?;
+        check_input(input, self.skip)?;
+        self.compress_with_progress(input, self.skip, &mut self.scratch(), 0.0, 1.0)
+    }
+
+    /// Compress the provided slice like [`compress`](Compressor::compress) does, and return a
+    /// result that stores the compressed data in a [`Bytes`](bytes::Bytes) buffer.
+    ///
+    /// The buffer takes over the allocation of the compressed data, so nothing is copied, and the
+    /// delta that in-place decompression needs is returned along with it. Since
+    /// `Bytes` and `BytesMut` dereference to slices, they can be passed as input as they are, and
+    /// input that arrives in several `Bytes` chunks can be compressed without joining them using
+    /// [`compress_segments`](Compressor::compress_segments).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no data left to compress after skipping the prefix/suffix bytes. See
+    /// [`try_compress_bytes`](Compressor::try_compress_bytes) for a variant that returns an error
+    /// instead.
+    #[cfg(feature = "bytes")]
+    pub fn compress_bytes(&mut self, input: &[u8]) -> CompressionResult<bytes::Bytes> {
+        unwrap(self.try_compress_bytes(input))
+    }
+
+    /// Compress the provided slice to a [`Bytes`](bytes::Bytes) buffer like
+    /// [`compress_bytes`](Compressor::compress_bytes) does, but return an error instead of
+    /// panicking if the input can't be compressed.
+    #[cfg(feature = "bytes")]
+    pub fn try_compress_bytes(&mut self, input: &[u8]) -> Result<CompressionResult<bytes::Bytes>, Error> {
+        self.try_compress(input).map(CompressionResult::into_bytes)
+    }
+
+    /// Compress input that is split over several slices, as if the slices were concatenated. This
+    /// produces the same result as passing the concatenated slices to
+    /// [`compress`](Compressor::compress), without the need to copy them into a single buffer
+    /// first. The slices may have any length, and the prefix/suffix bytes that are skipped may
+    /// span several of them.
+    ///
+    /// Accessing input that is split up is a little slower, so this is mostly useful for large
+    /// inputs that are already in separate buffers, such as the chunks of a rope or a list of
+    /// memory-mapped regions. For inputs too large to be compressed in memory at all, see
+    /// [`compress_stream`](Compressor::compress_stream).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no data left to compress after skipping the prefix/suffix bytes. See
+    /// [`try_compress_segments`](Compressor::try_compress_segments) for a variant that returns an
+    /// error instead.
+    pub fn compress_segments(&mut self, segments: &[&[u8]]) -> CompressionResult {
+        unwrap(self.try_compress_segments(segments))
+    }
+
+    /// Compress input that is split over several slices like
+    /// [`compress_segments`](Compressor::compress_segments) does, but return an error instead of
+    /// panicking if the input can't be compressed.
+    pub fn try_compress_segments(&mut self, segments: &[&[u8]]) -> Result<CompressionResult, Error> {
+        let input = Segments::new(segments);
+
+        self.check_input_len(input.len())?;
+        check_input(&input, self.skip)?;
+        self.compress_with_progress(&input, self.skip, &mut self.scratch(), 0.0, 1.0)
+    }
+
+    /// Compress everything that the reader produces, and write the compressed data to the writer
+    /// as it becomes available. The first [`skip`](Compressor::skip) bytes that are read form the
+    /// prefix dictionary.
+    ///
+    /// This is meant for inputs that are too large to be held in memory. Rather than parsing the
+    /// whole input at once, the input is parsed in segments of 64 KiB, each of which can refer back
+    /// to the window of data before it. The memory usage is therefore independent of the input
+    /// length, at the cost of a slightly worse compression ratio. The result is a single regular
+    /// ZX0 stream.
+    ///
+    /// The backwards mode is not supported, since it requires the end of the input to be known.
+    /// The progress callback and live stats are not used, because the total amount of input is
+    /// unknown. Errors are returned as an [`io::Error`], which wraps an [`Error`] when there is
+    /// nothing to compress.
+    pub fn compress_stream<R: Read, W: Write>(&mut self, reader: R, writer: W) -> io::Result<StreamResult> {
+        if self.backwards_mode {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "streams can't be compressed backwards"));
+        }
+
+        if self.max_literal_run != usize::MAX {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "literal runs can't be limited when compressing a stream"));
+        }
+
+        if self.max_tokens.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "tokens can't be limited when compressing a stream"));
+        }
+
+        stream::compress(reader, writer, self.skip, !self.classic_mode, self.settings(), self.scratch(), stream::SEGMENT_LEN)
+    }
+
+    /// Compress a sequence of blocks that are decompressed one after another, where each block
+    /// uses the tail of the block before it as its prefix dictionary.
+    ///
+    /// This matches the way streaming loaders operate, which keep the previously decompressed
+    /// block resident in memory directly before the block that is being decompressed. The
+    /// dictionary for every block but the first consists of the last bytes of the previous block,
+    /// up to the maximum offset of the current mode, since anything further back can't be
+    /// referenced anyway. The first block is compressed without a dictionary, and the skip
+    /// setting is ignored.
+    ///
+    /// The blocks should be provided in the order in which they are decompressed, so when using
+    /// the backwards mode each block as well as the order of the blocks should be reversed, unless
+    /// [`natural_order`](Compressor::natural_order) is enabled.
+    ///
+    /// This returns a [`CompressionResult`] for every block. The progress callback reports the
+    /// progress of the entire sequence.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the blocks is empty. See
+    /// [`try_compress_chained`](Compressor::try_compress_chained) for a variant that returns an
+    /// error instead.
+    pub fn compress_chained(&mut self, blocks: &[&[u8]]) -> Vec<CompressionResult> {
+        unwrap(self.try_compress_chained(blocks))
+    }
+
+    /// Compress a sequence of blocks like [`compress_chained`](Compressor::compress_chained)
+    /// does, but return an error instead of panicking if any of the blocks can't be compressed.
+    /// No block is compressed in that case.
+    pub fn try_compress_chained(&mut self, blocks: &[&[u8]]) -> Result<Vec<CompressionResult>, Error> {
+        for block in blocks {
+            self.check_input_len(block.len())?;
+            check_input(*block, 0)?;
+        }
+
+        let offset_limit = self.offset_limit();
+        let total = blocks.iter().map(|block| block.len()).sum::<usize>().max(1) as f32;
+
+        let mut results = Vec::with_capacity(blocks.len());
+        let mut scratch = self.scratch();
+        let mut done = 0;
+        let mut previous: &[u8] = &[];
+
+        // In natural order the blocks are decompressed starting with the last one, and every block
+        // uses the start of the block after it as its suffix, which ends up in front of the block
+        // once the compressor reverses it
+        let natural_order = self.reverses_data();
+        let mut ordered = blocks.to_vec();
+
+        if natural_order {
+            ordered.reverse();
+        }
+
+        for block in ordered {
+            let mut input = Vec::with_capacity(previous.len().min(offset_limit) + block.len());
+
+            let dictionary_len = if natural_order {
+                let dictionary = &previous[..previous.len().min(offset_limit)];
+                input.extend_from_slice(block);
+                input.extend_from_slice(dictionary);
+                dictionary.len()
+            } else {
+                let dictionary = &previous[previous.len() - previous.len().min(offset_limit)..];
+                input.extend_from_slice(dictionary);
+                input.extend_from_slice(block);
+                dictionary.len()
+            };
+
+            let start = done as f32 / total;
+            done += block.len();
+
+            results.push(self.compress_with_progress(&input[..], dictionary_len, &mut scratch, start, done as f32 / total)?);
+            previous = block;
+        }
+
+        if natural_order {
+            results.reverse();
+        }
+
+        Ok(results)
+    }
+
+    /// Compress several ranges of the provided slice independently of each other, as if every
+    /// range was passed to [`compress`](Compressor::compress) separately.
+    ///
+    /// This is useful for buffers that contain many separate assets, such as sprite sheets or ROM
+    /// images. The working memory of the compressor is reused for every range, which avoids
+    /// reallocating it for each of them. The ranges may overlap, and the skip setting is ignored.
+    ///
+    /// This returns a [`CompressionResult`] for every range, in the order in which the ranges
+    /// were provided. The progress callback reports the progress of all ranges combined.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the ranges is empty or out of bounds. See
+    /// [`try_compress_ranges`](Compressor::try_compress_ranges) for a variant that returns an
+    /// error instead.
+    pub fn compress_ranges(&mut self, input: &[u8], ranges: &[Range<usize>]) -> Vec<CompressionResult> {
+        unwrap(self.try_compress_ranges(input, ranges))
+    }
+
+    /// Compress several ranges of a slice like [`compress_ranges`](Compressor::compress_ranges)
+    /// does, but return an error instead of panicking if any of the ranges can't be compressed.
+    /// No range is compressed in that case.
+    pub fn try_compress_ranges(&mut self, input: &[u8], ranges: &[Range<usize>]) -> Result<Vec<CompressionResult>, Error> {
+        for range in ranges {
+            let slice = input.get(range.clone()).ok_or_else(|| Error::RangeOutOfBounds {
+                range: range.clone(),
+                input_len: input.len()
+            })?;
+
+            self.check_input_len(slice.len())?;
+            check_input(slice, 0)?;
+        }
+
+        let total = ranges.iter().map(|range| range.len()).sum::<usize>().max(1) as f32;
+
+        let mut results = Vec::with_capacity(ranges.len());
+        let mut scratch = self.scratch();
+        let mut done = 0;
+
+        for range in ranges {
+            let start = done as f32 / total;
+            done += range.len();
+
+            results.push(self.compress_with_progress(&input[range.clone()], 0, &mut scratch, start, done as f32 / total)?);
+        }
+
+        Ok(results)
+    }
+
+    /// Compress the provided slice using the experimental 16-bit word format.
+    ///
+    /// In this format literals, match lengths and offsets are all counted in big-endian 16-bit
+    /// words, and the compressed data consists of whole words only. This guarantees that a
+    /// decompressor never reads or writes memory at an odd address, which allows it to run much
+    /// faster on the Motorola 68000 and similar processors. A decompressor for the 68000 can be
+    /// found in `asm/dzx0w_68000.s`.
+    ///
+    /// The format uses the same optimal parse as the regular format, but is not compatible with
+    /// it. The skip, quick mode, maximum offset and effort settings apply in the same way, but
+    /// the offset limits are counted in words. The backwards mode and classic mode settings are
+    /// ignored, and no tokens are recorded or passed to the token callback.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of the input or the number of skipped bytes is odd, or if there is no
+    /// data left to compress after skipping the prefix bytes. See
+    /// [`try_compress_words`](Compressor::try_compress_words) for a variant that returns an error
+    /// instead.
+    pub fn compress_words(&mut self, input: &[u8]) -> CompressionResult {
+        unwrap(self.try_compress_words(input))
+    }
+
+    /// Compress the provided slice using the experimental 16-bit word format like
+    /// [`compress_words`](Compressor::compress_words) does, but return an error instead of
+    /// panicking if the input can't be compressed.
+    pub fn try_compress_words(&mut self, input: &[u8]) -> Result<CompressionResult, Error> {
+        self.check_input_len(input.len())?;
+
+        if !input.len().is_multiple_of(2) || !self.skip.is_multiple_of(2) {
+            return Err(Error::NotWordAligned {
+                input_len: input.len(),
+                skip: self.skip
+            });
+        }
+
+        check_input(input, self.skip)?;
+
+        let words = input.chunks_exact(2).map(|word| u16::from_be_bytes([word[0], word[1]])).collect::<Vec<_>>();
+        let settings = self.settings();
+        let mut scratch = self.scratch();
+        let live_stats = self.live_stats.as_deref();
+        let progress_callback = &mut self.progress_callback;
+
+        let chain = optimize_within_budget(
+            self.max_tokens,
+            settings,
+            words.len() - self.skip / 2,
+            &mut scratch,
+            &mut progress_callback.scoped(0.0..1.0),
+            &mut |settings, scratch, progress_callback| optimize(
+                &words[..],
+                self.skip / 2,
+                INITIAL_OFFSET,
+                settings,
+                scratch,
+                live_stats,
+                progress_callback
+            )
+        );
+
+        let chain = check_chain(chain?, settings)?;
+
+        let (output, delta) = word::compress(&chain, input);
+        progress_callback(1.0);
+
+        if let Some(live_stats) = live_stats {
+            live_stats.update_output(output.len());
+        }
+
+        Ok(CompressionResult {
+            delta,
+            stats: CompressionStats {
+                arena_blocks: chain.allocator.len(),
+                estimated_arena_blocks: chain.estimated_blocks,
+                peak_memory: chain.peak_memory + words.capacity() * 2 + output.capacity()
+            },
+            output,
+            tokens: Vec::new()
+        })
+    }
+
+    /// Compress the provided slice using the experimental dual-stream format.
+    ///
+    /// In this format the control bits and the bytes are stored in two separate streams, so a
+    /// decompressor never has to extract bits from between the bytes that it copies. This makes
+    /// the inner loop of a decompressor for an 8-bit processor simpler and faster, at the cost of
+    /// a slightly larger header. A decompressor for the Z80 can be found in `asm/dzx0d_z80.asm`,
+    /// and [`decompress_dual`](crate::decompress_dual) decompresses the data in Rust.
+    ///
+    /// The format uses the same parse as the regular format, but is not compatible with it. The
+    /// data stream holds at most 65535 bytes. The skip, quick mode, maximum offset, effort and
+    /// limit settings apply in the same way, while the backwards mode and classic mode settings
+    /// are ignored, and no tokens are recorded or passed to the token callback.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no data left to compress after skipping the prefix bytes, or if the
+    /// data stream is too long. See [`try_compress_dual`](Compressor::try_compress_dual) for a
+    /// variant that returns an error instead.
+    pub fn compress_dual(&mut self, input: &[u8]) -> CompressionResult {
+        unwrap(self.try_compress_dual(input))
+    }
+
+    /// Compress the provided slice using the experimental dual-stream format like
+    /// [`compress_dual`](Compressor::compress_dual) does, but return an error instead of
+    /// panicking if the input can't be compressed.
+    pub fn try_compress_dual(&mut self, input: &[u8]) -> Result<CompressionResult, Error> {
+        self.check_input_len(input.len())?;
+        check_input(input, self.skip)?;
+
+        let settings = self.settings();
+        let mut scratch = self.scratch();
+        let live_stats = self.live_stats.as_deref();
+        let progress_callback = &mut self.progress_callback;
+
+        let chain = optimize_within_budget(
+            self.max_tokens,
+            settings,
+            input.len() - self.skip,
+            &mut scratch,
+            &mut progress_callback.scoped(0.0..1.0),
+            &mut |settings, scratch, progress_callback| optimize(
                 input,
                 self.skip,
-                if self.quick_mode { MAX_OFFSET_ZX7 } else { MAX_OFFSET_ZX0 },
-                &mut self.progress_callback
-            );
+                INITIAL_OFFSET,
+                settings,
+                scratch,
+                live_stats,
+                progress_callback
+            )
+        );
+
+        let chain = check_chain(chain?, settings)?;
+        let (output, delta) = dual::compress(&chain, input)?;
+        progress_callback(1.0);
+
+        if let Some(live_stats) = live_stats {
+            live_stats.update_output(output.len());
+        }
 
-            let mut chain = Vec::new();
+        Ok(CompressionResult {
+            delta,
+            stats: CompressionStats {
+                arena_blocks: chain.allocator.len(),
+                estimated_arena_blocks: chain.estimated_blocks,
+                peak_memory: chain.peak_memory + output.capacity()
+            },
+            output,
+            tokens: Vec::new()
+        })
+    }
 
-            while optimal != 0 {
-                let oblock = allocator.get(optimal);
+    /// Run only the first phase of the compression process, which determines the optimal way of
+    /// splitting the input into literals and matches.
+    ///
+    /// The resulting [`Parse`] can be turned into compressed data using
+    /// [`encode`](Compressor::encode). Calling both methods in succession is equivalent to calling
+    /// [`compress`](Compressor::compress), but allows inspecting or measuring both phases
+    /// separately. The skip, quick mode and effort settings only affect this phase, and the
+    /// progress callback reports the progress of this phase only.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no data left to compress after skipping the prefix/suffix bytes. See
+    /// [`try_parse`](Compressor::try_parse) for a variant that returns an error instead.
+    pub fn parse(&mut self, input: &[u8]) -> Parse {
+        unwrap(self.try_parse(input))
+    }
 
-                chain.push(Block {
-                    bits: oblock.bits as u32,
-                    index: oblock.index as isize,
-                    offset: oblock.offset as usize
-                });
+    /// Parse the provided slice like [`parse`](Compressor::parse) does, but return an error
+    /// instead of panicking if the input can't be compressed.
+    pub fn try_parse(&mut self, input: &[u8]) -> Result<Parse, Error> {
+        self.check_input_len(input.len())?;
+        check_input(input, self.skip)?;
+        self.parse_with_progress(input, self.skip, &mut self.scratch(), 0.0, 1.0)
+    }
 
-                optimal = oblock.next_index;
-            }
+    /// Run only the second phase of the compression process, which encodes a [`Parse`] that was
+    /// produced by [`parse`](Compressor::parse) for the same input.
+    ///
+    /// The backwards mode and classic mode settings only affect this phase, and the progress
+    /// callback reports the progress of this phase only.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of the input differs from the length of the input that was parsed.
+    /// See [`try_encode`](Compressor::try_encode) for a variant that returns an error instead.
+    pub fn encode(&mut self, parse: &Parse, input: &[u8]) -> CompressionResult {
+        unwrap(self.try_encode(parse, input))
+    }
+
+    /// Encode a [`Parse`] like [`encode`](Compressor::encode) does, but return an error instead
+    /// of panicking if the input does not match the parse.
+    pub fn try_encode(&mut self, parse: &Parse, input: &[u8]) -> Result<CompressionResult, Error> {
+        check_parse(parse, input)?;
+        Ok(self.encode_with_progress(parse, input, 0.0, 1.0))
+    }
+
+    /// Encode a [`Parse`] like [`encode`](Compressor::encode) does, but write the compressed data
+    /// to the provided slice instead of allocating a new vector. This allows writing directly to
+    /// a preallocated buffer or a memory-mapped file, since the exact size of the compressed data
+    /// is known in advance from [`Parse::output_len`].
+    ///
+    /// The [`output`](CompressionResult::output) field of the returned [`CompressionResult`] is
+    /// left empty, and its peak memory statistic does not include the provided slice.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of the input differs from the length of the input that was parsed, or
+    /// if the length of the output slice differs from [`Parse::output_len`]. See
+    /// [`try_encode_into`](Compressor::try_encode_into) for a variant that returns an error
+    /// instead.
+    pub fn encode_into(&mut self, parse: &Parse, input: &[u8], output: &mut [u8]) -> CompressionResult {
+        unwrap(self.try_encode_into(parse, input, output))
+    }
+
+    /// Encode a [`Parse`] into a slice like [`encode_into`](Compressor::encode_into) does, but
+    /// return an error instead of panicking if the input does not match the parse, or if the
+    /// output slice has the wrong length.
+    pub fn try_encode_into(&mut self, parse: &Parse, input: &[u8], output: &mut [u8]) -> Result<CompressionResult, Error> {
+        check_parse(parse, input)?;
+
+        if output.len() != parse.output_len() {
+            return Err(Error::OutputLengthMismatch {
+                expected: parse.output_len(),
+                actual: output.len()
+            });
+        }
+
+        Ok(self.encode_into_with_progress(parse, input, output, 0.0, 1.0))
+    }
+
+    /// Return the part of the progress of compressing `input_len` bytes that the parse accounts
+    /// for, as a fraction between 0 and 1.
+    ///
+    /// [`compress`](Compressor::compress) reports the progress of the parse up to this value, and
+    /// that of the encoding after it. Callers that run [`parse`](Compressor::parse) and
+    /// [`encode`](Compressor::encode) separately can use it to report both phases as a single
+    /// range.
+    pub fn parse_share(&self, input_len: usize) -> f32 {
+        // Divide the progress between both phases according to their expected workloads. The
+        // optimizer evaluates every offset in the window for each input byte, while the encoder
+        // only spends a roughly constant amount of time on it.
+        let window = self.offset_limit().min(input_len) as f32;
+        window / (window + ENCODE_COST)
+    }
+
+    fn offset_limit(&self) -> usize {
+        let offset_limit = if self.quick_mode { MAX_OFFSET_ZX7 } else { MAX_OFFSET_ZX0 };
+        offset_limit.min(self.max_offset)
+    }
 
-            chain
+    fn check_input_len(&self, input_len: usize) -> Result<(), Error> {
+        match self.max_input_len {
+            Some(max_input_len) if input_len > max_input_len => Err(Error::InputTooLarge {
+                input_len,
+                max_input_len
+            }),
+            _ => Ok(())
+        }
+    }
+
+    fn settings(&self) -> Settings {
+        Settings {
+            offset_limit: self.offset_limit(),
+            candidate_limit: if self.effort < MAX_EFFORT { 1 << (2 * self.effort as usize) } else { usize::MAX },
+            extreme_mode: self.extreme_mode,
+            max_literal_run: self.max_literal_run,
+            token_cost: 0
+        }
+    }
+
+    // Create the working memory for a compression operation
+    fn scratch(&self) -> Scratch {
+        #[cfg(feature = "allocator-api2")]
+        if let Some(allocator) = &self.scratch_allocator {
+            return Scratch::new_in(allocator.clone());
+        }
+
+        Scratch::default()
+    }
+
+    // Compress the input using the given scratch allocations, while mapping the progress to the
+    // range from start to end.
+    fn compress_with_progress<I: Symbols<u8> + Sync + ?Sized>(&mut self, input: &I, skip: usize, scratch: &mut Scratch, start: f32, end: f32) -> Result<CompressionResult, Error> {
+        let split = start + (end - start) * self.parse_share(input.len());
+
+        let parse = self.parse_with_progress(input, skip, scratch, start, split)?;
+        let result = self.encode_with_progress(&parse, input, split, end);
+
+        scratch.recycle(parse.chain);
+        Ok(result)
+    }
+
+    // Whether the input and output of the backwards mode are reversed by the compressor
+    fn reverses_data(&self) -> bool {
+        self.backwards_mode && self.natural_order
+    }
+
+    // Parse the input using the given scratch allocations, while mapping the progress to the range
+    // from start to end.
+    fn parse_with_progress<I: Symbols<u8> + Sync + ?Sized>(&mut self, input: &I, skip: usize, scratch: &mut Scratch, start: f32, end: f32) -> Result<Parse, Error> {
+        if self.reverses_data() {
+            self.parse_in_order(&Reversed::new(input), skip, scratch, start, end)
+        } else {
+            self.parse_in_order(input, skip, scratch, start, end)
+        }
+    }
+
+    // Parse the input in the order in which it is compressed
+    fn parse_in_order<I: Symbols<u8> + Sync + ?Sized>(&mut self, input: &I, skip: usize, scratch: &mut Scratch, start: f32, end: f32) -> Result<Parse, Error> {
+        let settings = self.settings();
+        let progress_callback = &mut self.progress_callback;
+
+        #[cfg(feature = "debug-dump")]
+        if let Some((_, interval)) = self.debug_dump {
+            scratch.dump = Some(Dump::new(interval));
+        }
+
+        // Joining the parses of the chunks can merge literal runs and change the number of tokens,
+        // so limited runs and tokens need a full parse
+        let chain = if self.chunk_len > 0 && self.max_literal_run == usize::MAX && self.max_tokens.is_none() {
+            Ok(optimize_chunks(
+                input,
+                skip,
+                self.chunk_len,
+                self.chain_chunks,
+                settings,
+                scratch,
+                &mut progress_callback.scoped(start..end)
+            ))
+        } else {
+            let live_stats = self.live_stats.as_deref();
+
+            optimize_within_budget(
+                self.max_tokens,
+                settings,
+                input.len() - skip,
+                scratch,
+                &mut progress_callback.scoped(start..end),
+                &mut |settings, scratch, progress_callback| optimize(
+                    input,
+                    skip,
+                    INITIAL_OFFSET,
+                    settings,
+                    scratch,
+                    live_stats,
+                    progress_callback
+                )
+            )
         };
 
-        let invert_mode = !self.classic_mode && !self.backwards_mode;
-        let mut delta = 0;
+        #[cfg(feature = "debug-dump")]
+        if let (Some((path, _)), Some(dump)) = (&self.debug_dump, scratch.dump.take()) {
+            if let Err(err) = fs::write(path, dump.into_text()) {
+                panic!("could not write parse dump to {}: {}", path.display(), err);
+            }
+        }
+
+        let chain = check_chain(chain?, settings)?;
 
-        let output = compress(
+        Ok(Parse {
             chain,
+            input_len: input.len()
+        })
+    }
+
+    // Encode a parse while mapping the progress to the range from start to end.
+    fn encode_with_progress<I: Symbols<u8> + ?Sized>(&mut self, parse: &Parse, input: &I, start: f32, end: f32) -> CompressionResult {
+        let mut output = vec![0; parse.output_len()];
+        let mut result = self.encode_into_with_progress(parse, input, &mut output, start, end);
+
+        result.stats.peak_memory += output.capacity();
+        result.output = output;
+        result
+    }
+
+    // Encode a parse into a slice while mapping the progress to the range from start to end.
+    fn encode_into_with_progress<I: Symbols<u8> + ?Sized>(&mut self, parse: &Parse, input: &I, output: &mut [u8], start: f32, end: f32) -> CompressionResult {
+        if self.reverses_data() {
+            let result = self.encode_into_in_order(parse, &Reversed::new(input), output, start, end);
+            output.reverse();
+            result
+        } else {
+            self.encode_into_in_order(parse, input, output, start, end)
+        }
+    }
+
+    // Encode a parse of the input in the order in which it is compressed
+    fn encode_into_in_order<I: Symbols<u8> + ?Sized>(&mut self, parse: &Parse, input: &I, output: &mut [u8], start: f32, end: f32) -> CompressionResult {
+        let progress_callback = &mut self.progress_callback;
+        let token_callback = &mut self.token_callback;
+
+        let mut tokens = Vec::new();
+        let record_tokens = self.record_tokens;
+        let live_stats = self.live_stats.as_deref();
+
+        let invert_mode = !self.classic_mode && !self.backwards_mode;
+        let delta = compress(
+            &parse.chain,
             input,
-            self.skip,
             self.backwards_mode,
             invert_mode,
-            &mut delta
+            output,
+            &mut progress_callback.scoped(start..end),
+            &mut |token| {
+                if let Some(live_stats) = live_stats {
+                    live_stats.update_output(token.output_position);
+                }
+
+                token_callback(&token);
+
+                if record_tokens {
+                    tokens.push(token);
+                }
+            }
         );
 
+        if let Some(live_stats) = live_stats {
+            live_stats.update_output(output.len());
+        }
+
         CompressionResult {
-            output,
-            delta
+            output: Vec::new(),
+            delta,
+            stats: CompressionStats {
+                arena_blocks: parse.chain.allocator.len(),
+                estimated_arena_blocks: parse.chain.estimated_blocks,
+                peak_memory: parse.chain.peak_memory
+            },
+            tokens
+        }
+    }
+}
+
+// Check that there is data left to compress after skipping
+fn check_input<I: Symbols<u8> + ?Sized>(input: &I, skip: usize) -> Result<(), Error> {
+    if skip >= input.len() {
+        return Err(Error::NothingToCompress {
+            input_len: input.len(),
+            skip
+        });
+    }
+
+    Ok(())
+}
+
+// Parse the input using the given parser. With a token budget that the optimal parse exceeds, the
+// parse with the largest token cost has to fit, after which the smallest token cost that fits is
+// found by bisection. Every parse runs the optimizer in full, so each gets an equal share of the
+// progress.
+fn optimize_within_budget(
+    max_tokens: Option<usize>,
+    settings: Settings,
+    positions: usize,
+    scratch: &mut Scratch,
+    progress_callback: &mut dyn FnMut(f32),
+    parse: &mut Parser
+) -> Result<Chain, Error> {
+    let Some(max_tokens) = max_tokens else {
+        return Ok(parse(settings, scratch, progress_callback));
+    };
+
+    // Every position adds at most a token and the cost of a token to a parse, which has to stay
+    // clear of the cost of unreachable positions
+    let max_token_cost = (UNREACHABLE_BITS as usize / 2 / positions).min(MAX_TOKEN_COST as usize) as u32;
+    let steps = (2 + u32::BITS - max_token_cost.leading_zeros()) as f32;
+    let mut step = 0.0;
+
+    let mut attempt = |token_cost, scratch: &mut Scratch| {
+        let start = step / steps;
+        step += 1.0;
+
+        parse(Settings { token_cost, ..settings }, scratch, &mut progress_callback.scoped(start..step / steps))
+    };
+
+    let mut best = attempt(0, scratch);
+
+    // A parse that exceeds the literal run limit is reported as such by the caller
+    if best.tokens <= max_tokens || best.bits >= UNREACHABLE_BITS {
+        progress_callback(1.0);
+        return Ok(best);
+    }
+
+    scratch.recycle(best);
+    best = attempt(max_token_cost, scratch);
+
+    if best.tokens > max_tokens {
+        return Err(Error::TokenBudgetExceeded {
+            max_tokens,
+            tokens: best.tokens
+        });
+    }
+
+    let mut low = 0;
+    let mut high = max_token_cost;
+
+    while high - low > 1 {
+        let middle = low + (high - low) / 2;
+        let chain = attempt(middle, scratch);
+
+        if chain.tokens <= max_tokens {
+            high = middle;
+
+            if chain.bits <= best.bits {
+                scratch.recycle(std::mem::replace(&mut best, chain));
+                continue;
+            }
+        } else {
+            low = middle;
         }
+
+        scratch.recycle(chain);
+    }
+
+    progress_callback(1.0);
+    Ok(best)
+}
+
+// Check that the optimizer found a parse that stays within the literal run limit
+fn check_chain(chain: Chain, settings: Settings) -> Result<Chain, Error> {
+    if chain.bits >= UNREACHABLE_BITS {
+        return Err(Error::LiteralRunTooLong {
+            max_literal_run: settings.max_literal_run
+        });
+    }
+
+    Ok(chain)
+}
+
+// Check that the input is the one that was parsed, as far as that can be verified cheaply
+fn check_parse(parse: &Parse, input: &[u8]) -> Result<(), Error> {
+    if input.len() != parse.input_len {
+        return Err(Error::InputLengthMismatch {
+            expected: parse.input_len,
+            actual: input.len()
+        });
     }
+
+    Ok(())
+}
+
+// Unwrap the result of a fallible method for its panicking counterpart
+fn unwrap<T>(result: Result<T, Error>) -> T {
+    result.unwrap_or_else(|err| panic!("{}", err))
 }
 
 impl<'a> Default for Compressor<'a> {
@@ -0,0 +1,159 @@
+//! The convert command, which decompresses files in the ZX7 format or the classic ZX0 format and
+//! compresses them again, so that legacy assets can be migrated in bulk.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use zx0::{decompress_zx7_into, Compressor, DecompressStatus, Decompressor, Error};
+
+use crate::grow::grow_until_fits;
+use crate::{Failure, Status};
+
+/// The format of the files that are converted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The ZX7 format.
+    Zx7,
+
+    /// The classic (V1) ZX0 format.
+    Classic
+}
+
+/// Return the format of a file based on its extension: ZX7 for `.zx7` files, and the classic
+/// format for everything else.
+pub fn format_from_extension(path: &Path) -> Format {
+    match path.extension() {
+        Some(extension) if extension.eq_ignore_ascii_case("zx7") => Format::Zx7,
+        _ => Format::Classic
+    }
+}
+
+/// Return the path of the converted file, which replaces the extension of the input with `.zx0`.
+/// Classic files that already have this extension are converted in place.
+pub fn output_path(input_path: &Path) -> PathBuf {
+    input_path.with_extension("zx0")
+}
+
+/// Decompress the data of a file in the given format, and return it in its natural order. Data in
+/// the classic format is decompressed backwards if `backwards_mode` is set, which is never the
+/// case for ZX7 data.
+///
+/// The output buffer is grown until the data fits, up to `max_len` bytes, so that corrupt data
+/// that claims very long matches returns [`Error::OutputTooSmall`] instead of using up all memory.
+pub fn decompress(compressed: &[u8], format: Format, backwards_mode: bool, max_len: usize) -> Result<Vec<u8>, Error> {
+    // Backwards data is stored reversed, so it is decompressed from its end and results in
+    // reversed data as well
+    let mut compressed = compressed.to_vec();
+
+    if backwards_mode {
+        compressed.reverse();
+    }
+
+    let (mut output, ()) = grow_until_fits(4 * compressed.len() + 64, Some(max_len), |output| {
+        let len = match format {
+            Format::Zx7 => decompress_zx7_into(&compressed, output)?,
+            Format::Classic => {
+                match Decompressor::new().classic_mode(true).backwards_mode(backwards_mode).decompress(&compressed, output)? {
+                    DecompressStatus::Finished { len, .. } => len,
+                    DecompressStatus::NeedsInput => return Err(Error::Truncated)
+                }
+            }
+        };
+
+        Ok((len, ()))
+    })?;
+
+    if backwards_mode {
+        output.reverse();
+    }
+
+    Ok(output)
+}
+
+/// Convert a single file and print a summary line unless `quiet_mode` is set. The compressor
+/// determines the format of the output, and must use the natural order. Files that decompress to
+/// more than `max_len` bytes fail. Existing output files, including the input itself, are only
+/// replaced when `forced_mode` is set.
+pub fn convert_file(
+    compressor: &mut Compressor,
+    input_path: &Path,
+    format: Format,
+    backwards_mode: bool,
+    max_len: usize,
+    forced_mode: bool,
+    quiet_mode: bool
+) -> Result<(), Failure> {
+    let output_path = output_path(input_path);
+
+    if !forced_mode && output_path.exists() {
+        return Err(Failure::new(Status::OutputExists, format!("output file {} already exists", output_path.display())));
+    }
+
+    let compressed = fs::read(input_path)
+        .map_err(|err| Failure::new(Status::MissingInput, format!("could not read input file {}: {}", input_path.display(), err)))?;
+
+    let data = decompress(&compressed, format, backwards_mode, max_len)
+        .map_err(|err| Failure::new(Status::Failed, format!("could not decompress {}: {}", input_path.display(), err)))?;
+
+    let result = compressor.try_compress(&data)
+        .map_err(|err| Failure::new(Status::Failed, format!("could not compress {}: {}", input_path.display(), err)))?;
+
+    result.write_to(&output_path)
+        .map_err(|err| Failure::new(Status::Failed, format!("could not write to output file {}: {}", output_path.display(), err)))?;
+
+    if !quiet_mode {
+        println!(
+            "{} ({} bytes, {}) -> {} ({} bytes), decompressed = {} bytes, delta = {}",
+            input_path.display(),
+            compressed.len(),
+            match format {
+                Format::Zx7 => "zx7",
+                Format::Classic => "classic"
+            },
+            output_path.display(),
+            result.output.len(),
+            data.len(),
+            result.delta
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use zx0::{Compressor, Error};
+
+    use super::{decompress, format_from_extension, output_path, Format};
+
+    #[test]
+    fn convert() {
+        assert_eq!(format_from_extension(Path::new("gfx/title.ZX7")), Format::Zx7);
+        assert_eq!(format_from_extension(Path::new("gfx/title.zx0")), Format::Classic);
+        assert_eq!(output_path(Path::new("gfx/title.zx7")), Path::new("gfx/title.zx0"));
+
+        let input = std::fs::read("src/main.rs").unwrap();
+        let input = &input[..2000];
+
+        // Backwards classic data is stored reversed, and decompressed to its natural order
+        for backwards_mode in [false, true] {
+            let result = Compressor::new()
+                .classic_mode(true)
+                .backwards_mode(backwards_mode)
+                .natural_order(true)
+                .compress(input);
+
+            assert_eq!(decompress(&result.output, Format::Classic, backwards_mode, 65536).as_deref(), Ok(input));
+
+            // Data that doesn't fit in the maximum length fails instead of growing the buffer further
+            assert_eq!(decompress(&result.output, Format::Classic, backwards_mode, 1999), Err(Error::OutputTooSmall { len: 1999 }));
+        }
+
+        // A literal, followed by a match of four bytes at offset 1 and the end marker
+        let zx7 = [0x61, 0xb8, 0x00, 0x00, 0x04];
+        assert_eq!(decompress(&zx7, Format::Zx7, false, 65536).as_deref(), Ok(&b"aaaaa"[..]));
+        assert_eq!(decompress(&zx7, Format::Zx7, false, 4), Err(Error::OutputTooSmall { len: 4 }));
+    }
+}
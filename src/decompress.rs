@@ -0,0 +1,166 @@
+use crate::INITIAL_OFFSET;
+
+/// A simple decompressor that operates on a single memory buffer, which makes it possible to
+/// verify in-place decompression. The compressed data is read starting at `source`, while the
+/// decompressed data is written starting at `destination`. Data before `destination` acts as the
+/// prefix that matches are allowed to refer to.
+///
+/// Backwards streams are decoded front-to-back as well, which results in the reversed data. This
+/// mirrors how the compressor produces them from reversed input.
+///
+/// Returns the positions directly after the compressed and decompressed data. Panics if the
+/// compressed data is malformed, or if decompressing overwrites compressed data that has not been
+/// read yet.
+pub fn decompress(
+    memory: &mut [u8],
+    source: usize,
+    destination: usize,
+    backwards_mode: bool,
+    invert_mode: bool
+) -> (usize, usize) {
+    let mut context = Context {
+        memory,
+        source,
+        destination,
+        backwards_mode,
+        bit_mask: 0,
+        bit_value: 0,
+        backtrack: None
+    };
+
+    let mut last_offset = INITIAL_OFFSET;
+
+    // Streams always start with literals, so the first indicator bit is omitted
+    let mut state = State::Literals;
+
+    loop {
+        state = match state {
+            State::Literals => {
+                let length = context.read_interlaced_elias_gamma(false);
+
+                for _ in 0..length {
+                    let value = context.read_byte();
+                    context.write_byte(value);
+                }
+
+                if context.read_bit() { State::NewOffset } else { State::LastOffset }
+            },
+            State::LastOffset => {
+                let length = context.read_interlaced_elias_gamma(false);
+                context.copy(last_offset, length);
+
+                if context.read_bit() { State::NewOffset } else { State::Literals }
+            },
+            State::NewOffset => {
+                let msb = context.read_interlaced_elias_gamma(invert_mode);
+
+                if msb == 256 {
+                    return (context.source, context.destination);
+                }
+
+                let lsb = context.read_byte() as usize;
+
+                last_offset = if backwards_mode {
+                    msb * 128 - 127 + (lsb >> 1)
+                } else {
+                    msb * 128 - (lsb >> 1)
+                };
+
+                // The first bit of the length is stored in the lowest bit of the offset LSB
+                context.backtrack = Some(lsb & 1 != 0);
+
+                let length = context.read_interlaced_elias_gamma(false) + 1;
+                context.copy(last_offset, length);
+
+                if context.read_bit() { State::NewOffset } else { State::Literals }
+            }
+        }
+    }
+}
+
+enum State {
+    Literals,
+    LastOffset,
+    NewOffset
+}
+
+struct Context<'a> {
+    memory: &'a mut [u8],
+    source: usize,
+    destination: usize,
+    backwards_mode: bool,
+    bit_mask: u8,
+    bit_value: u8,
+    backtrack: Option<bool>
+}
+
+impl Context<'_> {
+    fn read_byte(&mut self) -> u8 {
+        let value = self.memory[self.source];
+        self.source += 1;
+        value
+    }
+
+    fn read_bit(&mut self) -> bool {
+        if let Some(bit) = self.backtrack.take() {
+            return bit;
+        }
+
+        if self.bit_mask == 0 {
+            self.bit_mask = 128;
+            self.bit_value = self.read_byte();
+        }
+
+        let bit = self.bit_value & self.bit_mask != 0;
+        self.bit_mask >>= 1;
+        bit
+    }
+
+    fn read_interlaced_elias_gamma(&mut self, inverted: bool) -> usize {
+        let mut value = 1;
+
+        while self.read_bit() == self.backwards_mode {
+            value = (value << 1) | (self.read_bit() ^ inverted) as usize;
+        }
+
+        value
+    }
+
+    fn write_byte(&mut self, value: u8) {
+        assert!(
+            self.destination < self.source || self.source >= self.memory.len(),
+            "decompressed data overwrites unread compressed data"
+        );
+
+        self.memory[self.destination] = value;
+        self.destination += 1;
+    }
+
+    fn copy(&mut self, offset: usize, length: usize) {
+        for _ in 0..length {
+            let value = self.memory[self.destination - offset];
+            self.write_byte(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decompress;
+    use crate::Compressor;
+
+    #[test]
+    fn round_trip() {
+        let input = std::fs::read("src/lib.rs").unwrap();
+        let result = Compressor::new().compress(&input);
+
+        let mut memory = vec![0; input.len() + result.output.len()];
+        memory[input.len()..].copy_from_slice(&result.output);
+
+        let (source, destination) = decompress(&mut memory, input.len(), 0, false, true);
+
+        assert_eq!(source, memory.len());
+        assert_eq!(destination, input.len());
+        assert_eq!(&memory[..destination], &input[..]);
+    }
+}
@@ -0,0 +1,571 @@
+use crate::{Error, INITIAL_OFFSET};
+#[cfg(feature = "std")]
+use crate::grow::grow_until_fits;
+
+// The number of input bytes that are decompressed between calls to a progress callback
+const PROGRESS_CHUNK_LEN: usize = 4096;
+
+/// The outcome of a successful call to [`Decompressor::decompress`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecompressStatus {
+    /// All of the input was consumed, but the end of the compressed data has not been reached
+    /// yet. Call [`decompress`](Decompressor::decompress) again with the next part of the input.
+    NeedsInput,
+
+    /// The end of the compressed data was reached.
+    Finished {
+        /// The number of input bytes that were consumed by the last call. Any bytes after these
+        /// are not part of the compressed data.
+        consumed: usize,
+
+        /// The total number of bytes that were decompressed.
+        len: usize
+    }
+}
+
+// The interlaced Elias Gamma coded fields of the format
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    LiteralLength,
+    LastOffsetLength,
+    OffsetMsb,
+    NewOffsetLength
+}
+
+#[derive(Clone, Copy, Debug)]
+enum State {
+    Gamma { field: Field, value: usize, data_bit: bool },
+    Literals { remaining: usize },
+    Copy { remaining: usize },
+    Indicator { after_literals: bool },
+    OffsetLsb { msb: usize },
+    Finished
+}
+
+/// A streaming ZX0 decompressor that does not allocate any memory.
+///
+/// The decompressor only requires `core`, so it can be used in `#![no_std]` environments such as
+/// bootloaders by disabling the default `std` feature of this crate. All of its state lives in
+/// this small struct, while the caller provides the buffer that the decompressed data is written
+/// to. That buffer also serves as the history that matches copy from, so it has to be large
+/// enough for the entire decompressed data.
+///
+/// The compressed data can be provided in parts of any size, for example as it arrives from a
+/// serial port or flash memory. Every call to [`decompress`](Decompressor::decompress) consumes
+/// as much of the input as possible, and continues exactly where the previous call left off.
+///
+/// Data that was compressed using the backwards mode is decompressed front-to-back as well, so
+/// both the compressed data and the decompressed data are reversed. This mirrors how the
+/// compressor produces backwards data from reversed input.
+///
+/// ```
+/// # let compressed = zx0::compress(b"Hello, hello, hello!");
+/// use zx0::{Decompressor, DecompressStatus};
+///
+/// let mut output = [0; 64];
+/// let mut decompressor = Decompressor::new();
+///
+/// for chunk in compressed.chunks(4) {
+///     if let DecompressStatus::Finished { len, .. } = decompressor.decompress(chunk, &mut output)? {
+///         assert_eq!(&output[..len], b"Hello, hello, hello!");
+///     }
+/// }
+/// # Ok::<(), zx0::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Decompressor {
+    backwards_mode: bool,
+    classic_mode: bool,
+    state: State,
+    last_offset: usize,
+    position: usize,
+    bit_mask: u8,
+    bit_value: u8,
+    backtrack: Option<bool>
+}
+
+impl Decompressor {
+    /// Instantiate a new decompressor for data in the current file format that was compressed in
+    /// the forward direction.
+    pub const fn new() -> Self {
+        Self {
+            backwards_mode: false,
+            classic_mode: false,
+
+            // Streams always start with literals, so the first indicator bit is omitted
+            state: State::Gamma { field: Field::LiteralLength, value: 1, data_bit: false },
+            last_offset: INITIAL_OFFSET,
+            position: 0,
+            bit_mask: 0,
+            bit_value: 0,
+            backtrack: None
+        }
+    }
+
+    /// Decompress data that was compressed using the backwards mode. This has to be set before
+    /// the first call to [`decompress`](Decompressor::decompress).
+    pub fn backwards_mode(&mut self, backwards_mode: bool) -> &mut Self {
+        self.backwards_mode = backwards_mode;
+        self
+    }
+
+    /// Decompress data in the classic (V1) file format. This has to be set before the first call
+    /// to [`decompress`](Decompressor::decompress).
+    pub fn classic_mode(&mut self, classic_mode: bool) -> &mut Self {
+        self.classic_mode = classic_mode;
+        self
+    }
+
+    /// Decompress data that was compressed with a `skip` of `prefix_len` bytes, so that matches
+    /// may refer to the prefix. The first `prefix_len` bytes of the output buffer must hold the
+    /// prefix, and the decompressed data is written directly after it. The positions and lengths
+    /// that the decompressor reports include the prefix.
+    ///
+    /// This has to be set before the first call to [`decompress`](Decompressor::decompress).
+    /// Data that was compressed backwards with a suffix is decompressed by passing the reversed
+    /// suffix as the prefix.
+    pub fn prefix_len(&mut self, prefix_len: usize) -> &mut Self {
+        self.position = prefix_len;
+        self
+    }
+
+    /// Return the number of bytes that have been decompressed so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Decompress the next part of the compressed data.
+    ///
+    /// The decompressed data is written to `output`, which must be the same buffer on every call,
+    /// since matches copy data that was written by earlier calls.
+    ///
+    /// Returns [`DecompressStatus::NeedsInput`] when all of the input was consumed without
+    /// reaching the end of the compressed data, and [`DecompressStatus::Finished`] when the end
+    /// was reached. Returns an error if the compressed data is malformed, or if the output buffer
+    /// is too small. The decompressor can't be used anymore after an error.
+    pub fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<DecompressStatus, Error> {
+        // The remaining space is computed from the position, so a prefix that doesn't fit in the
+        // output buffer has to be caught here
+        if self.position > output.len() {
+            return Err(Error::OutputTooSmall { len: output.len() });
+        }
+
+        let mut consumed = 0;
+
+        loop {
+            match self.state {
+                State::Gamma { field, value, data_bit } => {
+                    let Some(bit) = self.read_bit(input, &mut consumed) else {
+                        return Ok(DecompressStatus::NeedsInput);
+                    };
+
+                    self.state = if data_bit {
+                        if value > usize::MAX >> 1 {
+                            return Err(Error::InvalidData);
+                        }
+
+                        // Only the offset MSB is inverted, and only in the current forward format
+                        let inverted = matches!(field, Field::OffsetMsb) && !self.classic_mode && !self.backwards_mode;
+                        let value = (value << 1) | (bit ^ inverted) as usize;
+
+                        State::Gamma { field, value, data_bit: false }
+                    } else if bit == self.backwards_mode {
+                        State::Gamma { field, value, data_bit: true }
+                    } else {
+                        self.end_field(field, value, output.len())?
+                    };
+                },
+                State::Literals { remaining } => {
+                    let length = remaining.min(input.len() - consumed);
+
+                    if length == 0 {
+                        return Ok(DecompressStatus::NeedsInput);
+                    }
+
+                    output[self.position..self.position + length].copy_from_slice(&input[consumed..consumed + length]);
+                    self.position += length;
+                    consumed += length;
+
+                    self.state = if length == remaining {
+                        State::Indicator { after_literals: true }
+                    } else {
+                        State::Literals { remaining: remaining - length }
+                    };
+                },
+                State::Copy { remaining } => {
+                    // A new offset was already checked, but the initial offset was not
+                    if self.last_offset > self.position {
+                        return Err(Error::OffsetOutOfRange {
+                            offset: self.last_offset,
+                            position: self.position
+                        });
+                    }
+
+                    // Copy byte by byte, since the source and destination may overlap
+                    for _ in 0..remaining {
+                        output[self.position] = output[self.position - self.last_offset];
+                        self.position += 1;
+                    }
+
+                    self.state = State::Indicator { after_literals: false };
+                },
+                State::Indicator { after_literals } => {
+                    let Some(bit) = self.read_bit(input, &mut consumed) else {
+                        return Ok(DecompressStatus::NeedsInput);
+                    };
+
+                    let field = if bit {
+                        Field::OffsetMsb
+                    } else if after_literals {
+                        Field::LastOffsetLength
+                    } else {
+                        Field::LiteralLength
+                    };
+
+                    self.state = State::Gamma { field, value: 1, data_bit: false };
+                },
+                State::OffsetLsb { msb } => {
+                    let Some(&lsb) = input.get(consumed) else {
+                        return Ok(DecompressStatus::NeedsInput);
+                    };
+
+                    consumed += 1;
+
+                    let lsb = lsb as usize;
+
+                    self.last_offset = if self.backwards_mode {
+                        msb * 128 - 127 + (lsb >> 1)
+                    } else {
+                        msb * 128 - (lsb >> 1)
+                    };
+
+                    if self.last_offset > self.position {
+                        return Err(Error::OffsetOutOfRange {
+                            offset: self.last_offset,
+                            position: self.position
+                        });
+                    }
+
+                    // The first bit of the length is stored in the lowest bit of the offset LSB
+                    self.backtrack = Some(lsb & 1 != 0);
+                    self.state = State::Gamma { field: Field::NewOffsetLength, value: 1, data_bit: false };
+                },
+                State::Finished => {
+                    return Ok(DecompressStatus::Finished {
+                        consumed,
+                        len: self.position
+                    });
+                }
+            }
+        }
+    }
+
+    /// Decompress the next part of the compressed data like [`decompress`](Decompressor::decompress),
+    /// while calling the progress callback with the fraction of `input` that has been consumed,
+    /// between `0.0` and `1.0`. The input is decompressed in parts of a few kilobytes, after each
+    /// of which the callback is called. The final call always reports exactly `1.0`.
+    ///
+    /// This gives feedback when decompressing large data in a single call. The callback can be
+    /// combined with [`ProgressExt::scoped`](crate::ProgressExt::scoped) to report the progress
+    /// of a larger pipeline.
+    pub fn decompress_with_progress<C: FnMut(f32)>(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        mut progress_callback: C
+    ) -> Result<DecompressStatus, Error> {
+        let mut position = 0;
+
+        for chunk in input.chunks(PROGRESS_CHUNK_LEN) {
+            if let DecompressStatus::Finished { consumed, len } = self.decompress(chunk, output)? {
+                progress_callback(1.0);
+                return Ok(DecompressStatus::Finished { consumed: position + consumed, len });
+            }
+
+            position += chunk.len();
+            progress_callback(position as f32 / input.len() as f32);
+        }
+
+        // The end may have been reached by an earlier call, which is reported without any input
+        if input.is_empty() {
+            let status = self.decompress(input, output)?;
+            progress_callback(1.0);
+            return Ok(status);
+        }
+
+        Ok(DecompressStatus::NeedsInput)
+    }
+
+    // Determine the next state once a gamma coded field has been read completely
+    fn end_field(&mut self, field: Field, value: usize, output_len: usize) -> Result<State, Error> {
+        let length = match field {
+            Field::OffsetMsb if value == 256 => return Ok(State::Finished),
+            Field::OffsetMsb if value > 256 => return Err(Error::InvalidData),
+            Field::OffsetMsb => return Ok(State::OffsetLsb { msb: value }),
+            Field::NewOffsetLength => value + 1,
+            Field::LiteralLength | Field::LastOffsetLength => value
+        };
+
+        if length > output_len - self.position {
+            return Err(Error::OutputTooSmall { len: output_len });
+        }
+
+        Ok(match field {
+            Field::LiteralLength => State::Literals { remaining: length },
+            _ => State::Copy { remaining: length }
+        })
+    }
+
+    // Read the next bit, or return None if more input is needed
+    fn read_bit(&mut self, input: &[u8], consumed: &mut usize) -> Option<bool> {
+        if let Some(bit) = self.backtrack.take() {
+            return Some(bit);
+        }
+
+        if self.bit_mask == 0 {
+            self.bit_value = *input.get(*consumed)?;
+            self.bit_mask = 128;
+            *consumed += 1;
+        }
+
+        let bit = self.bit_value & self.bit_mask != 0;
+        self.bit_mask >>= 1;
+        Some(bit)
+    }
+}
+
+impl Default for Decompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decompress data in the current file format that was compressed in the forward direction into
+/// the provided buffer, and return the number of decompressed bytes.
+///
+/// This is a shortcut for a single call to [`Decompressor::decompress`] that treats the end of
+/// the input as an error. Use a [`Decompressor`] directly for the other modes, or to decompress
+/// data that arrives in parts.
+pub fn decompress_into(input: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+    match Decompressor::new().decompress(input, output)? {
+        DecompressStatus::Finished { len, .. } => Ok(len),
+        DecompressStatus::NeedsInput => Err(Error::Truncated)
+    }
+}
+
+// Decompress forward data in one of the file formats, and return the number of bytes consumed
+fn try_format(input: &[u8], output: &mut [u8], classic_mode: bool) -> Result<usize, Error> {
+    match Decompressor::new().classic_mode(classic_mode).decompress(input, output)? {
+        DecompressStatus::Finished { consumed, .. } => Ok(consumed),
+        DecompressStatus::NeedsInput => Err(Error::Truncated)
+    }
+}
+
+/// Detect whether data that was compressed in the forward direction uses the classic (V1) file
+/// format, for data of which the format was not recorded. Returns `true` for the classic format
+/// and `false` for the current format, which can then be passed to
+/// [`Decompressor::classic_mode`].
+///
+/// The data is decompressed in both formats, using `output` as scratch space, so `output` must be
+/// large enough for the decompressed data. The formats encode the end marker differently, so
+/// decoding in the wrong format nearly always fails. If both formats succeed, the one that
+/// consumes exactly all of `input` is preferred, followed by the current format. If neither
+/// format succeeds, the error of the current format is returned, unless the output buffer was
+/// too small for either.
+///
+/// Data that was compressed backwards is identical in both formats, and does not need detection.
+pub fn detect_classic_mode(input: &[u8], output: &mut [u8]) -> Result<bool, Error> {
+    let current = try_format(input, output, false);
+    let classic = try_format(input, output, true);
+
+    match (current, classic) {
+        (Ok(current), Ok(classic)) => Ok(classic == input.len() && current != input.len()),
+        (Ok(_), Err(_)) => Ok(false),
+        (Err(_), Ok(_)) => Ok(true),
+        (Err(_), Err(err @ Error::OutputTooSmall { .. })) | (Err(err), Err(_)) => Err(err)
+    }
+}
+
+/// Decompress data in the current file format that was compressed in the forward direction with a
+/// `skip` of `prefix.len()` bytes, and return the decompressed data without the prefix.
+///
+/// This is meant for verifying compressed data on the host. Since the decompressed length is not
+/// stored in the compressed data, the output buffer is grown until the data fits, so malformed
+/// data that claims very long matches can make this allocate a lot of memory. Use a
+/// [`Decompressor`] with [`prefix_len`](Decompressor::prefix_len) and a buffer of a known size
+/// for untrusted data, or for the other modes.
+#[cfg(feature = "std")]
+pub fn decompress_with_prefix(prefix: &[u8], compressed: &[u8]) -> Result<Vec<u8>, Error> {
+    let (mut output, ()) = grow_until_fits(prefix.len() + 4 * compressed.len() + 64, None, |output| {
+        output[..prefix.len()].copy_from_slice(prefix);
+
+        match Decompressor::new().prefix_len(prefix.len()).decompress(compressed, output)? {
+            DecompressStatus::Finished { len, .. } => Ok((len, ())),
+            DecompressStatus::NeedsInput => Err(Error::Truncated)
+        }
+    })?;
+
+    output.drain(..prefix.len());
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::{decompress_into, decompress_with_prefix, detect_classic_mode, Decompressor, DecompressStatus};
+    use crate::{Compressor, Error};
+
+    // Decompress the data in chunks of the given size
+    fn decompress_chunks(input: &[u8], len: usize, chunk_size: usize, backwards_mode: bool, classic_mode: bool) -> Vec<u8> {
+        let mut output = vec![0; len];
+        let mut decompressor = Decompressor::new();
+        decompressor.backwards_mode(backwards_mode).classic_mode(classic_mode);
+
+        for (i, chunk) in input.chunks(chunk_size).enumerate() {
+            match decompressor.decompress(chunk, &mut output).unwrap() {
+                DecompressStatus::NeedsInput => assert!((i + 1) * chunk_size < input.len()),
+                DecompressStatus::Finished { consumed, len: decompressed_len } => {
+                    assert_eq!(i * chunk_size + consumed, input.len());
+                    assert_eq!(decompressed_len, len);
+                }
+            }
+        }
+
+        output
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = std::fs::read("src/lib.rs").unwrap();
+        let text = &text[..5000];
+
+        for (backwards_mode, classic_mode) in [(false, false), (false, true), (true, false)] {
+            let mut input = text.to_vec();
+
+            if backwards_mode {
+                input.reverse();
+            }
+
+            let result = Compressor::new()
+                .backwards_mode(backwards_mode)
+                .classic_mode(classic_mode)
+                .compress(&input);
+
+            for chunk_size in [1, 7, result.output.len()] {
+                assert_eq!(decompress_chunks(&result.output, input.len(), chunk_size, backwards_mode, classic_mode), input);
+            }
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let input = b"Hello, hello, hello, world!";
+        let output = crate::compress(input);
+
+        let mut buffer = [0; 64];
+        assert_eq!(decompress_into(&output, &mut buffer), Ok(input.len()));
+        assert_eq!(&buffer[..input.len()], input);
+
+        assert_eq!(decompress_into(&output[..output.len() - 1], &mut buffer), Err(Error::Truncated));
+        assert_eq!(decompress_into(&output, &mut buffer[..input.len() - 1]), Err(Error::OutputTooSmall { len: input.len() - 1 }));
+
+        // A single literal, followed by a new offset of 256
+        assert_eq!(
+            decompress_into(&[0xd8, b'a', 0x00], &mut buffer),
+            Err(Error::OffsetOutOfRange { offset: 256, position: 1 })
+        );
+    }
+
+    #[test]
+    fn prefixes() {
+        let text = std::fs::read("src/lib.rs").unwrap();
+        let (prefix, data) = text[..3000].split_at(1000);
+
+        let result = Compressor::new().skip(prefix.len()).compress(&text[..3000]);
+
+        assert_eq!(decompress_with_prefix(prefix, &result.output).as_deref(), Ok(data));
+        assert!(matches!(decompress_with_prefix(&[], &result.output), Err(Error::OffsetOutOfRange { .. })));
+
+        // A prefix that is longer than the output buffer
+        let mut output = vec![0; 500];
+        assert_eq!(Decompressor::new().prefix_len(prefix.len()).decompress(&result.output, &mut output), Err(Error::OutputTooSmall { len: 500 }));
+
+        // A run that decompresses to much more than the initial guess of the output size
+        let run = vec![7; 3000];
+        let result = Compressor::new().skip(1).compress(&run);
+        assert_eq!(decompress_with_prefix(&run[..1], &result.output), Ok(run[1..].to_vec()));
+
+        // Backwards, the reversed suffix acts as the prefix
+        let mut input = [data, prefix].concat();
+        input.reverse();
+
+        let result = Compressor::new().backwards_mode(true).skip(prefix.len()).compress(&input);
+
+        let mut output = input.clone();
+        output[prefix.len()..].fill(0);
+
+        let status = Decompressor::new()
+            .backwards_mode(true)
+            .prefix_len(prefix.len())
+            .decompress(&result.output, &mut output);
+
+        assert_eq!(status, Ok(DecompressStatus::Finished { consumed: result.output.len(), len: input.len() }));
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn progress() {
+        let text = std::fs::read("src/lib.rs").unwrap();
+        let result = Compressor::new().quick_mode(true).compress(&text);
+
+        let mut reported = Vec::new();
+        let mut output = vec![0; text.len()];
+        let status = Decompressor::new().decompress_with_progress(&result.output, &mut output, |progress| reported.push(progress));
+
+        assert_eq!(status, Ok(DecompressStatus::Finished { consumed: result.output.len(), len: text.len() }));
+        assert_eq!(output, text);
+
+        // The input is consumed in parts
+        assert!(reported.len() > 2);
+        assert!(reported.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(reported.last(), Some(&1.0));
+
+        // Only part of the data
+        reported.clear();
+        let status = Decompressor::new().decompress_with_progress(&result.output[..5000], &mut output, |progress| reported.push(progress));
+        assert_eq!(status, Ok(DecompressStatus::NeedsInput));
+        assert_eq!(reported, [4096.0 / 5000.0, 1.0]);
+    }
+
+    #[test]
+    fn detection() {
+        let text = std::fs::read("src/lib.rs").unwrap();
+        let mut buffer = vec![0; 8192];
+
+        // Short offsets are encoded identically in both formats, but the end marker is not
+        for input in [&text[..5000], &[0; 100][..], b"a"] {
+            for classic_mode in [false, true] {
+                let output = Compressor::new().classic_mode(classic_mode).compress(input).output;
+                assert_eq!(detect_classic_mode(&output, &mut buffer), Ok(classic_mode));
+            }
+        }
+
+        let output = crate::compress(&text[..5000]);
+        assert_eq!(detect_classic_mode(&output[..output.len() - 1], &mut buffer), Err(Error::Truncated));
+        assert_eq!(detect_classic_mode(&output, &mut buffer[..4999]), Err(Error::OutputTooSmall { len: 4999 }));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(1000))]
+
+        /// Malformed data must result in an error instead of a panic, since the decompressor is
+        /// meant for environments in which a panic can't be recovered from.
+        #[test]
+        fn malformed_never_panics(input in proptest::collection::vec(any::<u8>(), 0..64), len in 0usize..256, prefix_len in 0usize..512) {
+            let mut output = vec![0; len];
+            let _ = decompress_into(&input, &mut output);
+            let _ = Decompressor::new().prefix_len(prefix_len).decompress(&input, &mut output);
+        }
+    }
+}
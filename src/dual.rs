@@ -0,0 +1,313 @@
+//! An experimental variant of the ZX0 format that stores the control bits and the bytes in two
+//! separate streams, like the LZSA formats do.
+//!
+//! The elements of the format are the same as in the regular format, but a decompressor never has
+//! to mix bits and bytes from the same stream, which removes the bit juggling from its inner loop.
+//! The compressed data consists of:
+//!
+//! - The length of the data stream, as a little-endian 16-bit number.
+//! - The data stream, which holds the literal bytes and the low bytes of the new offsets.
+//! - The control stream, which holds everything else in groups of 8 bits, read from the most
+//!   significant bit down. A new group is read at the point where its first bit is needed.
+//!
+//! Compared to the regular format, the differences are:
+//!
+//! - The offset MSB is the new offset minus one divided by 256, plus one, and the offset LSB is
+//!   the complement of the low byte of the offset minus one. This makes the offset easy to negate
+//!   on an 8-bit processor, and the first bit of the match length is not stored with it.
+//! - Elias Gamma codes are never inverted.
+//!
+//! The stream ends with a new offset whose MSB is 256, like in the regular format.
+
+use crate::{Error, INITIAL_OFFSET};
+#[cfg(feature = "std")]
+use crate::grow::grow_until_fits;
+#[cfg(feature = "std")]
+use crate::optimize::Chain;
+
+#[cfg(feature = "std")]
+struct Context {
+    data: Vec<u8>,
+    control: Vec<u8>,
+    bit_free: u32
+}
+
+#[cfg(feature = "std")]
+impl Context {
+    /// Write the lowest `count` bits of `value` to the control stream, most significant bit first.
+    fn write_bits(&mut self, value: u32, count: u32) {
+        for i in (0..count).rev() {
+            if self.bit_free == 0 {
+                self.bit_free = 8;
+                self.control.push(0);
+            }
+
+            self.bit_free -= 1;
+
+            let last = self.control.len() - 1;
+            self.control[last] |= (((value >> i) & 1) as u8) << self.bit_free;
+        }
+    }
+
+    fn write_interlaced_elias_gamma(&mut self, value: usize) {
+        let length = usize::BITS - value.leading_zeros() - 1;
+
+        for i in (0..length).rev() {
+            self.write_bits(((value >> i) & 1) as u32, 2);
+        }
+
+        self.write_bits(1, 1);
+    }
+}
+
+/// Encode an optimized chain of blocks.
+///
+/// Returns the compressed data and the delta value, or an error if the data stream is longer than
+/// its 16-bit length can describe.
+#[cfg(feature = "std")]
+pub fn compress(chain: &Chain, input: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+    let mut context = Context {
+        data: Vec::new(),
+        control: Vec::new(),
+        bit_free: 0
+    };
+
+    // The number of decompressed bytes and the number of bytes that were consumed from both
+    // streams at the point where every element comes closest to overwriting unread data
+    let mut progress = Vec::new();
+
+    let mut input_index = chain.skip;
+    let mut last_offset = INITIAL_OFFSET;
+    let mut first = true;
+
+    let mut previous_block = chain.allocator.get(chain.head);
+
+    while previous_block.next_index() != 0 {
+        let current_block = chain.allocator.get(previous_block.next_index());
+        let length = (current_block.index - previous_block.index) as usize;
+        let offset = current_block.offset as usize;
+
+        if offset == 0 {
+            // Copy literals indicator, which is omitted for the first literal run
+            if !first {
+                context.write_bits(0, 1);
+            }
+
+            // Copy literals length and values. Every literal is read right before it is written,
+            // so the first one is as close as any of them.
+            context.write_interlaced_elias_gamma(length);
+            context.data.extend_from_slice(&input[input_index..input_index + length]);
+            progress.push((input_index - chain.skip + 1, context.data.len() - length + 1, context.control.len()));
+        } else if offset == last_offset {
+            // Copy from last offset indicator and length
+            context.write_bits(0, 1);
+            context.write_interlaced_elias_gamma(length);
+            progress.push((input_index - chain.skip + length, context.data.len(), context.control.len()));
+        } else {
+            // Copy from new offset indicator, MSB, LSB and length
+            context.write_bits(1, 1);
+            context.write_interlaced_elias_gamma((offset - 1) / 256 + 1);
+            context.data.push(!((offset - 1) % 256) as u8);
+            context.write_interlaced_elias_gamma(length - 1);
+            progress.push((input_index - chain.skip + length, context.data.len(), context.control.len()));
+
+            last_offset = offset;
+        }
+
+        input_index += length;
+        first = false;
+        previous_block = current_block;
+    }
+
+    // End marker
+    context.write_bits(1, 1);
+    context.write_interlaced_elias_gamma(256);
+
+    let data_len = u16::try_from(context.data.len()).map_err(|_| Error::DataStreamTooLong {
+        len: context.data.len()
+    })?;
+
+    let mut output = Vec::with_capacity(2 + context.data.len() + context.control.len());
+    output.extend_from_slice(&data_len.to_le_bytes());
+    output.extend_from_slice(&context.data);
+    output.extend_from_slice(&context.control);
+
+    // The unread data starts in the data stream until it has been read completely, and in the
+    // control stream after that. The delta is the largest amount by which the decompressed data
+    // gets ahead of it, relative to the end of both.
+    let uncompressed_len = input.len() - chain.skip;
+    let data_len = context.data.len();
+
+    let max_diff = progress.iter().map(|&(written, data, control)| {
+        let read = if data < data_len { data } else { data_len + control };
+        written as isize - read as isize
+    }).max().unwrap_or(0);
+
+    let delta = (output.len() as isize - 2 - uncompressed_len as isize + max_diff).max(0);
+
+    Ok((output, delta as usize))
+}
+
+// The kinds of elements in the order in which they can occur
+enum Element {
+    Literals,
+    LastOffset,
+    NewOffset
+}
+
+struct Reader<'a> {
+    control: &'a [u8],
+    position: usize,
+    bit_mask: u8,
+    bit_value: u8
+}
+
+impl Reader<'_> {
+    fn read_bit(&mut self) -> Result<bool, Error> {
+        if self.bit_mask == 0 {
+            self.bit_value = *self.control.get(self.position).ok_or(Error::Truncated)?;
+            self.position += 1;
+            self.bit_mask = 128;
+        }
+
+        let bit = self.bit_value & self.bit_mask != 0;
+        self.bit_mask >>= 1;
+        Ok(bit)
+    }
+
+    fn read_interlaced_elias_gamma(&mut self) -> Result<usize, Error> {
+        let mut value = 1usize;
+
+        while !self.read_bit()? {
+            if value > usize::MAX >> 1 {
+                return Err(Error::InvalidData);
+            }
+
+            value = (value << 1) | self.read_bit()? as usize;
+        }
+
+        Ok(value)
+    }
+}
+
+/// Decompress data in the experimental dual-stream format that
+/// [`Compressor::compress_dual`](crate::Compressor::compress_dual) produces into the provided
+/// buffer, and return the number of decompressed bytes.
+///
+/// Returns an error if the compressed data is malformed or ends before its end marker, or if the
+/// decompressed data does not fit in the output buffer. Data that was compressed with a prefix
+/// can't be decompressed using this function.
+pub fn decompress_dual_into(input: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+    let header = input.get(..2).ok_or(Error::Truncated)?;
+    let data_len = u16::from_le_bytes([header[0], header[1]]) as usize;
+    let data = input.get(2..2 + data_len).ok_or(Error::Truncated)?;
+
+    let mut reader = Reader {
+        control: &input[2 + data_len..],
+        position: 0,
+        bit_mask: 0,
+        bit_value: 0
+    };
+
+    let mut data = data.iter();
+    let mut position = 0;
+    let mut last_offset = INITIAL_OFFSET;
+
+    // Streams always start with literals, so the first indicator bit is omitted
+    let mut element = Element::Literals;
+
+    loop {
+        let (offset, length) = match element {
+            Element::Literals => (0, reader.read_interlaced_elias_gamma()?),
+            Element::LastOffset => (last_offset, reader.read_interlaced_elias_gamma()?),
+            Element::NewOffset => {
+                let msb = reader.read_interlaced_elias_gamma()?;
+
+                match msb {
+                    256 => return Ok(position),
+                    257.. => return Err(Error::InvalidData),
+                    _ => ()
+                }
+
+                let lsb = !*data.next().ok_or(Error::Truncated)? as usize;
+                last_offset = ((msb - 1) << 8 | lsb) + 1;
+
+                (last_offset, reader.read_interlaced_elias_gamma()?.checked_add(1).ok_or(Error::InvalidData)?)
+            }
+        };
+
+        if length > output.len() - position {
+            return Err(Error::OutputTooSmall { len: output.len() });
+        }
+
+        if offset == 0 {
+            for _ in 0..length {
+                output[position] = *data.next().ok_or(Error::Truncated)?;
+                position += 1;
+            }
+        } else {
+            if offset > position {
+                return Err(Error::OffsetOutOfRange { offset, position });
+            }
+
+            // Copy byte by byte, since the source and destination may overlap
+            for _ in 0..length {
+                output[position] = output[position - offset];
+                position += 1;
+            }
+        }
+
+        // A set bit is followed by a new offset, and a clear bit by whatever can't follow the
+        // current element otherwise
+        element = match (reader.read_bit()?, offset) {
+            (true, _) => Element::NewOffset,
+            (false, 0) => Element::LastOffset,
+            (false, _) => Element::Literals
+        };
+    }
+}
+
+/// Decompress data in the experimental dual-stream format, and return the decompressed data.
+///
+/// Since the decompressed length is not stored in the compressed data, the output buffer is grown
+/// until the data fits. Use [`decompress_dual_into`] with a buffer of a known size for untrusted
+/// data.
+#[cfg(feature = "std")]
+pub fn decompress_dual(input: &[u8]) -> Result<Vec<u8>, Error> {
+    grow_until_fits(4 * input.len() + 64, None, |output| decompress_dual_into(input, output).map(|len| (len, ())))
+        .map(|(output, ())| output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decompress_dual, decompress_dual_into};
+    use crate::{Compressor, Error};
+
+    #[test]
+    fn round_trip() {
+        let text = std::fs::read("src/optimize.rs").unwrap();
+        let run = [7; 512];
+
+        for input in [&text[..], &text[..1], &run[..]] {
+            for quick_mode in [false, true] {
+                let result = Compressor::new().quick_mode(quick_mode).compress_dual(input);
+                assert_eq!(decompress_dual(&result.output), Ok(input.to_vec()));
+            }
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        let input = std::fs::read("src/compress.rs").unwrap();
+        let result = Compressor::new().compress_dual(&input);
+
+        let mut output = vec![0; input.len()];
+        assert_eq!(decompress_dual_into(&result.output, &mut output), Ok(input.len()));
+        assert_eq!(decompress_dual_into(&result.output, &mut output[1..]), Err(Error::OutputTooSmall { len: input.len() - 1 }));
+
+        // Both the control stream at the end and the data stream before it can run out
+        assert_eq!(decompress_dual_into(&result.output[..result.output.len() - 1], &mut output), Err(Error::Truncated));
+        assert_eq!(decompress_dual_into(&result.output[..100], &mut output), Err(Error::Truncated));
+        assert_eq!(decompress_dual_into(&result.output[..1], &mut output), Err(Error::Truncated));
+    }
+}
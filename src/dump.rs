@@ -0,0 +1,146 @@
+//! Dumps of the optimizer state, for diagnosing parses that turn out different than expected, such
+//! as the rare mismatches with the reference implementation that the fuzzer finds.
+//!
+//! A dump is plain text. It starts with the input length and the settings, followed by a snapshot
+//! of the state after every `interval` positions and after the last position. Each snapshot lists
+//! the arena usage, the number of allocated blocks for every offset, the blocks that the literal
+//! and match chains of every offset currently end in, and the optimal chain up to that position.
+//! Blocks are written as `bits@index` or `bits@index+offset`, where the index is the input
+//! position of the last byte that the block covers, and the bits include the token cost of the
+//! settings. When searching for a parse within a token budget, the dump holds every parse that was
+//! tried, each starting with its own settings.
+
+use std::fmt::Write;
+
+use crate::optimize::{Allocator, Block, Settings};
+
+/// The state of the optimizer as it is recorded during a single parse.
+pub struct Dump {
+    interval: usize,
+    text: String
+}
+
+fn format_block(block: &Block) -> String {
+    if block.offset == 0 {
+        format!("{}@{}", block.bits, block.index)
+    } else {
+        format!("{}@{}+{}", block.bits, block.index, block.offset)
+    }
+}
+
+impl Dump {
+    /// Create a dump that takes a snapshot after every `interval` positions.
+    pub fn new(interval: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            text: String::new()
+        }
+    }
+
+    pub fn interval(&self) -> usize {
+        self.interval
+    }
+
+    /// Record the input and the settings that the parse uses.
+    pub fn header(&mut self, input_len: usize, skip: usize, initial_offset: usize, settings: Settings) {
+        let Settings { offset_limit, candidate_limit, extreme_mode, max_literal_run, token_cost } = settings;
+
+        writeln!(
+            self.text,
+            "parse input_len={} skip={} initial_offset={} offset_limit={} candidate_limit={} extreme_mode={} max_literal_run={} token_cost={}",
+            input_len,
+            skip,
+            initial_offset,
+            offset_limit,
+            candidate_limit,
+            extreme_mode,
+            max_literal_run,
+            token_cost
+        ).unwrap();
+    }
+
+    /// Record the state after the given position, relative to the skipped bytes, was processed.
+    pub fn snapshot(&mut self, position: usize, allocator: &Allocator, last_literal: &[usize], last_match: &[usize], optimal: usize) {
+        let allocated = allocator.allocated();
+
+        writeln!(self.text).unwrap();
+        writeln!(
+            self.text,
+            "snapshot position={} arena_len={} allocated={}",
+            position,
+            allocator.len(),
+            allocated.iter().filter(|&&allocated| allocated).count()
+        ).unwrap();
+
+        // Literal blocks have no offset, so they are counted under offset 0
+        let mut counts = vec![0; last_match.len()];
+
+        for (index, _) in allocated.iter().enumerate().filter(|(_, &allocated)| allocated) {
+            counts[allocator.get(index).offset as usize] += 1;
+        }
+
+        writeln!(self.text, "offset blocks last_literal last_match").unwrap();
+
+        for (offset, &count) in counts.iter().enumerate() {
+            let last_literal = last_literal.get(offset).copied().unwrap_or(0);
+            let last_match = last_match.get(offset).copied().unwrap_or(0);
+
+            if count == 0 && last_literal == 0 && last_match == 0 {
+                continue;
+            }
+
+            let format_root = |index| if index == 0 { "-".to_string() } else { format_block(allocator.get(index)) };
+
+            writeln!(self.text, "{} {} {} {}", offset, count, format_root(last_literal), format_root(last_match)).unwrap();
+        }
+
+        // The chain is linked back-to-front while optimizing
+        let mut chain = Vec::new();
+        let mut index = optimal;
+
+        while index != 0 {
+            let block = allocator.get(index);
+            chain.push(format_block(block));
+            index = block.next_index();
+        }
+
+        chain.reverse();
+        writeln!(self.text, "optimal {}", chain.join(" ")).unwrap();
+    }
+
+    /// Return the text of the dump.
+    pub fn into_text(self) -> String {
+        self.text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Compressor;
+
+    #[test]
+    fn dump() {
+        let path = std::env::temp_dir().join(format!("zx0-dump-{}.txt", std::process::id()));
+        let input = b"abracadabra, abracadabra, abracadabra";
+
+        let result = Compressor::new().skip(2).debug_dump(&path, 16).compress(input);
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines = text.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("parse input_len=37 skip=2 initial_offset=1 "));
+
+        let snapshots = lines.iter().filter(|line| line.starts_with("snapshot ")).collect::<Vec<_>>();
+        assert_eq!(snapshots.len(), 4);
+        assert!(snapshots[3].starts_with("snapshot position=34 "));
+
+        // The optimal chain of the last snapshot starts with the fake block, and its last block
+        // holds the bits of the whole parse
+        let optimal = lines.iter().rfind(|line| line.starts_with("optimal ")).unwrap();
+        let blocks = optimal.split(' ').skip(1).collect::<Vec<_>>();
+        let bits = blocks.last().unwrap().split('@').next().unwrap().parse::<usize>().unwrap();
+
+        assert_eq!(blocks[0], "0@1+1");
+        assert_eq!((bits + 25) / 8, result.output.len());
+    }
+}
@@ -0,0 +1,106 @@
+//! Order-0 and order-1 entropy estimates, which give an indication of how well data can be
+//! compressed at all.
+
+/// Empirical entropy estimates of a slice of data, expressed in bits per byte.
+///
+/// These are rough lower bounds for the compressed size that a statistical coder could achieve
+/// with a static model of the given order. Dictionary compressors such as ZX0 can beat them on
+/// data with long repetitions, but they are a useful indication of how much redundancy is left.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Entropy {
+    /// The order-0 entropy, which treats every byte independently.
+    pub order0: f64,
+
+    /// The order-1 entropy, which conditions every byte on the byte before it.
+    pub order1: f64,
+
+    /// The number of bytes that the estimate was calculated for.
+    pub len: usize
+}
+
+impl Entropy {
+    /// The size in bytes that the data would occupy when coded at the order-0 entropy.
+    pub fn order0_size(&self) -> usize {
+        (self.order0 * self.len as f64 / 8.0).ceil() as usize
+    }
+
+    /// The size in bytes that the data would occupy when coded at the order-1 entropy.
+    pub fn order1_size(&self) -> usize {
+        (self.order1 * self.len as f64 / 8.0).ceil() as usize
+    }
+}
+
+// Sum -count * log2(count / total) over a histogram
+fn information(counts: &[usize], total: usize) -> f64 {
+    counts.iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| count as f64 * (total as f64 / count as f64).log2())
+        .sum()
+}
+
+/// Estimate the order-0 and order-1 entropy of the provided slice.
+///
+/// The first byte is coded with the order-0 model when calculating the order-1 entropy, since it
+/// has no preceding byte to condition on.
+pub fn entropy(input: &[u8]) -> Entropy {
+    if input.is_empty() {
+        return Entropy { order0: 0.0, order1: 0.0, len: 0 };
+    }
+
+    let mut counts = vec![0usize; 256];
+    let mut pair_counts = vec![0usize; 256 * 256];
+
+    for &byte in input {
+        counts[byte as usize] += 1;
+    }
+
+    for pair in input.windows(2) {
+        pair_counts[(pair[0] as usize) << 8 | pair[1] as usize] += 1;
+    }
+
+    let order0 = information(&counts, input.len());
+
+    let mut order1 = (input.len() as f64 / counts[input[0] as usize] as f64).log2();
+
+    for context in pair_counts.chunks(256) {
+        let total = context.iter().sum::<usize>();
+
+        if total > 0 {
+            order1 += information(context, total);
+        }
+    }
+
+    Entropy {
+        order0: order0 / input.len() as f64,
+        order1: order1 / input.len() as f64,
+        len: input.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::entropy;
+
+    #[test]
+    fn bounds() {
+        let constant = entropy(&[42; 1000]);
+        assert_eq!(constant.order0, 0.0);
+        assert_eq!(constant.order1, 0.0);
+        assert_eq!(constant.order0_size(), 0);
+
+        let all = (0..=255).collect::<Vec<u8>>();
+        let uniform = entropy(&all);
+        assert!((uniform.order0 - 8.0).abs() < 1e-9);
+        assert_eq!(uniform.order0_size(), 256);
+
+        // Every byte is fully determined by its predecessor
+        assert!(uniform.order1 < 0.1);
+
+        let text = std::fs::read("src/lib.rs").unwrap();
+        let estimate = entropy(&text);
+        assert!(estimate.order1 < estimate.order0);
+        assert!(estimate.order0 < 8.0);
+
+        assert_eq!(entropy(&[]).len, 0);
+    }
+}
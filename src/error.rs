@@ -0,0 +1,158 @@
+use core::fmt;
+use core::ops::Range;
+
+/// An error that is returned by the fallible methods of the `Compressor`, for input and option
+/// combinations that cause the infallible methods to panic, and by the
+/// [`Decompressor`](crate::Decompressor) for malformed compressed data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// There is no data left to compress after skipping the prefix/suffix bytes. This is also
+    /// returned for empty input.
+    NothingToCompress {
+        /// The length of the input.
+        input_len: usize,
+
+        /// The number of prefix/suffix bytes that were skipped.
+        skip: usize
+    },
+
+    /// A range that was passed for compression extends past the end of the input, or ends before
+    /// it starts.
+    RangeOutOfBounds {
+        /// The range that was passed.
+        range: Range<usize>,
+
+        /// The length of the input.
+        input_len: usize
+    },
+
+    /// The input is larger than the maximum input length that was configured using
+    /// [`Compressor::max_input_len`](crate::Compressor::max_input_len).
+    InputTooLarge {
+        /// The length of the input.
+        input_len: usize,
+
+        /// The maximum input length.
+        max_input_len: usize
+    },
+
+    /// The input length or the number of skipped bytes is odd, which can't be compressed using the
+    /// 16-bit word format.
+    NotWordAligned {
+        /// The length of the input.
+        input_len: usize,
+
+        /// The number of prefix bytes that were skipped.
+        skip: usize
+    },
+
+    /// The input contains a stretch that can't be matched, which is longer than the maximum
+    /// literal run that was configured using
+    /// [`Compressor::max_literal_run`](crate::Compressor::max_literal_run).
+    LiteralRunTooLong {
+        /// The maximum length of a literal run.
+        max_literal_run: usize
+    },
+
+    /// The input can't be parsed within the token budget that was configured using
+    /// [`Compressor::max_tokens`](crate::Compressor::max_tokens), even when the optimizer trades
+    /// as much compression ratio for fewer tokens as it can.
+    TokenBudgetExceeded {
+        /// The maximum number of tokens.
+        max_tokens: usize,
+
+        /// The smallest number of tokens that the optimizer found a parse for.
+        tokens: usize
+    },
+
+    /// The data stream of the dual-stream format is longer than the 65535 bytes that its header
+    /// can describe.
+    DataStreamTooLong {
+        /// The length of the data stream.
+        len: usize
+    },
+
+    /// The input that was passed for encoding differs in length from the input that was parsed.
+    InputLengthMismatch {
+        /// The length of the input that was parsed.
+        expected: usize,
+
+        /// The length of the input that was passed for encoding.
+        actual: usize
+    },
+
+    /// The output slice that was passed for encoding differs in length from the compressed data.
+    OutputLengthMismatch {
+        /// The length of the compressed data.
+        expected: usize,
+
+        /// The length of the output slice.
+        actual: usize
+    },
+
+    /// The compressed data ends before its end marker.
+    Truncated,
+
+    /// The compressed data contains a code that can't occur in valid data.
+    InvalidData,
+
+    /// The compressed data contains a match that refers to data before the start of the
+    /// decompressed data.
+    OffsetOutOfRange {
+        /// The offset of the match.
+        offset: usize,
+
+        /// The number of bytes that were decompressed before the match.
+        position: usize
+    },
+
+    /// The decompressed data does not fit in the output buffer.
+    OutputTooSmall {
+        /// The length of the output buffer.
+        len: usize
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NothingToCompress { input_len, skip } => {
+                write!(f, "nothing to compress after skipping {} of {} input bytes", skip, input_len)
+            },
+            Error::RangeOutOfBounds { range, input_len } => {
+                write!(f, "range {:?} is out of bounds for input length {}", range, input_len)
+            },
+            Error::InputTooLarge { input_len, max_input_len } => {
+                write!(f, "input length {} exceeds the maximum input length {}", input_len, max_input_len)
+            },
+            Error::NotWordAligned { input_len, skip } => {
+                write!(f, "input length {} and skip {} must both be even for the word format", input_len, skip)
+            },
+            Error::LiteralRunTooLong { max_literal_run } => {
+                write!(f, "input can't be parsed without literal runs longer than {}", max_literal_run)
+            },
+            Error::TokenBudgetExceeded { max_tokens, tokens } => {
+                write!(f, "input can't be parsed in {} tokens, the fewest found is {}", max_tokens, tokens)
+            },
+            Error::DataStreamTooLong { len } => {
+                write!(f, "data stream length {} exceeds the maximum of 65535 bytes", len)
+            },
+            Error::InputLengthMismatch { expected, actual } => {
+                write!(f, "input length {} differs from the parsed input length {}", actual, expected)
+            },
+            Error::OutputLengthMismatch { expected, actual } => {
+                write!(f, "output length {} differs from the compressed data length {}", actual, expected)
+            },
+            Error::Truncated => write!(f, "compressed data ends before its end marker"),
+            Error::InvalidData => write!(f, "compressed data contains an invalid code"),
+            Error::OffsetOutOfRange { offset, position } => {
+                write!(f, "match offset {} at position {} refers to data before the start of the output", offset, position)
+            },
+            Error::OutputTooSmall { len } => {
+                write!(f, "decompressed data does not fit in an output buffer of {} bytes", len)
+            }
+        }
+    }
+}
+
+impl core::error::Error for Error {}
@@ -0,0 +1,123 @@
+//! The extract command, which decompresses a stream that is embedded at an arbitrary offset of a
+//! larger file, such as a ROM or a snapshot.
+
+use zx0::{detect_classic_mode, DecompressStatus, Decompressor, Error};
+
+use crate::grow::grow_until_fits;
+
+/// Where to find the stream and how to decompress it.
+#[derive(Clone, Copy, Debug)]
+pub struct ExtractSettings {
+    /// The offset of the byte at which decompression starts, which is the last byte of the stream
+    /// when it was compressed backwards.
+    pub offset: usize,
+
+    /// The maximum length of the compressed data, or `None` to allow it to extend to the end (or
+    /// the start, when compressed backwards) of the file.
+    pub length: Option<usize>,
+
+    /// Whether the stream uses the classic (V1) file format.
+    pub classic_mode: bool,
+
+    /// Whether to detect the file format of a forward stream instead of using `classic_mode`.
+    pub detect_mode: bool,
+
+    /// Whether the stream was compressed backwards.
+    pub backwards_mode: bool,
+
+    /// The maximum length of the decompressed data.
+    pub max_len: usize
+}
+
+/// Decompress the stream at the given offset of the file, and return the length of the compressed
+/// data along with the decompressed data in its natural order, and whether the stream uses the
+/// classic file format.
+///
+/// Returns [`Error::Truncated`] if the stream does not end within the file or the given length,
+/// and [`Error::OutputTooSmall`] if it decompresses to more than `max_len` bytes.
+pub fn extract(file: &[u8], settings: ExtractSettings) -> Result<(usize, Vec<u8>, bool), Error> {
+    if settings.offset >= file.len() {
+        return Err(Error::Truncated);
+    }
+
+    // Backwards streams are decompressed from their last byte down
+    let data = if settings.backwards_mode {
+        let start = settings.length.map_or(0, |length| (settings.offset + 1).saturating_sub(length));
+        let mut data = file[start..=settings.offset].to_vec();
+        data.reverse();
+        data
+    } else {
+        let end = settings.length.map_or(file.len(), |length| file.len().min(settings.offset + length));
+        file[settings.offset..end].to_vec()
+    };
+
+    let (mut output, (consumed, classic_mode)) = grow_until_fits(4 * data.len() + 64, Some(settings.max_len), |output| {
+        // Both formats are identical when decompressing backwards
+        let classic_mode = if settings.detect_mode && !settings.backwards_mode {
+            detect_classic_mode(&data, output)?
+        } else {
+            settings.classic_mode
+        };
+
+        let status = Decompressor::new()
+            .classic_mode(classic_mode)
+            .backwards_mode(settings.backwards_mode)
+            .decompress(&data, output)?;
+
+        match status {
+            DecompressStatus::Finished { consumed, len } => Ok((len, (consumed, classic_mode))),
+            DecompressStatus::NeedsInput => Err(Error::Truncated)
+        }
+    })?;
+
+    if settings.backwards_mode {
+        output.reverse();
+    }
+
+    Ok((consumed, output, classic_mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use zx0::{Compressor, Error};
+
+    use super::{extract, ExtractSettings};
+
+    #[test]
+    fn extract_stream() {
+        let text = std::fs::read("src/main.rs").unwrap();
+        let input = &text[..3000];
+
+        let forwards = Compressor::new().compress(input).output;
+        let backwards = Compressor::new().backwards_mode(true).natural_order(true).compress(input).output;
+        let file = [&text[..100], &forwards, &text[..50], &backwards, &text[..10]].concat();
+
+        let settings = ExtractSettings {
+            offset: 100,
+            length: None,
+            classic_mode: false,
+            detect_mode: false,
+            backwards_mode: false,
+            max_len: 65536
+        };
+
+        assert_eq!(extract(&file, settings), Ok((forwards.len(), input.to_vec(), false)));
+
+        // Backwards streams start at their last byte
+        let last = 150 + forwards.len() + backwards.len() - 1;
+        let backwards_settings = ExtractSettings { offset: last, backwards_mode: true, ..settings };
+        assert_eq!(extract(&file, backwards_settings), Ok((backwards.len(), input.to_vec(), false)));
+
+        // The format of a forward stream can be detected
+        let classic = Compressor::new().classic_mode(true).compress(input).output;
+        let classic_file = [&text[..100], &classic].concat();
+        let detect_settings = ExtractSettings { detect_mode: true, ..settings };
+        assert_eq!(extract(&classic_file, detect_settings), Ok((classic.len(), input.to_vec(), true)));
+        assert_eq!(extract(&file, detect_settings), Ok((forwards.len(), input.to_vec(), false)));
+
+        assert_eq!(extract(&file, ExtractSettings { length: Some(forwards.len() - 1), ..settings }), Err(Error::Truncated));
+        assert_eq!(extract(&file, ExtractSettings { length: Some(backwards.len() - 1), ..backwards_settings }), Err(Error::Truncated));
+        assert_eq!(extract(&file, ExtractSettings { max_len: 2999, ..settings }), Err(Error::OutputTooSmall { len: 2999 }));
+        assert_eq!(extract(&file, ExtractSettings { offset: file.len(), ..settings }), Err(Error::Truncated));
+    }
+}
@@ -0,0 +1,251 @@
+//! Conversion of PNG and BMP images to the pixel formats of retro machines, so graphics can be
+//! compressed without a separate conversion step.
+
+use std::collections::HashMap;
+
+use image::RgbaImage;
+
+// The width and height of a ZX Spectrum screen in pixels
+const ZX_WIDTH: u32 = 256;
+const ZX_HEIGHT: u32 = 192;
+
+/// The pixel format that an image is converted to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// One byte per pixel, which holds the index of its color.
+    Linear,
+
+    /// Four bit planes per row of pixels, from the least significant bit of the color index up.
+    /// Every plane holds one bit per pixel, with the leftmost pixel in the most significant bit.
+    Planar4,
+
+    /// A ZX Spectrum screen: the 6144 byte bitmap in the order of the display file, followed by
+    /// 768 attribute bytes.
+    Zx
+}
+
+impl PixelFormat {
+    /// Return the name of the format, as it is given on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            PixelFormat::Linear => "linear",
+            PixelFormat::Planar4 => "planar4",
+            PixelFormat::Zx => "zx"
+        }
+    }
+}
+
+/// The properties of a converted image, for the summary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Conversion {
+    /// The width of the image in pixels.
+    pub width: u32,
+
+    /// The height of the image in pixels.
+    pub height: u32,
+
+    /// The number of distinct colors in the image.
+    pub colors: usize
+}
+
+// Number every distinct color in the order in which it first occurs, reading the image from left
+// to right and top to bottom. Fails if there are more colors than the format can index.
+fn index_colors(image: &RgbaImage, max_colors: usize) -> Result<(Vec<u8>, usize), String> {
+    let mut palette = HashMap::new();
+    let mut indices = Vec::with_capacity(image.len() / 4);
+
+    for pixel in image.pixels() {
+        let next = palette.len();
+        let index = *palette.entry(pixel.0).or_insert(next);
+
+        if index >= max_colors {
+            return Err(format!("image has more than {} colors", max_colors));
+        }
+
+        indices.push(index as u8);
+    }
+
+    Ok((indices, palette.len()))
+}
+
+fn convert_planar4(indices: &[u8], width: usize) -> Vec<u8> {
+    let mut output = Vec::with_capacity(indices.len() / 2);
+
+    for row in indices.chunks_exact(width) {
+        for plane in 0..4 {
+            output.extend(row.chunks_exact(8).map(|group| {
+                group.iter().fold(0, |value, index| (value << 1) | ((index >> plane) & 1))
+            }));
+        }
+    }
+
+    output
+}
+
+// Map a color to the ZX Spectrum palette, as its color number and whether it is bright. Channels
+// of at least half intensity are on, and a color is bright when its strongest channel is closer to
+// full intensity than to the 0xd7 of a regular color.
+fn zx_color(rgba: [u8; 4]) -> (u8, bool) {
+    let [r, g, b, _] = rgba;
+    let number = ((g >= 0x80) as u8) << 2 | ((r >= 0x80) as u8) << 1 | (b >= 0x80) as u8;
+
+    (number, r.max(g).max(b) >= 0xeb)
+}
+
+fn convert_zx(image: &RgbaImage) -> Result<(Vec<u8>, usize), String> {
+    let mut bitmap = vec![0; 6144];
+    let mut attributes = Vec::with_capacity(768);
+    let mut used = [false; 16];
+
+    for cell_y in 0..ZX_HEIGHT / 8 {
+        for cell_x in 0..ZX_WIDTH / 8 {
+            let mut colors = Vec::with_capacity(2);
+            let mut bright = None;
+
+            for y in 0..8 {
+                for x in 0..8 {
+                    let (number, is_bright) = zx_color(image.get_pixel(cell_x * 8 + x, cell_y * 8 + y).0);
+
+                    // Black looks the same with or without the bright bit
+                    if number != 0 && *bright.get_or_insert(is_bright) != is_bright {
+                        return Err(format!("cell at {}, {} mixes bright and regular colors", cell_x * 8, cell_y * 8));
+                    }
+
+                    if !colors.contains(&number) {
+                        if colors.len() == 2 {
+                            return Err(format!("cell at {}, {} has more than 2 colors", cell_x * 8, cell_y * 8));
+                        }
+
+                        colors.push(number);
+                    }
+                }
+            }
+
+            // The brighter color number is the ink, which is drawn by the set bits
+            colors.sort_unstable();
+            let paper = colors[0];
+            let ink = colors[colors.len() - 1];
+            let bright = bright.unwrap_or(false);
+
+            for y in 0..8 {
+                let line = cell_y * 8 + y;
+                let address = ((line & 0xc0) << 5 | (line & 7) << 8 | (line & 0x38) << 2 | cell_x) as usize;
+
+                bitmap[address] = (0..8).fold(0, |value, x| {
+                    let (number, _) = zx_color(image.get_pixel(cell_x * 8 + x, line).0);
+                    (value << 1) | (colors.len() == 2 && number == ink) as u8
+                });
+            }
+
+            attributes.push((bright as u8) << 6 | paper << 3 | ink);
+
+            for &number in &colors {
+                used[(number + 8 * (bright && number != 0) as u8) as usize] = true;
+            }
+        }
+    }
+
+    bitmap.extend(attributes);
+    Ok((bitmap, used.iter().filter(|&&used| used).count()))
+}
+
+/// Decode a PNG or BMP image and convert it to the given pixel format.
+///
+/// The colors of the linear and planar formats are numbered in the order in which they first
+/// occur, from the top left of the image. Returns an error message if the image can't be decoded,
+/// or if it doesn't fit the restrictions of the pixel format.
+pub fn convert_image(data: &[u8], format: PixelFormat) -> Result<(Vec<u8>, Conversion), String> {
+    let image = image::load_from_memory(data).map_err(|err| err.to_string())?.to_rgba8();
+    let (width, height) = image.dimensions();
+
+    let (output, colors) = match format {
+        PixelFormat::Linear => index_colors(&image, 256)?,
+        PixelFormat::Planar4 => {
+            if width % 8 != 0 {
+                return Err(format!("image width {} is not a multiple of 8", width));
+            }
+
+            let (indices, colors) = index_colors(&image, 16)?;
+            (convert_planar4(&indices, width as usize), colors)
+        },
+        PixelFormat::Zx => {
+            if (width, height) != (ZX_WIDTH, ZX_HEIGHT) {
+                return Err(format!("image is {}x{} pixels instead of {}x{}", width, height, ZX_WIDTH, ZX_HEIGHT));
+            }
+
+            convert_zx(&image)?
+        }
+    };
+
+    Ok((output, Conversion { width, height, colors }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageFormat, Rgba, RgbaImage};
+
+    use super::{convert_image, Conversion, PixelFormat};
+
+    fn encode(image: &RgbaImage, format: ImageFormat) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, format).unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn linear_and_planar() {
+        let colors = [Rgba([0, 0, 0, 255]), Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])];
+        let image = RgbaImage::from_fn(16, 2, |x, y| colors[((x + y) % 3) as usize]);
+
+        for format in [ImageFormat::Png, ImageFormat::Bmp] {
+            let data = encode(&image, format);
+
+            let (output, conversion) = convert_image(&data, PixelFormat::Linear).unwrap();
+            assert_eq!(conversion, Conversion { width: 16, height: 2, colors: 3 });
+            assert_eq!(&output[..5], [0, 1, 2, 0, 1]);
+            assert_eq!(&output[16..21], [1, 2, 0, 1, 2]);
+
+            // Bit 0 is set for color 1, and bit 1 for color 2
+            let (output, _) = convert_image(&data, PixelFormat::Planar4).unwrap();
+            assert_eq!(output.len(), 16);
+            assert_eq!(&output[..8], [0b01001001, 0b00100100, 0b00100100, 0b10010010, 0, 0, 0, 0]);
+        }
+
+        let wide = RgbaImage::from_fn(12, 1, |x, _| colors[(x % 3) as usize]);
+        assert!(convert_image(&encode(&wide, ImageFormat::Png), PixelFormat::Planar4).is_err());
+        assert!(convert_image(b"not an image", PixelFormat::Linear).is_err());
+    }
+
+    #[test]
+    fn zx() {
+        // Bright yellow ink on blue paper in the top left cell, and a regular red cell below it
+        let image = RgbaImage::from_fn(256, 192, |x, y| match (x, y) {
+            (0..=7, 0..=7) if x == y => Rgba([255, 255, 0, 255]),
+            (0..=7, 0..=7) => Rgba([0, 0, 255, 255]),
+            (0..=7, 8..=15) => Rgba([215, 0, 0, 255]),
+            _ => Rgba([0, 0, 0, 255])
+        });
+
+        let (output, conversion) = convert_image(&encode(&image, ImageFormat::Png), PixelFormat::Zx).unwrap();
+        assert_eq!(conversion.colors, 4);
+        assert_eq!(output.len(), 6912);
+
+        // The lines of the first cell are 256 bytes apart, and the second cell row follows the
+        // first line of the first one
+        assert_eq!(output[0], 0x80);
+        assert_eq!(output[256], 0x40);
+        assert_eq!(output[7 * 256], 0x01);
+        assert_eq!(output[32], 0);
+        assert_eq!(output[6144], 0x40 | 1 << 3 | 6);
+        assert_eq!(output[6144 + 32], 2 << 3 | 2);
+        assert_eq!(output[6144 + 1], 0);
+
+        let crowded = RgbaImage::from_fn(256, 192, |x, _| [Rgba([0, 0, 0, 255]), Rgba([255, 0, 0, 255]), Rgba([0, 255, 0, 255])][(x % 3) as usize]);
+        assert!(convert_image(&encode(&crowded, ImageFormat::Png), PixelFormat::Zx).is_err());
+
+        let small = RgbaImage::new(8, 8);
+        assert!(convert_image(&encode(&small, ImageFormat::Png), PixelFormat::Zx).is_err());
+    }
+}
@@ -0,0 +1,30 @@
+//! Decompression into a growing output buffer, for data whose decompressed length is not known up
+//! front.
+
+use crate::Error;
+
+/// Call `decompress` with an output buffer of `len` bytes, and double the buffer for as long as it
+/// returns [`Error::OutputTooSmall`]. If `max_len` is given, the buffer never grows beyond it, and
+/// the error is returned once the data doesn't fit in that many bytes either.
+///
+/// The closure returns the decompressed length along with a value of its own, which is returned
+/// together with the buffer truncated to that length.
+pub fn grow_until_fits<T>(len: usize, max_len: Option<usize>, mut decompress: impl FnMut(&mut [u8]) -> Result<(usize, T), Error>) -> Result<(Vec<u8>, T), Error> {
+    let max_len = max_len.unwrap_or(usize::MAX);
+    let mut len = len.min(max_len);
+    let mut output = vec![0; len];
+
+    loop {
+        match decompress(&mut output) {
+            Ok((len, value)) => {
+                output.truncate(len);
+                return Ok((output, value));
+            },
+            Err(Error::OutputTooSmall { .. }) if len < max_len => {
+                len = len.saturating_mul(2).min(max_len);
+                output.resize(len, 0);
+            },
+            Err(err) => return Err(err)
+        }
+    }
+}
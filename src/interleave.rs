@@ -0,0 +1,180 @@
+//! Transforms that split interleaved data into separate streams before it is compressed.
+//!
+//! Data such as sprite tables, sample frames or attribute records often stores several fields
+//! after each other for every entry. Every field on its own tends to change slowly or repeat,
+//! which the compressor can only find back when the fields are stored one after the other. The
+//! decompressed data has to be interleaved again by whoever uses it, either at runtime or by
+//! decompressing with the same layout.
+
+use std::fmt;
+
+use crate::Compressor;
+
+/// The number of bytes at the start of the input that [`detect_interleave`] compresses to compare
+/// the layouts.
+const SAMPLE_LEN: usize = 8192;
+
+/// The largest stride that [`detect_interleave`] tries.
+const MAX_DETECTED_STRIDE: usize = 16;
+
+/// The way the data is split into streams.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interleave {
+    /// Every byte is moved to the stream of its position modulo the stride, so the streams hold
+    /// bytes 0, N, 2N, etc., followed by bytes 1, N + 1, 2N + 1, etc.
+    Bytes(usize),
+
+    /// Every bit of every byte is moved to the plane of its bit position, from the most
+    /// significant bit down. Every plane packs 8 bytes into one, and the bytes that don't fill a
+    /// complete group of 8 are stored unchanged at the end.
+    Bits
+}
+
+impl fmt::Display for Interleave {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Interleave::Bytes(stride) => write!(f, "stride {}", stride),
+            Interleave::Bits => write!(f, "bit planes")
+        }
+    }
+}
+
+/// Split interleaved data into separate streams using the given layout.
+///
+/// The output has the same length as the input, and [`interleave`] turns it back into the input.
+///
+/// # Panics
+///
+/// Panics if the stride of [`Interleave::Bytes`] is zero.
+pub fn deinterleave(input: &[u8], layout: Interleave) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+
+    match layout {
+        Interleave::Bytes(stride) => {
+            assert!(stride > 0, "stride must be at least 1");
+
+            for start in 0..stride.min(input.len()) {
+                output.extend(input[start..].iter().step_by(stride));
+            }
+        },
+        Interleave::Bits => {
+            let groups = input.chunks_exact(8);
+            let tail = groups.remainder();
+
+            for bit in (0..8).rev() {
+                output.extend(groups.clone().map(|group| {
+                    group.iter().fold(0, |value, byte| (value << 1) | ((byte >> bit) & 1))
+                }));
+            }
+
+            output.extend_from_slice(tail);
+        }
+    }
+
+    output
+}
+
+/// Interleave streams that were split using [`deinterleave`] with the same layout again.
+///
+/// # Panics
+///
+/// Panics if the stride of [`Interleave::Bytes`] is zero.
+pub fn interleave(input: &[u8], layout: Interleave) -> Vec<u8> {
+    let mut output = vec![0; input.len()];
+
+    match layout {
+        Interleave::Bytes(stride) => {
+            assert!(stride > 0, "stride must be at least 1");
+
+            let mut bytes = input.iter();
+
+            for start in 0..stride.min(input.len()) {
+                for (target, byte) in output[start..].iter_mut().step_by(stride).zip(&mut bytes) {
+                    *target = *byte;
+                }
+            }
+        },
+        Interleave::Bits => {
+            let plane_len = input.len() / 8;
+
+            for (index, bit) in (0..8).rev().enumerate() {
+                let plane = &input[index * plane_len..(index + 1) * plane_len];
+
+                for (group, value) in output.chunks_exact_mut(8).zip(plane) {
+                    for (i, byte) in group.iter_mut().enumerate() {
+                        *byte |= ((value >> (7 - i)) & 1) << bit;
+                    }
+                }
+            }
+
+            output[plane_len * 8..].copy_from_slice(&input[plane_len * 8..]);
+        }
+    }
+
+    output
+}
+
+/// Find the layout that makes the input compress best.
+///
+/// This compresses a sample from the start of the input in quick mode with every stride from 2 up
+/// to 16 and with the bit planes split, and returns the layout that produced the smallest output,
+/// or `None` if none of them beat the data as it is. The smallest stride wins a tie, since larger
+/// multiples of the best stride tend to compress about as well.
+pub fn detect_interleave(input: &[u8]) -> Option<Interleave> {
+    let sample = &input[..input.len().min(SAMPLE_LEN)];
+
+    let mut compressor = Compressor::new();
+    compressor.quick_mode(true);
+
+    let mut best_len = compressor.compress(sample).output.len();
+    let mut best = None;
+
+    let candidates = (2..=MAX_DETECTED_STRIDE).map(Interleave::Bytes).chain([Interleave::Bits]);
+
+    for layout in candidates {
+        let len = compressor.compress(&deinterleave(sample, layout)).output.len();
+
+        if len < best_len {
+            best_len = len;
+            best = Some(layout);
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Interleave, deinterleave, detect_interleave, interleave};
+
+    #[test]
+    fn round_trip() {
+        let input = std::fs::read("src/interleave.rs").unwrap();
+        let layouts = [Interleave::Bytes(1), Interleave::Bytes(3), Interleave::Bytes(16), Interleave::Bits];
+
+        for len in [0, 1, 7, 8, 9, 100, input.len()] {
+            for layout in layouts {
+                let split = deinterleave(&input[..len], layout);
+                assert_eq!(split.len(), len);
+                assert_eq!(interleave(&split, layout), &input[..len]);
+            }
+        }
+
+        assert_eq!(deinterleave(&[1, 2, 3, 4, 5], Interleave::Bytes(2)), [1, 3, 5, 2, 4]);
+        assert_eq!(deinterleave(&[0x80, 0, 0, 0, 0, 0, 0, 0x01, 9], Interleave::Bits), [0x80, 0, 0, 0, 0, 0, 0, 0x01, 9]);
+        assert_eq!(deinterleave(&[0xff, 0, 0, 0, 0, 0, 0, 0], Interleave::Bits), [0x80; 8]);
+    }
+
+    #[test]
+    fn detect() {
+        // Records of four fields that each repeat with a different period
+        let records: Vec<u8> = (0..4096u32).flat_map(|i| {
+            [(i % 251) as u8, (i / 3 % 97) as u8, (i % 7) as u8 | 0x40, (i / 5 % 61) as u8]
+        }).collect();
+
+        assert_eq!(detect_interleave(&records), Some(Interleave::Bytes(4)));
+
+        let text = std::fs::read("src/interleave.rs").unwrap();
+        assert_eq!(detect_interleave(&text), None);
+    }
+}
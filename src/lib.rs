@@ -1,3 +1,4 @@
+#![cfg_attr(not(feature = "std"), no_std)]
 #![warn(missing_docs)]
 
 //! A ZX0 compressor implementation for Rust.
@@ -10,9 +11,9 @@
 //! Rust implementation also offers thread-safety, meaning that files can now be compressed in
 //! parallel. Finally, this implementation is also free of memory leaks.
 //!
-//! To guarantee correctness the crate offers a sub-crate containing a Rust wrapper of the original
-//! C code. This wrapper is used as a reference in the crate's test suite to ensure that its output
-//! is 100% equivalent to the original implementation.
+//! To guarantee correctness the crate's workspace contains a sub-crate with a Rust wrapper of the
+//! original C code. The tests of this sub-crate use it as a reference to ensure that the output of
+//! this crate is 100% equivalent to the original implementation.
 //!
 //! The compressor can be used in two ways:
 //!
@@ -26,20 +27,107 @@
 //!
 //! Additionally, there is a wealth of information provided in the readme file of Einar Saukas'
 //! original implementation.
-
+//!
+//! Compressed data can be decompressed using the [`Decompressor`], which does not allocate any
+//! memory, and [`detect_classic_mode`] determines the file format of data from unknown sources.
+//! Disabling the default `std` feature leaves only the decompressor, which makes the
+//! crate usable in `#![no_std]` environments. Data in the older ZX7 format can be decompressed
+//! using [`decompress_zx7_into`], and data in the experimental dual-stream format using
+//! [`decompress_dual_into`].
+//!
+//! The optional `bytes` feature adds `Compressor::compress_bytes`, which returns the compressed
+//! data as a `bytes::Bytes` buffer without copying it, along with its delta and statistics.
+
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+mod bits;
+#[cfg(feature = "std")]
 mod compress;
+#[cfg(feature = "std")]
 mod compressor;
+#[cfg(test)]
+mod decompress;
+mod decompressor;
+mod dual;
+#[cfg(feature = "debug-dump")]
+mod dump;
+#[cfg(feature = "std")]
+mod entropy;
+mod error;
+#[cfg(feature = "std")]
+mod grow;
+#[cfg(feature = "std")]
+mod interleave;
+#[cfg(feature = "std")]
+mod live;
+#[cfg(test)]
+mod noise;
+#[cfg(feature = "std")]
 mod optimize;
+#[cfg(feature = "std")]
+mod parallel;
+#[cfg(feature = "std")]
+mod progress;
+#[cfg(feature = "std")]
+mod registers;
+#[cfg(feature = "std")]
+mod segments;
+#[cfg(feature = "std")]
+mod stream;
+mod transcode;
+#[cfg(feature = "std")]
+mod word;
+#[cfg(test)]
+mod z80;
+mod zx7;
 
 const INITIAL_OFFSET: usize = 1;
-const MAX_OFFSET_ZX0: usize = 32640;
-const MAX_OFFSET_ZX7: usize = 2176;
+/// The largest offset that the ZX0 format can encode.
+#[cfg(feature = "std")]
+pub const MAX_OFFSET_ZX0: usize = 32640;
+
+/// The largest offset that the quick mode uses, which is the largest offset of the ZX7 format.
+#[cfg(feature = "std")]
+pub const MAX_OFFSET_ZX7: usize = 2176;
 
+/// The highest effort level, which is the default and always finds the optimal parse.
+#[cfg(feature = "std")]
+pub const MAX_EFFORT: u8 = 8;
+
+#[cfg(feature = "std")]
 pub use compressor::{
     CompressionResult,
-    Compressor
+    CompressionStats,
+    Compressor,
+    Parse,
+    StreamResult,
+    Token,
+    TokenKind
 };
 
+pub use decompressor::{DecompressStatus, Decompressor, decompress_into, detect_classic_mode};
+#[cfg(feature = "std")]
+pub use decompressor::decompress_with_prefix;
+pub use dual::decompress_dual_into;
+#[cfg(feature = "std")]
+pub use dual::decompress_dual;
+#[cfg(feature = "std")]
+pub use entropy::{Entropy, entropy};
+pub use error::Error;
+#[cfg(feature = "std")]
+pub use interleave::{Interleave, deinterleave, detect_interleave, interleave};
+#[cfg(feature = "std")]
+pub use live::LiveStats;
+#[cfg(feature = "std")]
+pub use progress::ProgressExt;
+#[cfg(feature = "std")]
+pub use registers::{AY_REGISTERS, SID_REGISTERS, filter_registers, unfilter_registers};
+pub use transcode::transcode;
+pub use zx7::decompress_zx7_into;
+#[cfg(feature = "std")]
+pub use zx7::decompress_zx7;
+
 /// Compress the input slice to an output vector.
 ///
 /// This is a shortcut for:
@@ -49,94 +137,509 @@ pub use compressor::{
 /// ```
 ///
 /// For a more customized experience please see the [`Compressor`] struct.
+#[cfg(feature = "std")]
 pub fn compress(input: &[u8]) -> Vec<u8> {
     Compressor::new().compress(input).output
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Compressor;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    use std::sync::Arc;
+
+    use super::{Compressor, DecompressStatus, Decompressor, Error, LiveStats, TokenKind, MAX_OFFSET_ZX7};
+    use super::decompress::decompress;
+    use super::noise::noise;
+
+    #[test]
+    fn max_offset() {
+        let input = std::fs::read("src/lib.rs").unwrap();
+
+        // The quick mode is the same as limiting the offsets to its window
+        let quick = Compressor::new().quick_mode(true).compress(&input);
+        let result = Compressor::new().max_offset(MAX_OFFSET_ZX7).compress(&input);
+
+        assert_eq!(result.output, quick.output);
+        assert_eq!(Compressor::new().quick_mode(true).max_offset(5000).compress(&input).output, quick.output);
+
+        let result = Compressor::new().max_offset(100).record_tokens(true).compress(&input);
+        assert!(result.tokens.iter().all(|token| token.offset <= 100));
+
+        let mut memory = vec![0; input.len() + result.delta];
+        let source = memory.len() - result.output.len();
+        memory[source..].copy_from_slice(&result.output);
+
+        let (_, destination) = decompress(&mut memory, source, 0, false, true);
+        assert_eq!(&memory[..destination], input);
+    }
+
+    #[test]
+    fn effort() {
+        let input = std::fs::read("src/lib.rs").unwrap();
+        let optimal = Compressor::new().compress(&input);
+
+        // A pruned parse can never beat the optimal one, which is checked against the reference
+        // implementation in the reference crate
+        for effort in 0..8 {
+            let result = Compressor::new().effort(effort).compress(&input);
+            assert!(result.output.len() >= optimal.output.len());
+        }
+    }
+
+    #[test]
+    fn extreme_mode() {
+        let input = std::fs::read("src/live.rs").unwrap();
+
+        let optimal = Compressor::new().compress(&input);
+        let result = Compressor::new().extreme_mode(true).compress(&input);
+        assert!(result.output.len() <= optimal.output.len());
+
+        // Noise with short repeats at small offsets, on which the reference parse misses a cheaper
+        // encoding
+        let mut random = noise(2048, 140).into_iter();
+        let mut next = || random.next().unwrap() as usize;
+
+        let mut input = Vec::new();
+
+        while input.len() < 512 {
+            if input.len() > 16 && next() % 4 == 0 {
+                let offset = 1 + next() % 8;
+
+                for _ in 0..2 + next() % 4 {
+                    input.push(input[input.len() - offset]);
+                }
+            } else {
+                input.push(next() as u8);
+            }
+        }
+
+        let reference = Compressor::new().compress(&input);
+        let result = Compressor::new().extreme_mode(true).compress(&input);
+        assert!(result.output.len() < reference.output.len());
+    }
+
+    #[test]
+    fn max_literal_run() {
+        let input = std::fs::read("src/live.rs").unwrap();
+        let optimal = Compressor::new().record_tokens(true).compress(&input);
+        let longest = optimal.tokens.iter().filter(|token| token.kind == TokenKind::Literals).map(|token| token.length).max().unwrap();
+
+        // A limit that the optimal parse already stays within changes nothing
+        assert_eq!(Compressor::new().max_literal_run(longest).compress(&input).output, optimal.output);
+
+        for (max_literal_run, extreme_mode) in [(longest - 4, false), (longest - 4, true), (longest - 8, false)] {
+            let result = Compressor::new()
+                .max_literal_run(max_literal_run)
+                .extreme_mode(extreme_mode)
+                .record_tokens(true)
+                .compress(&input);
+
+            assert!(result.output.len() >= optimal.output.len());
+            assert!(result.tokens.iter().all(|token| token.kind != TokenKind::Literals || token.length <= max_literal_run));
+
+            let mut memory = vec![0; input.len() + result.delta];
+            let source = memory.len() - result.output.len();
+            memory[source..].copy_from_slice(&result.output);
+
+            let (_, destination) = decompress(&mut memory, source, 0, false, true);
+            assert_eq!(&memory[..destination], input);
+        }
+
+        // Bytes that all differ can't be split into short literal runs
+        let input = (0..=255).collect::<Vec<u8>>();
+        let result = Compressor::new().max_literal_run(16).try_compress(&input);
+        assert_eq!(result.err(), Some(Error::LiteralRunTooLong { max_literal_run: 16 }));
+        assert!(Compressor::new().max_literal_run(256).try_compress(&input).is_ok());
+    }
 
+    #[cfg(feature = "bytes")]
     #[test]
-    fn defaults() {
+    fn compress_bytes() {
+        let input = bytes::Bytes::from(std::fs::read("src/lib.rs").unwrap());
+        let result = Compressor::new().compress_bytes(&input);
+        let expected = Compressor::new().compress(&input);
+
+        assert_eq!(result.output, expected.output);
+        assert_eq!(result.delta, expected.delta);
+        assert_eq!(Compressor::new().skip(input.len()).try_compress_bytes(&input).map(|result| result.output), Err(Error::NothingToCompress { input_len: input.len(), skip: input.len() }));
+    }
+
+    #[test]
+    fn max_tokens() {
+        let input = std::fs::read("src/live.rs").unwrap();
+        let optimal = Compressor::new().record_tokens(true).compress(&input);
+
+        // A budget that the optimal parse already stays within changes nothing
+        assert_eq!(Compressor::new().max_tokens(optimal.tokens.len()).compress(&input).output, optimal.output);
+
+        for max_tokens in [optimal.tokens.len() * 3 / 4, optimal.tokens.len() / 2] {
+            let result = Compressor::new().max_tokens(max_tokens).record_tokens(true).compress(&input);
+
+            assert!(result.output.len() > optimal.output.len());
+            assert!(result.tokens.len() <= max_tokens);
+
+            let mut memory = vec![0; input.len() + result.delta];
+            let source = memory.len() - result.output.len();
+            memory[source..].copy_from_slice(&result.output);
+
+            let (_, destination) = decompress(&mut memory, source, 0, false, true);
+            assert_eq!(&memory[..destination], input);
+        }
+
+        // Text can't be parsed as a single literal run, since the optimizer ends literal runs at
+        // every offset that a byte repeats at
+        let result = Compressor::new().max_tokens(1).try_compress(&input[..200]);
+        assert!(matches!(result.err(), Some(Error::TokenBudgetExceeded { max_tokens: 1, .. })));
+    }
+
+    #[test]
+    fn max_input_len() {
+        let input = [1, 2, 3, 1, 2, 3, 1, 2];
+        let too_large = Error::InputTooLarge { input_len: 8, max_input_len: 7 };
+
+        let mut compressor = Compressor::new();
+        compressor.max_input_len(8);
+
+        assert_eq!(compressor.try_compress(&input).unwrap().output, Compressor::new().compress(&input).output);
+
+        compressor.max_input_len(7);
+
+        assert_eq!(compressor.try_compress(&input).err(), Some(too_large.clone()));
+        assert_eq!(compressor.try_parse(&input).err(), Some(too_large.clone()));
+        assert_eq!(compressor.try_compress_words(&input).err(), Some(too_large.clone()));
+        assert_eq!(compressor.try_compress_chained(&[&input[..4], &input]).err(), Some(too_large.clone()));
+        assert_eq!(compressor.try_compress_segments(&[&input[..4], &input[4..]]).err(), Some(too_large.clone()));
+        assert_eq!(compressor.try_compress_ranges(&input, &[0..4, 0..8]).err(), Some(too_large));
+        assert!(compressor.try_compress_ranges(&input, &[0..4, 1..8]).is_ok());
+    }
+
+    #[test]
+    fn segments() {
         let input = std::fs::read("src/lib.rs").unwrap();
+        let input = &input[..3000];
+        let segments = [&input[..300], &input[300..1000], &[], &input[1000..2999], &input[2999..]];
+
+        for (skip, backwards_mode) in [(0, false), (500, false), (1500, true)] {
+            let mut compressor = Compressor::new();
+            compressor.skip(skip).backwards_mode(backwards_mode).record_tokens(true);
 
-        let reference = reference::Compressor::new().compress(&input);
-        let result = Compressor::new().compress(&input);
+            let expected = compressor.compress(input);
+            let result = compressor.compress_segments(&segments);
+
+            assert_eq!(result.output, expected.output);
+            assert_eq!(result.delta, expected.delta);
+            assert_eq!(result.tokens, expected.tokens);
+        }
 
-        assert_eq!(result.output, reference.output);
-        assert_eq!(result.delta, reference.delta);
+        assert_eq!(
+            Compressor::new().skip(3).try_compress_segments(&[&[1, 2], &[], &[3]]).err(),
+            Some(Error::NothingToCompress { input_len: 3, skip: 3 })
+        );
     }
 
     #[test]
-    fn defaults_with_prefix() {
+    fn chunks() {
         let input = std::fs::read("src/lib.rs").unwrap();
+        let input = &input[..6000];
+
+        // A single chunk is parsed like the input as a whole
+        let expected = Compressor::new().skip(500).record_tokens(true).compress(input);
+        let result = Compressor::new().skip(500).record_tokens(true).chunk_len(input.len()).compress(input);
+
+        assert_eq!(result.output, expected.output);
+        assert_eq!(result.delta, expected.delta);
+        assert_eq!(result.tokens, expected.tokens);
+
+        let mut sizes = Vec::new();
+
+        for (skip, classic_mode, chain_chunks) in [(0, false, true), (500, true, true), (0, false, false)] {
+            let result = Compressor::new()
+                .skip(skip)
+                .classic_mode(classic_mode)
+                .chunk_len(700)
+                .chain_chunks(chain_chunks)
+                .compress(input);
 
-        // This may take a minute on a debug build
-        for skip in (0..input.len()).step_by(512) {
-            let reference = reference::Compressor::new().skip(skip).compress(&input);
-            unsafe { reference::reset(); }
+            let mut memory = vec![0; input.len() + result.delta];
+            let source = memory.len() - result.output.len();
+            memory[..skip].copy_from_slice(&input[..skip]);
+            memory[source..].copy_from_slice(&result.output);
 
-            let result = Compressor::new().skip(skip).compress(&input);
+            let (source, destination) = decompress(&mut memory, source, skip, false, !classic_mode);
 
-            assert_eq!(result.output, reference.output);
-            assert_eq!(result.delta, reference.delta);
+            assert_eq!(source, memory.len());
+            assert_eq!(&memory[..destination], input);
+
+            sizes.push(result.output.len());
         }
+
+        // Chunks that can't refer to each other compress worse
+        assert!(sizes[0] < sizes[2]);
+
+        let mut reversed = input.to_vec();
+        reversed.reverse();
+
+        let result = Compressor::new().backwards_mode(true).chunk_len(1000).compress(&reversed);
+        let mut output = vec![0; input.len()];
+
+        let status = Decompressor::new().backwards_mode(true).decompress(&result.output, &mut output);
+
+        assert_eq!(status, Ok(DecompressStatus::Finished { consumed: result.output.len(), len: input.len() }));
+        assert_eq!(output, reversed);
     }
 
     #[test]
-    fn backwards_mode() {
+    fn natural_order() {
         let input = std::fs::read("src/lib.rs").unwrap();
+        let input = &input[..3000];
+
+        let mut reversed = input.to_vec();
+        reversed.reverse();
+
+        let reverse = |mut output: Vec<u8>| {
+            output.reverse();
+            output
+        };
+
+        let mut compressor = Compressor::new();
+        compressor.backwards_mode(true).skip(200);
 
-        let reference = reference::Compressor::new().backwards_mode(true).compress(&input);
-        unsafe { reference::reset(); }
+        let expected = reverse(compressor.compress(&reversed).output);
 
-        let result = Compressor::new().backwards_mode(true).compress(&input);
+        compressor.natural_order(true);
 
-        assert_eq!(result.output, reference.output);
-        assert_eq!(result.delta, reference.delta);
+        assert_eq!(compressor.compress(input).output, expected);
+        assert_eq!(compressor.compress_segments(&[&input[..1000], &input[1000..]]).output, expected);
+
+        let parse = compressor.parse(input);
+        let mut output = vec![0; parse.output_len()];
+        compressor.encode_into(&parse, input, &mut output);
+
+        assert_eq!(output, expected);
+
+        // The blocks are passed in natural order, and are decompressed starting with the last one
+        let (first, second) = input.split_at(1800);
+        let mut first_reversed = first.to_vec();
+        first_reversed.reverse();
+
+        let expected = Compressor::new().backwards_mode(true).compress_chained(&[&reversed[..1200], &first_reversed]);
+        let results = compressor.compress_chained(&[first, second]);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].output, reverse(expected[1].output.clone()));
+        assert_eq!(results[1].output, reverse(expected[0].output.clone()));
+
+        // Without the backwards mode the setting has no effect
+        assert_eq!(Compressor::new().natural_order(true).compress(input).output, Compressor::new().compress(input).output);
     }
 
+    #[cfg(feature = "allocator-api2")]
     #[test]
-    fn backwards_mode_with_suffix() {
+    fn scratch_allocator() {
+        use std::alloc::Layout;
+        use std::ptr::NonNull;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use allocator_api2::alloc::{AllocError, Allocator, Global};
+
+        // An allocator that keeps track of the number of bytes that are currently allocated, and
+        // of the peak
+        struct Counting {
+            allocated: &'static AtomicUsize,
+            peak: &'static AtomicUsize
+        }
+
+        unsafe impl Allocator for Counting {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                let allocated = self.allocated.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+                self.peak.fetch_max(allocated, Ordering::Relaxed);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.allocated.fetch_sub(layout.size(), Ordering::Relaxed);
+                Global.deallocate(ptr, layout)
+            }
+        }
+
+        let allocated = Box::leak(Box::new(AtomicUsize::new(0)));
+        let peak = Box::leak(Box::new(AtomicUsize::new(0)));
+
         let input = std::fs::read("src/lib.rs").unwrap();
+        let input = &input[..2000];
+
+        let expected = Compressor::new().compress(input);
+        let result = Compressor::new().scratch_allocator(Counting { allocated, peak }).compress(input);
 
-        // This may take a minute on a debug build
-        for skip in (0..input.len()).step_by(512) {
-            let reference = reference::Compressor::new().backwards_mode(true).skip(skip).compress(&input);
-            unsafe { reference::reset(); }
+        assert_eq!(result.output, expected.output);
+
+        // Everything but the output comes from the allocator, and is freed again afterwards. The
+        // peak can be a little higher than reported, since growing a vector briefly holds both
+        // its old and its new allocation.
+        assert!(peak.load(Ordering::Relaxed) >= result.stats.peak_memory - result.output.capacity());
+        assert_eq!(allocated.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn stats() {
+        let result = Compressor::new().compress(&[0; 16]);
+
+        assert!(result.stats.arena_blocks > 1);
+        assert_eq!(result.stats.estimated_arena_blocks, 4096);
+        assert!(result.stats.peak_memory >= 4096 * 20);
+    }
 
-            let result = Compressor::new().backwards_mode(true).skip(skip).compress(&input);
+    #[test]
+    fn live_stats() {
+        let input = std::fs::read("src/lib.rs").unwrap();
+        let skip = 100;
+
+        let live_stats = Arc::new(LiveStats::new());
+        let mut last_input_bytes = 0;
+
+        let result = Compressor::new()
+            .skip(skip)
+            .live_stats(live_stats.clone())
+            .progress_callback(|_| {
+                // The counters never move backwards within a phase
+                assert!(live_stats.input_bytes() >= last_input_bytes);
+                last_input_bytes = live_stats.input_bytes();
+            })
+            .compress(&input);
+
+        assert_eq!(live_stats.input_bytes(), input.len() - skip);
+        assert_eq!(live_stats.arena_blocks(), result.stats.arena_blocks);
+        assert_eq!(live_stats.output_bytes(), result.output.len());
+    }
 
-            assert_eq!(result.output, reference.output);
-            assert_eq!(result.delta, reference.delta);
+    #[test]
+    fn tokens() {
+        let input = std::fs::read("src/lib.rs").unwrap();
+        let skip = 100;
+
+        let mut seen = Vec::new();
+        let result = Compressor::new()
+            .skip(skip)
+            .record_tokens(true)
+            .on_token(|token| seen.push(token.clone()))
+            .compress(&input);
+
+        let tokens = &result.tokens;
+        assert_eq!(&seen, tokens);
+
+        assert_eq!(tokens[0].kind, TokenKind::Literals);
+        assert_eq!(tokens[0].input_position, skip);
+        assert_eq!(tokens[0].output_position, 0);
+
+        for pair in tokens.windows(2) {
+            assert_eq!(pair[1].input_position, pair[0].input_position + pair[0].length);
+            assert!(pair[1].output_position >= pair[0].output_position);
+            assert!((pair[1].kind == TokenKind::Literals) == (pair[1].offset == 0));
         }
+
+        // Only the end marker and the padding of the last bit group are not covered by tokens
+        let bits = tokens.iter().map(|token| token.bits).sum::<usize>() + 18;
+        assert!(bits <= 8 * result.output.len() && bits + 8 > 8 * result.output.len());
+        assert_eq!(tokens.iter().map(|token| token.length).sum::<usize>(), input.len() - skip);
+
+        assert!(Compressor::new().compress(&input).tokens.is_empty());
+    }
+
+    #[test]
+    fn encode_into() {
+        let input = std::fs::read("src/lib.rs").unwrap();
+
+        let mut compressor = Compressor::new();
+        compressor.skip(100).backwards_mode(true);
+
+        let expected = compressor.compress(&input);
+        let parse = compressor.parse(&input);
+        assert_eq!(parse.output_len(), expected.output.len());
+
+        // Stale contents of the buffer must not leak into the output
+        let mut output = vec![0xff; parse.output_len()];
+        let result = compressor.encode_into(&parse, &input, &mut output);
+
+        assert_eq!(output, expected.output);
+        assert_eq!(result.delta, expected.delta);
+        assert!(result.output.is_empty());
     }
 
     #[test]
-    fn quick_mode() {
+    fn chained() {
         let input = std::fs::read("src/lib.rs").unwrap();
+        let blocks = input.chunks(3000).collect::<Vec<_>>();
+
+        let results = Compressor::new().compress_chained(&blocks);
+        assert_eq!(results.len(), blocks.len());
+
+        let mut previous: &[u8] = &[];
+
+        for (block, result) in blocks.iter().zip(&results) {
+            // The previous block is still resident right before the block being decompressed
+            let mut memory = previous.to_vec();
+            memory.resize(previous.len() + block.len(), 0);
+            memory.extend_from_slice(&result.output);
 
-        let reference = reference::Compressor::new().quick_mode(true).compress(&input);
-        unsafe { reference::reset(); }
+            let (_, destination) = decompress(&mut memory, previous.len() + block.len(), previous.len(), false, true);
 
-        let result = Compressor::new().quick_mode(true).compress(&input);
+            assert_eq!(destination, previous.len() + block.len());
+            assert_eq!(&memory[previous.len()..destination], *block);
 
-        assert_eq!(result.output, reference.output);
-        assert_eq!(result.delta, reference.delta);
+            previous = block;
+        }
+
+        // Using the previous block as a dictionary should help on repetitive data
+        let independent = blocks.iter().map(|block| Compressor::new().compress(block).output.len()).sum::<usize>();
+        assert!(results.iter().map(|result| result.output.len()).sum::<usize>() < independent);
+    }
+
+    #[test]
+    fn write_to() {
+        let result = Compressor::new().compress(b"Hello, hello, hello, world!");
+
+        let mut written = Vec::new();
+        result.write_to_writer(&mut written).unwrap();
+        assert_eq!(written, result.output);
+
+        let directory = std::env::temp_dir().join(format!("zx0-write-to-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        // Writing replaces an existing file without leaving the temporary file behind
+        let path = directory.join("hello.zx0");
+        std::fs::write(&path, b"stale data that is longer than the output").unwrap();
+        result.write_to(&path).unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), result.output);
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+
+        // Errors are returned instead of panicking
+        assert!(result.write_to(directory.join("missing").join("hello.zx0")).is_err());
+        assert!(result.write_to("").is_err());
+
+        std::fs::remove_dir_all(&directory).unwrap();
     }
 
     #[test]
-    fn classic_mode() {
+    fn ranges() {
         let input = std::fs::read("src/lib.rs").unwrap();
 
-        let reference = reference::Compressor::new().classic_mode(true).compress(&input);
-        unsafe { reference::reset(); }
+        // Alternate between large and small ranges, so that the reused allocations both grow and
+        // shrink, and include an overlapping range as well
+        let ranges = [0..2000, 2000..2001, 2500..2600, 500..3500, 3000..3300];
+        let results = Compressor::new().compress_ranges(&input, &ranges);
 
-        let result = Compressor::new().classic_mode(true).compress(&input);
+        for (range, result) in ranges.iter().zip(&results) {
+            let expected = Compressor::new().compress(&input[range.clone()]);
 
-        assert_eq!(result.output, reference.output);
-        assert_eq!(result.delta, reference.delta);
+            assert_eq!(result.output, expected.output);
+            assert_eq!(result.delta, expected.delta);
+        }
+
+        let result = Compressor::new().try_compress_ranges(&input, &[0..10, 0..input.len() + 1]);
+        assert_eq!(result.err(), Some(Error::RangeOutOfBounds { range: 0..input.len() + 1, input_len: input.len() }));
     }
 
     #[test]
@@ -153,4 +656,156 @@ mod tests {
 
         assert!(*called.borrow());
     }
+
+    #[test]
+    fn progress_callback_covers_encoding() {
+        let input = std::fs::read("src/lib.rs").unwrap();
+
+        for skip in [0, 512] {
+            let values = std::cell::RefCell::new(Vec::new());
+
+            Compressor::new().skip(skip).progress_callback(|progress| {
+                values.borrow_mut().push(progress);
+            }).compress(&input);
+
+            let values = values.into_inner();
+
+            assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
+            assert!(values[0] >= 0.0);
+            assert_eq!(*values.last().unwrap(), 1.0);
+
+            // The encoding reports the part of the progress after the share of the parse
+            let share = Compressor::new().skip(skip).parse_share(input.len());
+            assert!(share > 0.0 && share < 1.0);
+            assert!(values.iter().any(|&value| value > share && value < 1.0));
+        }
+    }
+
+    /// Runs of identical bytes with random lengths.
+    fn runs() -> impl Strategy<Value = Vec<u8>> {
+        vec((any::<u8>(), 1..300usize), 1..16).prop_map(|runs| {
+            runs.into_iter().flat_map(|(value, length)| std::iter::repeat_n(value, length)).collect()
+        })
+    }
+
+    /// A repeating pattern with a random period, sprinkled with a few random changes.
+    fn periodic() -> impl Strategy<Value = Vec<u8>> {
+        (vec(any::<u8>(), 1..160), 1..1200usize, vec((any::<prop::sample::Index>(), any::<u8>()), 0..8))
+            .prop_map(|(pattern, length, changes)| {
+                let mut input = pattern.iter().cycle().take(length).copied().collect::<Vec<_>>();
+
+                for (index, value) in changes {
+                    let index = index.index(input.len());
+                    input[index] = value;
+                }
+
+                input
+            })
+    }
+
+    /// A block of data that is repeated at a distance close to the quick mode window size.
+    fn near_window_repeat() -> impl Strategy<Value = Vec<u8>> {
+        (vec(any::<u8>(), 4..64), -8..8isize, any::<u64>()).prop_map(|(block, distance, seed)| {
+            let distance = (MAX_OFFSET_ZX7 as isize + distance) as usize;
+
+            // Cheap deterministic filler that doesn't compress well
+            let mut state = seed | 1;
+            let filler = std::iter::repeat_with(|| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            });
+
+            let mut input = block.clone();
+            input.extend(filler.take(distance - block.len()));
+            input.extend(&block);
+            input
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(48))]
+
+        /// Compressing and then decompressing in place must reproduce the input for every
+        /// combination of modes, with the compressed data placed exactly `delta` bytes past the
+        /// end of the decompressed data.
+        #[test]
+        fn round_trip_in_place(
+            input in prop_oneof![runs(), periodic(), near_window_repeat()],
+            skip_fraction in 0.0..0.5f64,
+            quick_mode: bool,
+            backwards_mode: bool,
+            classic_mode: bool,
+            extreme_mode: bool
+        ) {
+            let skip = (input.len() as f64 * skip_fraction) as usize;
+
+            let result = Compressor::new()
+                .skip(skip)
+                .quick_mode(quick_mode)
+                .backwards_mode(backwards_mode)
+                .classic_mode(classic_mode)
+                .extreme_mode(extreme_mode)
+                .compress(&input);
+
+            // Backwards streams are decoded front-to-back here as well, so that the same memory
+            // layout applies to both directions. Incompressible data can't overlap the prefix.
+            let mut memory = vec![0; (input.len() + result.delta).max(skip + result.output.len())];
+            let source = memory.len() - result.output.len();
+            memory[..skip].copy_from_slice(&input[..skip]);
+            memory[source..].copy_from_slice(&result.output);
+
+            let invert_mode = !classic_mode && !backwards_mode;
+            let (source, destination) = decompress(&mut memory, source, skip, backwards_mode, invert_mode);
+
+            prop_assert_eq!(source, memory.len());
+            prop_assert_eq!(destination, input.len());
+            prop_assert_eq!(&memory[..destination], &input[..]);
+        }
+
+        /// The fallible methods must return an error instead of panicking for any combination of
+        /// input and options, including empty input and skips at or past the end of the input.
+        #[test]
+        fn fallible_never_panics(
+            input in vec(any::<u8>(), 0..48),
+            other in vec(any::<u8>(), 0..48),
+            skip in 0..64usize,
+            quick_mode: bool,
+            backwards_mode: bool,
+            classic_mode: bool,
+            effort in 0..16u8
+        ) {
+            let mut compressor = Compressor::new();
+
+            compressor
+                .skip(skip)
+                .quick_mode(quick_mode)
+                .backwards_mode(backwards_mode)
+                .classic_mode(classic_mode)
+                .effort(effort);
+
+            let nothing_to_compress = Error::NothingToCompress { input_len: input.len(), skip };
+
+            match compressor.try_compress(&input) {
+                Ok(result) => prop_assert!(skip < input.len() && !result.output.is_empty()),
+                Err(err) => prop_assert_eq!(err, nothing_to_compress.clone())
+            }
+
+            match compressor.try_parse(&input) {
+                Ok(parse) => {
+                    let result = compressor.try_encode(&parse, &other);
+                    prop_assert_eq!(result.is_ok(), other.len() == input.len());
+
+                    let mut output = vec![0; other.len()];
+                    let result = compressor.try_encode_into(&parse, &input, &mut output);
+                    prop_assert_eq!(result.is_ok(), other.len() == parse.output_len());
+                },
+                Err(err) => prop_assert_eq!(err, nothing_to_compress)
+            }
+
+            let results = compressor.try_compress_chained(&[&input, &other]);
+            prop_assert_eq!(results.is_ok(), !input.is_empty() && !other.is_empty());
+        }
+    }
 }
@@ -0,0 +1,58 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counters that are updated while a [`Compressor`](crate::Compressor) is running, and that can be
+/// polled from other threads to show that compression is making progress.
+///
+/// The counters are shared by wrapping them in an [`Arc`](std::sync::Arc) and passing a clone to
+/// [`Compressor::live_stats`](crate::Compressor::live_stats). They describe the operation that is
+/// currently running, or the last one that completed. The input counters are reset when parsing
+/// starts, and the output counter is reset when encoding starts. All counters are updated
+/// periodically rather than for every byte, so intermediate values lag slightly behind.
+///
+/// ```
+/// use std::sync::Arc;
+/// use zx0::{Compressor, LiveStats};
+///
+/// let live_stats = Arc::new(LiveStats::new());
+/// let result = Compressor::new().live_stats(live_stats.clone()).compress(&[0; 1000]);
+///
+/// assert_eq!(live_stats.input_bytes(), 1000);
+/// assert_eq!(live_stats.output_bytes(), result.output.len());
+/// ```
+#[derive(Debug, Default)]
+pub struct LiveStats {
+    input_bytes: AtomicUsize,
+    arena_blocks: AtomicUsize,
+    output_bytes: AtomicUsize
+}
+
+impl LiveStats {
+    /// Create a new set of counters that are all zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of input bytes that the optimizer has processed, not including skipped bytes.
+    pub fn input_bytes(&self) -> usize {
+        self.input_bytes.load(Ordering::Relaxed)
+    }
+
+    /// The number of blocks that are allocated in the optimizer's block arena.
+    pub fn arena_blocks(&self) -> usize {
+        self.arena_blocks.load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes of compressed data that the encoder has written.
+    pub fn output_bytes(&self) -> usize {
+        self.output_bytes.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn update_input(&self, input_bytes: usize, arena_blocks: usize) {
+        self.input_bytes.store(input_bytes, Ordering::Relaxed);
+        self.arena_blocks.store(arena_blocks, Ordering::Relaxed);
+    }
+
+    pub(crate) fn update_output(&self, output_bytes: usize) {
+        self.output_bytes.store(output_bytes, Ordering::Relaxed);
+    }
+}
@@ -1,164 +1,2557 @@
+use std::cell::Cell;
 use std::env;
 use std::fs;
 use std::fs::File;
-use std::io::{ErrorKind, Write};
+use std::io::{ErrorKind, IsTerminal, Read, Write};
+use std::ops::Range;
+use std::panic;
+use std::path::{Path, PathBuf};
 use std::process;
-use zx0::Compressor;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use zx0::{deinterleave, detect_interleave, entropy, filter_registers, CompressionResult, Compressor, Error, Interleave, MAX_EFFORT, MAX_OFFSET_ZX0, Parse, Token, TokenKind, AY_REGISTERS, SID_REGISTERS};
+use zx0::batch::Config;
+
+mod compare;
+mod convert;
+mod extract;
+#[cfg(feature = "image")]
+mod graphics;
+mod grow;
+mod noise;
+mod profile;
+mod report;
+mod scan;
+mod selftest;
+mod sha256;
+mod vectors;
+
+use compare::{compare_modes, format_comparison};
+use convert::{convert_file, format_from_extension, Format};
+use extract::{extract, ExtractSettings};
+#[cfg(feature = "image")]
+use graphics::{convert_image, PixelFormat};
+use profile::{enable_counting, format_profile, Profile};
+use report::report;
+use scan::{format_candidates, scan, ScanSettings};
+use selftest::selftest;
+use sha256::sha256_hex;
+use vectors::write_vectors;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-fn usage(program_name: String) -> ! {
-    eprintln!("Usage: {} [OPTIONS] INPUT [OUTPUT]", program_name.rsplit('/').next().unwrap());
-    eprintln!();
-    eprintln!("Options:");
-    eprintln!("    -h, --help         Display this message");
-    eprintln!("    -V, --version      Print version info and exit");
-    eprintln!("    -f, --force        Force overwrite of output file");
-    eprintln!("    -c, --classic      Classic file format (v1.*)");
-    eprintln!("    -b, --backwards    Compress backwards");
-    eprintln!("    -q, --quick        Quick non-optimal compression");
-    eprintln!("    -Q, --quiet        Do not show any progress or summary information");
-    eprintln!("    -s, --skip AMOUNT  Skip AMOUNT bytes of input data");
+/// The exit statuses of the command line tool. These are part of its interface, so existing
+/// values must never change.
+#[derive(Clone, Copy)]
+enum Status {
+    Success = 0,
+    Failed = 1,
+    Skipped = 2,
+    Usage = 3,
+    MissingInput = 4,
+    OutputExists = 5,
+    VerificationFailed = 6,
+    OverBudget = 7,
+    Internal = 8,
+    Cancelled = 130
+}
+
+impl Status {
+    fn exit(self) -> ! {
+        process::exit(self as i32)
+    }
+}
+
+/// An error that prevented a single file from being compressed.
+struct Failure {
+    status: Status,
+    message: String
+}
+
+impl Failure {
+    fn new(status: Status, message: String) -> Self {
+        Self { status, message }
+    }
+}
+
+fn usage(program_name: String, status: Status) -> ! {
+    let program_name = program_name.rsplit('/').next().unwrap().to_string();
+
+    eprintln!("Usage: {} [OPTIONS] INPUT [OUTPUT]", program_name);
+    eprintln!("       {} [OPTIONS] --batch INPUT...", program_name);
+    eprintln!("       {} selftest", program_name);
+    eprintln!("       {} report [REPORT OPTIONS] DIRECTORY", program_name);
+    eprintln!("       {} vectors [-f] DIRECTORY", program_name);
+    eprintln!("       {} convert [CONVERT OPTIONS] INPUT...", program_name);
+    eprintln!("       {} scan [SCAN OPTIONS] FILE", program_name);
+    eprintln!("       {} extract [EXTRACT OPTIONS] --at OFFSET FILE [OUTPUT]", program_name);
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("    -h, --help         Display this message");
+    eprintln!("    -V, --version      Print version info and exit");
+    eprintln!("    -f, --force        Force overwrite of output file");
+    eprintln!("    -n, --dry-run      Compress and show the resulting sizes, but do not write any files");
+    eprintln!("    -B, --batch        Compress every INPUT (recursing into directories) to INPUT.zx0");
+    eprintln!("        --files-from LIST");
+    eprintln!("                       Compress the files listed in LIST (- for stdin) like --batch,");
+    eprintln!("                       one path per line or separated by NUL characters");
+    eprintln!("    -k, --keep-going   Continue with the next file when a file can't be compressed, and");
+    eprintln!("                       exit with status 1 after listing all failures");
+    eprintln!("        --manifest FILE");
+    eprintln!("                       Write the sizes and SHA-256 of every output file to FILE");
+    eprintln!("        --log-csv FILE Append a row with the sizes, options and duration of every");
+    eprintln!("                       output file to FILE");
+    eprintln!("        --max-size SIZE");
+    eprintln!("                       Fail with status 7 if an output file is larger than SIZE");
+    eprintln!("        --budgets FILE Read per-file size budgets from FILE, which overrule --max-size.");
+    eprintln!("                       Every line holds a SIZE and an INPUT path, separated by");
+    eprintln!("                       whitespace. Empty lines and lines starting with # are ignored.");
+    eprintln!("    -c, --classic      Classic file format (v1.*)");
+    eprintln!("    -b, --backwards    Compress backwards");
+    eprintln!("    -q, --quick        Quick non-optimal compression");
+    eprintln!("        --compare-modes");
+    eprintln!("                       Compress with every combination of -q, -b and -c and show a");
+    eprintln!("                       table of the sizes and deltas, without writing any files");
+    eprintln!("        --max-offset SIZE");
+    eprintln!("                       Only use match offsets up to SIZE (at most 32640), for");
+    eprintln!("                       decompressors with a restricted window");
+    eprintln!("        --max-literal-run SIZE");
+    eprintln!("                       Limit literal runs to SIZE bytes, for decompressors that buffer");
+    eprintln!("                       them. Fails if the input has a longer stretch that can't be matched");
+    eprintln!("        --max-tokens COUNT");
+    eprintln!("                       Limit the number of literal runs and matches to COUNT, trading");
+    eprintln!("                       ratio for a bounded decoding time. Fails if no parse fits");
+    eprintln!("    -e, --effort LEVEL Compression effort from 0 (fastest) to {} (optimal)", MAX_EFFORT);
+    eprintln!("    -x, --extreme      Search harder for a smaller parse than the original implementation");
+    eprintln!("                       finds, so the output is no longer identical to it");
+    eprintln!("    -Q, --quiet        Do not show any progress or summary information");
+    eprintln!("        --progress MODE");
+    eprintln!("                       Progress display on stderr: fancy (default), plain, machine or");
+    eprintln!("                       none. Machine mode prints PROGRESS FILE PERCENT lines for scripts");
+    eprintln!("        --stats        Show compression statistics after the summary");
+    eprintln!("        --profile      Show the time and allocations of every phase after the summary");
+    eprintln!("        --summary-format TEMPLATE");
+    eprintln!("                       Print the summary of every file using TEMPLATE, in which {{input}},");
+    eprintln!("                       {{output}}, {{in_size}}, {{out_size}}, {{ratio}}, {{delta}} and {{ms}}");
+    eprintln!("                       are replaced by their values, and {{{{ by a literal brace");
+    eprintln!("        --verify       Read back every output file and check that it was written");
+    eprintln!("                       correctly");
+    eprintln!("        --min-savings PERCENT");
+    eprintln!("                       Do not write an output file (and exit with status 2) if");
+    eprintln!("                       compression saves less than PERCENT of the input size");
+    eprintln!("        --dump-tokens FORMAT");
+    eprintln!("                       Write the token stream to OUTPUT.tokens.FORMAT (json or csv)");
+    eprintln!("        --dump-dot     Write a Graphviz graph of the parse to OUTPUT.dot");
+    eprintln!("    -s, --skip AMOUNT  Skip AMOUNT bytes of input data");
+    #[cfg(feature = "image")]
+    {
+        eprintln!("        --image FORMAT Decode every INPUT as a PNG or BMP image and convert it to");
+        eprintln!("                       linear (8 bits per pixel), planar4 (4 bit planes per row) or");
+        eprintln!("                       zx (ZX Spectrum bitmap and attributes) before compressing");
+    }
+    eprintln!("        --deinterleave LAYOUT");
+    eprintln!("                       Split interleaved records into separate streams before");
+    eprintln!("                       compressing, using a stride of LAYOUT bytes, bits to split the");
+    eprintln!("                       bit planes, or auto to pick whichever compresses a sample best");
+    eprintln!("        --registers COUNT");
+    eprintln!("                       Filter a sound chip register dump with frames of COUNT registers");
+    eprintln!("                       (or ay for 14, sid for 25) into per-register frame deltas");
+    eprintln!("        --blocks SIZE  Split the input into blocks of SIZE bytes that are written to");
+    eprintln!("                       OUTPUT.000, OUTPUT.001, etc., each using the previous block");
+    eprintln!("                       as its dictionary");
+    eprintln!("        --chunks SIZE  Parse the input in chunks of SIZE bytes on all processor cores,");
+    eprintln!("                       which is much faster on large files but compresses slightly");
+    eprintln!("                       worse");
+    eprintln!("        --length-prefix KIND");
+    eprintln!("                       Write the compressed length, the decompressed length or both");
+    eprintln!("                       (compressed, decompressed or both) as little-endian 16-bit");
+    eprintln!("                       words in front of every output file");
+    eprintln!();
+    eprintln!("The selftest command compresses a set of built-in test vectors and checks that the");
+    eprintln!("output matches exactly, and that it decompresses again. To compress a file named");
+    eprintln!("selftest, use ./selftest instead.");
+    eprintln!();
+    eprintln!("The report command compresses every file under DIRECTORY without writing anything, and");
+    eprintln!("shows the total sizes for every file extension and subdirectory, followed by the files");
+    eprintln!("that compress the least.");
+    eprintln!();
+    eprintln!("Report options:");
+    eprintln!("    -c, -q, -e LEVEL   Compress like the options above");
+    eprintln!("        --worst N      Number of least compressible files to list (default 10)");
+    eprintln!();
+    eprintln!("The vectors command writes a suite of test inputs to DIRECTORY, together with their");
+    eprintln!("compressed outputs in every combination of -c, -b and -q and an index.json that");
+    eprintln!("describes them, for testing other decompressors. Existing files are only overwritten");
+    eprintln!("with -f.");
+    eprintln!();
+    eprintln!("The convert command decompresses every INPUT in the ZX7 format (.zx7 files) or the");
+    eprintln!("classic format (any other file) and compresses it again to INPUT.zx0, replacing the");
+    eprintln!("extension of INPUT. Classic .zx0 files are converted in place.");
+    eprintln!();
+    eprintln!("Convert options:");
+    eprintln!("    -c, -q, -e LEVEL, -x, -f, -Q, -k");
+    eprintln!("                       Compress and handle files like the options above");
+    eprintln!("    -b, --backwards    Decompress classic input backwards, and compress backwards");
+    eprintln!("        --from FORMAT  Read every INPUT as zx7 or classic, regardless of its extension");
+    eprintln!("        --max-len SIZE Fail if an INPUT decompresses to more than SIZE bytes");
+    eprintln!("                       (default 16m)");
+    eprintln!();
+    eprintln!("The scan command looks for plausible ZX0 streams inside FILE, such as a ROM or a");
+    eprintln!("snapshot, and lists their offsets, lengths and end marker positions.");
+    eprintln!();
+    eprintln!("Scan options:");
+    eprintln!("    -c, --classic      Look for streams in the classic file format");
+    eprintln!("    -b, --backwards    Look for streams that were compressed backwards");
+    eprintln!("        --min-len SIZE Only list streams that decompress to at least SIZE bytes");
+    eprintln!("                       (default 64)");
+    eprintln!("        --max-len SIZE Only list streams that decompress to at most SIZE bytes");
+    eprintln!("                       (default 64k)");
+    eprintln!();
+    eprintln!("The extract command decompresses the stream that starts at OFFSET inside FILE, and");
+    eprintln!("writes the data to OUTPUT, or to FILE.OFFSET when OUTPUT is omitted. Offsets are");
+    eprintln!("those listed by the scan command, which is the last byte of backwards streams.");
+    eprintln!();
+    eprintln!("Extract options:");
+    eprintln!("        --at OFFSET    Decompress the stream that starts at OFFSET");
+    eprintln!("        --length SIZE  Fail if the stream is longer than SIZE bytes");
+    eprintln!("    -c, --classic      Decompress a stream in the classic file format");
+    eprintln!("    -d, --detect       Detect whether a forward stream uses the classic file format");
+    eprintln!("    -b, --backwards    Decompress a stream that was compressed backwards");
+    eprintln!("    -f, --force        Force overwrite of output file");
+    eprintln!("        --max-len SIZE Fail if the stream decompresses to more than SIZE bytes");
+    eprintln!("                       (default 16m)");
+    eprintln!();
+    eprintln!("Sizes can be given in decimal (16384), hexadecimal (0x4000) or kilobytes (16k).");
+    eprintln!();
+    eprintln!("Exit status:");
+    eprintln!("    0    All files were compressed");
+    eprintln!("    1    Compression failed or an output file could not be written, a test vector");
+    eprintln!("         failed (selftest, vectors), or a file could not be compressed (report) or");
+    eprintln!("         decompressed (convert, extract)");
+    eprintln!("    2    A file was skipped because of --min-savings");
+    eprintln!("    3    Invalid command line arguments");
+    eprintln!("    4    An input file or file list is missing or can't be read");
+    eprintln!("    5    An output file already exists and --force was not specified");
+    eprintln!("    6    An output file does not match the compressed data (--verify)");
+    eprintln!("    7    An output file exceeds its size budget (--max-size or --budgets)");
+    eprintln!("    8    Internal error, which is a bug that should be reported");
+    eprintln!("    130  Cancelled using Ctrl-C");
+    eprintln!();
+    eprintln!("With --keep-going, the status of the first failed file is used.");
+
+    status.exit();
+}
+
+fn version() -> ! {
+    eprintln!("zx0-rs {}\nBased on ZX0 v2.2 by Einar Saukas", VERSION);
+    Status::Success.exit();
+}
+
+// Parse a size value, which may be decimal, hexadecimal with a 0x prefix, or carry a k or m suffix
+// for kilobytes or megabytes
+fn parse_size(argument: &str) -> Option<usize> {
+    let (digits, multiplier) = match argument.chars().last()? {
+        'k' | 'K' => (&argument[..argument.len() - 1], 1024),
+        'm' | 'M' => (&argument[..argument.len() - 1], 1024 * 1024),
+        _ => (argument, 1)
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        usize::from_str_radix(hex, 16).ok()?
+    } else {
+        digits.parse::<usize>().ok()?
+    };
+
+    value.checked_mul(multiplier)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ProgressMode {
+    Fancy,
+    Plain,
+    Machine,
+    None
+}
+
+fn parse_progress_mode(argument: &str) -> ProgressMode {
+    match argument {
+        "fancy" => ProgressMode::Fancy,
+        "plain" => ProgressMode::Plain,
+        "machine" => ProgressMode::Machine,
+        "none" => ProgressMode::None,
+        _ => {
+            eprintln!("error: progress mode must be fancy, plain, machine or none");
+            Status::Usage.exit();
+        }
+    }
+}
+
+// Determine the width of the console, falling back to the traditional 80 columns if it's unknown
+fn console_width() -> usize {
+    env::var("COLUMNS").ok()
+        .and_then(|columns| columns.parse::<usize>().ok())
+        .filter(|&columns| columns > 0)
+        .unwrap_or(80)
+}
+
+// Overwrite the current console line using only a carriage return, which unlike ANSI escape
+// sequences is understood by every console. The text is kept shy of the last column, because
+// some consoles (most notably on Windows) wrap the cursor as soon as that column is written.
+fn redraw_line(text: &str, width: usize) {
+    let text = &text[..text.len().min(width - 1)];
+    eprint!("\r{:width$}\r{}", "", text, width = width - 1);
+}
+
+#[derive(Clone, Copy)]
+enum TokenFormat {
+    Json,
+    Csv
+}
+
+impl TokenFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            TokenFormat::Json => "json",
+            TokenFormat::Csv => "csv"
+        }
+    }
+}
+
+/// How the input is split into streams before it is compressed.
+#[derive(Clone, Copy)]
+enum DeinterleaveMode {
+    Fixed(Interleave),
+    Auto
+}
+
+impl DeinterleaveMode {
+    fn name(self) -> String {
+        match self {
+            DeinterleaveMode::Fixed(Interleave::Bytes(stride)) => stride.to_string(),
+            DeinterleaveMode::Fixed(Interleave::Bits) => "bits".to_string(),
+            DeinterleaveMode::Auto => "auto".to_string()
+        }
+    }
+}
+
+/// The lengths that are written in front of the compressed data.
+#[derive(Clone, Copy)]
+enum LengthPrefix {
+    Compressed,
+    Decompressed,
+    Both
+}
+
+impl LengthPrefix {
+    fn name(self) -> &'static str {
+        match self {
+            LengthPrefix::Compressed => "compressed",
+            LengthPrefix::Decompressed => "decompressed",
+            LengthPrefix::Both => "both"
+        }
+    }
+}
+
+// Build the length prefix of an output file, with the compressed length before the decompressed
+// length when both are included. Fails if a length doesn't fit in 16 bits.
+fn format_length_prefix(
+    length_prefix: Option<LengthPrefix>,
+    compressed_len: usize,
+    decompressed_len: usize,
+    output_filename: &str
+) -> Result<Vec<u8>, Failure> {
+    let lengths: &[(&str, usize)] = match length_prefix {
+        None => &[],
+        Some(LengthPrefix::Compressed) => &[("compressed", compressed_len)],
+        Some(LengthPrefix::Decompressed) => &[("decompressed", decompressed_len)],
+        Some(LengthPrefix::Both) => &[("compressed", compressed_len), ("decompressed", decompressed_len)]
+    };
+
+    let mut result = Vec::new();
+
+    for &(name, len) in lengths {
+        let value = u16::try_from(len).map_err(|_| {
+            Failure::new(Status::Failed, format!("{} length {} of output file {} does not fit in the 16-bit length prefix", name, len, output_filename))
+        })?;
+
+        result.extend_from_slice(&value.to_le_bytes());
+    }
+
+    Ok(result)
+}
+
+fn token_kind_name(kind: TokenKind) -> &'static str {
+    match kind {
+        TokenKind::Literals => "literals",
+        TokenKind::LastOffset => "last_offset",
+        TokenKind::NewOffset => "new_offset"
+    }
+}
+
+fn format_tokens(tokens: &[Token], format: TokenFormat) -> String {
+    let mut result = String::new();
+
+    match format {
+        TokenFormat::Json => {
+            result.push_str("[\n");
+
+            for (i, token) in tokens.iter().enumerate() {
+                result.push_str(&format!(
+                    "  {{\"kind\": \"{}\", \"offset\": {}, \"length\": {}, \"bits\": {}, \"input_position\": {}, \"output_position\": {}}}{}\n",
+                    token_kind_name(token.kind),
+                    token.offset,
+                    token.length,
+                    token.bits,
+                    token.input_position,
+                    token.output_position,
+                    if i + 1 < tokens.len() { "," } else { "" }
+                ));
+            }
+
+            result.push_str("]\n");
+        },
+        TokenFormat::Csv => {
+            result.push_str("kind,offset,length,bits,input_position,output_position\n");
+
+            for token in tokens {
+                result.push_str(&format!(
+                    "{},{},{},{},{},{}\n",
+                    token_kind_name(token.kind),
+                    token.offset,
+                    token.length,
+                    token.bits,
+                    token.input_position,
+                    token.output_position
+                ));
+            }
+        }
+    }
+
+    result
+}
+
+// Format a short, escaped preview of the bytes covered by a token for use in a DOT label
+fn dot_preview(bytes: &[u8]) -> String {
+    let mut result = String::new();
+
+    for &byte in bytes.iter().take(16) {
+        match byte {
+            b'"' | b'\\' => result.push_str(&format!("\\{}", byte as char)),
+            b' '..=b'~' => result.push(byte as char),
+            _ => result.push_str(&format!("\\\\x{:02x}", byte))
+        }
+    }
+
+    if bytes.len() > 16 {
+        result.push_str("...");
+    }
+
+    result
+}
+
+fn format_dot(tokens: &[Token], input: &[u8]) -> String {
+    let mut result = String::from("digraph parse {\n    rankdir=LR;\n    node [shape=circle];\n");
+
+    for token in tokens {
+        let bytes = &input[token.input_position..token.input_position + token.length];
+
+        let label = match token.kind {
+            TokenKind::Literals => format!("literals x{}", token.length),
+            TokenKind::LastOffset => format!("last offset {} x{}", token.offset, token.length),
+            TokenKind::NewOffset => format!("new offset {} x{}", token.offset, token.length)
+        };
+
+        result.push_str(&format!(
+            "    {} -> {} [label=\"{}\\n{} bits\\n\\\"{}\\\"\"{}];\n",
+            token.input_position,
+            token.input_position + token.length,
+            label,
+            token.bits,
+            dot_preview(bytes),
+            if token.kind == TokenKind::Literals { ", style=dashed" } else { "" }
+        ));
+    }
+
+    result.push_str("}\n");
+    result
+}
+
+// Read the peak resident set size of the process in bytes, where the platform provides it
+fn peak_resident_memory() -> Option<usize> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes = line.split_whitespace().nth(1)?.parse::<usize>().ok()?;
+
+    Some(kilobytes * 1024)
+}
+
+// Escape a string for use in a JSON document
+fn json_string(value: &str) -> String {
+    let mut result = String::from("\"");
+
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c)
+        }
+    }
+
+    result.push('"');
+    result
+}
+
+// Collect the files to compress in batch mode, walking directories recursively in sorted order.
+// Files that look like the output of an earlier run are left out.
+fn collect_batch_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        let entries = fs::read_dir(path).and_then(|entries| entries.collect::<Result<Vec<_>, _>>());
+
+        let mut entries = entries.unwrap_or_else(|err| {
+            eprintln!("error: could not read directory {}: {}", path.display(), err);
+            Status::MissingInput.exit();
+        });
+
+        entries.sort_by_key(|entry| entry.path());
+
+        for entry in entries {
+            let path = entry.path();
+
+            if path.is_dir() || path.extension().is_none_or(|extension| extension != "zx0") {
+                collect_batch_files(&path, files);
+            }
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+}
+
+// Split a file list into its paths. Paths are separated by NUL characters if there are any, like
+// the output of find -print0, and by newlines otherwise. Empty entries are ignored.
+fn split_file_list(list: &[u8]) -> Vec<String> {
+    let separator = if list.contains(&0) { 0 } else { b'\n' };
+
+    list.split(|&byte| byte == separator)
+        .map(|path| if separator == b'\n' { path.strip_suffix(b"\r").unwrap_or(path) } else { path })
+        .filter(|path| !path.is_empty())
+        .map(|path| String::from_utf8_lossy(path).into_owned())
+        .collect()
+}
+
+// Read a file list from the given file, or from stdin if the filename is -
+fn read_file_list(list_filename: &str) -> Vec<String> {
+    let list = if list_filename == "-" {
+        let mut list = Vec::new();
+        std::io::stdin().read_to_end(&mut list).map(|_| list)
+    } else {
+        fs::read(list_filename)
+    };
+
+    let list = list.unwrap_or_else(|err| {
+        eprintln!("error: could not read file list {}: {}", list_filename, err);
+        Status::MissingInput.exit();
+    });
+
+    split_file_list(&list)
+}
+
+// Parse a list of size budgets. Every line holds a size followed by the path of an input file, so
+// that paths can contain whitespace.
+fn parse_budgets(list: &str) -> Result<Vec<(PathBuf, usize)>, String> {
+    let mut budgets = Vec::new();
+
+    for (i, line) in list.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (size, path) = line.split_once(char::is_whitespace)
+            .ok_or_else(|| format!("line {}: expected a size and a path", i + 1))?;
+
+        let size = parse_size(size).ok_or_else(|| format!("line {}: invalid size {}", i + 1, size))?;
+        budgets.push((PathBuf::from(path.trim_start()), size));
+    }
+
+    Ok(budgets)
+}
+
+/// The settings that apply to every file processed by a single invocation.
+struct Options {
+    backwards_mode: bool,
+    classic_mode: bool,
+    quick_mode: bool,
+    max_offset: Option<usize>,
+    max_literal_run: Option<usize>,
+    max_tokens: Option<usize>,
+    effort: u8,
+    extreme_mode: bool,
+    forced_mode: bool,
+    dry_run: bool,
+    keep_going: bool,
+    verify_mode: bool,
+    quiet_mode: bool,
+    progress_mode: ProgressMode,
+    stats_mode: bool,
+    profile_mode: bool,
+    summary_format: Option<String>,
+    token_format: Option<TokenFormat>,
+    dot_mode: bool,
+    min_savings: Option<f32>,
+    skip: usize,
+    #[cfg(feature = "image")]
+    image_format: Option<PixelFormat>,
+    deinterleave: Option<DeinterleaveMode>,
+    registers: Option<usize>,
+    block_size: Option<usize>,
+    chunk_len: Option<usize>,
+    length_prefix: Option<LengthPrefix>
+}
+
+/// A compressed file as it is listed in the manifest.
+struct ManifestEntry {
+    input_filename: String,
+    output_filename: String,
+    input_size: usize,
+    output_size: usize,
+    delta: usize,
+    sha256: String,
+    max_size: Option<usize>,
+    duration: Duration
+}
+
+/// The value of an option that affects the compressed data.
+enum OptionValue {
+    Flag(bool),
+    Number(usize),
+    Name(String),
+    Unset
+}
+
+// List the options that affect the compressed data, under the names of their command line
+// arguments. The manifest and the log both record these, so that every option ends up in both.
+fn output_options(options: &Options) -> Vec<(&'static str, OptionValue)> {
+    let number = |value: Option<usize>| value.map_or(OptionValue::Unset, OptionValue::Number);
+    let name = |value: Option<String>| value.map_or(OptionValue::Unset, OptionValue::Name);
+
+    vec![
+        ("effort", OptionValue::Number(options.effort as usize)),
+        ("skip", OptionValue::Number(options.skip)),
+        ("classic", OptionValue::Flag(options.classic_mode)),
+        ("backwards", OptionValue::Flag(options.backwards_mode)),
+        ("quick", OptionValue::Flag(options.quick_mode)),
+        ("extreme", OptionValue::Flag(options.extreme_mode)),
+        ("max-offset", number(options.max_offset)),
+        ("max-literal-run", number(options.max_literal_run)),
+        ("max-tokens", number(options.max_tokens)),
+        #[cfg(feature = "image")]
+        ("image", name(options.image_format.map(|format| format.name().to_string()))),
+        ("deinterleave", name(options.deinterleave.map(DeinterleaveMode::name))),
+        ("registers", number(options.registers)),
+        ("blocks", number(options.block_size)),
+        ("chunks", number(options.chunk_len)),
+        ("length-prefix", name(options.length_prefix.map(|length_prefix| length_prefix.name().to_string())))
+    ]
+}
+
+fn format_manifest(options: &Options, entries: &[ManifestEntry]) -> String {
+    let fields = output_options(options).into_iter().map(|(name, value)| {
+        let value = match value {
+            OptionValue::Flag(value) => value.to_string(),
+            OptionValue::Number(value) => value.to_string(),
+            OptionValue::Name(value) => json_string(&value),
+            OptionValue::Unset => "null".to_string()
+        };
+
+        format!("{}: {}", json_string(&name.replace('-', "_")), value)
+    }).collect::<Vec<_>>();
+
+    let mut result = format!(
+        "{{\n  \"version\": {},\n  \"options\": {{{}}},\n  \"files\": [\n",
+        json_string(VERSION),
+        fields.join(", ")
+    );
+
+    for (i, entry) in entries.iter().enumerate() {
+        result.push_str(&format!(
+            "    {{\"input\": {}, \"output\": {}, \"input_size\": {}, \"output_size\": {}, \"delta\": {}, \"sha256\": \"{}\", \"max_size\": {}}}{}\n",
+            json_string(&entry.input_filename),
+            json_string(&entry.output_filename),
+            entry.input_size,
+            entry.output_size,
+            entry.delta,
+            entry.sha256,
+            entry.max_size.map_or("null".to_string(), |max_size| max_size.to_string()),
+            if i + 1 < entries.len() { "," } else { "" }
+        ));
+    }
+
+    result.push_str("  ]\n}\n");
+    result
+}
+
+// Format a point in time as an ISO 8601 timestamp in UTC
+fn format_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+    // Convert the number of days since the epoch to a date in the proleptic Gregorian calendar,
+    // using eras of 400 years that start on the 1st of March
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = era * 400 + year_of_era + (month <= 2) as u64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+// Quote a CSV field if it contains any characters that have a special meaning
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Describe the options that affect the compressed data in a single CSV field
+fn format_log_options(options: &Options) -> String {
+    let mut fields = Vec::new();
+
+    for (name, value) in output_options(options) {
+        match value {
+            OptionValue::Flag(true) => fields.push(name.to_string()),
+            OptionValue::Flag(false) | OptionValue::Unset => (),
+            OptionValue::Number(value) => fields.push(format!("{}={}", name, value)),
+            OptionValue::Name(value) => fields.push(format!("{}={}", name, value))
+        }
+    }
+
+    fields.join(" ")
+}
+
+const LOG_HEADER: &str = "timestamp,input,output,input_size,output_size,ratio,delta,options,duration_ms";
+
+fn format_log_row(timestamp: &str, options: &Options, entry: &ManifestEntry) -> String {
+    format!(
+        "{},{},{},{},{},{:.3},{},{},{}",
+        timestamp,
+        csv_field(&entry.input_filename),
+        csv_field(&entry.output_filename),
+        entry.input_size,
+        entry.output_size,
+        entry.input_size as f32 / entry.output_size as f32,
+        entry.delta,
+        format_log_options(options),
+        entry.duration.as_millis()
+    )
+}
+
+// Open a CSV log for appending, and write the header if the log is new
+fn open_log(log_filename: &str) -> Result<File, std::io::Error> {
+    let mut file = File::options().append(true).create(true).open(log_filename)?;
+
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{}", LOG_HEADER)?;
+    }
+
+    Ok(file)
+}
+
+// The minimum time between two machine-readable progress lines, except for the final one
+const MACHINE_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+// Format a machine-readable progress line. The percentage is the last field, so filenames that
+// contain spaces can be split off at the last space.
+fn format_machine_progress(filename: &str, percentage: u32) -> String {
+    format!("PROGRESS {} {}", filename, percentage)
+}
+
+// Clear the progress line, or report completion in machine mode
+fn finish_progress(progress_mode: ProgressMode, width: usize, filename: &str) {
+    match progress_mode {
+        ProgressMode::Fancy => redraw_line("", width),
+        ProgressMode::Machine => eprintln!("{}", format_machine_progress(filename, 100)),
+        ProgressMode::Plain | ProgressMode::None => ()
+    }
+}
+
+// The part of the progress range that the running phase maps to, which can be changed while the
+// progress callback is installed
+type ProgressPhase = Rc<Cell<(f32, f32)>>;
+
+// Install a progress callback for the given mode, and return the console width that it assumes
+// along with the phase that the progress is mapped to, which starts out as the whole range
+fn install_progress(compressor: &mut Compressor, progress_mode: ProgressMode, filename: &str) -> (usize, ProgressPhase) {
+    let width = console_width().max(2);
+    let phase = ProgressPhase::new(Cell::new((0.0, 1.0)));
+
+    // Map the progress of the running phase to its part of the range
+    let scale = {
+        let phase = phase.clone();
+
+        move |progress: f32| {
+            let (start, end) = phase.get();
+            start + (end - start) * progress
+        }
+    };
+
+    // Progress is written to stderr so that it doesn't end up in redirected output. Every mode
+    // checks for Ctrl-C, since the progress callback is called regularly while compressing.
+    match progress_mode {
+        ProgressMode::Fancy => {
+            let mut last_text = String::new();
+
+            compressor.progress_callback(move |progress| {
+                exit_if_cancelled();
+
+                let progress = scale(progress);
+                let text = format!("Progress: {:.1} %", progress * 100.0);
+
+                // Only redraw when the text changes, since console output can be slow
+                if text != last_text {
+                    redraw_line(&text, width);
+                    last_text = text;
+                }
+            });
+        },
+        ProgressMode::Plain => {
+            let mut last_percentage = None;
+
+            compressor.progress_callback(move |progress| {
+                exit_if_cancelled();
+
+                let progress = scale(progress);
+                let percentage = (progress * 100.0) as u32;
+
+                if last_percentage != Some(percentage) {
+                    eprintln!("Progress: {} %", percentage);
+                    last_percentage = Some(percentage);
+                }
+            });
+        },
+        ProgressMode::Machine => {
+            let filename = filename.to_string();
+            let mut last_line: Option<(u32, Instant)> = None;
+
+            compressor.progress_callback(move |progress| {
+                exit_if_cancelled();
+
+                let progress = scale(progress);
+                let percentage = (progress * 100.0) as u32;
+
+                // Completion is reported by finish_progress, because not every phase ends at 100
+                let due = percentage < 100 && match last_line {
+                    None => true,
+                    Some((last_percentage, time)) => last_percentage != percentage && time.elapsed() >= MACHINE_PROGRESS_INTERVAL
+                };
+
+                if due {
+                    eprintln!("{}", format_machine_progress(&filename, percentage));
+                    last_line = Some((percentage, Instant::now()));
+                }
+            });
+        },
+        ProgressMode::None => { compressor.progress_callback(|_| exit_if_cancelled()); }
+    }
+
+    (width, phase)
+}
+
+// Compress a file with every mode and print a table of the results, without writing any output
+fn compare_file(compressor: &mut Compressor, options: &Options, input_filename: &str) -> Result<(), Failure> {
+    let input = fs::read(input_filename)
+        .map_err(|err| Failure::new(Status::MissingInput, format!("could not read input file {}: {}", input_filename, err)))?;
+
+    if options.skip >= input.len() {
+        return Err(Failure::new(Status::Failed, format!("skipping entire input file {}", input_filename)));
+    }
+
+    // Progress starts over for every mode
+    let (width, _) = install_progress(compressor, options.progress_mode, input_filename);
+    let rows = compare_modes(compressor, &input);
+    finish_progress(options.progress_mode, width, input_filename);
+
+    let rows = rows.map_err(|err| Failure::new(Status::Failed, format!("could not compress {}: {}", input_filename, err)))?;
+
+    println!("{} ({} bytes):", input_filename, input.len());
+    print!("{}", format_comparison(input.len(), &rows));
+
+    Ok(())
+}
+
+// Compress a file as a sequence of blocks of at most block_size bytes, where each block uses the
+// previous one as its dictionary. Every block is written to its own numbered output file.
+fn compress_file_blocks(
+    compressor: &mut Compressor,
+    options: &Options,
+    block_size: usize,
+    input_filename: &str,
+    output_filename: &str
+) -> Result<Vec<ManifestEntry>, Failure> {
+    let mut input = fs::read(input_filename)
+        .map_err(|err| Failure::new(Status::MissingInput, format!("could not read input file {}: {}", input_filename, err)))?;
+
+    if input.is_empty() {
+        return Err(Failure::new(Status::Failed, format!("input file {} is empty", input_filename)));
+    }
+
+    // When working backwards the blocks are decompressed starting at the end of the file, so
+    // reversing the whole input puts both the blocks and their contents in decompression order
+    if options.backwards_mode {
+        input.reverse();
+    }
+
+    let blocks = input.chunks(block_size).collect::<Vec<_>>();
+    let output_filenames = (0..blocks.len())
+        .map(|i| format!("{}.{:03}", output_filename, i))
+        .collect::<Vec<_>>();
+
+    if !options.forced_mode && !options.dry_run {
+        if let Some(existing) = output_filenames.iter().find(|filename| Path::new(filename).exists()) {
+            return Err(Failure::new(Status::OutputExists, format!("output file {} already exists and --force was not specified", existing)));
+        }
+    }
+
+    let (width, _) = install_progress(compressor, options.progress_mode, input_filename);
+    let start = Instant::now();
+    let results = compressor.try_compress_chained(&blocks);
+    let duration = start.elapsed();
+
+    finish_progress(options.progress_mode, width, input_filename);
+
+    let results = results.map_err(|err| Failure::new(Status::Failed, format!("could not compress {}: {}", input_filename, err)))?;
+
+    let mut manifest = Vec::new();
+
+    for ((block, mut result), output_filename) in blocks.iter().zip(results).zip(output_filenames) {
+        if options.backwards_mode {
+            result.output.reverse();
+        }
+
+        let prefix = format_length_prefix(options.length_prefix, result.output.len(), block.len(), &output_filename)?;
+        result.output.splice(..0, prefix);
+
+        if !options.dry_run {
+            fs::write(&output_filename, &result.output)
+                .map_err(|err| Failure::new(Status::Failed, format!("could not write to output file {}: {}", output_filename, err)))?;
+
+            if options.verify_mode {
+                verify_output(&output_filename, &result.output)?;
+            }
+        }
+
+        // The blocks are compressed in one go, so each of them reports the duration of the file
+        if !options.quiet_mode {
+            print_summary(options, &Summary {
+                input_filename,
+                output_filename: &output_filename,
+                input_size: block.len(),
+                output_size: result.output.len(),
+                delta: result.delta,
+                duration
+            });
+        }
+
+        manifest.push(ManifestEntry {
+            input_filename: input_filename.to_string(),
+            output_filename: output_filename.clone(),
+            input_size: block.len(),
+            output_size: result.output.len(),
+            delta: result.delta,
+            sha256: sha256_hex(&result.output),
+            max_size: None,
+            duration
+        });
+    }
+
+    Ok(manifest)
+}
+
+/// The values that can be included in the summary of a compressed file.
+struct Summary<'a> {
+    input_filename: &'a str,
+    output_filename: &'a str,
+    input_size: usize,
+    output_size: usize,
+    delta: usize,
+    duration: Duration
+}
+
+// Format a summary using a template with placeholders in braces. A double opening brace stands for
+// a literal brace.
+fn format_summary(template: &str, summary: &Summary) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        if let Some(stripped) = rest.strip_prefix('{') {
+            result.push('{');
+            rest = stripped;
+            continue;
+        }
+
+        let end = rest.find('}').ok_or_else(|| "unterminated placeholder".to_string())?;
+
+        let value = match &rest[..end] {
+            "input" => summary.input_filename.to_string(),
+            "output" => summary.output_filename.to_string(),
+            "in_size" => summary.input_size.to_string(),
+            "out_size" => summary.output_size.to_string(),
+            "ratio" => format!("{:.3}", summary.input_size as f32 / summary.output_size as f32),
+            "delta" => summary.delta.to_string(),
+            "ms" => summary.duration.as_millis().to_string(),
+            name => return Err(format!("unknown placeholder {{{}}}", name))
+        };
+
+        result.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn print_summary(options: &Options, summary: &Summary) {
+    if let Some(template) = &options.summary_format {
+        // The template was validated while parsing the arguments
+        println!("{}", format_summary(template, summary).unwrap());
+        return;
+    }
+
+    // A restricted window, literal buffer or token budget matters to whoever writes the
+    // decompressor, so they are repeated here
+    let max_offset = options.max_offset.map_or(String::new(), |max_offset| format!(", max offset = {}", max_offset));
+    let max_literal_run = options.max_literal_run.map_or(String::new(), |max_literal_run| format!(", max literal run = {}", max_literal_run));
+    let max_tokens = options.max_tokens.map_or(String::new(), |max_tokens| format!(", max tokens = {}", max_tokens));
+
+    println!(
+        "{} ({} bytes) -> {} ({} bytes), ratio = {:.3}, delta = {}{}{}{}{}",
+        summary.input_filename,
+        summary.input_size,
+        summary.output_filename,
+        summary.output_size,
+        summary.input_size as f32 / summary.output_size as f32,
+        summary.delta,
+        max_offset,
+        max_literal_run,
+        max_tokens,
+        if options.dry_run { " (dry run, not written)" } else { "" }
+    );
+}
+
+// Read an output file back and check that it contains exactly the compressed data
+fn verify_output(output_filename: &str, output: &[u8]) -> Result<(), Failure> {
+    let written = fs::read(output_filename).map_err(|err| {
+        Failure::new(Status::VerificationFailed, format!("could not read back output file {}: {}", output_filename, err))
+    })?;
+
+    if written != output {
+        return Err(Failure::new(Status::VerificationFailed, format!("output file {} does not match the compressed data", output_filename)));
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "mmap")]
+type OutputData = memmap2::MmapMut;
+
+#[cfg(not(feature = "mmap"))]
+type OutputData = Vec<u8>;
+
+// Encode a parse directly into a memory-mapped output file. Since the exact size of the output is
+// known from the parse, the file can be sized up front, and the compressed data never has to be
+// held in a separate buffer. Ctrl-C is deferred until the file has been written, since the
+// progress callback is still called while encoding into it.
+#[cfg(feature = "mmap")]
+fn write_output(
+    compressor: &mut Compressor,
+    parse: &Parse,
+    input: &[u8],
+    output_filename: &str,
+    backwards_mode: bool,
+    prefix: &[u8],
+    profile: &mut Profile
+) -> Result<(CompressionResult, OutputData), Failure> {
+    WRITING.store(true, Ordering::Relaxed);
+    let written = write_mapped_output(compressor, parse, input, output_filename, backwards_mode, prefix, profile);
+    WRITING.store(false, Ordering::Relaxed);
+
+    written
+}
+
+#[cfg(feature = "mmap")]
+fn write_mapped_output(
+    compressor: &mut Compressor,
+    parse: &Parse,
+    input: &[u8],
+    output_filename: &str,
+    backwards_mode: bool,
+    prefix: &[u8],
+    profile: &mut Profile
+) -> Result<(CompressionResult, OutputData), Failure> {
+    let mapped = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(output_filename)
+        .and_then(|file| {
+            file.set_len((prefix.len() + parse.output_len()) as u64)?;
+
+            // Safety: the file was just created by this process and is not resized while mapped
+            unsafe { memmap2::MmapMut::map_mut(&file) }
+        });
+
+    let mut output = mapped.map_err(|err| Failure::new(Status::Failed, format!("could not write to output file {}: {}", output_filename, err)))?;
+
+    output[..prefix.len()].copy_from_slice(prefix);
+    let result = compressor.encode_into(parse, input, &mut output[prefix.len()..]);
+
+    // Reverse the output if working backwards, leaving the prefix in front
+    if backwards_mode {
+        output[prefix.len()..].reverse();
+    }
+
+    profile.lap("encode");
+
+    output.flush().map_err(|err| Failure::new(Status::Failed, format!("could not write to output file {}: {}", output_filename, err)))?;
+    profile.lap("write");
+
+    Ok((result, output))
+}
+
+// Encode a parse and write the output file
+#[cfg(not(feature = "mmap"))]
+fn write_output(
+    compressor: &mut Compressor,
+    parse: &Parse,
+    input: &[u8],
+    output_filename: &str,
+    backwards_mode: bool,
+    prefix: &[u8],
+    profile: &mut Profile
+) -> Result<(CompressionResult, OutputData), Failure> {
+    let mut result = compressor.encode(parse, input);
+    let mut output = std::mem::take(&mut result.output);
+
+    // Reverse the output if working backwards
+    if backwards_mode {
+        output.reverse();
+    }
+
+    output.splice(..0, prefix.iter().copied());
+    profile.lap("encode");
+
+    fs::write(output_filename, &output).map_err(|err| Failure::new(Status::Failed, format!("could not write to output file {}: {}", output_filename, err)))?;
+    profile.lap("write");
+
+    Ok((result, output))
+}
+
+// The part of an input file that ends up in the compressed data. The skipped bytes only serve as a
+// dictionary, so transforms have to leave them as they are. They are at the end of the file when
+// compressing backwards, since the input is only reversed right before it is compressed.
+fn compressed_range(len: usize, skip: usize, backwards_mode: bool) -> Range<usize> {
+    if backwards_mode {
+        0..len - skip
+    } else {
+        skip..len
+    }
+}
+
+// Compress a single file. Returns None if the file was skipped because it didn't compress well
+// enough, or an error message if the file could not be compressed.
+fn compress_file(
+    compressor: &mut Compressor,
+    options: &Options,
+    input_filename: &str,
+    output_filename: &str
+) -> Result<Option<ManifestEntry>, Failure> {
+    let skip = options.skip;
+    let mut profile = Profile::start();
+
+    // Read input file
+    let mut input = fs::read(input_filename)
+        .map_err(|err| Failure::new(Status::MissingInput, format!("could not read input file {}: {}", input_filename, err)))?;
+
+    // Convert images to the chosen pixel format, which is what gets compressed
+    #[cfg(feature = "image")]
+    let conversion = match options.image_format {
+        Some(format) => {
+            let (pixels, conversion) = convert_image(&input, format)
+                .map_err(|err| Failure::new(Status::Failed, format!("could not convert image {}: {}", input_filename, err)))?;
+
+            input = pixels;
+            Some((format, conversion))
+        },
+        None => None
+    };
+
+    // Validate skip length
+    if skip >= input.len() {
+        return Err(Failure::new(Status::Failed, format!("skipping entire input file {}", input_filename)));
+    }
+
+    // Check if output file already exists
+    if !options.forced_mode && !options.dry_run {
+        match File::open(output_filename) {
+            Ok(_) => {
+                return Err(Failure::new(Status::OutputExists, format!("output file {} already exists and --force was not specified", output_filename)));
+            },
+            Err(err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => {
+                return Err(Failure::new(Status::Failed, format!("could not open output file {}: {}", output_filename, err)));
+            }
+        };
+    }
+
+    // Split interleaved records into streams
+    let range = compressed_range(input.len(), skip, options.backwards_mode);
+
+    let layout = match options.deinterleave {
+        Some(DeinterleaveMode::Fixed(layout)) => Some(layout),
+        Some(DeinterleaveMode::Auto) => detect_interleave(&input[range.clone()]),
+        None => None
+    };
+
+    if let Some(layout) = layout {
+        let streams = deinterleave(&input[range.clone()], layout);
+        input[range.clone()].copy_from_slice(&streams);
+    }
+
+    // Turn a register dump into per-register frame deltas
+    if let Some(registers) = options.registers {
+        let streams = filter_registers(&input[range.clone()], registers);
+        input[range.clone()].copy_from_slice(&streams);
+    }
+
+    // Reverse the input if working backwards
+    if options.backwards_mode {
+        input.reverse();
+    }
+
+    profile.lap("read");
+
+    let (width, phase) = install_progress(compressor, options.progress_mode, input_filename);
+    let finish = || finish_progress(options.progress_mode, width, input_filename);
+    let start = Instant::now();
+
+    // Parse. The parse and the encoding share the progress range like they do in a regular
+    // compression, so the progress only ends once the output has been written.
+    phase.set((0.0, compressor.parse_share(input.len())));
+    let parse = compressor.try_parse(&input);
+    profile.lap("optimize");
+
+    let parse = parse
+        .inspect_err(|_| finish())
+        .map_err(|err| Failure::new(Status::Failed, format!("could not compress {}: {}", input_filename, err)))?;
+
+    // Skip writing the output if it doesn't save enough space. Only the part of the input that was
+    // not skipped ends up in the compressed data, so that is what the savings are compared to.
+    if let Some(min_savings) = options.min_savings {
+        let compressed_len = input.len() - skip;
+        let savings = 100.0 * (1.0 - parse.output_len() as f32 / compressed_len as f32);
+
+        if savings < min_savings {
+            finish();
+
+            if !options.quiet_mode {
+                println!(
+                    "{} ({} bytes) -> skipped, savings = {:.1} % is below the minimum of {} %",
+                    input_filename,
+                    compressed_len,
+                    savings,
+                    min_savings
+                );
+            }
+
+            return Ok(None);
+        }
+    }
+
+    // Encode and write output file, or only encode it for a dry run
+    let prefix = format_length_prefix(options.length_prefix, parse.output_len(), input.len() - skip, output_filename)
+        .inspect_err(|_| finish())?;
+
+    let encoded;
+    let written;
+
+    phase.set((compressor.parse_share(input.len()), 1.0));
+
+    let outcome: Result<(CompressionResult, &[u8]), Failure> = if options.dry_run {
+        let mut result = compressor.encode(&parse, &input);
+        let mut output = std::mem::take(&mut result.output);
+
+        if options.backwards_mode {
+            output.reverse();
+        }
+
+        output.splice(..0, prefix);
+        profile.lap("encode");
+
+        encoded = output;
+        Ok((result, &encoded))
+    } else {
+        match write_output(compressor, &parse, &input, output_filename, options.backwards_mode, &prefix, &mut profile) {
+            Ok((result, output)) => {
+                written = output;
+                Ok((result, &written))
+            },
+            Err(failure) => Err(failure)
+        }
+    };
+
+    compressor.progress_callback(|_| ());
+    finish();
+
+    // A Ctrl-C during writing is acted on now that the output file is complete
+    exit_if_cancelled();
+
+    let (result, output) = outcome?;
+
+    let duration = start.elapsed();
+
+    if options.verify_mode && !options.dry_run {
+        verify_output(output_filename, output)?;
+        profile.lap("verify");
+    }
+
+    // Write token stream
+    if let Some(format) = options.token_format.filter(|_| !options.dry_run) {
+        let tokens_filename = format!("{}.tokens.{}", output_filename, format.extension());
+
+        fs::write(&tokens_filename, format_tokens(&result.tokens, format))
+            .map_err(|err| Failure::new(Status::Failed, format!("could not write to token file {}: {}", tokens_filename, err)))?;
+    }
+
+    // Write parse graph
+    if options.dot_mode && !options.dry_run {
+        let dot_filename = format!("{}.dot", output_filename);
+
+        fs::write(&dot_filename, format_dot(&result.tokens, &input))
+            .map_err(|err| Failure::new(Status::Failed, format!("could not write to graph file {}: {}", dot_filename, err)))?;
+    }
+
+    // Print a summary
+    if !options.quiet_mode {
+        print_summary(options, &Summary {
+            input_filename,
+            output_filename,
+            input_size: input.len(),
+            output_size: output.len(),
+            delta: result.delta,
+            duration
+        });
+    }
+
+    // Print entropy estimates as part of the default summary
+    if !options.quiet_mode && options.summary_format.is_none() {
+        let estimate = entropy(&input[skip..]);
+
+        println!(
+            "Entropy: order-0 = {:.3} bits/byte ({} bytes), order-1 = {:.3} bits/byte ({} bytes)",
+            estimate.order0,
+            estimate.order0_size(),
+            estimate.order1,
+            estimate.order1_size()
+        );
+    }
+
+    #[cfg(feature = "image")]
+    if let Some((format, conversion)) = conversion.filter(|_| !options.quiet_mode) {
+        println!("Image: {}x{} pixels, {} colors, converted to {}", conversion.width, conversion.height, conversion.colors, format.name());
+    }
+
+    // The layout has to be known to interleave the decompressed data again
+    if !options.quiet_mode && options.deinterleave.is_some() {
+        println!("Deinterleaved: {}", layout.map_or("no layout compresses better".to_string(), |layout| layout.to_string()));
+    }
+
+    // The frame size has to be known to restore the register dump
+    if let Some(registers) = options.registers.filter(|_| !options.quiet_mode) {
+        println!("Registers: {} frames of {} registers", range.len().div_ceil(registers), registers);
+    }
+
+    // Print statistics
+    if options.stats_mode {
+        println!(
+            "Arena: {} blocks allocated, {} blocks estimated",
+            result.stats.arena_blocks,
+            result.stats.estimated_arena_blocks
+        );
+
+        print!("Memory: {} KiB peak working memory", result.stats.peak_memory.div_ceil(1024));
+
+        if let Some(peak_resident_memory) = peak_resident_memory() {
+            print!(", {} KiB peak resident", peak_resident_memory.div_ceil(1024));
+        }
+
+        println!();
+    }
+
+    // Print the profile
+    if options.profile_mode {
+        print!("{}", format_profile(profile.phases(), input.len() - skip, result.stats.arena_blocks));
+    }
+
+    Ok(Some(ManifestEntry {
+        input_filename: input_filename.to_string(),
+        output_filename: output_filename.to_string(),
+        input_size: input.len(),
+        output_size: output.len(),
+        delta: result.delta,
+        sha256: sha256_hex(output),
+        max_size: None,
+        duration
+    }))
+}
+
+// Parse the arguments of the vectors command and run it
+fn vectors_command(program_name: String, iter: impl Iterator<Item = String>) -> ! {
+    let mut forced_mode = false;
+    let mut directory = None;
+
+    for argument in iter {
+        match argument.as_str() {
+            "-f" | "--force" => { forced_mode = true; },
+            "-h" | "--help" => usage(program_name, Status::Success),
+            _ if argument.starts_with('-') => {
+                eprintln!("error: unknown vectors option {}", argument);
+                Status::Usage.exit();
+            },
+            _ if directory.is_none() => { directory = Some(argument); },
+            _ => {
+                eprintln!("error: vectors takes a single directory");
+                Status::Usage.exit();
+            }
+        }
+    }
+
+    let directory = directory.unwrap_or_else(|| usage(program_name, Status::Usage));
+
+    match write_vectors(Path::new(&directory), forced_mode) {
+        Ok(count) => {
+            println!("Wrote {} test vectors to {}", count, directory);
+            Status::Success.exit();
+        },
+        Err(failure) => {
+            eprintln!("error: {}", failure.message);
+            failure.status.exit();
+        }
+    }
+}
+
+// Parse the arguments of the convert command and run it
+fn convert_command(program_name: String, mut iter: impl Iterator<Item = String>) -> ! {
+    let mut compressor = Compressor::new();
+    compressor.natural_order(true);
+
+    let mut backwards_mode = false;
+    let mut forced_mode = false;
+    let mut quiet_mode = false;
+    let mut keep_going = false;
+    let mut max_len = 16 * 1024 * 1024;
+    let mut format = None;
+    let mut filenames = Vec::new();
+
+    while let Some(argument) = iter.next() {
+        match argument.as_str() {
+            "-c" | "--classic" => { compressor.classic_mode(true); },
+            "-b" | "--backwards" => {
+                backwards_mode = true;
+                compressor.backwards_mode(true);
+            },
+            "-q" | "--quick" => { compressor.quick_mode(true); },
+            "-x" | "--extreme" => { compressor.extreme_mode(true); },
+            "-e" | "--effort" => {
+                match iter.next().map(|argument| argument.parse::<u8>()) {
+                    Some(Ok(value)) if value <= MAX_EFFORT => { compressor.effort(value); },
+                    _ => {
+                        eprintln!("error: expected effort level between 0 and {}", MAX_EFFORT);
+                        Status::Usage.exit();
+                    }
+                }
+            },
+            "-f" | "--force" => { forced_mode = true; },
+            "-Q" | "--quiet" => { quiet_mode = true; },
+            "-k" | "--keep-going" => { keep_going = true; },
+            "--from" => {
+                format = match iter.next().as_deref() {
+                    Some("zx7") => Some(Format::Zx7),
+                    Some("classic") => Some(Format::Classic),
+                    _ => {
+                        eprintln!("error: expected zx7 or classic for from argument");
+                        Status::Usage.exit();
+                    }
+                };
+            },
+            "--max-len" => {
+                max_len = iter.next().and_then(|argument| parse_size(&argument)).unwrap_or_else(|| {
+                    eprintln!("error: expected size value for max-len argument");
+                    Status::Usage.exit();
+                });
+            },
+            "-h" | "--help" => usage(program_name, Status::Success),
+            _ if argument.starts_with('-') => {
+                eprintln!("error: unknown convert option {}", argument);
+                Status::Usage.exit();
+            },
+            _ => filenames.push(argument)
+        }
+    }
+
+    if filenames.is_empty() {
+        usage(program_name, Status::Usage);
+    }
+
+    let mut failures = Vec::new();
+
+    for filename in &filenames {
+        let path = Path::new(filename);
+        let format = format.unwrap_or_else(|| format_from_extension(path));
+
+        if format == Format::Zx7 && backwards_mode {
+            eprintln!("warning: {} is decompressed forwards, since ZX7 data can't be backwards", filename);
+        }
+
+        if let Err(failure) = convert_file(&mut compressor, path, format, backwards_mode && format == Format::Classic, max_len, forced_mode, quiet_mode) {
+            eprintln!("error: {}", failure.message);
+
+            if !keep_going {
+                failure.status.exit();
+            }
+
+            failures.push(failure);
+        }
+    }
+
+    match failures.first() {
+        Some(failure) => {
+            eprintln!();
+            eprintln!("{} of {} files failed", failures.len(), filenames.len());
+            failure.status.exit();
+        },
+        None => Status::Success.exit()
+    }
+}
+
+// Parse the arguments of the scan command and run it
+fn scan_command(program_name: String, mut iter: impl Iterator<Item = String>) -> ! {
+    let mut settings = ScanSettings {
+        classic_mode: false,
+        backwards_mode: false,
+        min_len: 64,
+        max_len: 65536
+    };
+
+    let mut filename = None;
+
+    while let Some(argument) = iter.next() {
+        match argument.as_str() {
+            "-c" | "--classic" => { settings.classic_mode = true; },
+            "-b" | "--backwards" => { settings.backwards_mode = true; },
+            "--min-len" | "--max-len" => {
+                if let Some(value) = iter.next().and_then(|argument| parse_size(&argument)) {
+                    if argument == "--min-len" { settings.min_len = value; } else { settings.max_len = value; }
+                } else {
+                    eprintln!("error: expected size value for {} argument", &argument[2..]);
+                    Status::Usage.exit();
+                }
+            },
+            "-h" | "--help" => usage(program_name, Status::Success),
+            _ if argument.starts_with('-') => {
+                eprintln!("error: unknown scan option {}", argument);
+                Status::Usage.exit();
+            },
+            _ if filename.is_none() => { filename = Some(argument); },
+            _ => {
+                eprintln!("error: scan takes a single file");
+                Status::Usage.exit();
+            }
+        }
+    }
+
+    let filename = filename.unwrap_or_else(|| usage(program_name, Status::Usage));
+
+    let file = fs::read(&filename).unwrap_or_else(|err| {
+        eprintln!("error: could not read input file {}: {}", filename, err);
+        Status::MissingInput.exit();
+    });
+
+    print!("{}", format_candidates(&scan(&file, settings), settings.backwards_mode));
+    Status::Success.exit();
+}
+
+// Parse the arguments of the extract command and run it
+fn extract_command(program_name: String, mut iter: impl Iterator<Item = String>) -> ! {
+    let mut settings = ExtractSettings {
+        offset: 0,
+        length: None,
+        classic_mode: false,
+        detect_mode: false,
+        backwards_mode: false,
+        max_len: 16 * 1024 * 1024
+    };
+
+    let mut offset = None;
+    let mut forced_mode = false;
+    let mut filenames = Vec::new();
+
+    while let Some(argument) = iter.next() {
+        match argument.as_str() {
+            "-c" | "--classic" => { settings.classic_mode = true; },
+            "-b" | "--backwards" => { settings.backwards_mode = true; },
+            "-d" | "--detect" => { settings.detect_mode = true; },
+            "-f" | "--force" => { forced_mode = true; },
+            "--at" | "--length" | "--max-len" => {
+                let value = iter.next().and_then(|argument| parse_size(&argument)).unwrap_or_else(|| {
+                    eprintln!("error: expected size value for {} argument", &argument[2..]);
+                    Status::Usage.exit();
+                });
+
+                match argument.as_str() {
+                    "--at" => offset = Some(value),
+                    "--length" => settings.length = Some(value),
+                    _ => settings.max_len = value
+                }
+            },
+            "-h" | "--help" => usage(program_name, Status::Success),
+            _ if argument.starts_with('-') => {
+                eprintln!("error: unknown extract option {}", argument);
+                Status::Usage.exit();
+            },
+            _ if filenames.len() < 2 => filenames.push(argument),
+            _ => {
+                eprintln!("error: extract takes a single file and an optional output file");
+                Status::Usage.exit();
+            }
+        }
+    }
+
+    if filenames.is_empty() {
+        usage(program_name, Status::Usage);
+    }
+
+    settings.offset = offset.unwrap_or_else(|| {
+        eprintln!("error: the offset of the stream must be given with --at");
+        Status::Usage.exit();
+    });
+
+    let offset = settings.offset;
+
+    let filename = &filenames[0];
+    let output_filename = filenames.get(1).cloned().unwrap_or_else(|| format!("{}.{:06x}", filename, offset));
+
+    if !forced_mode && Path::new(&output_filename).exists() {
+        eprintln!("error: output file {} already exists", output_filename);
+        Status::OutputExists.exit();
+    }
+
+    let file = fs::read(filename).unwrap_or_else(|err| {
+        eprintln!("error: could not read input file {}: {}", filename, err);
+        Status::MissingInput.exit();
+    });
+
+    let (consumed, data, classic_mode) = extract(&file, settings).unwrap_or_else(|err| {
+        eprintln!("error: could not decompress the stream at {:#06x} of {}: {}", offset, filename, err);
+        Status::Failed.exit();
+    });
+
+    fs::write(&output_filename, &data).unwrap_or_else(|err| {
+        eprintln!("error: could not write to output file {}: {}", output_filename, err);
+        Status::Failed.exit();
+    });
+
+    println!(
+        "{} at {:#06x} ({} bytes, {}) -> {} ({} bytes)",
+        filename,
+        offset,
+        consumed,
+        if classic_mode { "classic" } else { "v2" },
+        output_filename,
+        data.len()
+    );
+    Status::Success.exit();
+}
+
+// Parse the arguments of the report command and run it
+fn report_command(program_name: String, mut iter: impl Iterator<Item = String>) -> ! {
+    let mut config = Config::default();
+    let mut worst = 10;
+    let mut directory = None;
+
+    while let Some(argument) = iter.next() {
+        match argument.as_str() {
+            "-c" | "--classic" => { config.classic_mode = true; },
+            "-q" | "--quick" => { config.quick_mode = true; },
+            "-e" | "--effort" => {
+                match iter.next().map(|argument| argument.parse::<u8>()) {
+                    Some(Ok(value)) if value <= MAX_EFFORT => { config.effort = value; },
+                    _ => {
+                        eprintln!("error: expected effort level between 0 and {}", MAX_EFFORT);
+                        Status::Usage.exit();
+                    }
+                }
+            },
+            "--worst" => {
+                if let Some(Ok(value)) = iter.next().map(|argument| argument.parse()) {
+                    worst = value;
+                } else {
+                    eprintln!("error: expected integer value for worst argument");
+                    Status::Usage.exit();
+                }
+            },
+            "-h" | "--help" => usage(program_name, Status::Success),
+            _ if argument.starts_with('-') => {
+                eprintln!("error: unknown report option {}", argument);
+                Status::Usage.exit();
+            },
+            _ if directory.is_none() => { directory = Some(argument); },
+            _ => {
+                eprintln!("error: report takes a single directory");
+                Status::Usage.exit();
+            }
+        }
+    }
+
+    let directory = directory.unwrap_or_else(|| usage(program_name, Status::Usage));
+
+    if !Path::new(&directory).is_dir() {
+        eprintln!("error: {} is not a directory", directory);
+        Status::MissingInput.exit();
+    }
+
+    if report(Path::new(&directory), config, worst) { Status::Success.exit() } else { Status::Failed.exit() }
+}
+
+// Report a panic as an internal error, which is a bug in the compressor rather than a problem with
+// the input. The hook only prints the message, so that panics caught by a batch still fail just
+// their own job. Panics that reach main exit with a status of their own.
+fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        eprintln!("\ninternal error: {}", info);
+        eprintln!("This is a bug, please report it at {}/issues", env!("CARGO_PKG_REPOSITORY"));
+    }));
+}
+
+// Set when Ctrl-C is pressed. The signal handler can't safely do much more than that, so the flag
+// is checked while compressing, and the tool exits from there.
+static CANCELLED: AtomicBool = AtomicBool::new(false);
 
-    process::exit(1);
+// Set while an output file is being written from code that calls the progress callback, during
+// which Ctrl-C is only acted on once the file is complete
+static WRITING: AtomicBool = AtomicBool::new(false);
+
+// Exit with the cancelled status if Ctrl-C has been pressed, unless an output file is being
+// written, so that no partially written or temporary files are left behind.
+fn exit_if_cancelled() {
+    if CANCELLED.load(Ordering::Relaxed) && !WRITING.load(Ordering::Relaxed) {
+        eprintln!();
+        Status::Cancelled.exit();
+    }
 }
 
-fn version() -> ! {
-    eprintln!("zx0-rs {}\nBased on ZX0 v2.2 by Einar Saukas", VERSION);
-    process::exit(1);
+// Record Ctrl-C presses in the cancelled flag instead of terminating the process right away
+#[cfg(unix)]
+fn install_cancel_handler() {
+    extern "C" fn cancel(_: libc::c_int) {
+        CANCELLED.store(true, Ordering::Relaxed);
+    }
+
+    // Safety: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = cancel as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+    }
+}
+
+// Record Ctrl-C presses in the cancelled flag instead of terminating the process right away.
+// Returning true tells Windows that the event has been handled.
+#[cfg(windows)]
+fn install_cancel_handler() {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: extern "system" fn(u32) -> i32, add: i32) -> i32;
+    }
+
+    extern "system" fn cancel(_: u32) -> i32 {
+        CANCELLED.store(true, Ordering::Relaxed);
+        1
+    }
+
+    // Safety: the handler is a plain function that lives as long as the process
+    unsafe {
+        SetConsoleCtrlHandler(cancel, 1);
+    }
 }
 
+#[cfg(not(any(unix, windows)))]
+fn install_cancel_handler() {}
+
 fn main() {
-    let mut compressor = Compressor::new();
+    install_panic_hook();
 
-    let mut input_filename = None;
-    let mut output_filename = None;
+    // The hook has already reported the panic, so all that is left is the exit status
+    if panic::catch_unwind(run).is_err() {
+        Status::Internal.exit();
+    }
+}
 
-    let mut backwards_mode = false;
-    let mut forced_mode = false;
-    let mut quiet_mode = false;
+fn run() {
+    let mut compressor = Compressor::new();
 
-    let mut skip = 0;
+    let mut options = Options {
+        backwards_mode: false,
+        classic_mode: false,
+        quick_mode: false,
+        max_offset: None,
+        max_literal_run: None,
+        max_tokens: None,
+        effort: MAX_EFFORT,
+        extreme_mode: false,
+        forced_mode: false,
+        dry_run: false,
+        keep_going: false,
+        verify_mode: false,
+        quiet_mode: false,
+        progress_mode: ProgressMode::Fancy,
+        stats_mode: false,
+        profile_mode: false,
+        summary_format: None,
+        token_format: None,
+        dot_mode: false,
+        min_savings: None,
+        skip: 0,
+        #[cfg(feature = "image")]
+        image_format: None,
+        deinterleave: None,
+        registers: None,
+        block_size: None,
+        chunk_len: None,
+        length_prefix: None
+    };
+
+    let mut filenames = Vec::new();
+    let mut batch_mode = false;
+    let mut compare_mode = false;
+    let mut manifest_filename = None;
+    let mut max_size = None;
+    let mut log_filename = None;
+    let mut budgets = Vec::new();
 
     let mut iter = env::args();
     let program_name = iter.next().unwrap_or_else(|| {
         eprintln!("error: expected at least one argument containing the program name");
-        process::exit(1);
+        Status::Usage.exit();
     });
 
+    if env::args().nth(1).as_deref() == Some("selftest") {
+        if env::args().len() > 2 {
+            eprintln!("error: selftest does not take any arguments");
+            Status::Usage.exit();
+        }
+
+        if selftest() { Status::Success.exit() } else { Status::Failed.exit() }
+    }
+
+    if env::args().nth(1).as_deref() == Some("report") {
+        report_command(program_name, iter.skip(1));
+    }
+
+    if env::args().nth(1).as_deref() == Some("convert") {
+        convert_command(program_name, iter.skip(1));
+    }
+
+    if env::args().nth(1).as_deref() == Some("scan") {
+        scan_command(program_name, iter.skip(1));
+    }
+
+    if env::args().nth(1).as_deref() == Some("extract") {
+        extract_command(program_name, iter.skip(1));
+    }
+
+    if env::args().nth(1).as_deref() == Some("vectors") {
+        vectors_command(program_name, iter.skip(1));
+    }
+
     while let Some(argument) = iter.next() {
         match argument.as_str() {
-            "-c" | "--classic" => { compressor.classic_mode(true); },
+            "-c" | "--classic" => {
+                options.classic_mode = true;
+                compressor.classic_mode(true);
+            },
             "-b" | "--backwards" => {
-                backwards_mode = true;
+                options.backwards_mode = true;
                 compressor.backwards_mode(true);
             },
-            "-q" | "--quick" => { compressor.quick_mode(true); },
-            "-f" | "--force" => { forced_mode = true; },
-            "-Q" | "--quiet" => { quiet_mode = true; },
-            "-h" | "--help" => usage(program_name),
+            "-q" | "--quick" => {
+                options.quick_mode = true;
+                compressor.quick_mode(true);
+            },
+            "--max-offset" => {
+                if let Some(argument) = iter.next() {
+                    if let Some(value) = parse_size(&argument).filter(|value| (1..=MAX_OFFSET_ZX0).contains(value)) {
+                        options.max_offset = Some(value);
+                        compressor.max_offset(value);
+                    } else {
+                        eprintln!("error: maximum offset must be between 1 and {}", MAX_OFFSET_ZX0);
+                        Status::Usage.exit();
+                    }
+                } else {
+                    eprintln!("error: expected value for max-offset argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--compare-modes" => { compare_mode = true; },
+            "--max-literal-run" => {
+                if let Some(argument) = iter.next() {
+                    if let Some(value) = parse_size(&argument).filter(|&value| value > 0) {
+                        options.max_literal_run = Some(value);
+                        compressor.max_literal_run(value);
+                    } else {
+                        eprintln!("error: expected positive size value for max-literal-run argument");
+                        Status::Usage.exit();
+                    }
+                } else {
+                    eprintln!("error: expected value for max-literal-run argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--max-tokens" => {
+                if let Some(argument) = iter.next() {
+                    if let Some(value) = argument.parse::<usize>().ok().filter(|&value| value > 0) {
+                        options.max_tokens = Some(value);
+                        compressor.max_tokens(value);
+                    } else {
+                        eprintln!("error: expected positive count value for max-tokens argument");
+                        Status::Usage.exit();
+                    }
+                } else {
+                    eprintln!("error: expected value for max-tokens argument");
+                    Status::Usage.exit();
+                }
+            },
+            "-x" | "--extreme" => {
+                options.extreme_mode = true;
+                compressor.extreme_mode(true);
+            },
+            "-f" | "--force" => { options.forced_mode = true; },
+            "-n" | "--dry-run" => { options.dry_run = true; },
+            "-Q" | "--quiet" => { options.quiet_mode = true; },
+            "-B" | "--batch" => { batch_mode = true; },
+            "-k" | "--keep-going" => { options.keep_going = true; },
+            "--files-from" => {
+                if let Some(argument) = iter.next() {
+                    filenames.extend(read_file_list(&argument));
+                    batch_mode = true;
+                } else {
+                    eprintln!("error: expected value for files-from argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--stats" => { options.stats_mode = true; },
+            "--profile" => { options.profile_mode = true; },
+            "--verify" => { options.verify_mode = true; },
+            "--manifest" => {
+                if let Some(argument) = iter.next() {
+                    manifest_filename = Some(argument);
+                } else {
+                    eprintln!("error: expected value for manifest argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--log-csv" => {
+                if let Some(argument) = iter.next() {
+                    log_filename = Some(argument);
+                } else {
+                    eprintln!("error: expected value for log-csv argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--max-size" => {
+                if let Some(argument) = iter.next() {
+                    if let Some(value) = parse_size(&argument) {
+                        max_size = Some(value);
+                    } else {
+                        eprintln!("error: expected size value for max-size argument");
+                        Status::Usage.exit();
+                    }
+                } else {
+                    eprintln!("error: expected value for max-size argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--budgets" => {
+                if let Some(argument) = iter.next() {
+                    let list = fs::read_to_string(&argument).unwrap_or_else(|err| {
+                        eprintln!("error: could not read budgets file {}: {}", argument, err);
+                        Status::MissingInput.exit();
+                    });
+
+                    budgets = parse_budgets(&list).unwrap_or_else(|message| {
+                        eprintln!("error: invalid budgets file {}: {}", argument, message);
+                        Status::Usage.exit();
+                    });
+                } else {
+                    eprintln!("error: expected value for budgets argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--min-savings" => {
+                if let Some(argument) = iter.next() {
+                    if let Ok(value) = argument.trim_end_matches('%').parse::<f32>() {
+                        if !(0.0..=100.0).contains(&value) {
+                            eprintln!("error: minimum savings must be between 0 and 100 percent");
+                            Status::Usage.exit();
+                        }
+
+                        options.min_savings = Some(value);
+                    } else {
+                        eprintln!("error: expected numeric value for min-savings argument");
+                        Status::Usage.exit();
+                    }
+                } else {
+                    eprintln!("error: expected value for min-savings argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--summary-format" => {
+                if let Some(argument) = iter.next() {
+                    let example = Summary {
+                        input_filename: "",
+                        output_filename: "",
+                        input_size: 1,
+                        output_size: 1,
+                        delta: 0,
+                        duration: Duration::ZERO
+                    };
+
+                    if let Err(message) = format_summary(&argument, &example) {
+                        eprintln!("error: invalid summary format: {}", message);
+                        Status::Usage.exit();
+                    }
+
+                    options.summary_format = Some(argument);
+                } else {
+                    eprintln!("error: expected value for summary-format argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--progress" => {
+                if let Some(argument) = iter.next() {
+                    options.progress_mode = parse_progress_mode(&argument);
+                } else {
+                    eprintln!("error: expected value for progress argument");
+                    Status::Usage.exit();
+                }
+            },
+            _ if argument.starts_with("--progress=") => {
+                options.progress_mode = parse_progress_mode(&argument["--progress=".len()..]);
+            },
+            "-h" | "--help" => usage(program_name, Status::Success),
             "-V" | "--version" => version(),
+            "-e" | "--effort" => {
+                if let Some(argument) = iter.next() {
+                    if let Ok(value) = argument.parse::<u8>() {
+                        if value > MAX_EFFORT {
+                            eprintln!("error: effort level must be between 0 and {}", MAX_EFFORT);
+                            Status::Usage.exit();
+                        }
+
+                        options.effort = value;
+                        compressor.effort(value);
+                    } else {
+                        eprintln!("error: expected integer value for effort argument");
+                        Status::Usage.exit();
+                    }
+                } else {
+                    eprintln!("error: expected value for effort argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--dump-tokens" => {
+                if let Some(argument) = iter.next() {
+                    options.token_format = Some(match argument.as_str() {
+                        "json" => TokenFormat::Json,
+                        "csv" => TokenFormat::Csv,
+                        _ => {
+                            eprintln!("error: token format must be json or csv");
+                            Status::Usage.exit();
+                        }
+                    });
+
+                    compressor.record_tokens(true);
+                } else {
+                    eprintln!("error: expected value for dump-tokens argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--dump-dot" => {
+                options.dot_mode = true;
+                compressor.record_tokens(true);
+            },
+            "--blocks" => {
+                if let Some(argument) = iter.next() {
+                    if let Some(value) = parse_size(&argument).filter(|&value| value > 0) {
+                        options.block_size = Some(value);
+                    } else {
+                        eprintln!("error: expected positive size value for blocks argument");
+                        Status::Usage.exit();
+                    }
+                } else {
+                    eprintln!("error: expected value for blocks argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--chunks" => {
+                if let Some(argument) = iter.next() {
+                    if let Some(value) = parse_size(&argument).filter(|&value| value > 0) {
+                        options.chunk_len = Some(value);
+                        compressor.chunk_len(value);
+                    } else {
+                        eprintln!("error: expected positive size value for chunks argument");
+                        Status::Usage.exit();
+                    }
+                } else {
+                    eprintln!("error: expected value for chunks argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--length-prefix" => {
+                if let Some(argument) = iter.next() {
+                    options.length_prefix = Some(match argument.as_str() {
+                        "compressed" => LengthPrefix::Compressed,
+                        "decompressed" => LengthPrefix::Decompressed,
+                        "both" => LengthPrefix::Both,
+                        _ => {
+                            eprintln!("error: length prefix must be compressed, decompressed or both");
+                            Status::Usage.exit();
+                        }
+                    });
+                } else {
+                    eprintln!("error: expected value for length-prefix argument");
+                    Status::Usage.exit();
+                }
+            },
+            #[cfg(feature = "image")]
+            "--image" => {
+                if let Some(argument) = iter.next() {
+                    options.image_format = Some(match argument.as_str() {
+                        "linear" => PixelFormat::Linear,
+                        "planar4" => PixelFormat::Planar4,
+                        "zx" => PixelFormat::Zx,
+                        _ => {
+                            eprintln!("error: image format must be linear, planar4 or zx");
+                            Status::Usage.exit();
+                        }
+                    });
+                } else {
+                    eprintln!("error: expected value for image argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--deinterleave" => {
+                if let Some(argument) = iter.next() {
+                    options.deinterleave = Some(match argument.as_str() {
+                        "auto" => DeinterleaveMode::Auto,
+                        "bits" => DeinterleaveMode::Fixed(Interleave::Bits),
+                        _ => match argument.parse::<usize>() {
+                            Ok(stride) if stride >= 2 => DeinterleaveMode::Fixed(Interleave::Bytes(stride)),
+                            _ => {
+                                eprintln!("error: deinterleave layout must be a stride of at least 2, bits or auto");
+                                Status::Usage.exit();
+                            }
+                        }
+                    });
+                } else {
+                    eprintln!("error: expected value for deinterleave argument");
+                    Status::Usage.exit();
+                }
+            },
+            "--registers" => {
+                if let Some(argument) = iter.next() {
+                    options.registers = Some(match argument.as_str() {
+                        "ay" => AY_REGISTERS,
+                        "sid" => SID_REGISTERS,
+                        _ => match argument.parse::<usize>() {
+                            Ok(registers) if registers > 0 => registers,
+                            _ => {
+                                eprintln!("error: expected positive count, ay or sid for registers argument");
+                                Status::Usage.exit();
+                            }
+                        }
+                    });
+                } else {
+                    eprintln!("error: expected value for registers argument");
+                    Status::Usage.exit();
+                }
+            },
             "-s" | "--skip" => {
                 if let Some(argument) = iter.next() {
-                    if let Ok(value) = argument.parse() {
-                        skip = value;
+                    if let Some(value) = parse_size(&argument) {
+                        options.skip = value;
                         compressor.skip(value);
                     } else {
-                        eprintln!("error: expected integer value for skip argument");
-                        process::exit(1);
+                        eprintln!("error: expected size value for skip argument");
+                        Status::Usage.exit();
                     }
                 } else {
                     eprintln!("error: expected value for skip argument");
-                    process::exit(1);
+                    Status::Usage.exit();
                 }
             }
             _ => {
                 if argument.starts_with('-') {
                     eprintln!("error: unrecognized argument: {}", argument);
-                    process::exit(1);
-                } else if input_filename.is_none() {
-                    input_filename = Some(argument);
-                } else if output_filename.is_none() {
-                    output_filename = Some(argument);
-                } else {
-                    eprintln!("error: too many filename arguments provided");
-                    process::exit(1);
+                    Status::Usage.exit();
                 }
+
+                filenames.push(argument);
             }
         }
     }
 
-    // Unwrap and optionally generate filenames
-    let input_filename = input_filename.unwrap_or_else(|| usage(program_name));
-    let output_filename = output_filename.unwrap_or_else(|| format!("{}.zx0", input_filename));
+    if options.block_size.is_some() && (options.skip > 0 || options.min_savings.is_some() || options.token_format.is_some() || options.dot_mode || options.profile_mode) {
+        eprintln!("error: --blocks can't be combined with --skip, --min-savings, --dump-tokens, --dump-dot or --profile");
+        Status::Usage.exit();
+    }
 
-    // Read input file
-    let mut input = fs::read(&input_filename).unwrap_or_else(|err| {
-        eprintln!("error: could not read input file: {}", err);
-        process::exit(1);
-    });
+    if options.profile_mode {
+        enable_counting();
+    }
 
-    // Validate skip length
-    if skip >= input.len() {
-        eprintln!("error: skipping entire input file");
-        process::exit(1);
+    #[cfg(feature = "image")]
+    if options.image_format.is_some() && (options.block_size.is_some() || compare_mode) {
+        eprintln!("error: --image can't be combined with --blocks or --compare-modes");
+        Status::Usage.exit();
     }
 
-    // Check if output file already exists
-    if !forced_mode {
-        match File::open(&output_filename) {
-            Ok(_) => {
-                eprintln!("error: output file already exists and --force was not specified");
-                process::exit(1);
+    if options.deinterleave.is_some() && (options.block_size.is_some() || compare_mode) {
+        eprintln!("error: --deinterleave can't be combined with --blocks or --compare-modes");
+        Status::Usage.exit();
+    }
+
+    if options.registers.is_some() && (options.deinterleave.is_some() || options.block_size.is_some() || compare_mode) {
+        eprintln!("error: --registers can't be combined with --deinterleave, --blocks or --compare-modes");
+        Status::Usage.exit();
+    }
+
+    if compare_mode && (options.block_size.is_some() || options.token_format.is_some() || options.dot_mode) {
+        eprintln!("error: --compare-modes can't be combined with --blocks, --dump-tokens or --dump-dot");
+        Status::Usage.exit();
+    }
+
+    if options.quiet_mode {
+        options.progress_mode = ProgressMode::None;
+    } else if options.progress_mode == ProgressMode::Fancy && !std::io::stderr().is_terminal() {
+        // Redrawing a line makes no sense when stderr is redirected to a file or pipe
+        options.progress_mode = ProgressMode::Plain;
+    }
+
+    // Determine the input and output filenames. In batch mode every argument is an input file or
+    // directory, and outputs are always written next to their inputs.
+    let jobs = if batch_mode {
+        let mut files = Vec::new();
+
+        for filename in &filenames {
+            collect_batch_files(Path::new(filename), &mut files);
+        }
+
+        files.into_iter().map(|path| {
+            let input_filename = path.to_string_lossy().into_owned();
+            let output_filename = format!("{}.zx0", input_filename);
+            (input_filename, output_filename)
+        }).collect::<Vec<_>>()
+    } else {
+        if filenames.len() > 2 {
+            eprintln!("error: too many filename arguments provided");
+            Status::Usage.exit();
+        }
+
+        let mut filenames = filenames.into_iter();
+        let input_filename = filenames.next().unwrap_or_else(|| usage(program_name, Status::Usage));
+        let output_filename = filenames.next().unwrap_or_else(|| format!("{}.zx0", input_filename));
+
+        vec![(input_filename, output_filename)]
+    };
+
+    if jobs.is_empty() {
+        eprintln!("error: no input files found");
+        Status::MissingInput.exit();
+    }
+
+    let mut manifest = Vec::new();
+    let mut skipped = false;
+    let mut failures = Vec::new();
+
+    // Open the log up front, so that a log that can't be written is noticed before compressing
+    let mut log = log_filename.filter(|_| !options.dry_run).map(|log_filename| {
+        open_log(&log_filename).unwrap_or_else(|err| {
+            eprintln!("error: could not open log file {}: {}", log_filename, err);
+            Status::Failed.exit();
+        })
+    });
+
+    // Only compression checks the cancelled flag, so Ctrl-C keeps its default behavior in the
+    // subcommands and while the arguments and file lists are read
+    install_cancel_handler();
+
+    for (input_filename, output_filename) in &jobs {
+        exit_if_cancelled();
+
+        let outcome = if compare_mode {
+            compare_file(&mut compressor, &options, input_filename).map(|()| Vec::new())
+        } else if let Some(block_size) = options.block_size {
+            compress_file_blocks(&mut compressor, &options, block_size, input_filename, output_filename)
+        } else {
+            compress_file(&mut compressor, &options, input_filename, output_filename).map(|entry| {
+                skipped |= entry.is_none();
+                entry.into_iter().collect()
+            })
+        };
+
+        // Budgets apply to every output file of an input, which matters when it is split into blocks
+        let budget = budgets.iter()
+            .find(|(path, _)| path == Path::new(input_filename))
+            .map(|&(_, size)| size)
+            .or(max_size);
+
+        match outcome {
+            Ok(entries) => {
+                // Rows are appended as soon as a file is done, so that an interrupted run is logged
+                if let Some(log) = &mut log {
+                    let timestamp = format_timestamp(SystemTime::now());
+
+                    for entry in &entries {
+                        if let Err(err) = writeln!(log, "{}", format_log_row(&timestamp, &options, entry)) {
+                            eprintln!("error: could not write to log file: {}", err);
+                            Status::Failed.exit();
+                        }
+                    }
+                }
+
+                manifest.extend(entries.into_iter().map(|entry| ManifestEntry { max_size: budget, ..entry }));
             },
-            Err(err) if err.kind() == ErrorKind::NotFound => (),
-            Err(err) => {
-                eprintln!("error: could not open output file: {}", err);
-                process::exit(1);
+            Err(failure) => {
+                eprintln!("error: {}", failure.message);
+
+                if !options.keep_going {
+                    failure.status.exit();
+                }
+
+                failures.push((input_filename, failure));
             }
-        };
+        }
     }
 
-    // Reverse the input if working backwards
-    if backwards_mode {
-        input.reverse();
+    // Write manifest
+    if let Some(manifest_filename) = manifest_filename.filter(|_| !options.dry_run) {
+        if let Err(err) = fs::write(&manifest_filename, format_manifest(&options, &manifest)) {
+            eprintln!("error: could not write to manifest file: {}", err);
+            Status::Failed.exit();
+        }
     }
 
-    if !quiet_mode {
-        compressor.progress_callback(|progress| {
-            print!("\rProgress: {:.1} %", progress * 100.0);
+    let over_budget = manifest.iter()
+        .filter(|entry| entry.max_size.is_some_and(|max_size| entry.output_size > max_size))
+        .collect::<Vec<_>>();
 
-            if let Err(err) = std::io::stdout().flush() {
-                eprintln!("error: could not flush stdout: {}", err);
-                process::exit(1);
-            }
-        });
+    if !over_budget.is_empty() {
+        eprintln!();
+        eprintln!("{} of {} output files exceed their size budget:", over_budget.len(), manifest.len());
+
+        for entry in &over_budget {
+            let max_size = entry.max_size.unwrap();
+
+            eprintln!(
+                "    {}: {} bytes, budget {} bytes ({} bytes over)",
+                entry.output_filename,
+                entry.output_size,
+                max_size,
+                entry.output_size - max_size
+            );
+        }
     }
 
-    // Compress
-    let mut result = compressor.compress(&input);
+    if !failures.is_empty() {
+        eprintln!();
+        eprintln!("{} of {} files failed:", failures.len(), jobs.len());
 
-    // Reverse the output if working backwards
-    if backwards_mode {
-        result.output.reverse();
+        for (input_filename, failure) in &failures {
+            eprintln!("    {}: {}", input_filename, failure.message);
+        }
+
+        failures[0].1.status.exit();
     }
 
-    // Write output file
-    if let Err(err) = fs::write(&output_filename, &result.output) {
-        eprintln!("error: could not write to output file: {}", err);
-        process::exit(1);
+    if !over_budget.is_empty() {
+        Status::OverBudget.exit();
     }
 
-    // Print a summary
-    if !quiet_mode {
-        println!(
-            "\r{} ({} bytes) -> {} ({} bytes), ratio = {:.3}, delta = {}",
-            input_filename,
-            input.len(),
-            output_filename,
-            result.output.len(),
-            input.len() as f32 / result.output.len() as f32,
-            result.delta
+    if skipped {
+        Status::Skipped.exit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use zx0::{deinterleave, filter_registers, interleave, unfilter_registers, Interleave};
+
+    use super::{compressed_range, csv_field, format_length_prefix, format_machine_progress, format_summary, format_timestamp, parse_budgets, parse_size, split_file_list, LengthPrefix, Status, Summary};
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("16384"), Some(16384));
+        assert_eq!(parse_size("0x4000"), Some(0x4000));
+        assert_eq!(parse_size("0XC000"), Some(0xc000));
+        assert_eq!(parse_size("16k"), Some(16384));
+        assert_eq!(parse_size("48K"), Some(49152));
+        assert_eq!(parse_size("1m"), Some(1048576));
+        assert_eq!(parse_size("0x10k"), Some(16384));
+
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("k"), None);
+        assert_eq!(parse_size("-1"), None);
+        assert_eq!(parse_size("0x"), None);
+        assert_eq!(parse_size("16kb"), None);
+    }
+
+    #[test]
+    fn file_lists() {
+        assert_eq!(split_file_list(b"a.bin\nb c.bin\r\n\nd.bin"), ["a.bin", "b c.bin", "d.bin"]);
+        assert_eq!(split_file_list(b"a.bin\0b\nc.bin\0"), ["a.bin", "b\nc.bin"]);
+        assert!(split_file_list(b"").is_empty());
+    }
+
+    #[test]
+    fn budgets() {
+        let budgets = parse_budgets("# Loader bank\n0x1800 gfx/loading screen.scr\n\n  16k\tmusic.pt3\n").unwrap();
+        assert_eq!(budgets, [(PathBuf::from("gfx/loading screen.scr"), 0x1800), (PathBuf::from("music.pt3"), 16384)]);
+
+        assert!(parse_budgets("4096").is_err());
+        assert!(parse_budgets("4kb level.bin").is_err());
+    }
+
+    #[test]
+    fn summaries() {
+        let summary = Summary {
+            input_filename: "level.bin",
+            output_filename: "level.bin.zx0",
+            input_size: 4096,
+            output_size: 1000,
+            delta: 3,
+            duration: Duration::from_micros(12345)
+        };
+
+        assert_eq!(
+            format_summary("{input},{output},{in_size},{out_size},{ratio},{delta},{ms}", &summary).unwrap(),
+            "level.bin,level.bin.zx0,4096,1000,4.096,3,12"
         );
+
+        assert_eq!(format_summary("{{\"size\": {out_size}}", &summary).unwrap(), "{\"size\": 1000}");
+        assert_eq!(format_summary("no placeholders", &summary).unwrap(), "no placeholders");
+
+        assert!(format_summary("{size}", &summary).is_err());
+        assert!(format_summary("{input", &summary).is_err());
+    }
+
+    #[test]
+    fn log_fields() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(UNIX_EPOCH + Duration::from_secs(951782400)), "2000-02-29T00:00:00Z");
+        assert_eq!(format_timestamp(UNIX_EPOCH + Duration::from_secs(1792108800 + 45296)), "2026-10-16T12:34:56Z");
+
+        assert_eq!(csv_field("gfx/title.scr"), "gfx/title.scr");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn length_prefixes() {
+        let prefix = |length_prefix, compressed_len, decompressed_len| {
+            format_length_prefix(length_prefix, compressed_len, decompressed_len, "level.zx0").map_err(|failure| failure.message)
+        };
+
+        assert_eq!(prefix(None, 0x1234, 0x5678), Ok(vec![]));
+        assert_eq!(prefix(Some(LengthPrefix::Compressed), 0x1234, 0x5678), Ok(vec![0x34, 0x12]));
+        assert_eq!(prefix(Some(LengthPrefix::Decompressed), 0x1234, 0x5678), Ok(vec![0x78, 0x56]));
+        assert_eq!(prefix(Some(LengthPrefix::Both), 0x1234, 0xffff), Ok(vec![0x34, 0x12, 0xff, 0xff]));
+
+        assert!(prefix(Some(LengthPrefix::Compressed), 0x1234, 0x10000).is_ok());
+        assert!(prefix(Some(LengthPrefix::Decompressed), 0x1234, 0x10000).is_err());
+    }
+
+    #[test]
+    fn compressed_ranges() {
+        assert_eq!(compressed_range(6, 0, false), 0..6);
+        assert_eq!(compressed_range(6, 2, false), 2..6);
+        assert_eq!(compressed_range(6, 2, true), 0..4);
+
+        // With -b -s 2 --deinterleave 2, the last two bytes of the file are the dictionary, and
+        // the rest can be interleaved again after decompressing
+        let mut input = vec![1, 2, 3, 4, 5, 6];
+        let range = compressed_range(input.len(), 2, true);
+        let streams = deinterleave(&input[range.clone()], Interleave::Bytes(2));
+        input[range.clone()].copy_from_slice(&streams);
+
+        assert_eq!(input, [1, 3, 2, 4, 5, 6]);
+        assert_eq!(interleave(&input[range], Interleave::Bytes(2)), [1, 2, 3, 4]);
+
+        // The same goes for -b -s 3 --registers 2
+        let mut input = vec![1, 10, 3, 12, 2, 10, 7];
+        let range = compressed_range(input.len(), 3, true);
+        let streams = filter_registers(&input[range.clone()], 2);
+        input[range.clone()].copy_from_slice(&streams);
+
+        assert_eq!(input, [1, 2, 10, 2, 2, 10, 7]);
+        assert_eq!(unfilter_registers(&input[range], 2), [1, 10, 3, 12]);
+    }
+
+    #[test]
+    fn machine_progress() {
+        assert_eq!(format_machine_progress("level.bin", 0), "PROGRESS level.bin 0");
+        assert_eq!(format_machine_progress("my level.bin", 100), "PROGRESS my level.bin 100");
+    }
+
+    #[test]
+    fn statuses() {
+        // Scripts depend on these values, so they must never change
+        assert_eq!(Status::Success as i32, 0);
+        assert_eq!(Status::Failed as i32, 1);
+        assert_eq!(Status::Skipped as i32, 2);
+        assert_eq!(Status::Usage as i32, 3);
+        assert_eq!(Status::MissingInput as i32, 4);
+        assert_eq!(Status::OutputExists as i32, 5);
+        assert_eq!(Status::VerificationFailed as i32, 6);
+        assert_eq!(Status::OverBudget as i32, 7);
+        assert_eq!(Status::Internal as i32, 8);
+        assert_eq!(Status::Cancelled as i32, 130);
     }
 }
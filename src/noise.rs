@@ -0,0 +1,12 @@
+//! Seeded pseudo-random bytes for tests and generated inputs. The generator is a plain linear
+//! congruential generator, so the bytes never change between platforms or releases.
+
+/// Return `len` pseudo-random bytes for the given seed. The bytes hardly compress.
+pub fn noise(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed;
+
+    (0..len).map(|_| {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        (state >> 16) as u8
+    }).collect()
+}
@@ -1,7 +1,93 @@
-use std::collections::VecDeque;
+use crate::{INITIAL_OFFSET, LiveStats};
+use crate::segments::Symbols;
+#[cfg(feature = "allocator-api2")]
+use std::alloc::Layout;
+#[cfg(feature = "allocator-api2")]
+use std::ptr::NonNull;
+#[cfg(feature = "allocator-api2")]
+use std::rc::Rc;
+
+#[cfg(feature = "allocator-api2")]
+use allocator_api2::alloc::{AllocError, Allocator as MemoryAllocator, Global};
+
+#[cfg(feature = "debug-dump")]
+use crate::dump::Dump;
+
+/// A handle to the allocator that the working memory of the optimizer is allocated from. Cloning
+/// the handle shares the allocator.
+#[cfg(feature = "allocator-api2")]
+#[derive(Clone)]
+pub struct ScratchAllocator(Rc<dyn MemoryAllocator>);
+
+#[cfg(feature = "allocator-api2")]
+impl ScratchAllocator {
+    pub fn new<A: MemoryAllocator + 'static>(allocator: A) -> Self {
+        Self(Rc::new(allocator))
+    }
+}
+
+#[cfg(feature = "allocator-api2")]
+impl Default for ScratchAllocator {
+    fn default() -> Self {
+        Self::new(Global)
+    }
+}
+
+// Safety: every call is forwarded to the shared allocator, which upholds the contract itself
+#[cfg(feature = "allocator-api2")]
+unsafe impl MemoryAllocator for ScratchAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.allocate(layout)
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.0.deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.grow(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn grow_zeroed(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.grow_zeroed(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.shrink(ptr, old_layout, new_layout)
+    }
+}
+
+/// The vectors that the optimizer keeps its working data in, which come from the
+/// [`ScratchAllocator`] when the `allocator-api2` feature is enabled.
+#[cfg(feature = "allocator-api2")]
+pub type ScratchVec<T> = allocator_api2::vec::Vec<T, ScratchAllocator>;
+
+#[cfg(not(feature = "allocator-api2"))]
+pub type ScratchVec<T> = Vec<T>;
+
+// Take the contents of a vector, leaving an empty vector that uses the same allocator
+#[cfg(feature = "allocator-api2")]
+fn take<T>(vector: &mut ScratchVec<T>) -> ScratchVec<T> {
+    let empty = ScratchVec::new_in(vector.allocator().clone());
+    std::mem::replace(vector, empty)
+}
+
+#[cfg(not(feature = "allocator-api2"))]
+fn take<T>(vector: &mut ScratchVec<T>) -> ScratchVec<T> {
+    std::mem::take(vector)
+}
 
-use crate::INITIAL_OFFSET;
-use crate::compressor::ProgressCallback;
+const MIN_ARENA_BLOCKS: usize = 4096;
+const MAX_ARENA_BLOCKS: usize = 16 * 1024 * 1024;
+
+/// The cost of the special null block, which stands in for positions that no block can end at
+/// under the [`Settings::max_literal_run`] limit. It is large enough to never be preferred over a
+/// real block, while leaving room to add the bits of a whole parse without overflowing.
+pub const UNREACHABLE_BITS: u32 = u32::MAX / 2;
 
 fn offset_ceiling(index: usize, offset_limit: usize) -> usize {
     if index > offset_limit {
@@ -20,33 +106,78 @@ fn elias_gamma_bits(value: u32) -> u32 {
     2 * (u32::BITS - value.leading_zeros() - 1) + 1
 }
 
+/// A block of the parse, which is either a literal run or a match ending at `index`.
+///
+/// The optimizer spends most of its time following and updating blocks that are scattered across
+/// the arena, so the fields use the narrowest types that fit. Offsets never exceed the window of
+/// 32640, and arena indices are stored in 32 bits, which limits the arena to 4G blocks. This makes
+/// a block take 20 bytes instead of 24.
 pub struct Block {
     pub bits: u32,
     pub index: i32,
-    pub offset: u32,
-    pub next_index: usize,
-    refcount: u32
+    next_index: u32,
+
+    // The number of references to this block. Once a block is freed this field is no longer needed
+    // for that purpose, and it is used to store the index of the next block in the free list.
+    refcount: u32,
+
+    pub offset: u16
+}
+
+impl Block {
+    /// Return the index of the block that this block links to, or `0` if there is none.
+    #[inline(always)]
+    pub fn next_index(&self) -> usize {
+        self.next_index as usize
+    }
 }
 
+/// An arena of blocks with an intrusive free list.
+///
+/// Blocks whose reference count drops to zero are pushed onto the free list, which is threaded
+/// through the freed blocks themselves. To keep the hot path short, reusing a block does not
+/// release the block that it used to link to. Those blocks are instead reclaimed in batches by
+/// [`Allocator::reclaim`], which recomputes all reference counts from the live roots.
 pub struct Allocator {
-    free_list: VecDeque<u32>,
-    blocks: Vec<Block>
+    free_head: usize,
+    free_count: usize,
+    blocks: ScratchVec<Block>
 }
 
 impl Allocator {
-    fn new() -> Self {
+    // Create an allocator that stores its blocks in the given vector, which is cleared and grown
+    // to the given capacity if needed.
+    fn new(mut blocks: ScratchVec<Block>, capacity: usize) -> Self {
+        blocks.clear();
+        blocks.reserve(capacity);
+
+        // Special block index only used for null values
+        blocks.push(Block {
+            bits: UNREACHABLE_BITS, index: 0, next_index: 0, refcount: 0, offset: 0
+        });
+
         Self {
-            free_list: VecDeque::new(),
-            blocks: {
-                let mut blocks = Vec::with_capacity(1024 * 1024);
+            free_head: 0,
+            free_count: 0,
+            blocks
+        }
+    }
+
+    // Push a block with a zero refcount onto the free list.
+    #[inline(always)]
+    fn free(&mut self, index: usize) {
+        self.blocks[index].refcount = self.free_head as u32;
+        self.free_head = index;
+        self.free_count += 1;
+    }
 
-                // Special block index only used for null values
-                blocks.push(Block {
-                    bits: 0, index: 0, offset: 0, next_index: 0, refcount: 0
-                });
+    // Drop a reference to a block, adding it to the free list if its refcount reaches zero.
+    #[inline(always)]
+    fn release(&mut self, index: usize) {
+        self.blocks[index].refcount -= 1;
 
-                blocks
-            }
+        if self.blocks[index].refcount == 0 {
+            self.free(index);
         }
     }
 
@@ -57,42 +188,46 @@ impl Allocator {
         self.blocks[next_index].refcount += 1;
 
         if *ptr != 0 {
-            self.blocks[*ptr].refcount -= 1;
-
-            if self.blocks[*ptr].refcount == 0 {
-                self.free_list.push_back(*ptr as u32);
-            }
+            self.release(*ptr);
         }
 
         *ptr = next_index;
     }
 
+    // Drop the reference that *ptr holds, if any, and clear it.
     #[inline(always)]
-    fn assign_new(&mut self, ptr: &mut usize, bits: u32, index: i32, offset: u32, next_index: usize) {
+    fn clear(&mut self, ptr: &mut usize) {
+        if *ptr != 0 {
+            self.release(*ptr);
+            *ptr = 0;
+        }
+    }
+
+    #[inline(always)]
+    fn assign_new(&mut self, ptr: &mut usize, bits: u32, index: i32, offset: u16, next_index: usize) {
         if next_index != 0 {
             self.blocks[next_index].refcount += 1;
         }
 
         if *ptr != 0 {
-            self.blocks[*ptr].refcount -= 1;
-
-            if self.blocks[*ptr].refcount == 0 {
-                self.free_list.push_back(*ptr as u32);
-            }
+            self.release(*ptr);
         }
 
         let block = Block {
             bits,
             index,
-            offset,
-            next_index,
-            refcount: 1
+            next_index: next_index as u32,
+            refcount: 1,
+            offset
         };
 
-        *ptr = if let Some(i) = self.free_list.pop_front() {
-            self.blocks[i as usize] = block;
+        *ptr = if self.free_head != 0 {
+            let i = self.free_head;
+            self.free_head = self.blocks[i].refcount as usize;
+            self.free_count -= 1;
+            self.blocks[i] = block;
 
-            i as usize
+            i
         } else {
             // Nothing in the free list, allocate a block
             self.blocks.push(block);
@@ -100,82 +235,371 @@ impl Allocator {
         };
     }
 
+    /// Return the number of blocks that can be allocated without growing the arena.
+    fn available(&self) -> usize {
+        self.free_count + self.blocks.capacity() - self.blocks.len()
+    }
+
+    /// Recompute all reference counts by tracing the chains reachable from the given roots, and
+    /// rebuild the free list from the blocks that turn out to be unreachable. This reclaims the
+    /// blocks that were only kept alive by the links of reused blocks.
+    ///
+    /// If less than half of the arena is available afterwards, its capacity is doubled, so that
+    /// the cost of reclaiming stays proportional to the number of allocations.
+    fn reclaim(&mut self, roots: &[&[usize]]) {
+        for block in self.blocks.iter_mut() {
+            block.refcount = 0;
+        }
+
+        let mut stack = Vec::new();
+
+        for &root in roots.iter().flat_map(|roots| roots.iter()) {
+            if root != 0 {
+                if self.blocks[root].refcount == 0 {
+                    stack.push(root);
+                }
+
+                self.blocks[root].refcount += 1;
+            }
+        }
+
+        // Every reachable block is visited exactly once, contributing one reference to the block
+        // that it links to
+        while let Some(index) = stack.pop() {
+            let next_index = self.blocks[index].next_index();
+
+            if next_index != 0 {
+                if self.blocks[next_index].refcount == 0 {
+                    stack.push(next_index);
+                }
+
+                self.blocks[next_index].refcount += 1;
+            }
+        }
+
+        self.free_head = 0;
+        self.free_count = 0;
+
+        for index in (1..self.blocks.len()).rev() {
+            if self.blocks[index].refcount == 0 {
+                self.free(index);
+            }
+        }
+
+        if self.available() < self.blocks.capacity() / 2 {
+            self.blocks.reserve(self.blocks.capacity());
+        }
+    }
+
     pub fn get(&self, index: usize) -> &Block {
         &self.blocks[index]
     }
+
+    /// Return the number of bytes that are reserved for the arena.
+    pub fn memory(&self) -> usize {
+        self.blocks.capacity() * std::mem::size_of::<Block>()
+    }
+
+    /// Return for every block in the arena whether it is allocated, which is every block except the
+    /// special null block and the blocks on the free list. Unreachable blocks that have not been
+    /// reclaimed yet count as allocated.
+    #[cfg(feature = "debug-dump")]
+    pub fn allocated(&self) -> Vec<bool> {
+        let mut allocated = vec![true; self.blocks.len()];
+        allocated[0] = false;
+
+        let mut index = self.free_head;
+
+        while index != 0 {
+            allocated[index] = false;
+            index = self.blocks[index].refcount as usize;
+        }
+
+        allocated
+    }
+
+    /// Return the number of blocks in the arena, including the special null block.
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Reverse the direction of the chain ending at block `tail` by rewriting its links in place,
+    /// and return the index of the block that is now at its head, along with the number of blocks
+    /// in the chain. This invalidates the reference counts of the affected blocks, so it should
+    /// only be used once optimization is complete.
+    fn reverse_chain(&mut self, tail: usize) -> (usize, usize) {
+        let mut previous = 0;
+        let mut current = tail;
+        let mut len = 0;
+
+        while current != 0 {
+            let next = self.blocks[current].next_index();
+            self.blocks[current].next_index = previous as u32;
+            previous = current;
+            current = next;
+            len += 1;
+        }
+
+        (previous, len)
+    }
 }
 
-pub fn optimize(
-    input: &[u8],
-    skip: usize,
-    offset_limit: usize,
-    progress_callback: &mut ProgressCallback
-) -> (Allocator, usize) {
-    let mut allocator = Allocator::new();
+/// The optimal parse of an input slice, stored as a chain of blocks in forward order.
+pub struct Chain {
+    pub allocator: Allocator,
 
-    let max_offset = offset_ceiling(input.len() - 1, offset_limit);
+    /// The number of blocks that the arena was initially sized for.
+    pub estimated_blocks: usize,
+
+    /// The index of the first block, which is a placeholder that precedes the actual blocks.
+    pub head: usize,
+
+    /// The number of skipped input bytes that precede the first block.
+    pub skip: usize,
+
+    /// The total number of bits required to encode the chain, which is at least
+    /// [`UNREACHABLE_BITS`] if the input can't be parsed within the literal run limit.
+    pub bits: u32,
+
+    /// The number of literal runs and matches in the chain, not counting the placeholder.
+    pub tokens: usize,
+
+    /// The peak number of bytes used by the optimizer's data structures.
+    pub peak_memory: usize
+}
+
+/// The allocations used by the optimizer, which can be reused when optimizing several inputs in
+/// succession. The arena is handed over to the resulting [`Chain`], and can be returned using
+/// [`Scratch::recycle`] once the chain has been encoded.
+#[cfg_attr(not(feature = "allocator-api2"), derive(Default))]
+pub struct Scratch {
+    blocks: ScratchVec<Block>,
+    last_literal: ScratchVec<usize>,
+    last_match: ScratchVec<usize>,
+    match_length: ScratchVec<usize>,
+    literal_base: ScratchVec<usize>,
+    optimal: ScratchVec<usize>,
+    best_length: ScratchVec<usize>,
+
+    /// The dump that the optimizer records its state to, if any.
+    #[cfg(feature = "debug-dump")]
+    pub dump: Option<Dump>
+}
 
-    // Allocate all main data structures at once
-    let mut last_literal = vec![0; max_offset + 1];
-    let mut last_match = vec![0; max_offset + 1];
-    let mut optimal = vec![0; input.len()];
-    let mut match_length: Vec<usize> = vec![0; max_offset + 1];
-    let mut best_length: Vec<usize> = vec![0; input.len()];
+#[cfg(feature = "allocator-api2")]
+impl Default for Scratch {
+    fn default() -> Self {
+        Self::new_in(ScratchAllocator::default())
+    }
+}
+
+impl Scratch {
+    /// Create scratch allocations that are allocated from the given allocator.
+    #[cfg(feature = "allocator-api2")]
+    pub fn new_in(allocator: ScratchAllocator) -> Self {
+        Self {
+            blocks: ScratchVec::new_in(allocator.clone()),
+            last_literal: ScratchVec::new_in(allocator.clone()),
+            last_match: ScratchVec::new_in(allocator.clone()),
+            match_length: ScratchVec::new_in(allocator.clone()),
+            literal_base: ScratchVec::new_in(allocator.clone()),
+            optimal: ScratchVec::new_in(allocator.clone()),
+            best_length: ScratchVec::new_in(allocator),
+            #[cfg(feature = "debug-dump")]
+            dump: None
+        }
+    }
 
-    if input.len() > 2 {
-        best_length[2] = 2;
+    /// Take back the arena of a chain that is no longer needed.
+    pub fn recycle(&mut self, chain: Chain) {
+        self.blocks = chain.allocator.blocks;
     }
+}
+
+// Clear a vector and fill it with the given number of zeroes, reusing its allocation
+fn zeroed(mut vector: ScratchVec<usize>, len: usize) -> ScratchVec<usize> {
+    vector.clear();
+    vector.resize(len, 0);
+    vector
+}
+
+/// Estimate the number of blocks that the arena will need when optimizing an input of the given
+/// length, so that it can be allocated in one go.
+///
+/// The arena only has to hold the blocks that are still reachable, since everything else is
+/// periodically reclaimed. Measurements on text, graphics, random and constant data show that the
+/// reachable blocks are dominated by the chains hanging off each offset in the window, with the
+/// optimal chain for each position adding a smaller share. The measured peaks range from about 1.5
+/// blocks per window offset for constant data to 30 for text, plus 1 to 3 blocks per position.
+/// The estimate uses 32 and 2 respectively, so that compressible data rarely needs to grow the
+/// arena, which is where the optimizer spends most of its time.
+pub fn estimate_blocks(length: usize, skip: usize, offset_limit: usize) -> usize {
+    let positions = length.saturating_sub(skip);
+    let window = offset_ceiling(length.saturating_sub(1), offset_limit);
+
+    (32 * window + 2 * positions).clamp(MIN_ARENA_BLOCKS, MAX_ARENA_BLOCKS)
+}
+
+/// The settings that control how thoroughly the optimizer searches for the optimal parse.
+#[derive(Clone, Copy)]
+pub struct Settings {
+    /// The largest offset that a match may use.
+    pub offset_limit: usize,
+
+    /// The number of new offsets that are considered as match candidates for every position.
+    pub candidate_limit: usize,
+
+    /// Whether literal runs may also start from a match other than the most recent one for its
+    /// offset, which can find cheaper parses than the reference implementation.
+    pub extreme_mode: bool,
+
+    /// The maximum number of symbols in a literal run, or `usize::MAX` for no limit.
+    pub max_literal_run: usize,
+
+    /// The number of bits that every token costs on top of its encoded size, which makes the
+    /// optimizer prefer parses with fewer tokens. The bits of the resulting chain don't include
+    /// this penalty.
+    pub token_cost: u32
+}
+
+/// Find the optimal parse of the input, which is a sequence of bytes for regular ZX0 data or a
+/// sequence of words for the word variant. Literals cost the full size of a symbol, while offsets
+/// and lengths are counted in symbols.
+///
+/// The parse starts out as if a match with the given initial offset directly precedes the first
+/// position, which is [`INITIAL_OFFSET`] for a new stream.
+pub fn optimize<T: Copy + PartialEq, I: Symbols<T> + ?Sized>(
+    input: &I,
+    skip: usize,
+    initial_offset: usize,
+    settings: Settings,
+    scratch: &mut Scratch,
+    live_stats: Option<&LiveStats>,
+    progress_callback: &mut dyn FnMut(f32)
+) -> Chain {
+    let symbol_bytes = std::mem::size_of::<T>();
+    let symbol_bits = 8 * symbol_bytes as u32;
+    let Settings { offset_limit, candidate_limit, extreme_mode, max_literal_run, token_cost } = settings;
+
+    let estimated_blocks = estimate_blocks(input.len(), skip, offset_limit);
+    let mut allocator = Allocator::new(take(&mut scratch.blocks), estimated_blocks);
+
+    let max_offset = offset_ceiling(input.len() - 1, offset_limit);
+
+    // Allocate the data structures that are indexed by offset. These are bounded by the window
+    // size.
+    let mut last_literal = zeroed(take(&mut scratch.last_literal), max_offset + 1);
+    let mut last_match = zeroed(take(&mut scratch.last_match), max_offset + 1);
+    let mut match_length = zeroed(take(&mut scratch.match_length), max_offset + 1);
+
+    // In extreme mode every offset also remembers the match that is the cheapest base for a long
+    // literal run. The reference implementation only ever continues with literals after the most
+    // recent match for an offset, even when an earlier match was cheaper once the cost of the
+    // literals in between is taken into account.
+    let mut literal_base = zeroed(take(&mut scratch.literal_base), if extreme_mode { max_offset + 1 } else { 0 });
+
+    // The data structures that scale with the input length are filled on demand instead, so that
+    // large inputs don't pay for initializing them up front. The optimal blocks are indexed by the
+    // position relative to the skipped bytes, and a new entry is pushed for every position. The
+    // best lengths are only ever needed up to the longest match seen so far.
+    let mut optimal = take(&mut scratch.optimal);
+    optimal.clear();
+    optimal.reserve(input.len() - skip);
+
+    let mut best_length = zeroed(take(&mut scratch.best_length), 3);
+    best_length[2] = 2;
 
     // Start with fake block
     allocator.assign_new(
-        &mut last_match[INITIAL_OFFSET],
+        &mut last_match[initial_offset],
         0,
         skip as i32 - 1,
-        INITIAL_OFFSET as u32,
+        initial_offset as u16,
         0
     );
 
+    if extreme_mode {
+        allocator.assign(&mut literal_base[initial_offset], last_match[initial_offset]);
+    }
+
+    if let Some(live_stats) = live_stats {
+        live_stats.update_input(0, allocator.len());
+    }
+
+    #[cfg(feature = "debug-dump")]
+    if let Some(dump) = &mut scratch.dump {
+        dump.header(input.len(), skip, initial_offset, settings);
+    }
+
     // Process remaining bytes
     for index in skip..input.len() {
+        let position = index - skip;
+        optimal.push(0);
+
         if index % 128 == 0 {
-            progress_callback(index as f32 / (input.len() - skip) as f32);
+            progress_callback((index - skip) as f32 / (input.len() - skip) as f32);
+
+            if let Some(live_stats) = live_stats {
+                live_stats.update_input(position * symbol_bytes, allocator.len());
+            }
         }
 
+        let symbol = input.at(index);
         let mut best_length_size = 2;
+        let mut candidates = 0;
         let max_offset = offset_ceiling(index, offset_limit);
 
+        // Every offset allocates at most two blocks, so reclaim unreachable blocks if the arena
+        // might have to grow while processing this position
+        if allocator.available() < 2 * max_offset {
+            allocator.reclaim(&[&last_literal, &last_match, &literal_base, &optimal[..position]]);
+        }
+
         for offset in 1..=max_offset {
-            if index >= offset && index != skip && input[index] == input[index - offset] {
+            if index >= offset && index != skip && symbol == input.at(index - offset) {
+                let mut matched = false;
+
                 // Copy from last offset
                 if last_literal[offset] != 0 {
                     let length = index as i32 - allocator.get(last_literal[offset]).index;
-                    let bits = allocator.get(last_literal[offset]).bits + 1 + elias_gamma_bits(length as u32);
+                    let bits = allocator.get(last_literal[offset]).bits + 1 + elias_gamma_bits(length as u32) + token_cost;
 
                     allocator.assign_new(
                         &mut last_match[offset],
-                        bits, index as i32, offset as u32, last_literal[offset]
+                        bits, index as i32, offset as u16, last_literal[offset]
                     );
+                    matched = true;
 
-                    if optimal[index] == 0 || allocator.get(optimal[index]).bits > bits {
-                        allocator.assign(&mut optimal[index], last_match[offset]);
+                    if optimal[position] == 0 || allocator.get(optimal[position]).bits > bits {
+                        allocator.assign(&mut optimal[position], last_match[offset]);
                     }
                 }
 
                 // Copy from new offset
                 match_length[offset] += 1;
-                if match_length[offset] > 1 {
+
+                // Only the nearest candidate_limit offsets are considered as new offset candidates
+                // for each position. Skipping a candidate never invalidates the parse, it merely
+                // makes it less likely to be optimal.
+                if match_length[offset] > 1 && candidates < candidate_limit {
+                    candidates += 1;
+
+                    if best_length.len() <= match_length[offset] {
+                        best_length.resize(match_length[offset] + 1, 0);
+                    }
+
                     if best_length_size < match_length[offset] {
-                        let mut bits = allocator.get(optimal[index - best_length[best_length_size]]).bits + elias_gamma_bits(best_length[best_length_size] as u32 - 1);
+                        let mut bits = allocator.get(optimal[position - best_length[best_length_size]]).bits + elias_gamma_bits(best_length[best_length_size] as u32 - 1);
 
                         loop {
                             best_length_size += 1;
-                            let bits2 = allocator.get(optimal[index - best_length_size]).bits + elias_gamma_bits(best_length_size as u32 - 1);
+                            let bits2 = allocator.get(optimal[position - best_length_size]).bits + elias_gamma_bits(best_length_size as u32 - 1);
 
                             if bits2 <= bits {
                                 best_length[best_length_size] = best_length_size;
                                 bits = bits2;
                             } else {
-                                best_length[best_length_size] = best_length[best_length_size as usize - 1];
+                                best_length[best_length_size] = best_length[best_length_size - 1];
                             }
 
                             if best_length_size >= match_length[offset] {
@@ -185,39 +609,204 @@ pub fn optimize(
                     }
 
                     let length = best_length[match_length[offset]];
-                    let bits = allocator.get(optimal[index - length]).bits + 8 + elias_gamma_bits((offset as u32 - 1) / 128 + 1) + elias_gamma_bits(length as u32 - 1);
+                    let bits = allocator.get(optimal[position - length]).bits + 8 + elias_gamma_bits((offset as u32 - 1) / 128 + 1) + elias_gamma_bits(length as u32 - 1) + token_cost;
+
+                    // Under a literal run limit the match may start at a position that no block
+                    // ends at, in which case it must not replace a match that literals can follow
+                    let reachable = optimal[position - length] != 0;
 
-                    if last_match[offset] == 0 || allocator.get(last_match[offset]).index != index as i32 || allocator.get(last_match[offset]).bits > bits {
+                    if reachable && (last_match[offset] == 0 || allocator.get(last_match[offset]).index != index as i32 || allocator.get(last_match[offset]).bits > bits) {
                         allocator.assign_new(
                             &mut last_match[offset],
-                            bits, index as i32, offset as u32, optimal[index - length as usize]
+                            bits, index as i32, offset as u16, optimal[position - length]
                         );
+                        matched = true;
 
-                        if optimal[index] == 0 || allocator.get(optimal[index]).bits > bits {
-                            allocator.assign(&mut optimal[index], last_match[offset]);
+                        if optimal[position] == 0 || allocator.get(optimal[position]).bits > bits {
+                            allocator.assign(&mut optimal[position], last_match[offset]);
                         }
                     }
                 }
+
+                // A match at this position is a better base for literals than the current one if
+                // it is cheaper after paying for the literals that separate both
+                if extreme_mode && matched {
+                    let current = allocator.get(last_match[offset]);
+
+                    let replace = literal_base[offset] == 0 || {
+                        let base = allocator.get(literal_base[offset]);
+                        let literals = (current.index - base.index) as u32 * symbol_bits;
+                        current.bits <= base.bits + literals
+                    };
+
+                    if replace {
+                        allocator.assign(&mut literal_base[offset], last_match[offset]);
+                    }
+                }
             } else {
                 // Copy literals
                 match_length[offset] = 0;
 
                 if last_match[offset] != 0 {
-                    let length = index as i32 - allocator.get(last_match[offset]).index;
-                    let bits = allocator.get(last_match[offset]).bits + 1 + elias_gamma_bits(length as u32) + length as u32 * 8;
+                    let mut base = last_match[offset];
+                    let mut length = index as i32 - allocator.get(base).index;
+                    let mut bits = allocator.get(base).bits + 1 + elias_gamma_bits(length as u32) + length as u32 * symbol_bits + token_cost;
+
+                    if extreme_mode && literal_base[offset] != 0 && literal_base[offset] != base {
+                        let length2 = index as i32 - allocator.get(literal_base[offset]).index;
+                        let bits2 = allocator.get(literal_base[offset]).bits + 1 + elias_gamma_bits(length2 as u32) + length2 as u32 * symbol_bits + token_cost;
+
+                        if bits2 < bits && length2 as usize <= max_literal_run {
+                            base = literal_base[offset];
+                            length = length2;
+                            bits = bits2;
+                        }
+                    }
 
-                    allocator.assign_new(
-                        &mut last_literal[offset],
-                        bits, index as i32, 0, last_match[offset]
-                    );
+                    if length as usize <= max_literal_run {
+                        allocator.assign_new(
+                            &mut last_literal[offset],
+                            bits, index as i32, 0, base
+                        );
 
-                    if optimal[index] == 0 || allocator.get(optimal[index]).bits > bits {
-                        allocator.assign(&mut optimal[index], last_literal[offset]);
+                        if optimal[position] == 0 || allocator.get(optimal[position]).bits > bits {
+                            allocator.assign(&mut optimal[position], last_literal[offset]);
+                        }
+                    } else {
+                        // The most recent match is too far back, so no literal run that is short
+                        // enough ends here, and the next match can't copy from the last offset
+                        allocator.clear(&mut last_literal[offset]);
                     }
                 }
             }
         }
+
+        #[cfg(feature = "debug-dump")]
+        if let Some(dump) = &mut scratch.dump {
+            if position.is_multiple_of(dump.interval()) || index == input.len() - 1 {
+                dump.snapshot(position, &allocator, &last_literal, &last_match, optimal[position]);
+            }
+        }
+    }
+
+    // The optimal chain is linked back-to-front, so flip it around in place. The placeholder at
+    // its head is not a token, and every token carries the penalty of the settings.
+    let tail = optimal[input.len() - 1 - skip];
+    let (head, len) = allocator.reverse_chain(tail);
+    let tokens = len.saturating_sub(1);
+    let bits = allocator.get(tail).bits - tokens as u32 * token_cost;
+
+    if let Some(live_stats) = live_stats {
+        live_stats.update_input((input.len() - skip) * symbol_bytes, allocator.len());
+    }
+
+    // None of the data structures ever shrink, so their current sizes are also their peak sizes
+    let peak_memory = allocator.memory() + std::mem::size_of::<usize>() * (
+        last_literal.capacity() +
+        last_match.capacity() +
+        match_length.capacity() +
+        literal_base.capacity() +
+        optimal.capacity() +
+        best_length.capacity()
+    );
+
+    *scratch = Scratch {
+        blocks: take(&mut scratch.blocks),
+        last_literal,
+        last_match,
+        match_length,
+        literal_base,
+        optimal,
+        best_length,
+        #[cfg(feature = "debug-dump")]
+        dump: scratch.dump.take()
+    };
+
+    Chain {
+        allocator,
+        estimated_blocks,
+        head,
+        skip,
+        bits,
+        tokens,
+        peak_memory
+    }
+}
+
+/// Join the parses of consecutive parts of the input into a single chain. Every part is given as
+/// the blocks of its parse, each consisting of the index of its last symbol and its offset, and
+/// the parts must cover the input after the skipped symbols without gaps.
+///
+/// Since every part was parsed on its own, the blocks are adapted to the stream that they end up
+/// in. A literal run at the start of a part is merged with one at the end of the part before it,
+/// and every match is costed for the offset that was actually used last, so a match from what a
+/// part assumed to be the last offset may have to be encoded with a new offset instead.
+///
+/// Such a mismatch can only occur before the first match that a part encodes with a new offset,
+/// since that offset is the last offset from then on in both views. Up to that point every match
+/// directly follows literals, because every part starts with literals, which is what makes it
+/// valid to encode these matches either way.
+pub fn join(parts: &[Vec<(i32, u32)>], skip: usize, symbol_bits: u32, scratch: &mut Scratch) -> Chain {
+    let mut blocks = Vec::<(i32, u32)>::with_capacity(parts.iter().map(Vec::len).sum());
+    let mut last_offset = INITIAL_OFFSET as u32;
+    let mut last_index = skip as i32 - 1;
+
+    for &(index, mut offset) in parts.iter().flatten() {
+        // A single byte can't be copied from a new offset, so it becomes a literal instead
+        if offset != 0 && offset != last_offset && index - last_index == 1 {
+            offset = 0;
+        }
+
+        if offset != 0 {
+            last_offset = offset;
+        }
+
+        last_index = index;
+
+        match blocks.last_mut() {
+            Some(last) if offset == 0 && last.1 == 0 => last.0 = index,
+            _ => blocks.push((index, offset))
+        }
     }
 
-    (allocator, optimal[input.len() - 1])
+    let estimated_blocks = blocks.len() + 2;
+    let mut allocator = Allocator::new(take(&mut scratch.blocks), estimated_blocks);
+
+    // Build the chain back-to-front like the optimizer does, starting with the fake block
+    let mut tail = 0;
+    allocator.assign_new(&mut tail, 0, skip as i32 - 1, INITIAL_OFFSET as u16, 0);
+
+    let mut bits = 0;
+    last_offset = INITIAL_OFFSET as u32;
+
+    for (index, offset) in blocks {
+        let length = (index - allocator.get(tail).index) as u32;
+
+        bits += if offset == 0 {
+            1 + elias_gamma_bits(length) + length * symbol_bits
+        } else if offset == last_offset {
+            1 + elias_gamma_bits(length)
+        } else {
+            8 + elias_gamma_bits((offset - 1) / 128 + 1) + elias_gamma_bits(length - 1)
+        };
+
+        if offset != 0 {
+            last_offset = offset;
+        }
+
+        let previous = tail;
+        allocator.assign_new(&mut tail, bits, index, offset as u16, previous);
+    }
+
+    let (head, len) = allocator.reverse_chain(tail);
+
+    Chain {
+        peak_memory: allocator.memory(),
+        allocator,
+        estimated_blocks,
+        head,
+        skip,
+        bits,
+        tokens: len - 1
+    }
 }
@@ -0,0 +1,103 @@
+//! Parsing of large inputs in chunks on multiple threads.
+//!
+//! The input after the skipped bytes is split into chunks that are parsed independently of each
+//! other, by as many threads as there are chunks or processor cores. Each chunk can use the window
+//! before it as its prefix, since the whole input is available up front. The parses of the chunks
+//! are then joined into a single chain, which is encoded as a regular ZX0 stream.
+//!
+//! The parse of a chunk can't take the chunks around it into account, so the output is usually
+//! slightly larger than when parsing the input as a whole. The difference shrinks as the chunks
+//! grow.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::INITIAL_OFFSET;
+use crate::optimize::{Chain, Scratch, Settings, join, optimize};
+use crate::segments::{Symbols, Window};
+
+/// Find a parse of the input by parsing chunks of `chunk_len` bytes in parallel and joining the
+/// results. When `chain_chunks` is set, every chunk uses the window before it as its prefix.
+/// Otherwise only the first chunk uses the skipped bytes as its prefix, and every other chunk is
+/// parsed as if it was the start of the input.
+///
+/// The threads use their own working memory, and only the joined chain uses the given scratch
+/// allocations. The progress callback is invoked whenever a chunk has been parsed.
+pub fn optimize_chunks<I: Symbols<u8> + Sync + ?Sized>(
+    input: &I,
+    skip: usize,
+    chunk_len: usize,
+    chain_chunks: bool,
+    settings: Settings,
+    scratch: &mut Scratch,
+    progress_callback: &mut dyn FnMut(f32)
+) -> Chain {
+    let chunks = (input.len() - skip).div_ceil(chunk_len);
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get()).min(chunks);
+
+    let next_chunk = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    let mut parts = vec![Vec::new(); chunks];
+    let mut chunk_peak_memory = 0;
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let next_chunk = &next_chunk;
+
+            scope.spawn(move || {
+                let mut scratch = Scratch::default();
+
+                loop {
+                    let chunk = next_chunk.fetch_add(1, Ordering::Relaxed);
+
+                    if chunk >= chunks {
+                        break;
+                    }
+
+                    let start = skip + chunk * chunk_len;
+                    let end = (start + chunk_len).min(input.len());
+
+                    // Anything further back than the window can't be referenced anyway
+                    let prefix_len = if chunk == 0 || chain_chunks { start.min(settings.offset_limit) } else { 0 };
+                    let base = start - prefix_len;
+
+                    let window = Window::new(input, base..end);
+                    let chain = optimize(&window, prefix_len, INITIAL_OFFSET, settings, &mut scratch, None, &mut |_| ());
+
+                    let mut blocks = Vec::new();
+                    let mut block = chain.allocator.get(chain.head);
+
+                    while block.next_index() != 0 {
+                        block = chain.allocator.get(block.next_index());
+                        blocks.push((base as i32 + block.index, block.offset as u32));
+                    }
+
+                    let peak_memory = chain.peak_memory;
+                    scratch.recycle(chain);
+
+                    // The receiver only goes away when the progress callback panicked
+                    if sender.send((chunk, blocks, peak_memory)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        drop(sender);
+
+        for (done, (chunk, blocks, peak_memory)) in receiver.iter().enumerate() {
+            parts[chunk] = blocks;
+            chunk_peak_memory = chunk_peak_memory.max(peak_memory);
+            progress_callback((done + 1) as f32 / chunks as f32);
+        }
+    });
+
+    let mut chain = join(&parts, skip, 8, scratch);
+
+    // Every thread parses a chunk at a time, and the parts are held until all chunks are done
+    chain.peak_memory += threads * chunk_peak_memory + parts.iter().map(|part| part.capacity() * std::mem::size_of::<(i32, u32)>()).sum::<usize>();
+    chain
+}
@@ -0,0 +1,201 @@
+//! The profile that is printed with --profile, which breaks the time that was spent on a file down
+//! into its phases, along with the allocations that were made during each of them.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// Allocations are only counted once --profile asks for them, so that other runs only pay for
+// checking this flag
+static COUNTING: AtomicBool = AtomicBool::new(false);
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, extended with counters for the number of allocations and the number of
+/// bytes that were allocated. Reallocations count as allocations of their new size. Nothing is
+/// counted until [`enable_counting`] has been called.
+pub struct CountingAllocator;
+
+fn count(size: usize) {
+    if COUNTING.load(Ordering::Relaxed) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(size, Ordering::Relaxed);
+    }
+}
+
+/// Start counting allocations, which is needed for the allocations in a profile.
+pub fn enable_counting() {
+    COUNTING.store(true, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The time and allocations of a single phase.
+pub struct Phase {
+    /// The name of the phase.
+    pub name: &'static str,
+
+    /// The time that the phase took.
+    pub duration: Duration,
+
+    /// The number of allocations that were made during the phase.
+    pub allocations: usize,
+
+    /// The number of bytes that were allocated during the phase.
+    pub allocated_bytes: usize
+}
+
+/// A profile that is recorded one phase after another.
+pub struct Profile {
+    phases: Vec<Phase>,
+    start: Instant,
+    allocations: usize,
+    allocated_bytes: usize
+}
+
+impl Profile {
+    /// Start recording the first phase.
+    pub fn start() -> Self {
+        Self {
+            phases: Vec::new(),
+            start: Instant::now(),
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed)
+        }
+    }
+
+    /// End the current phase under the given name, and start recording the next one.
+    pub fn lap(&mut self, name: &'static str) {
+        let now = Instant::now();
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+
+        self.phases.push(Phase {
+            name,
+            duration: now - self.start,
+            allocations: allocations - self.allocations,
+            allocated_bytes: allocated_bytes - self.allocated_bytes
+        });
+
+        self.start = now;
+        self.allocations = allocations;
+        self.allocated_bytes = allocated_bytes;
+    }
+
+    /// Return the phases that have been recorded so far.
+    pub fn phases(&self) -> &[Phase] {
+        &self.phases
+    }
+}
+
+fn format_phase(phase: &Phase, total: Duration) -> String {
+    format!(
+        "    {:<10} {:>11.3} ms {:>6.1} % {:>9} allocations {:>10} KiB",
+        phase.name,
+        phase.duration.as_secs_f64() * 1000.0,
+        100.0 * phase.duration.as_secs_f64() / total.as_secs_f64().max(f64::MIN_POSITIVE),
+        phase.allocations,
+        phase.allocated_bytes.div_ceil(1024)
+    )
+}
+
+/// Format the recorded phases as a table, followed by the throughput of the optimize phase for the
+/// given number of input bytes and arena blocks.
+pub fn format_profile(phases: &[Phase], input_len: usize, arena_blocks: usize) -> String {
+    let total = Phase {
+        name: "total",
+        duration: phases.iter().map(|phase| phase.duration).sum(),
+        allocations: phases.iter().map(|phase| phase.allocations).sum(),
+        allocated_bytes: phases.iter().map(|phase| phase.allocated_bytes).sum()
+    };
+
+    let mut result = "Profile:\n".to_string();
+
+    for phase in phases.iter().chain([&total]) {
+        result.push_str(&format_phase(phase, total.duration));
+        result.push('\n');
+    }
+
+    if let Some(optimize) = phases.iter().find(|phase| phase.name == "optimize") {
+        let seconds = optimize.duration.as_secs_f64().max(f64::MIN_POSITIVE);
+
+        result.push_str(&format!(
+            "Optimizer: {:.1} KiB/s, {:.0} arena blocks/s ({} blocks)\n",
+            input_len as f64 / 1024.0 / seconds,
+            arena_blocks as f64 / seconds,
+            arena_blocks
+        ));
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{enable_counting, format_profile, Phase, Profile};
+
+    fn phase(name: &'static str, milliseconds: u64, allocations: usize, allocated_bytes: usize) -> Phase {
+        Phase {
+            name,
+            duration: Duration::from_millis(milliseconds),
+            allocations,
+            allocated_bytes
+        }
+    }
+
+    #[test]
+    fn profile() {
+        let phases = [
+            phase("read", 10, 2, 4096),
+            phase("optimize", 750, 9, 2 * 1024 * 1024),
+            phase("encode", 40, 1, 1000),
+            phase("write", 200, 0, 0)
+        ];
+
+        let profile = format_profile(&phases, 3 * 1024 * 1024, 1500000);
+        let lines = profile.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "Profile:");
+        assert_eq!(lines[1], "    read            10.000 ms    1.0 %         2 allocations          4 KiB");
+        assert_eq!(lines[2], "    optimize       750.000 ms   75.0 %         9 allocations       2048 KiB");
+        assert_eq!(lines[4], "    write          200.000 ms   20.0 %         0 allocations          0 KiB");
+        assert_eq!(lines[5], "    total         1000.000 ms  100.0 %        12 allocations       2053 KiB");
+        assert_eq!(lines[6], "Optimizer: 4096.0 KiB/s, 2000000 arena blocks/s (1500000 blocks)");
+        assert_eq!(lines.len(), 7);
+
+        // Allocations are attributed to the phase that made them
+        enable_counting();
+        let mut profile = Profile::start();
+        let buffer = vec![0u8; 100000];
+        profile.lap("allocate");
+
+        assert_eq!(profile.phases()[0].name, "allocate");
+        assert!(profile.phases()[0].allocations >= 1);
+        assert!(profile.phases()[0].allocated_bytes >= buffer.len());
+    }
+}
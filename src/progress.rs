@@ -0,0 +1,53 @@
+use std::ops::Range;
+
+/// An extension trait for progress callbacks, which makes it easy to report the progress of a
+/// compression operation as part of a larger pipeline.
+///
+/// ```
+/// use zx0::{Compressor, ProgressExt};
+///
+/// let mut overall = 0.0;
+///
+/// // Compression is the second of three equally long stages
+/// Compressor::new()
+///     .progress_callback((|progress| overall = progress).scoped(1.0 / 3.0..2.0 / 3.0))
+///     .compress(&[0; 64]);
+/// ```
+pub trait ProgressExt: FnMut(f32) + Sized {
+    /// Return a callback that maps progress values between `0.0` and `1.0` to the given range
+    /// before passing them on to this callback. Scoped callbacks can be nested, in which case the
+    /// ranges compose: `callback.scoped(0.5..1.0).scoped(0.0..0.5)` reports `0.5` to `0.75`.
+    fn scoped(mut self, range: Range<f32>) -> impl FnMut(f32) {
+        move |progress| self(range.start + progress * (range.end - range.start))
+    }
+}
+
+impl<F: FnMut(f32)> ProgressExt for F {}
+
+#[cfg(test)]
+mod tests {
+    use super::ProgressExt;
+
+    #[test]
+    fn scoped() {
+        let mut reported = Vec::new();
+
+        {
+            let mut callback = (|progress| reported.push(progress)).scoped(0.2..0.7);
+            callback(0.0);
+            callback(0.5);
+            callback(1.0);
+        }
+
+        assert_eq!(reported, [0.2, 0.45, 0.7]);
+        reported.clear();
+
+        {
+            let mut callback = (|progress| reported.push(progress)).scoped(0.5..1.0).scoped(0.0..0.5);
+            callback(0.0);
+            callback(1.0);
+        }
+
+        assert_eq!(reported, [0.5, 0.75]);
+    }
+}
@@ -0,0 +1,97 @@
+//! A pre-filter for register dumps of sound chips such as the AY-3-8910, the YM2149 or the SID.
+//!
+//! A register dump stores the values of all registers of the chip for every frame of the music.
+//! Most registers keep their value or change by a small step from one frame to the next, but the
+//! frames as a whole rarely repeat. The filter stores the change of every register since the
+//! previous frame, and moves the changes of every register into a stream of their own, which turns
+//! the dump into long runs of zeros and repeated steps.
+
+use crate::{Interleave, deinterleave, interleave};
+
+/// The number of registers in a frame of an AY-3-8910 or YM2149 register dump.
+pub const AY_REGISTERS: usize = 14;
+
+/// The number of registers in a frame of a SID register dump, which leaves out the read-only
+/// registers at the end.
+pub const SID_REGISTERS: usize = 25;
+
+/// Filter a register dump with frames of the given number of registers.
+///
+/// Every register value is replaced by its difference to the same register in the previous frame,
+/// and the differences are then split into one stream per register. The values of the first frame
+/// are stored as they are. The output has the same length as the input, and may end with a partial
+/// frame. Use [`unfilter_registers`] with the same number of registers to get the dump back.
+///
+/// # Panics
+///
+/// Panics if `registers` is zero.
+pub fn filter_registers(input: &[u8], registers: usize) -> Vec<u8> {
+    assert!(registers > 0, "a frame must have at least 1 register");
+
+    let deltas: Vec<u8> = input.iter().enumerate().map(|(i, &value)| {
+        if i < registers { value } else { value.wrapping_sub(input[i - registers]) }
+    }).collect();
+
+    deinterleave(&deltas, Interleave::Bytes(registers))
+}
+
+/// Restore a register dump that was filtered using [`filter_registers`] with the same number of
+/// registers.
+///
+/// # Panics
+///
+/// Panics if `registers` is zero.
+pub fn unfilter_registers(input: &[u8], registers: usize) -> Vec<u8> {
+    assert!(registers > 0, "a frame must have at least 1 register");
+
+    let mut output = interleave(input, Interleave::Bytes(registers));
+
+    for i in registers..output.len() {
+        output[i] = output[i].wrapping_add(output[i - registers]);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AY_REGISTERS, filter_registers, unfilter_registers};
+    use crate::Compressor;
+
+    // An AY dump of a repeating note that slides down while its volume decays, with a constant
+    // mixer and envelope shape
+    fn dump() -> Vec<u8> {
+        (0..1500u32).flat_map(|frame| {
+            let period = 0x200 + (frame % 50) * 3;
+            let volume = 15 - (frame % 50 / 4) as u8;
+
+            [period as u8, (period >> 8) as u8, 0, 0, 0, 0, 0, 0x3e, volume, 0, 0, 0, 0, 0x0e]
+        }).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let input = dump();
+
+        for len in [0, 1, 13, 14, 15, 100, input.len()] {
+            for registers in [1, 3, AY_REGISTERS] {
+                let filtered = filter_registers(&input[..len], registers);
+                assert_eq!(filtered.len(), len);
+                assert_eq!(unfilter_registers(&filtered, registers), &input[..len]);
+            }
+        }
+
+        assert_eq!(filter_registers(&[1, 10, 3, 12, 2, 10], 2), [1, 2, 255, 10, 2, 254]);
+    }
+
+    #[test]
+    fn compresses_better() {
+        let input = dump();
+        let mut compressor = Compressor::new();
+        compressor.quick_mode(true);
+
+        let plain = compressor.compress(&input).output.len();
+        let filtered = compressor.compress(&filter_registers(&input, AY_REGISTERS)).output.len();
+        assert!(filtered < plain / 2, "{} bytes filtered, {} bytes plain", filtered, plain);
+    }
+}
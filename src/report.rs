@@ -0,0 +1,224 @@
+//! The report command, which compresses every file in a directory without writing any output, and
+//! summarizes how much compression saves for each file extension and subdirectory.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use zx0::batch::{Batch, Config, Outcome};
+
+use crate::collect_batch_files;
+
+/// The sizes of a single file that was compressed.
+pub struct Entry {
+    /// The path of the file relative to the directory of the report.
+    pub path: PathBuf,
+
+    /// The size of the file.
+    pub input_size: usize,
+
+    /// The size of the compressed data.
+    pub output_size: usize
+}
+
+#[derive(Default)]
+struct Group {
+    files: usize,
+    input_size: usize,
+    output_size: usize
+}
+
+// The lowercase extension of a file including its dot, which is how extensions are grouped
+fn extension_key(entry: &Entry) -> String {
+    match entry.path.extension() {
+        Some(extension) => format!(".{}", extension.to_string_lossy().to_lowercase()),
+        None => "(none)".to_string()
+    }
+}
+
+// The top level subdirectory that contains a file, or . for files directly in the directory
+fn directory_key(entry: &Entry) -> String {
+    let mut components = entry.path.components();
+
+    match (components.next(), components.next()) {
+        (Some(directory), Some(_)) => format!("{}/", directory.as_os_str().to_string_lossy()),
+        _ => ".".to_string()
+    }
+}
+
+fn ratio(input_size: usize, output_size: usize) -> f32 {
+    input_size as f32 / output_size.max(1) as f32
+}
+
+fn saved(input_size: usize, output_size: usize) -> f32 {
+    100.0 * (1.0 - output_size as f32 / input_size.max(1) as f32)
+}
+
+fn format_row(name: &str, group: &Group) -> String {
+    format!(
+        "{:<24} {:>7} {:>12} {:>12} {:>7.3} {:>7.1}%\n",
+        name,
+        group.files,
+        group.input_size,
+        group.output_size,
+        ratio(group.input_size, group.output_size),
+        saved(group.input_size, group.output_size)
+    )
+}
+
+// Format a table with a row for every group of files, with the largest groups first
+fn format_table(title: &str, entries: &[Entry], key: fn(&Entry) -> String) -> String {
+    let mut groups = BTreeMap::<String, Group>::new();
+
+    for entry in entries {
+        let group = groups.entry(key(entry)).or_default();
+        group.files += 1;
+        group.input_size += entry.input_size;
+        group.output_size += entry.output_size;
+    }
+
+    let mut groups = groups.into_iter().collect::<Vec<_>>();
+    groups.sort_by_key(|(_, group)| std::cmp::Reverse(group.input_size));
+
+    let mut result = format!("{:<24} {:>7} {:>12} {:>12} {:>7} {:>8}\n", title, "Files", "Before", "After", "Ratio", "Saved");
+
+    for (name, group) in &groups {
+        result.push_str(&format_row(name, group));
+    }
+
+    result
+}
+
+/// Format the report for the given files, listing the `worst` files that compress the least.
+pub fn format_report(entries: &[Entry], worst: usize) -> String {
+    let total = Group {
+        files: entries.len(),
+        input_size: entries.iter().map(|entry| entry.input_size).sum(),
+        output_size: entries.iter().map(|entry| entry.output_size).sum()
+    };
+
+    let mut result = format_table("Extension", entries, extension_key);
+    result.push('\n');
+    result.push_str(&format_table("Directory", entries, directory_key));
+    result.push('\n');
+    result.push_str(&format_row("Total", &total));
+
+    let mut offenders = entries.iter().collect::<Vec<_>>();
+    offenders.sort_by(|a, b| ratio(a.input_size, a.output_size).total_cmp(&ratio(b.input_size, b.output_size)));
+    offenders.truncate(worst);
+
+    if !offenders.is_empty() {
+        result.push_str("\nLeast compressible files:\n");
+
+        for entry in offenders {
+            result.push_str(&format!(
+                "    {} ({} -> {} bytes, ratio {:.3})\n",
+                entry.path.display(),
+                entry.input_size,
+                entry.output_size,
+                ratio(entry.input_size, entry.output_size)
+            ));
+        }
+    }
+
+    result
+}
+
+/// Compress every file in the directory in parallel and print the report. Empty files are left
+/// out, and files that can't be read or compressed are listed separately. Returns whether every
+/// file was compressed.
+pub fn report(directory: &Path, config: Config, worst: usize) -> bool {
+    let mut files = Vec::new();
+    collect_batch_files(directory, &mut files);
+
+    let mut batch = Batch::new(0);
+    let mut input_sizes = Vec::new();
+    let mut empty = 0;
+    let mut failures = Vec::new();
+
+    for path in &files {
+        let relative = path.strip_prefix(directory).unwrap_or(path);
+
+        match fs::read(path) {
+            Ok(input) if input.is_empty() => empty += 1,
+            Ok(input) => {
+                input_sizes.push(input.len());
+                batch.submit(relative.to_string_lossy(), input, config.clone());
+            },
+            Err(err) => failures.push(format!("{}: {}", relative.display(), err))
+        }
+    }
+
+    let mut entries = Vec::new();
+
+    for job in batch.results() {
+        match job.outcome {
+            Outcome::Compressed(result) => entries.push(Entry {
+                path: PathBuf::from(job.name),
+                input_size: input_sizes[job.index],
+                output_size: result.output.len()
+            }),
+            Outcome::Cancelled => failures.push(format!("{}: cancelled", job.name)),
+            Outcome::Failed(message) => failures.push(format!("{}: {}", job.name, message))
+        }
+    }
+
+    print!("{}", format_report(&entries, worst));
+
+    if empty > 0 {
+        println!();
+        println!("{} empty files were left out", empty);
+    }
+
+    if !failures.is_empty() {
+        println!();
+        println!("{} files could not be compressed:", failures.len());
+
+        for failure in &failures {
+            println!("    {}", failure);
+        }
+    }
+
+    failures.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{format_report, Entry};
+
+    fn entry(path: &str, input_size: usize, output_size: usize) -> Entry {
+        Entry {
+            path: PathBuf::from(path),
+            input_size,
+            output_size
+        }
+    }
+
+    #[test]
+    fn report() {
+        let entries = [
+            entry("gfx/title.SCR", 6912, 1728),
+            entry("gfx/font.bin", 768, 512),
+            entry("music.pt3", 3000, 2900),
+            entry("README", 1000, 500)
+        ];
+
+        let report = format_report(&entries, 2);
+        let lines = report.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "Extension                  Files       Before        After   Ratio    Saved");
+        assert_eq!(lines[1], ".scr                           1         6912         1728   4.000    75.0%");
+        assert_eq!(lines[2], ".pt3                           1         3000         2900   1.034     3.3%");
+        assert_eq!(lines[3], "(none)                         1         1000          500   2.000    50.0%");
+        assert_eq!(lines[4], ".bin                           1          768          512   1.500    33.3%");
+        assert_eq!(lines[7], "gfx/                           2         7680         2240   3.429    70.8%");
+        assert_eq!(lines[8], ".                              2         4000         3400   1.176    15.0%");
+        assert_eq!(lines[10], "Total                          4        11680         5640   2.071    51.7%");
+        assert_eq!(lines[12], "Least compressible files:");
+        assert_eq!(lines[13], "    music.pt3 (3000 -> 2900 bytes, ratio 1.034)");
+        assert_eq!(lines[14], "    gfx/font.bin (768 -> 512 bytes, ratio 1.500)");
+        assert_eq!(lines.len(), 15);
+    }
+}
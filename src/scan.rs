@@ -0,0 +1,218 @@
+//! The scan command, which looks for plausible ZX0 streams inside a larger binary such as a ROM or
+//! a snapshot, by trying to decompress a stream at every offset of the file.
+
+use std::collections::BTreeMap;
+
+use zx0::{DecompressStatus, Decompressor};
+
+/// The settings that determine which streams are found.
+#[derive(Clone, Copy, Debug)]
+pub struct ScanSettings {
+    /// Look for streams in the classic (V1) file format.
+    pub classic_mode: bool,
+
+    /// Look for streams that were compressed backwards, which are decompressed from their end.
+    pub backwards_mode: bool,
+
+    /// The minimum decompressed length of a stream, below which streams are too likely to be
+    /// accidental.
+    pub min_len: usize,
+
+    /// The maximum decompressed length of a stream, which limits the time spent on every offset.
+    pub max_len: usize
+}
+
+/// A plausible stream that was found in the file. Offsets are those of the byte at which
+/// decompression starts, which is the last byte of the stream when compressing backwards.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    /// The most likely start of the stream.
+    pub offset: usize,
+
+    /// The earliest start from which the stream can be decompressed.
+    pub earliest_offset: usize,
+
+    /// The length of the compressed data from the most likely start.
+    pub compressed_len: usize,
+
+    /// The length of the decompressed data from the most likely start.
+    pub decompressed_len: usize
+}
+
+impl Candidate {
+    /// Return the offset of the byte that holds the end marker.
+    pub fn end_marker(&self, backwards_mode: bool) -> usize {
+        if backwards_mode { self.offset + 1 - self.compressed_len } else { self.offset + self.compressed_len - 1 }
+    }
+}
+
+// Decompress the stream that starts at the beginning of the data, and return the length of the
+// compressed and the decompressed data if it is a valid stream that fits in the output buffer
+fn try_decompress(data: &[u8], output: &mut [u8], settings: ScanSettings) -> Option<(usize, usize)> {
+    let status = Decompressor::new()
+        .classic_mode(settings.classic_mode)
+        .backwards_mode(settings.backwards_mode)
+        .decompress(data, output)
+        .ok()?;
+
+    match status {
+        DecompressStatus::Finished { consumed, len } => Some((consumed, len)),
+        DecompressStatus::NeedsInput => None
+    }
+}
+
+/// Find the plausible streams in a file. A stream is plausible when it decompresses without
+/// errors to at least `min_len` bytes, and is shorter than its decompressed data.
+///
+/// Decompressing from an offset near the start of a stream often falls into step with the stream
+/// and ends at its end marker, after decompressing some garbage first. This happens both before
+/// the stream and inside its first literals, so there is no telling which offset is the real
+/// start. The offset that decompresses to the least data is taken as the most likely start, and
+/// the real start is usually between the earliest offset and that one. Streams never overlap, so
+/// the search for the next stream starts after the end of the previous one.
+pub fn scan(file: &[u8], settings: ScanSettings) -> Vec<Candidate> {
+    let mut data = file.to_vec();
+
+    // Backwards streams are decompressed from their last byte down
+    if settings.backwards_mode {
+        data.reverse();
+    }
+
+    let mut output = vec![0; settings.max_len];
+
+    // The starts of the streams that end at every position, in ascending order
+    let mut streams = BTreeMap::<usize, Vec<(usize, usize)>>::new();
+
+    for position in 0..data.len() {
+        if let Some((consumed, len)) = try_decompress(&data[position..], &mut output, settings) {
+            streams.entry(position + consumed).or_default().push((position, len));
+        }
+    }
+
+    let mut candidates = Vec::new();
+    let mut position = 0;
+
+    // Offsets in the reversed data are mapped back to the file
+    let offset = |start: usize| if settings.backwards_mode { data.len() - 1 - start } else { start };
+
+    for (end, starts) in streams {
+        let starts = starts.into_iter()
+            .filter(|&(start, len)| start >= position && len >= settings.min_len && end - start < len)
+            .collect::<Vec<_>>();
+
+        let (Some(&(earliest, _)), Some(&(start, len))) = (starts.first(), starts.iter().rev().min_by_key(|&&(_, len)| len)) else {
+            continue;
+        };
+
+        candidates.push(Candidate {
+            offset: offset(start),
+            earliest_offset: offset(earliest),
+            compressed_len: end - start,
+            decompressed_len: len
+        });
+
+        position = end;
+    }
+
+    if settings.backwards_mode {
+        candidates.reverse();
+    }
+
+    candidates
+}
+
+/// Format the streams that were found as a table.
+pub fn format_candidates(candidates: &[Candidate], backwards_mode: bool) -> String {
+    if candidates.is_empty() {
+        return "No plausible streams found\n".to_string();
+    }
+
+    let mut result = format!(
+        "{:<10} {:>10} {:>12} {:>12} {:>12} {:>7}\n",
+        "Offset",
+        "Earliest",
+        "Compressed",
+        "Decompressed",
+        "End marker",
+        "Ratio"
+    );
+
+    for candidate in candidates {
+        result.push_str(&format!(
+            "{:<10} {:>10} {:>12} {:>12} {:>12} {:>7.3}\n",
+            format!("{:#08x}", candidate.offset),
+            format!("{:#08x}", candidate.earliest_offset),
+            candidate.compressed_len,
+            candidate.decompressed_len,
+            format!("{:#08x}", candidate.end_marker(backwards_mode)),
+            candidate.decompressed_len as f32 / candidate.compressed_len as f32
+        ));
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use zx0::Compressor;
+
+    use crate::noise::noise;
+
+    use super::{format_candidates, scan, Candidate, ScanSettings};
+
+    // Pseudo-random words, which compress well and don't change along with the sources. Every word
+    // is at least four bytes long, so a quarter of the length is enough choices.
+    fn words(len: usize) -> Vec<u8> {
+        const WORDS: [&str; 8] = ["block ", "stream ", "offset ", "literal ", "match ", "length ", "marker ", "end\n"];
+
+        let mut text = noise(len / 4 + 1, 11).into_iter()
+            .flat_map(|choice| WORDS[choice as usize % WORDS.len()].bytes())
+            .collect::<Vec<_>>();
+
+        text.truncate(len);
+        text
+    }
+
+    #[test]
+    fn scan_file() {
+        let text = words(5000);
+        let first = Compressor::new().compress(&text[..3000]).output;
+        let second = Compressor::new().compress(&text[3000..5000]).output;
+
+        // Noise doesn't look like compressed data for long
+        let file = [&noise(1000, 7)[..], &first, &noise(500, 7), &second, &noise(200, 7)].concat();
+
+        let settings = ScanSettings {
+            classic_mode: false,
+            backwards_mode: false,
+            min_len: 64,
+            max_len: 65536
+        };
+
+        let candidates = scan(&file, settings);
+
+        // The first stream can also be decompressed from offsets just before its start, which
+        // results in garbage in front of its data
+        assert_eq!(candidates, [
+            Candidate { offset: 1000, earliest_offset: 997, compressed_len: first.len(), decompressed_len: 3000 },
+            Candidate { offset: 1500 + first.len(), earliest_offset: 1500 + first.len(), compressed_len: second.len(), decompressed_len: 2000 }
+        ]);
+
+        let table = format_candidates(&candidates[..1], false);
+        let lines = table.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "Offset       Earliest   Compressed Decompressed   End marker   Ratio");
+        assert!(lines[1].starts_with("0x0003e8     0x0003e5          526         3000     0x0005f5"));
+        assert_eq!(lines.len(), 2);
+
+        // Backwards streams are stored reversed
+        let backwards = Compressor::new().backwards_mode(true).natural_order(true).compress(&text[..3000]).output;
+        let file = [&noise(300, 7)[..], &backwards, &noise(300, 7)].concat();
+        let candidates = scan(&file, ScanSettings { backwards_mode: true, ..settings });
+
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].end_marker(true), 300);
+        assert_eq!(candidates[0].offset, 300 + backwards.len() - 1);
+        assert_eq!(format_candidates(&[], true), "No plausible streams found\n");
+    }
+}
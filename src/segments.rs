@@ -0,0 +1,142 @@
+//! Input data that is split over several slices, such as the chunks of a rope or a memory map,
+//! which can be compressed without concatenating it first.
+
+use std::ops::Range;
+
+/// A sequence of symbols that can be accessed by index. The optimizer and the encoder use this to
+/// work on a single slice as well as on input that is split over several slices.
+pub trait Symbols<T> {
+    /// Return the number of symbols.
+    fn len(&self) -> usize;
+
+    /// Return the symbol at the given index.
+    fn at(&self, index: usize) -> T;
+}
+
+impl<T: Copy> Symbols<T> for [T] {
+    #[inline(always)]
+    fn len(&self) -> usize {
+        <[T]>::len(self)
+    }
+
+    #[inline(always)]
+    fn at(&self, index: usize) -> T {
+        self[index]
+    }
+}
+
+/// Bytes that are split over several slices, which are treated as if they were concatenated.
+pub struct Segments<'a> {
+    // Only the segments that aren't empty, so that every index falls in exactly one of them
+    segments: Vec<&'a [u8]>,
+
+    // The index of the first byte of every segment
+    starts: Vec<usize>,
+
+    len: usize
+}
+
+impl<'a> Segments<'a> {
+    pub fn new(segments: &[&'a [u8]]) -> Self {
+        let segments = segments.iter().copied().filter(|segment| !segment.is_empty()).collect::<Vec<_>>();
+        let mut starts = Vec::with_capacity(segments.len());
+        let mut len = 0;
+
+        for segment in &segments {
+            starts.push(len);
+            len += segment.len();
+        }
+
+        Self {
+            segments,
+            starts,
+            len
+        }
+    }
+}
+
+impl Symbols<u8> for Segments<'_> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    fn at(&self, index: usize) -> u8 {
+        let segment = self.starts.partition_point(|&start| start <= index) - 1;
+        self.segments[segment][index - self.starts[segment]]
+    }
+}
+
+/// A contiguous range of other symbols, which is indexed from the start of the range.
+pub struct Window<'a, I: ?Sized> {
+    symbols: &'a I,
+    start: usize,
+    len: usize
+}
+
+impl<'a, I: ?Sized> Window<'a, I> {
+    pub fn new(symbols: &'a I, range: Range<usize>) -> Self {
+        Self {
+            symbols,
+            start: range.start,
+            len: range.len()
+        }
+    }
+}
+
+impl<T, I: Symbols<T> + ?Sized> Symbols<T> for Window<'_, I> {
+    #[inline(always)]
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline(always)]
+    fn at(&self, index: usize) -> T {
+        self.symbols.at(self.start + index)
+    }
+}
+
+/// Other symbols in reverse order, which is the order in which the backwards mode compresses them.
+pub struct Reversed<'a, I: ?Sized>(&'a I);
+
+impl<'a, I: ?Sized> Reversed<'a, I> {
+    pub fn new(symbols: &'a I) -> Self {
+        Self(symbols)
+    }
+}
+
+impl<T, I: Symbols<T> + ?Sized> Symbols<T> for Reversed<'_, I> {
+    #[inline(always)]
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline(always)]
+    fn at(&self, index: usize) -> T {
+        self.0.at(self.0.len() - 1 - index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reversed, Segments, Symbols, Window};
+
+    #[test]
+    fn indexing() {
+        let segments = Segments::new(&[b"ab", b"", b"c", b"defg", b""]);
+
+        assert_eq!(segments.len(), 7);
+        assert_eq!((0..7).map(|index| segments.at(index)).collect::<Vec<_>>(), b"abcdefg");
+        assert_eq!(Segments::new(&[b"", b""]).len(), 0);
+
+        let window = Window::new(&segments, 1..5);
+
+        assert_eq!(window.len(), 4);
+        assert_eq!((0..4).map(|index| window.at(index)).collect::<Vec<_>>(), b"bcde");
+
+        let reversed = Reversed::new(&window);
+
+        assert_eq!(reversed.len(), 4);
+        assert_eq!((0..4).map(|index| reversed.at(index)).collect::<Vec<_>>(), b"edcb");
+    }
+}
@@ -0,0 +1,239 @@
+//! Embedded test vectors for the selftest command, which checks that the installed binary produces
+//! byte-exact output. The expected outputs were produced by the original C implementation.
+
+use zx0::{Compressor, DecompressStatus, Decompressor};
+
+use crate::noise::noise;
+
+struct Vector {
+    name: &'static str,
+    input: fn() -> Vec<u8>,
+    classic_mode: bool,
+    backwards_mode: bool,
+    quick_mode: bool,
+    skip: usize,
+    expected: &'static [u8],
+    delta: usize
+}
+
+const TEXT: &[u8] = b"The quick brown fox jumps over the lazy dog. The quick brown fox jumps over the lazy dog!";
+
+fn single_byte() -> Vec<u8> {
+    vec![0x42]
+}
+
+fn text() -> Vec<u8> {
+    TEXT.to_vec()
+}
+
+fn run() -> Vec<u8> {
+    vec![0xaa; 300]
+}
+
+// A stretch of noise that is repeated after a run, which is too far back for the quick mode
+fn distant() -> Vec<u8> {
+    let noise = noise(64, 1);
+
+    [&noise[..], &[0; 3000], &noise[..]].concat()
+}
+
+const VECTORS: &[Vector] = &[
+    Vector {
+        name: "single byte",
+        input: single_byte,
+        classic_mode: false,
+        backwards_mode: false,
+        quick_mode: false,
+        skip: 0,
+        expected: &[0xd5, 0x42, 0x55, 0x60],
+        delta: 2
+    },
+    Vector {
+        name: "text",
+        input: text,
+        classic_mode: false,
+        backwards_mode: false,
+        quick_mode: false,
+        skip: 0,
+        expected: &[
+            0x00, 0x3a, 0x54, 0x68, 0x65, 0x20, 0x71, 0x75, 0x69, 0x63, 0x6b, 0x20, 0x62, 0x72, 0x6f, 0x77,
+            0x6e, 0x20, 0x66, 0x6f, 0x78, 0x20, 0x6a, 0x75, 0x6d, 0x70, 0x73, 0x20, 0x6f, 0x76, 0x65, 0x72,
+            0x20, 0x74, 0xc2, 0x13, 0x6c, 0x61, 0x7a, 0x79, 0x20, 0x64, 0x6f, 0x67, 0x2e, 0x20, 0x91, 0xa6,
+            0x2d, 0x21, 0x55, 0x56
+        ],
+        delta: 2
+    },
+    Vector {
+        name: "text, classic",
+        input: text,
+        classic_mode: true,
+        backwards_mode: false,
+        quick_mode: false,
+        skip: 0,
+        expected: &[
+            0x00, 0x3a, 0x54, 0x68, 0x65, 0x20, 0x71, 0x75, 0x69, 0x63, 0x6b, 0x20, 0x62, 0x72, 0x6f, 0x77,
+            0x6e, 0x20, 0x66, 0x6f, 0x78, 0x20, 0x6a, 0x75, 0x6d, 0x70, 0x73, 0x20, 0x6f, 0x76, 0x65, 0x72,
+            0x20, 0x74, 0xc2, 0x13, 0x6c, 0x61, 0x7a, 0x79, 0x20, 0x64, 0x6f, 0x67, 0x2e, 0x20, 0x91, 0xa6,
+            0x2c, 0x21, 0x00, 0x02
+        ],
+        delta: 2
+    },
+    Vector {
+        name: "text, backwards",
+        input: text,
+        classic_mode: false,
+        backwards_mode: true,
+        quick_mode: false,
+        skip: 0,
+        expected: &[
+            0x80, 0xaa, 0x6a, 0xee, 0x59, 0x2e, 0x54, 0x04, 0x3d, 0x71, 0x75, 0x69, 0x63, 0x6b, 0x20, 0x62,
+            0x72, 0x6f, 0x77, 0x6e, 0x20, 0x66, 0x6f, 0x78, 0x20, 0x6a, 0x75, 0x6d, 0x70, 0x73, 0x20, 0x6f,
+            0x76, 0x65, 0x72, 0x20, 0x74, 0x68, 0x65, 0x20, 0x6c, 0x61, 0x7a, 0x79, 0x20, 0x64, 0x6f, 0x67,
+            0x21, 0x90, 0xba
+        ],
+        delta: 2
+    },
+    Vector {
+        name: "text, skip",
+        input: text,
+        classic_mode: false,
+        backwards_mode: false,
+        quick_mode: false,
+        skip: 45,
+        expected: &[0xe4, 0x54, 0xa6, 0x1b, 0x21, 0x55, 0x55, 0x80],
+        delta: 3
+    },
+    Vector {
+        name: "run",
+        input: run,
+        classic_mode: false,
+        backwards_mode: false,
+        quick_mode: false,
+        skip: 0,
+        expected: &[0x81, 0xaa, 0x11, 0x75, 0x55, 0x58],
+        delta: 2
+    },
+    Vector {
+        name: "distant",
+        input: distant,
+        classic_mode: false,
+        backwards_mode: false,
+        quick_mode: false,
+        skip: 0,
+        expected: &[
+            0x00, 0x18, 0xc6, 0x7e, 0x81, 0x6b, 0x4b, 0xfb, 0xe2, 0xfb, 0x54, 0xf6, 0xbd, 0xdf, 0x7c, 0x1c,
+            0xe1, 0x87, 0x01, 0xbf, 0x31, 0xde, 0x56, 0x72, 0x0f, 0x47, 0x67, 0x66, 0x87, 0x59, 0xaa, 0x88,
+            0x3c, 0x59, 0xea, 0x56, 0x13, 0x7b, 0xd2, 0x85, 0xa1, 0xd8, 0x3c, 0x54, 0x55, 0x2f, 0x37, 0xae,
+            0x65, 0x5b, 0xda, 0x02, 0x79, 0x98, 0xcc, 0xe3, 0x1a, 0x76, 0x8e, 0x5f, 0xd9, 0x99, 0x8f, 0x1f,
+            0x3f, 0x36, 0x00, 0x54, 0x51, 0x5c, 0x57, 0x10, 0x55, 0xd5, 0x55, 0x60
+        ],
+        delta: 2
+    },
+    Vector {
+        name: "distant, quick",
+        input: distant,
+        classic_mode: false,
+        backwards_mode: false,
+        quick_mode: true,
+        skip: 0,
+        expected: &[
+            0x00, 0x18, 0xc6, 0x7e, 0x81, 0x6b, 0x4b, 0xfb, 0xe2, 0xfb, 0x54, 0xf6, 0xbd, 0xdf, 0x7c, 0x1c,
+            0xe1, 0x87, 0x01, 0xbf, 0x31, 0xde, 0x56, 0x72, 0x0f, 0x47, 0x67, 0x66, 0x87, 0x59, 0xaa, 0x88,
+            0x3c, 0x59, 0xea, 0x56, 0x13, 0x7b, 0xd2, 0x85, 0xa1, 0xd8, 0x3c, 0x54, 0x55, 0x2f, 0x37, 0xae,
+            0x65, 0x5b, 0xda, 0x02, 0x79, 0x98, 0xcc, 0xe3, 0x1a, 0x76, 0x8e, 0x5f, 0xd9, 0x99, 0x8f, 0x1f,
+            0x3f, 0x36, 0x00, 0x54, 0x51, 0x58, 0x00, 0x35, 0xc6, 0x7e, 0x81, 0x6b, 0x4b, 0xfb, 0xe2, 0xfb,
+            0x54, 0xf6, 0xbd, 0xdf, 0x7c, 0x1c, 0xe1, 0x87, 0x01, 0xbf, 0x31, 0xde, 0x56, 0x72, 0x0f, 0x47,
+            0x67, 0x66, 0x87, 0x59, 0xaa, 0x88, 0x3c, 0x59, 0xea, 0x56, 0x13, 0x7b, 0xd2, 0x85, 0xa1, 0xd8,
+            0x3c, 0x54, 0x55, 0x2f, 0x37, 0xae, 0x65, 0x5b, 0xda, 0x02, 0x79, 0x98, 0xcc, 0xe3, 0x1a, 0x76,
+            0x8e, 0x5f, 0xd9, 0x99, 0x8f, 0x1f, 0x3f, 0x36, 0x55, 0x58
+        ],
+        delta: 4
+    }
+];
+
+// Compress the input of a test vector the way the command line tool would, and compare the result
+// with the expected output. The expected output is then decompressed again, except for vectors
+// that need a prefix.
+fn check(vector: &Vector) -> Result<(), String> {
+    let mut input = (vector.input)();
+
+    if vector.backwards_mode {
+        input.reverse();
+    }
+
+    let mut result = Compressor::new()
+        .classic_mode(vector.classic_mode)
+        .backwards_mode(vector.backwards_mode)
+        .quick_mode(vector.quick_mode)
+        .skip(vector.skip)
+        .try_compress(&input)
+        .map_err(|err| err.to_string())?;
+
+    if vector.backwards_mode {
+        result.output.reverse();
+    }
+
+    if result.output != vector.expected {
+        return Err(format!("output of {} bytes differs from the expected {} bytes", result.output.len(), vector.expected.len()));
+    }
+
+    if result.delta != vector.delta {
+        return Err(format!("expected delta {}, got {}", vector.delta, result.delta));
+    }
+
+    if vector.skip == 0 {
+        let mut compressed = vector.expected.to_vec();
+
+        // Backwards data is decompressed from its end, like it was compressed
+        if vector.backwards_mode {
+            compressed.reverse();
+        }
+
+        let mut output = vec![0; input.len()];
+
+        let status = Decompressor::new()
+            .classic_mode(vector.classic_mode)
+            .backwards_mode(vector.backwards_mode)
+            .decompress(&compressed, &mut output)
+            .map_err(|err| format!("decompression failed: {}", err))?;
+
+        if status != (DecompressStatus::Finished { consumed: compressed.len(), len: input.len() }) || output != input {
+            return Err("decompressed data differs from the input".to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Run all test vectors and report the outcome of each of them. Returns whether all of them
+/// passed.
+pub fn selftest() -> bool {
+    let mut failures = 0;
+
+    for vector in VECTORS {
+        match check(vector) {
+            Ok(()) => println!("{:<20} ok", vector.name),
+            Err(message) => {
+                println!("{:<20} FAILED: {}", vector.name, message);
+                failures += 1;
+            }
+        }
+    }
+
+    println!();
+    println!("{} of {} test vectors passed", VECTORS.len() - failures, VECTORS.len());
+
+    failures == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, VECTORS};
+
+    #[test]
+    fn vectors() {
+        for vector in VECTORS {
+            assert_eq!(check(vector), Ok(()), "{}", vector.name);
+        }
+    }
+}
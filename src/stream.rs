@@ -0,0 +1,286 @@
+//! Compression of inputs that are too large to be held in memory as a whole.
+//!
+//! The input is parsed in segments, each of which uses the window before it as its prefix and
+//! starts out with the offset that was used last. Only the part of a parse up to its last match is
+//! encoded, and the literals after it are parsed again as part of the next segment. Since every
+//! segment starts directly after a match, where a literal run is always allowed, the segments join
+//! up to form a single stream that any standard decompressor accepts.
+//!
+//! The parse can't look past the end of the segment that is being parsed, so the output is
+//! usually slightly larger than when parsing the input as a whole.
+
+use std::io::{self, Read, Write};
+
+use crate::{Error, INITIAL_OFFSET};
+use crate::bits::BitWriter;
+use crate::compressor::StreamResult;
+use crate::optimize::{Scratch, Settings, optimize};
+
+/// The number of new input bytes that are parsed at a time.
+pub const SEGMENT_LEN: usize = 64 * 1024;
+
+/// An encoder that writes the compressed data to a writer as soon as it is complete, which is
+/// everything but the bit group that is being filled and the byte that the next bit may be
+/// backtracked into.
+struct Encoder<W: Write> {
+    writer: W,
+    invert_mode: bool,
+    context: BitWriter<Vec<u8>>,
+    written: u64,
+    last_offset: usize,
+    input_len: u64
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(writer: W, invert_mode: bool) -> Self {
+        Self {
+            writer,
+            invert_mode,
+            context: BitWriter::new(Vec::new(), 0),
+            written: 0,
+            last_offset: INITIAL_OFFSET,
+            input_len: 0
+        }
+    }
+
+    fn read_bytes(&mut self, n: usize) {
+        self.input_len += n as u64;
+        self.context.read_bytes(n);
+    }
+
+    fn literals(&mut self, bytes: &[u8]) {
+        self.context.write_bit(0);
+        self.context.write_interlaced_elias_gamma(bytes.len(), false, false);
+
+        for &byte in bytes {
+            self.context.write_byte(byte);
+            self.read_bytes(1);
+        }
+    }
+
+    fn copy(&mut self, offset: usize, length: usize) {
+        if offset == self.last_offset {
+            self.context.write_bit(0);
+            self.context.write_interlaced_elias_gamma(length, false, false);
+        } else {
+            self.context.write_bit(1);
+            self.context.write_interlaced_elias_gamma((offset - 1) / 128 + 1, false, self.invert_mode);
+            self.context.write_byte(((127 - (offset - 1) % 128) << 1) as u8);
+            self.context.backtrack = true;
+            self.context.write_interlaced_elias_gamma(length - 1, false, false);
+            self.last_offset = offset;
+        }
+
+        self.read_bytes(length);
+    }
+
+    // Write everything that can no longer change
+    fn flush(&mut self) -> io::Result<()> {
+        let complete = self.context.drain_complete();
+        let len = complete.len();
+
+        self.writer.write_all(complete.as_slice())?;
+        self.written += len as u64;
+
+        Ok(())
+    }
+
+    // Write the end marker and the remaining data, and return the output length and the delta
+    fn finish(mut self) -> io::Result<(u64, usize)> {
+        self.context.write_bit(1);
+        self.context.write_interlaced_elias_gamma(256, false, self.invert_mode);
+
+        let remaining = self.context.output();
+        self.writer.write_all(remaining)?;
+        self.writer.flush()?;
+
+        let output_len = self.written + remaining.len() as u64;
+        let delta = (output_len as i64 - self.input_len as i64 + self.context.max_diff()).max(0);
+
+        Ok((output_len, delta as usize))
+    }
+}
+
+// Read from the reader until the buffer holds the given number of bytes, and return whether the
+// end of the input was reached before that
+fn fill<R: Read>(reader: &mut R, buffer: &mut Vec<u8>, len: usize) -> io::Result<bool> {
+    let wanted = len.saturating_sub(buffer.len());
+    let n = reader.by_ref().take(wanted as u64).read_to_end(buffer)?;
+
+    Ok(n < wanted)
+}
+
+/// Compress everything that the reader produces into a single stream that is written to the
+/// writer. The first `skip` bytes form the prefix, like they do when compressing a slice. The input
+/// is parsed `segment_len` bytes at a time, using the given scratch allocations.
+///
+/// Besides the output buffer of the encoder, the memory usage is bounded by the window and the
+/// segment length, unless a long stretch of input can neither be matched profitably nor contains
+/// a single byte that matches at the last offset. Such a stretch is kept in memory until either
+/// happens, since a literal run can only be encoded once its length is known.
+pub fn compress<R: Read, W: Write>(
+    mut reader: R,
+    writer: W,
+    skip: usize,
+    invert_mode: bool,
+    settings: Settings,
+    mut scratch: Scratch,
+    segment_len: usize
+) -> io::Result<StreamResult> {
+    let mut encoder = Encoder::new(writer, invert_mode);
+    let mut peak_memory = 0;
+
+    // The buffer holds the window before the first byte that hasn't been encoded yet at start,
+    // followed by the bytes that have been read but not encoded
+    let mut buffer = Vec::new();
+    let mut start = skip;
+    let mut wanted = segment_len;
+    let mut at_end = false;
+
+    loop {
+        if !at_end {
+            at_end = fill(&mut reader, &mut buffer, start + wanted)?;
+        }
+
+        if start >= buffer.len() {
+            if encoder.input_len == 0 {
+                let err = Error::NothingToCompress {
+                    input_len: buffer.len(),
+                    skip
+                };
+
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+            }
+
+            break;
+        }
+
+        let chain = optimize(&buffer[..], start, encoder.last_offset, settings, &mut scratch, None, &mut |_| ());
+        peak_memory = peak_memory.max(chain.peak_memory + buffer.capacity() + encoder.context.output().capacity());
+
+        // Encode the parse up to its last match, or the whole parse at the end of the input
+        let mut blocks = Vec::new();
+        let mut block = chain.allocator.get(chain.head);
+
+        while block.next_index() != 0 {
+            let next = chain.allocator.get(block.next_index());
+            blocks.push(((block.index + 1) as usize, (next.index + 1) as usize, next.offset as usize));
+            block = next;
+        }
+
+        scratch.recycle(chain);
+
+        let committed = if at_end {
+            blocks.len()
+        } else {
+            blocks.iter().rposition(|&(_, _, offset)| offset != 0).map_or(0, |last| last + 1)
+        };
+
+        for &(from, to, offset) in &blocks[..committed] {
+            if offset == 0 {
+                encoder.literals(&buffer[from..to]);
+            } else {
+                encoder.copy(offset, to - from);
+            }
+        }
+
+        let mut end = blocks[..committed].last().map_or(start, |&(_, to, _)| to);
+
+        // A long run of literals after the last match would be parsed over and over again, so cut
+        // it short at the last byte that can be copied from the last offset
+        if !at_end && buffer.len() - end > segment_len / 2 {
+            let offset = encoder.last_offset;
+
+            if let Some(cut) = (end + 1..buffer.len()).rev().find(|&i| i >= offset && buffer[i] == buffer[i - offset]) {
+                encoder.literals(&buffer[end..cut]);
+                encoder.copy(offset, 1);
+                end = cut + 1;
+            }
+        }
+
+        encoder.flush()?;
+
+        if at_end {
+            break;
+        }
+
+        // Read another segment on top of the current one if nothing could be encoded
+        if end == start {
+            wanted += segment_len;
+            continue;
+        }
+
+        wanted = segment_len;
+
+        let obsolete = end.saturating_sub(settings.offset_limit);
+        buffer.drain(..obsolete);
+        start = end - obsolete;
+    }
+
+    let input_len = encoder.input_len;
+    let (output_len, delta) = encoder.finish()?;
+
+    Ok(StreamResult {
+        input_len,
+        output_len,
+        delta,
+        peak_memory
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::Error;
+    use crate::decompress::decompress;
+    use crate::noise::noise;
+    use crate::optimize::{Scratch, Settings};
+
+    use super::compress;
+
+    // A small window keeps the tests fast, and makes sure that the window is trimmed repeatedly
+    const SETTINGS: Settings = Settings {
+        offset_limit: 1000,
+        candidate_limit: usize::MAX,
+        extreme_mode: false,
+        max_literal_run: usize::MAX,
+        token_cost: 0
+    };
+
+    #[test]
+    fn round_trip_in_place() {
+        let text = std::fs::read("src/optimize.rs").unwrap();
+
+        // Noise in which only a few bytes happen to match, so that hardly any matches are worth it
+        let noise = noise(6000, 1);
+
+        for (input, skip, classic_mode) in [(&text[..8000], 0, false), (&text[..8000], 700, true), (&noise[..], 0, false)] {
+            let mut output = Vec::new();
+            let result = compress(input, &mut output, skip, !classic_mode, SETTINGS, Scratch::default(), 1500).unwrap();
+
+            assert_eq!(result.input_len, (input.len() - skip) as u64);
+            assert_eq!(result.output_len, output.len() as u64);
+
+            // The compressed data ends delta bytes past the end of the decompressed data
+            let mut memory = vec![0; input.len() + result.delta];
+            let source = memory.len() - output.len();
+            memory[..skip].copy_from_slice(&input[..skip]);
+            memory[source..].copy_from_slice(&output);
+
+            let (source, destination) = decompress(&mut memory, source, skip, false, !classic_mode);
+
+            assert_eq!(source, memory.len());
+            assert_eq!(destination, input.len());
+            assert_eq!(&memory[..destination], input);
+        }
+    }
+
+    #[test]
+    fn nothing_to_compress() {
+        let err = compress(&[1, 2, 3][..], io::sink(), 3, true, SETTINGS, Scratch::default(), 3000).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.into_inner().unwrap().downcast_ref::<Error>(), Some(&Error::NothingToCompress { input_len: 3, skip: 3 }));
+    }
+}
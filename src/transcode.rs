@@ -0,0 +1,169 @@
+//! Conversion between the classic (V1) and the current (V2) file format without compressing the
+//! data again.
+//!
+//! Both formats consist of the same elements with the same lengths and offsets, and only differ in
+//! the data bits of the offset MSB codes, which are inverted in the current format when
+//! compressing forwards. The compressed data is therefore walked element by element, and these
+//! bits are flipped in place. The data of the backwards mode is identical in both formats.
+
+use crate::Error;
+
+enum State {
+    Literals,
+    LastOffset,
+    NewOffset
+}
+
+struct Transcoder<'a> {
+    data: &'a mut [u8],
+    position: usize,
+    bit_index: usize,
+    bit_mask: u8,
+    backtrack: Option<bool>
+}
+
+impl Transcoder<'_> {
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        let value = *self.data.get(self.position).ok_or(Error::Truncated)?;
+        self.position += 1;
+        Ok(value)
+    }
+
+    // Read the next bit, and flip it in the data if requested
+    fn read_bit(&mut self, flip: bool) -> Result<bool, Error> {
+        if let Some(bit) = self.backtrack.take() {
+            return Ok(bit);
+        }
+
+        if self.bit_mask == 0 {
+            self.bit_index = self.position;
+            self.bit_mask = 128;
+            self.read_byte()?;
+        }
+
+        let bit = self.data[self.bit_index] & self.bit_mask != 0;
+
+        if flip {
+            self.data[self.bit_index] ^= self.bit_mask;
+        }
+
+        self.bit_mask >>= 1;
+        Ok(bit)
+    }
+
+    // Read an interlaced Elias gamma code, flipping its data bits if requested
+    fn read_interlaced_elias_gamma(&mut self, flip: bool, inverted: bool) -> Result<usize, Error> {
+        let mut value = 1usize;
+
+        while !self.read_bit(false)? {
+            if value > usize::MAX >> 1 {
+                return Err(Error::InvalidData);
+            }
+
+            value = (value << 1) | (self.read_bit(flip)? ^ inverted) as usize;
+        }
+
+        Ok(value)
+    }
+}
+
+/// Convert data that was compressed forwards from the classic (V1) file format to the current
+/// (V2) format in place, or the other way around when `classic_mode` is not set. This is much
+/// faster than decompressing and compressing the data again, and results in exactly the output
+/// that compressing the data in the other format would have produced.
+///
+/// Returns the length of the compressed data, after which the rest of `data` is left untouched.
+/// Returns an error if the compressed data is malformed or ends before its end marker, in which
+/// case `data` may be partially converted. The lengths and offsets of the matches are not checked
+/// against the decompressed data, so data that was compressed with a `skip` can be converted as
+/// well. Data that was compressed backwards does not need to be converted, since it is identical in
+/// both formats.
+pub fn transcode(data: &mut [u8], classic_mode: bool) -> Result<usize, Error> {
+    let mut transcoder = Transcoder {
+        data,
+        position: 0,
+        bit_index: 0,
+        bit_mask: 0,
+        backtrack: None
+    };
+
+    // Streams always start with literals, so the first indicator bit is omitted
+    let mut state = State::Literals;
+
+    loop {
+        state = match state {
+            State::Literals => {
+                let length = transcoder.read_interlaced_elias_gamma(false, false)?;
+
+                // Literals are stored as they are, so they are skipped
+                if length > transcoder.data.len() - transcoder.position {
+                    return Err(Error::Truncated);
+                }
+
+                transcoder.position += length;
+
+                if transcoder.read_bit(false)? { State::NewOffset } else { State::LastOffset }
+            },
+            State::LastOffset => {
+                transcoder.read_interlaced_elias_gamma(false, false)?;
+
+                if transcoder.read_bit(false)? { State::NewOffset } else { State::Literals }
+            },
+            State::NewOffset => {
+                let msb = transcoder.read_interlaced_elias_gamma(true, !classic_mode)?;
+
+                if msb == 256 {
+                    return Ok(transcoder.position);
+                } else if msb > 256 {
+                    return Err(Error::InvalidData);
+                }
+
+                // The first bit of the length is stored in the lowest bit of the offset LSB
+                let lsb = transcoder.read_byte()?;
+                transcoder.backtrack = Some(lsb & 1 != 0);
+                transcoder.read_interlaced_elias_gamma(false, false)?;
+
+                if transcoder.read_bit(false)? { State::NewOffset } else { State::Literals }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::transcode;
+    use crate::{Compressor, Error};
+
+    #[test]
+    fn round_trip() {
+        let text = std::fs::read("src/lib.rs").unwrap();
+
+        for (input, skip) in [(&text[..8000], 0), (&text[..5000], 1000), (&[0; 3000][..], 0), (b"a", 0)] {
+            let classic = Compressor::new().classic_mode(true).skip(skip).compress(input).output;
+            let current = Compressor::new().skip(skip).compress(input).output;
+
+            // Trailing data is left alone
+            let mut data = [&classic[..], &[0x55; 4]].concat();
+            assert_eq!(transcode(&mut data, true), Ok(classic.len()));
+            assert_eq!(data, [&current[..], &[0x55; 4]].concat());
+
+            assert_eq!(transcode(&mut data, false), Ok(current.len()));
+            assert_eq!(&data[..classic.len()], classic);
+        }
+
+        let mut data = Compressor::new().compress(&text[..1000]).output;
+        let len = data.len();
+        assert_eq!(transcode(&mut data[..len - 1], false), Err(Error::Truncated));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(1000))]
+
+        #[test]
+        fn malformed_never_panics(mut data in proptest::collection::vec(any::<u8>(), 0..64), classic_mode in any::<bool>()) {
+            let _ = transcode(&mut data, classic_mode);
+        }
+    }
+}
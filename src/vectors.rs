@@ -0,0 +1,296 @@
+//! The vectors command, which writes a suite of small inputs along with their compressed outputs in
+//! every combination of modes to a directory, for people who implement decompressors in other
+//! languages. The outputs are checked with the decompressor of this crate before they are written.
+
+use std::fs;
+use std::path::Path;
+
+use zx0::batch::{Batch, Config, Outcome};
+use zx0::{DecompressStatus, Decompressor, MAX_OFFSET_ZX0};
+
+use crate::noise::noise;
+
+use crate::{json_string, sha256_hex, Failure, Status, VERSION};
+
+/// An input of the suite, along with the number of bytes at its start that are skipped (or at its
+/// end, when compressing backwards).
+pub struct Input {
+    /// The name of the input, which is also the base name of its files.
+    pub name: &'static str,
+
+    /// What the input is meant to exercise.
+    pub description: &'static str,
+
+    /// The data in its natural order.
+    pub data: Vec<u8>,
+
+    /// The number of bytes that are skipped.
+    pub skip: usize
+}
+
+/// The compressed output of an input in a single combination of modes.
+pub struct Vector {
+    /// The name of the output file.
+    pub filename: String,
+
+    /// Whether the output uses the classic (V1) file format.
+    pub classic_mode: bool,
+
+    /// Whether the input was compressed backwards.
+    pub backwards_mode: bool,
+
+    /// Whether the output was compressed using the quick mode.
+    pub quick_mode: bool,
+
+    /// The compressed data, in the order in which it is stored.
+    pub output: Vec<u8>,
+
+    /// The delta of the compressed data.
+    pub delta: usize
+}
+
+fn input(name: &'static str, description: &'static str, data: Vec<u8>, skip: usize) -> Input {
+    Input { name, description, data, skip }
+}
+
+/// Return the inputs of the suite.
+pub fn inputs() -> Vec<Input> {
+    let text = b"The quick brown fox jumps over the lazy dog. The quick brown fox jumps over the lazy dog!".to_vec();
+    let distant = noise(64, 1);
+
+    // Runs of a single byte would match at every offset, which takes much longer to compress
+    let pattern = noise(256, 4);
+
+    vec![
+        input("single-byte", "the shortest possible input", vec![0x42], 0),
+        input("text", "a sentence that repeats with a difference at its end", text.clone(), 0),
+        input("run", "a single byte repeated, which is a match that overlaps itself", vec![0xaa; 300], 0),
+        input("literals", "noise that only compresses to literals, in a single long run", noise(1000, 2), 0),
+        input(
+            "repeated-offsets",
+            "matches that reuse the previous offset after a literal",
+            (0..40).flat_map(|i| [b"ABCDEFGH".as_slice(), &[i]].concat()).collect(),
+            0
+        ),
+        input("long-match", "a match that is longer than 65536 bytes", [&[0x01][..], &pattern.repeat(275)].concat(), 0),
+        input("distant", "a match that is too far back for the quick mode", [&distant[..], &[0; 3000], &distant[..]].concat(), 0),
+        input(
+            "max-offset",
+            "a match at the largest offset that the format can encode",
+            [&distant[..], &noise(MAX_OFFSET_ZX0 - distant.len(), 3), &distant[..]].concat(),
+            0
+        ),
+        input("skip", "matches that refer to a skipped prefix (or suffix, when compressing backwards)", text, 45)
+    ]
+}
+
+// Decompress a vector the way a decompressor on the target machine would see it, and check that it
+// produces the input again
+fn check(input: &Input, vector: &Vector) -> Result<(), String> {
+    let mut expected = input.data.clone();
+    let mut compressed = vector.output.clone();
+
+    // Backwards data is decompressed from its end, like it was compressed
+    if vector.backwards_mode {
+        expected.reverse();
+        compressed.reverse();
+    }
+
+    let mut output = vec![0; expected.len()];
+    output[..input.skip].copy_from_slice(&expected[..input.skip]);
+
+    let status = Decompressor::new()
+        .classic_mode(vector.classic_mode)
+        .backwards_mode(vector.backwards_mode)
+        .prefix_len(input.skip)
+        .decompress(&compressed, &mut output)
+        .map_err(|err| format!("decompression failed: {}", err))?;
+
+    if status != (DecompressStatus::Finished { consumed: compressed.len(), len: expected.len() }) || output != expected {
+        return Err("decompressed data differs from the input".to_string());
+    }
+
+    Ok(())
+}
+
+// The combinations of the classic, backwards and quick modes that every input is compressed in
+const MODES: [(bool, bool, bool); 8] = [
+    (false, false, false),
+    (false, false, true),
+    (false, true, false),
+    (false, true, true),
+    (true, false, false),
+    (true, false, true),
+    (true, true, false),
+    (true, true, true)
+];
+
+fn vector_filename(input: &Input, (classic_mode, backwards_mode, quick_mode): (bool, bool, bool)) -> String {
+    format!(
+        "{}.{}{}{}.zx0",
+        input.name,
+        if classic_mode { "classic" } else { "v2" },
+        if backwards_mode { "-backwards" } else { "" },
+        if quick_mode { "-quick" } else { "" }
+    )
+}
+
+/// Compress the inputs in every combination of the classic, backwards and quick modes in
+/// parallel, and check that every output decompresses again. Returns the outputs of every input.
+pub fn compress_inputs(inputs: &[Input]) -> Result<Vec<Vec<Vector>>, String> {
+    let mut batch = Batch::new(0);
+
+    for input in inputs {
+        for mode in MODES {
+            let (classic_mode, backwards_mode, quick_mode) = mode;
+            let mut data = input.data.clone();
+
+            // Like the compressor, the batch expects backwards input to be reversed
+            if backwards_mode {
+                data.reverse();
+            }
+
+            batch.submit(vector_filename(input, mode), data, Config { skip: input.skip, quick_mode, backwards_mode, classic_mode, ..Config::default() });
+        }
+    }
+
+    let mut results = batch.results();
+    let mut suite = Vec::new();
+
+    for input in inputs {
+        let mut vectors = Vec::new();
+
+        for (classic_mode, backwards_mode, quick_mode) in MODES {
+            // Every submitted job has a result
+            let job = results.next().unwrap();
+
+            let mut result = match job.outcome {
+                Outcome::Compressed(result) => result,
+                Outcome::Cancelled => return Err(format!("{}: cancelled", job.name)),
+                Outcome::Failed(message) => return Err(format!("{}: {}", job.name, message))
+            };
+
+            if backwards_mode {
+                result.output.reverse();
+            }
+
+            let vector = Vector {
+                filename: job.name,
+                classic_mode,
+                backwards_mode,
+                quick_mode,
+                output: result.output,
+                delta: result.delta
+            };
+
+            check(input, &vector).map_err(|message| format!("{}: {}", vector.filename, message))?;
+            vectors.push(vector);
+        }
+
+        suite.push(vectors);
+    }
+
+    Ok(suite)
+}
+
+/// Format the index of the suite, which describes every input and output file.
+pub fn format_index(inputs: &[(Input, Vec<Vector>)]) -> String {
+    let mut result = format!("{{\n  \"version\": {},\n  \"inputs\": [\n", json_string(VERSION));
+
+    for (i, (input, vectors)) in inputs.iter().enumerate() {
+        result.push_str(&format!(
+            "    {{\n      \"input\": {}, \"description\": {}, \"size\": {}, \"skip\": {}, \"sha256\": \"{}\",\n      \"outputs\": [\n",
+            json_string(&format!("{}.bin", input.name)),
+            json_string(input.description),
+            input.data.len(),
+            input.skip,
+            sha256_hex(&input.data)
+        ));
+
+        for (j, vector) in vectors.iter().enumerate() {
+            result.push_str(&format!(
+                "        {{\"output\": {}, \"classic\": {}, \"backwards\": {}, \"quick\": {}, \"size\": {}, \"delta\": {}, \"sha256\": \"{}\"}}{}\n",
+                json_string(&vector.filename),
+                vector.classic_mode,
+                vector.backwards_mode,
+                vector.quick_mode,
+                vector.output.len(),
+                vector.delta,
+                sha256_hex(&vector.output),
+                if j + 1 < vectors.len() { "," } else { "" }
+            ));
+        }
+
+        result.push_str(&format!("      ]\n    }}{}\n", if i + 1 < inputs.len() { "," } else { "" }));
+    }
+
+    result.push_str("  ]\n}\n");
+    result
+}
+
+/// Compress all inputs and write them to the directory along with their outputs and an
+/// `index.json` that describes them. Existing files are only overwritten when `forced_mode` is
+/// set. Returns the number of outputs that were written.
+pub fn write_vectors(directory: &Path, forced_mode: bool) -> Result<usize, Failure> {
+    let inputs = inputs();
+
+    let mut filenames = vec!["index.json".to_string()];
+
+    for input in &inputs {
+        filenames.push(format!("{}.bin", input.name));
+        filenames.extend(MODES.iter().map(|&mode| vector_filename(input, mode)));
+    }
+
+    // Check for existing files before spending time on compressing anything
+    if !forced_mode {
+        if let Some(filename) = filenames.iter().find(|filename| directory.join(filename).exists()) {
+            return Err(Failure::new(Status::OutputExists, format!("output file {} already exists", directory.join(filename).display())));
+        }
+    }
+
+    let suite = compress_inputs(&inputs).map_err(|message| Failure::new(Status::Failed, message))?;
+    let suite = inputs.into_iter().zip(suite).collect::<Vec<_>>();
+
+    let mut files = vec![("index.json".to_string(), format_index(&suite).into_bytes())];
+
+    for (input, vectors) in &suite {
+        files.push((format!("{}.bin", input.name), input.data.clone()));
+        files.extend(vectors.iter().map(|vector| (vector.filename.clone(), vector.output.clone())));
+    }
+
+    fs::create_dir_all(directory)
+        .map_err(|err| Failure::new(Status::Failed, format!("could not create directory {}: {}", directory.display(), err)))?;
+
+    for (filename, data) in &files {
+        let path = directory.join(filename);
+        fs::write(&path, data).map_err(|err| Failure::new(Status::Failed, format!("could not write to output file {}: {}", path.display(), err)))?;
+    }
+
+    Ok(suite.iter().map(|(_, vectors)| vectors.len()).sum())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress_inputs, format_index, inputs};
+
+    #[test]
+    fn vectors() {
+        let inputs = inputs().into_iter().filter(|input| input.name == "single-byte" || input.name == "skip").collect::<Vec<_>>();
+        let suite = compress_inputs(&inputs).unwrap();
+
+        // The outputs match the selftest vectors of the original implementation
+        assert_eq!(suite[0].len(), 8);
+        assert_eq!(suite[0][0].filename, "single-byte.v2.zx0");
+        assert_eq!(suite[0][0].output, [0xd5, 0x42, 0x55, 0x60]);
+        assert_eq!(suite[0][7].filename, "single-byte.classic-backwards-quick.zx0");
+        assert_eq!(suite[1][0].output, [0xe4, 0x54, 0xa6, 0x1b, 0x21, 0x55, 0x55, 0x80]);
+
+        let index = format_index(&inputs.into_iter().zip(suite).skip(1).collect::<Vec<_>>());
+        let lines = index.lines().collect::<Vec<_>>();
+
+        assert!(lines[4].starts_with("      \"input\": \"skip.bin\", \"description\": "));
+        assert!(lines[4].contains("\"size\": 89, \"skip\": 45, \"sha256\": \""));
+        assert!(lines[6].starts_with("        {\"output\": \"skip.v2.zx0\", \"classic\": false, \"backwards\": false, \"quick\": false, \"size\": 8, \"delta\": 3, \"sha256\": \""));
+        assert_eq!(lines.len(), 18);
+    }
+}
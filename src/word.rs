@@ -0,0 +1,295 @@
+//! An experimental variant of the ZX0 format that operates on 16-bit words instead of bytes.
+//!
+//! The stream consists of big-endian words only, so a decompressor never has to read or write
+//! memory at an odd address. Compared to the regular format, the differences are:
+//!
+//! - Literal runs and match lengths are counted in words, and offsets are measured in words.
+//! - Bits are read from 16-bit groups instead of 8-bit groups. Like in the regular format, a new
+//!   group is stored in the stream at the point where its first bit is needed.
+//! - The offset LSB is stored as 7 bits in the bit groups rather than as a separate byte, so the
+//!   first bit of the match length is not shared with it.
+//! - Elias Gamma codes are never inverted.
+//!
+//! The stream ends with a new offset whose MSB is 256, like in the regular format.
+
+use crate::INITIAL_OFFSET;
+use crate::optimize::Chain;
+
+struct Context {
+    output: Vec<u8>,
+    bit_index: usize,
+    bit_free: u32,
+    diff: isize,
+    max_diff: isize
+}
+
+impl Context {
+    fn read_words(&mut self, n: usize) {
+        self.diff += 2 * n as isize;
+        self.max_diff = self.max_diff.max(self.diff);
+    }
+
+    fn write_word(&mut self, value: &[u8]) {
+        self.output.extend_from_slice(value);
+        self.diff -= 2;
+    }
+
+    /// Write the lowest `count` bits of `value`, most significant bit first.
+    fn write_bits(&mut self, value: u64, mut count: u32) {
+        while count > 0 {
+            if self.bit_free == 0 {
+                self.bit_free = 16;
+                self.bit_index = self.output.len();
+                self.write_word(&[0, 0]);
+            }
+
+            let n = count.min(self.bit_free);
+            count -= n;
+            self.bit_free -= n;
+
+            let chunk = ((value >> count) & ((1 << n) - 1)) << self.bit_free;
+            self.output[self.bit_index] |= (chunk >> 8) as u8;
+            self.output[self.bit_index + 1] |= chunk as u8;
+        }
+    }
+
+    fn write_interlaced_elias_gamma(&mut self, value: usize) {
+        // Every value bit below the most significant one is preceded by a zero bit, followed by a
+        // final one bit
+        let value = value as u64;
+        let length = u64::BITS - value.leading_zeros() - 1;
+        let mut code = 0;
+
+        for i in (0..length).rev() {
+            code = (code << 2) | ((value >> i) & 1);
+        }
+
+        self.write_bits((code << 1) | 1, 2 * length + 1);
+    }
+}
+
+/// Encode an optimized chain of words. The input is the original byte data, of which every pair
+/// of bytes forms a word.
+///
+/// Returns the compressed data and the delta value in bytes.
+pub fn compress(chain: &Chain, input: &[u8]) -> (Vec<u8>, usize) {
+    let mut context = Context {
+        output: Vec::new(),
+        bit_index: 0,
+        bit_free: 0,
+        diff: 0,
+        max_diff: 0
+    };
+
+    let mut input_index = chain.skip;
+    let mut last_offset = INITIAL_OFFSET;
+    let mut first = true;
+
+    let mut previous_block = chain.allocator.get(chain.head);
+
+    while previous_block.next_index() != 0 {
+        let current_block = chain.allocator.get(previous_block.next_index());
+        let length = (current_block.index - previous_block.index) as usize;
+        let offset = current_block.offset as usize;
+
+        if offset == 0 {
+            // Copy literals indicator, which is omitted for the first literal run
+            if !first {
+                context.write_bits(0, 1);
+            }
+
+            // Copy literals length and values
+            context.write_interlaced_elias_gamma(length);
+
+            for _ in 0..length {
+                context.write_word(&input[2 * input_index..2 * input_index + 2]);
+                context.read_words(1);
+                input_index += 1;
+            }
+        } else if offset == last_offset {
+            // Copy from last offset indicator and length
+            context.write_bits(0, 1);
+            context.write_interlaced_elias_gamma(length);
+            context.read_words(length);
+            input_index += length;
+        } else {
+            // Copy from new offset indicator, MSB, LSB and length
+            context.write_bits(1, 1);
+            context.write_interlaced_elias_gamma((offset - 1) / 128 + 1);
+            context.write_bits(((offset - 1) % 128) as u64, 7);
+            context.write_interlaced_elias_gamma(length - 1);
+            context.read_words(length);
+            input_index += length;
+
+            last_offset = offset;
+        }
+
+        first = false;
+        previous_block = current_block;
+    }
+
+    // End marker
+    context.write_bits(1, 1);
+    context.write_interlaced_elias_gamma(256);
+
+    // The delta is the largest amount by which the decompressed data ever gets ahead of the
+    // compressed data, relative to the end of both
+    let uncompressed_len = 2 * (input.len() / 2 - chain.skip);
+    let delta = (context.output.len() as isize - uncompressed_len as isize + context.max_diff).max(0);
+
+    (context.output, delta as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Compressor;
+
+    // A decompressor that mirrors the 68000 routine in asm/dzx0w_68000.s, including the sentinel
+    // bit that marks the end of the current bit group. It operates on a single memory buffer, so
+    // that in-place decompression can be verified.
+    struct Decoder<'a> {
+        memory: &'a mut [u8],
+        source: usize,
+        destination: usize,
+        bits: u32
+    }
+
+    impl Decoder<'_> {
+        fn read_word(&mut self) -> [u8; 2] {
+            let word = [self.memory[self.source], self.memory[self.source + 1]];
+            self.source += 2;
+            word
+        }
+
+        fn read_bit(&mut self) -> usize {
+            let mut bit = self.bits >> 31;
+            self.bits <<= 1;
+
+            if self.bits == 0 {
+                self.bits = (u16::from_be_bytes(self.read_word()) as u32) << 16 | 0x8000;
+                bit = self.bits >> 31;
+                self.bits <<= 1;
+            }
+
+            bit as usize
+        }
+
+        fn read_gamma(&mut self) -> usize {
+            let mut value = 1;
+
+            while self.read_bit() == 0 {
+                value = (value << 1) | self.read_bit();
+            }
+
+            value
+        }
+
+        fn write_word(&mut self, word: [u8; 2]) {
+            assert!(
+                self.destination + 2 <= self.source || self.source >= self.memory.len(),
+                "decompressed data overwrites unread compressed data"
+            );
+
+            self.memory[self.destination..self.destination + 2].copy_from_slice(&word);
+            self.destination += 2;
+        }
+
+        fn copy(&mut self, offset: usize, length: usize) {
+            for _ in 0..length {
+                let source = self.destination - offset;
+                self.write_word([self.memory[source], self.memory[source + 1]]);
+            }
+        }
+    }
+
+    // Decompress the data at source to destination, and return the end of the decompressed data
+    fn decompress(memory: &mut [u8], source: usize, destination: usize) -> usize {
+        let mut decoder = Decoder {
+            memory,
+            source,
+            destination,
+            bits: 0x8000_0000
+        };
+
+        let mut offset = 2;
+
+        loop {
+            // Literals
+            for _ in 0..decoder.read_gamma() {
+                let word = decoder.read_word();
+                decoder.write_word(word);
+            }
+
+            if decoder.read_bit() == 0 {
+                // Copy from last offset
+                let length = decoder.read_gamma();
+                decoder.copy(offset, length);
+
+                if decoder.read_bit() == 0 {
+                    continue;
+                }
+            }
+
+            // Copy from new offset, until the next match is followed by literals
+            loop {
+                let msb = decoder.read_gamma();
+
+                if msb == 256 {
+                    assert_eq!(decoder.source, decoder.memory.len());
+                    return decoder.destination;
+                }
+
+                let mut value = msb - 1;
+
+                for _ in 0..7 {
+                    value = (value << 1) | decoder.read_bit();
+                }
+
+                offset = 2 * (value + 1);
+
+                let length = decoder.read_gamma() + 1;
+                decoder.copy(offset, length);
+
+                if decoder.read_bit() == 0 {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut input = std::fs::read("src/optimize.rs").unwrap();
+        input.truncate(input.len() & !1);
+
+        for quick_mode in [false, true] {
+            let result = Compressor::new().quick_mode(quick_mode).compress_words(&input);
+            assert_eq!(result.output.len() % 2, 0);
+
+            let mut memory = vec![0; input.len()];
+            memory.extend_from_slice(&result.output);
+
+            assert_eq!(decompress(&mut memory, input.len(), 0), input.len());
+            assert_eq!(&memory[..input.len()], &input[..]);
+        }
+    }
+
+    #[test]
+    fn round_trip_in_place() {
+        let text = std::fs::read("src/compress.rs").unwrap();
+        let run = [7; 512];
+
+        for (input, skip) in [(&text[..4000], 1000), (&text[1000..1300], 0), (&run[..], 2)] {
+            let result = Compressor::new().skip(skip).compress_words(input);
+
+            // The compressed data ends delta bytes past the end of the decompressed data
+            let mut memory = vec![0; input.len() + result.delta];
+            let source = memory.len() - result.output.len();
+            memory[..skip].copy_from_slice(&input[..skip]);
+            memory[source..].copy_from_slice(&result.output);
+
+            assert_eq!(decompress(&mut memory, source, skip), input.len());
+            assert_eq!(&memory[..input.len()], input);
+        }
+    }
+}
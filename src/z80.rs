@@ -0,0 +1,554 @@
+//! A minimal Z80 interpreter that runs the official ZX0 decompression routines, which is used by
+//! the test suite to verify that compressed data decodes correctly on the actual target hardware.
+//!
+//! Only the instructions that are used by the decompression routines are implemented, and only the
+//! carry and zero flags are tracked since no other flags are ever tested by them.
+
+/// The "standard" forward decompressor from the official ZX0 distribution (68 bytes), assembled
+/// at address zero. It is called with the source address in HL and the destination in DE.
+pub const DZX0_STANDARD: [u8; 68] = [
+    0x01, 0xff, 0xff,       // 0000         ld   bc, $ffff      ; preserve default offset 1
+    0xc5,                   // 0003         push bc
+    0x03,                   // 0004         inc  bc
+    0x3e, 0x80,             // 0005         ld   a, $80
+    0xcd, 0x35, 0x00,       // 0007 lit:    call elias          ; obtain length
+    0xed, 0xb0,             // 000a         ldir                ; copy literals
+    0x87,                   // 000c         add  a, a           ; last offset or new offset?
+    0x38, 0x0d,             // 000d         jr   c, new
+    0xcd, 0x35, 0x00,       // 000f         call elias          ; obtain length
+    0xe3,                   // 0012 copy:   ex   (sp), hl       ; preserve source, restore offset
+    0xe5,                   // 0013         push hl             ; preserve offset
+    0x19,                   // 0014         add  hl, de         ; destination - offset
+    0xed, 0xb0,             // 0015         ldir                ; copy from offset
+    0xe1,                   // 0017         pop  hl             ; restore offset
+    0xe3,                   // 0018         ex   (sp), hl       ; preserve offset, restore source
+    0x87,                   // 0019         add  a, a           ; literals or new offset?
+    0x30, 0xeb,             // 001a         jr   nc, lit
+    0xc1,                   // 001c new:    pop  bc             ; discard last offset
+    0x0e, 0xfe,             // 001d         ld   c, $fe         ; prepare negative offset
+    0xcd, 0x36, 0x00,       // 001f         call loop           ; obtain offset MSB
+    0x0c,                   // 0022         inc  c
+    0xc8,                   // 0023         ret  z              ; check end marker
+    0x41,                   // 0024         ld   b, c
+    0x4e,                   // 0025         ld   c, (hl)        ; obtain offset LSB
+    0x23,                   // 0026         inc  hl
+    0xcb, 0x18,             // 0027         rr   b              ; last offset bit becomes
+    0xcb, 0x19,             // 0029         rr   c              ; first length bit
+    0xc5,                   // 002b         push bc             ; preserve new offset
+    0x01, 0x01, 0x00,       // 002c         ld   bc, 1          ; obtain length
+    0xd4, 0x3d, 0x00,       // 002f         call nc, back
+    0x03,                   // 0032         inc  bc
+    0x18, 0xdd,             // 0033         jr   copy
+    0x0c,                   // 0035 elias:  inc  c              ; interlaced Elias gamma coding
+    0x87,                   // 0036 loop:   add  a, a
+    0x20, 0x03,             // 0037         jr   nz, skip
+    0x7e,                   // 0039         ld   a, (hl)        ; load another group of 8 bits
+    0x23,                   // 003a         inc  hl
+    0x17,                   // 003b         rla
+    0xd8,                   // 003c skip:   ret  c
+    0x87,                   // 003d back:   add  a, a
+    0xcb, 0x11,             // 003e         rl   c
+    0xcb, 0x10,             // 0040         rl   b
+    0x18, 0xf2              // 0042         jr   loop
+];
+
+/// The "standard" backwards decompressor from the official ZX0 distribution (69 bytes), assembled
+/// at address zero. It is called with the last source address in HL and the last destination
+/// address in DE.
+pub const DZX0_STANDARD_BACK: [u8; 69] = [
+    0x01, 0x01, 0x00,       // 0000         ld   bc, 1          ; preserve default offset 1
+    0xc5,                   // 0003         push bc
+    0x3e, 0x80,             // 0004         ld   a, $80
+    0xcd, 0x3c, 0x00,       // 0006 lit:    call elias          ; obtain length
+    0xed, 0xb8,             // 0009         lddr                ; copy literals
+    0x0c,                   // 000b         inc  c
+    0x87,                   // 000c         add  a, a           ; last offset or new offset?
+    0x38, 0x0e,             // 000d         jr   c, new
+    0xcd, 0x3c, 0x00,       // 000f         call elias          ; obtain length
+    0xe3,                   // 0012 copy:   ex   (sp), hl       ; preserve source, restore offset
+    0xe5,                   // 0013         push hl             ; preserve offset
+    0x19,                   // 0014         add  hl, de         ; destination + offset
+    0xed, 0xb8,             // 0015         lddr                ; copy from offset
+    0x0c,                   // 0017         inc  c
+    0xe1,                   // 0018         pop  hl             ; restore offset
+    0xe3,                   // 0019         ex   (sp), hl       ; preserve offset, restore source
+    0x87,                   // 001a         add  a, a           ; literals or new offset?
+    0x30, 0xe9,             // 001b         jr   nc, lit
+    0x33,                   // 001d new:    inc  sp             ; discard last offset
+    0x33,                   // 001e         inc  sp
+    0xcd, 0x3c, 0x00,       // 001f         call elias          ; obtain offset MSB
+    0x05,                   // 0022         dec  b
+    0xc8,                   // 0023         ret  z              ; check end marker
+    0x0d,                   // 0024         dec  c              ; adjust for positive offset
+    0x41,                   // 0025         ld   b, c
+    0x4e,                   // 0026         ld   c, (hl)        ; obtain offset LSB
+    0x2b,                   // 0027         dec  hl
+    0xcb, 0x38,             // 0028         srl  b              ; last offset bit becomes
+    0xcb, 0x19,             // 002a         rr   c              ; first length bit
+    0x03,                   // 002c         inc  bc
+    0xc5,                   // 002d         push bc             ; preserve new offset
+    0x01, 0x01, 0x00,       // 002e         ld   bc, 1          ; obtain length
+    0xdc, 0x37, 0x00,       // 0031         call c, back
+    0x03,                   // 0034         inc  bc
+    0x18, 0xdb,             // 0035         jr   copy
+    0x87,                   // 0037 back:   add  a, a
+    0xcb, 0x11,             // 0038         rl   c
+    0xcb, 0x10,             // 003a         rl   b
+    0x87,                   // 003c elias:  add  a, a           ; inverted interlaced Elias gamma
+    0x20, 0x03,             // 003d         jr   nz, skip
+    0x7e,                   // 003f         ld   a, (hl)        ; load another group of 8 bits
+    0x2b,                   // 0040         dec  hl
+    0x17,                   // 0041         rla
+    0x38, 0xf3,             // 0042 skip:   jr   c, back
+    0xc9                    // 0044         ret
+];
+
+/// The decompressor for the experimental dual-stream format in `asm/dzx0d_z80.asm` (96 bytes),
+/// assembled at address zero. It is called with the source address in HL and the destination in
+/// DE.
+pub const DZX0_DUAL: [u8; 96] = [
+    0x4e,                   // 0000         ld   c, (hl)        ; length of the data stream
+    0x23,                   // 0001         inc  hl
+    0x46,                   // 0002         ld   b, (hl)
+    0x23,                   // 0003         inc  hl
+    0xe5,                   // 0004         push hl             ; data stream
+    0x09,                   // 0005         add  hl, bc         ; control stream
+    0xe5,                   // 0006         push hl
+    0xdd, 0xe1,             // 0007         pop  ix
+    0xe1,                   // 0009         pop  hl
+    0x01, 0xff, 0xff,       // 000a         ld   bc, $ffff      ; preserve default offset 1
+    0xc5,                   // 000d         push bc
+    0x3e, 0x80,             // 000e         ld   a, $80
+    0xcd, 0x3b, 0x00,       // 0010 lit:    call elias          ; obtain length
+    0xed, 0xb0,             // 0013         ldir                ; copy literals
+    0xcd, 0x57, 0x00,       // 0015         call bit            ; last offset or new offset?
+    0x38, 0x0f,             // 0018         jr   c, new
+    0xcd, 0x3b, 0x00,       // 001a         call elias          ; obtain length
+    0xe3,                   // 001d copy:   ex   (sp), hl       ; preserve source, restore offset
+    0xe5,                   // 001e         push hl             ; preserve offset
+    0x19,                   // 001f         add  hl, de         ; destination - offset
+    0xed, 0xb0,             // 0020         ldir                ; copy from offset
+    0xe1,                   // 0022         pop  hl             ; restore offset
+    0xe3,                   // 0023         ex   (sp), hl       ; preserve offset, restore source
+    0xcd, 0x57, 0x00,       // 0024         call bit            ; literals or new offset?
+    0x30, 0xe7,             // 0027         jr   nc, lit
+    0xc1,                   // 0029 new:    pop  bc             ; discard last offset
+    0x0e, 0xfe,             // 002a         ld   c, $fe         ; prepare negative offset
+    0xcd, 0x4b, 0x00,       // 002c         call msb            ; obtain offset MSB
+    0x0c,                   // 002f         inc  c
+    0xc8,                   // 0030         ret  z              ; check end marker
+    0x41,                   // 0031         ld   b, c
+    0x4e,                   // 0032         ld   c, (hl)        ; obtain offset LSB
+    0x23,                   // 0033         inc  hl
+    0xc5,                   // 0034         push bc             ; preserve new offset
+    0xcd, 0x3b, 0x00,       // 0035         call elias          ; obtain length - 1
+    0x03,                   // 0038         inc  bc
+    0x18, 0xe2,             // 0039         jr   copy
+    0x01, 0x01, 0x00,       // 003b elias:  ld   bc, 1          ; interlaced Elias gamma coding
+    0xcd, 0x57, 0x00,       // 003e eloop:  call bit
+    0xd8,                   // 0041         ret  c
+    0xcd, 0x57, 0x00,       // 0042         call bit
+    0xcb, 0x11,             // 0045         rl   c
+    0xcb, 0x10,             // 0047         rl   b
+    0x18, 0xf3,             // 0049         jr   eloop
+    0xcd, 0x57, 0x00,       // 004b msb:    call bit            ; inverted Elias gamma coding
+    0xd8,                   // 004e         ret  c
+    0xcd, 0x57, 0x00,       // 004f         call bit
+    0x3f,                   // 0052         ccf
+    0xcb, 0x11,             // 0053         rl   c
+    0x18, 0xf4,             // 0055         jr   msb
+    0x87,                   // 0057 bit:    add  a, a
+    0xc0,                   // 0058         ret  nz
+    0xdd, 0x7e, 0x00,       // 0059         ld   a, (ix+0)      ; load another group of 8 bits
+    0xdd, 0x23,             // 005c         inc  ix
+    0x17,                   // 005e         rla
+    0xc9                    // 005f         ret
+];
+
+/// The address that the decompression routine returns to, which ends the emulation.
+const RETURN_ADDRESS: u16 = 0xfffe;
+
+/// The maximum number of instructions to execute before giving up.
+const MAX_STEPS: usize = 100_000_000;
+
+pub struct Z80 {
+    pub memory: Vec<u8>,
+    a: u8,
+    b: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    h: u8,
+    l: u8,
+    ix: u16,
+    sp: u16,
+    pc: u16,
+    carry: bool,
+    zero: bool
+}
+
+impl Z80 {
+    /// Create a machine with 64K of memory containing the provided routine at address zero.
+    pub fn new(routine: &[u8]) -> Self {
+        let mut memory = vec![0; 0x10000];
+        memory[..routine.len()].copy_from_slice(routine);
+
+        Self {
+            memory,
+            a: 0, b: 0, c: 0, d: 0, e: 0, h: 0, l: 0,
+            ix: 0,
+            sp: RETURN_ADDRESS,
+            pc: 0,
+            carry: false,
+            zero: false
+        }
+    }
+
+    /// Call the routine at address zero with the given values for HL and DE, and run it until it
+    /// returns.
+    pub fn call(&mut self, hl: u16, de: u16) {
+        self.set_hl(hl);
+        self.set_de(de);
+        self.pc = 0;
+        self.push(RETURN_ADDRESS);
+
+        for _ in 0..MAX_STEPS {
+            if self.pc == RETURN_ADDRESS {
+                return;
+            }
+
+            self.step();
+        }
+
+        panic!("routine did not return after {} steps", MAX_STEPS);
+    }
+
+    fn bc(&self) -> u16 { u16::from_be_bytes([self.b, self.c]) }
+    fn de(&self) -> u16 { u16::from_be_bytes([self.d, self.e]) }
+    fn hl(&self) -> u16 { u16::from_be_bytes([self.h, self.l]) }
+    fn set_bc(&mut self, value: u16) { [self.b, self.c] = value.to_be_bytes(); }
+    fn set_de(&mut self, value: u16) { [self.d, self.e] = value.to_be_bytes(); }
+    fn set_hl(&mut self, value: u16) { [self.h, self.l] = value.to_be_bytes(); }
+
+    fn read(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+
+    fn read_word(&self, address: u16) -> u16 {
+        u16::from_le_bytes([self.read(address), self.read(address.wrapping_add(1))])
+    }
+
+    fn write_word(&mut self, address: u16, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.write(address, low);
+        self.write(address.wrapping_add(1), high);
+    }
+
+    fn fetch(&mut self) -> u8 {
+        let value = self.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        value
+    }
+
+    fn fetch_word(&mut self) -> u16 {
+        let value = self.read_word(self.pc);
+        self.pc = self.pc.wrapping_add(2);
+        value
+    }
+
+    fn push(&mut self, value: u16) {
+        self.sp = self.sp.wrapping_sub(2);
+        self.write_word(self.sp, value);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let value = self.read_word(self.sp);
+        self.sp = self.sp.wrapping_add(2);
+        value
+    }
+
+    fn jump_relative(&mut self, condition: bool) {
+        let displacement = self.fetch() as i8;
+
+        if condition {
+            self.pc = self.pc.wrapping_add(displacement as u16);
+        }
+    }
+
+    fn call_if(&mut self, condition: bool) {
+        let address = self.fetch_word();
+
+        if condition {
+            self.push(self.pc);
+            self.pc = address;
+        }
+    }
+
+    fn return_if(&mut self, condition: bool) {
+        if condition {
+            self.pc = self.pop();
+        }
+    }
+
+    // Rotate a value left through the carry flag
+    fn rl(&mut self, value: u8) -> u8 {
+        let result = (value << 1) | self.carry as u8;
+        self.carry = value & 0x80 != 0;
+        self.zero = result == 0;
+        result
+    }
+
+    // Rotate a value right through the carry flag
+    fn rr(&mut self, value: u8) -> u8 {
+        let result = (value >> 1) | ((self.carry as u8) << 7);
+        self.carry = value & 1 != 0;
+        self.zero = result == 0;
+        result
+    }
+
+    // Shift a value right logically
+    fn srl(&mut self, value: u8) -> u8 {
+        self.carry = value & 1 != 0;
+        self.zero = value >> 1 == 0;
+        value >> 1
+    }
+
+    // Perform a block transfer of BC bytes, moving in the given direction
+    fn block_transfer(&mut self, step: u16) {
+        loop {
+            self.write(self.de(), self.read(self.hl()));
+            self.set_hl(self.hl().wrapping_add(step));
+            self.set_de(self.de().wrapping_add(step));
+            self.set_bc(self.bc().wrapping_sub(1));
+
+            if self.bc() == 0 {
+                break;
+            }
+        }
+    }
+
+    fn step(&mut self) {
+        let opcode = self.fetch();
+
+        match opcode {
+            0x01 => { let value = self.fetch_word(); self.set_bc(value); },
+            0x03 => self.set_bc(self.bc().wrapping_add(1)),
+            0x05 => { self.b = self.b.wrapping_sub(1); self.zero = self.b == 0; },
+            0x0c => { self.c = self.c.wrapping_add(1); self.zero = self.c == 0; },
+            0x0d => { self.c = self.c.wrapping_sub(1); self.zero = self.c == 0; },
+            0x09 => {
+                let (result, carry) = self.hl().overflowing_add(self.bc());
+                self.set_hl(result);
+                self.carry = carry;
+            },
+            0x0e => self.c = self.fetch(),
+            0x17 => {
+                let carry = self.a & 0x80 != 0;
+                self.a = (self.a << 1) | self.carry as u8;
+                self.carry = carry;
+            },
+            0x18 => self.jump_relative(true),
+            0x19 => {
+                let (result, carry) = self.hl().overflowing_add(self.de());
+                self.set_hl(result);
+                self.carry = carry;
+            },
+            0x20 => self.jump_relative(!self.zero),
+            0x23 => self.set_hl(self.hl().wrapping_add(1)),
+            0x2b => self.set_hl(self.hl().wrapping_sub(1)),
+            0x30 => self.jump_relative(!self.carry),
+            0x33 => self.sp = self.sp.wrapping_add(1),
+            0x38 => self.jump_relative(self.carry),
+            0x3e => self.a = self.fetch(),
+            0x3f => self.carry = !self.carry,
+            0x41 => self.b = self.c,
+            0x46 => self.b = self.read(self.hl()),
+            0x4e => self.c = self.read(self.hl()),
+            0x7e => self.a = self.read(self.hl()),
+            0x87 => {
+                self.carry = self.a & 0x80 != 0;
+                self.a <<= 1;
+                self.zero = self.a == 0;
+            },
+            0xc0 => self.return_if(!self.zero),
+            0xc1 => { let value = self.pop(); self.set_bc(value); },
+            0xc5 => self.push(self.bc()),
+            0xc8 => self.return_if(self.zero),
+            0xc9 => self.return_if(true),
+            0xcb => match self.fetch() {
+                0x10 => self.b = self.rl(self.b),
+                0x11 => self.c = self.rl(self.c),
+                0x18 => self.b = self.rr(self.b),
+                0x19 => self.c = self.rr(self.c),
+                0x38 => self.b = self.srl(self.b),
+                opcode => panic!("unimplemented instruction cb {:02x}", opcode)
+            },
+            0xcd => self.call_if(true),
+            0xd4 => self.call_if(!self.carry),
+            0xd8 => self.return_if(self.carry),
+            0xdc => self.call_if(self.carry),
+            0xdd => match self.fetch() {
+                0x23 => self.ix = self.ix.wrapping_add(1),
+                0x7e => {
+                    let displacement = self.fetch() as i8;
+                    self.a = self.read(self.ix.wrapping_add(displacement as u16));
+                },
+                0xe1 => self.ix = self.pop(),
+                opcode => panic!("unimplemented instruction dd {:02x}", opcode)
+            },
+            0xe1 => { let value = self.pop(); self.set_hl(value); },
+            0xe3 => {
+                let value = self.read_word(self.sp);
+                self.write_word(self.sp, self.hl());
+                self.set_hl(value);
+            },
+            0xe5 => self.push(self.hl()),
+            0xed => match self.fetch() {
+                0xb0 => self.block_transfer(1),
+                0xb8 => self.block_transfer(0xffff),
+                opcode => panic!("unimplemented instruction ed {:02x}", opcode)
+            },
+            opcode => panic!("unimplemented instruction {:02x}", opcode)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DZX0_DUAL, DZX0_STANDARD, DZX0_STANDARD_BACK, Z80};
+    use crate::Compressor;
+
+    /// The address at which the prefix, suffix or compressed data starts, leaving room for the
+    /// decompression routine.
+    const START: usize = 0x0100;
+
+    fn inputs() -> Vec<Vec<u8>> {
+        let source = std::fs::read("src/lib.rs").unwrap();
+
+        // Deterministic noise that doesn't compress well
+        let mut state = 0x2545f4914f6cdd1du64;
+        let noise = std::iter::repeat_with(|| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).take(4096).collect::<Vec<_>>();
+
+        let runs = (0..4096).map(|i| ((i / 37) % 5) as u8).collect();
+
+        vec![
+            source[..source.len().min(8192)].to_vec(),
+            noise,
+            runs,
+            vec![0x55; 1],
+            vec![0xaa; 3000]
+        ]
+    }
+
+    /// Decompress forwards in place, with the compressed data ending `delta` bytes after the end
+    /// of the decompressed data, and the prefix right before it.
+    fn decompress_forwards(input: &[u8], skip: usize) {
+        let result = Compressor::new().skip(skip).compress(input);
+
+        let destination = START + skip;
+        let end = (START + input.len() + result.delta).max(destination + result.output.len());
+        let source = end - result.output.len();
+
+        let mut z80 = Z80::new(&DZX0_STANDARD);
+        z80.memory[START..destination].copy_from_slice(&input[..skip]);
+        z80.memory[source..end].copy_from_slice(&result.output);
+        z80.call(source as u16, destination as u16);
+
+        assert_eq!(&z80.memory[START..START + input.len()], input);
+    }
+
+    /// Decompress backwards in place, with the compressed data starting `delta` bytes before the
+    /// start of the decompressed data, and the suffix right after it.
+    fn decompress_backwards(input: &[u8], skip: usize) {
+        let mut reversed = input.to_vec();
+        reversed.reverse();
+
+        let mut result = Compressor::new().backwards_mode(true).skip(skip).compress(&reversed);
+        result.output.reverse();
+
+        let gap = result.delta.max(result.output.len().saturating_sub(input.len() - skip));
+        let source = START;
+        let destination = source + gap;
+        let end = destination + input.len();
+
+        let mut z80 = Z80::new(&DZX0_STANDARD_BACK);
+        z80.memory[end - skip..end].copy_from_slice(&input[input.len() - skip..]);
+        z80.memory[source..source + result.output.len()].copy_from_slice(&result.output);
+        z80.call((source + result.output.len() - 1) as u16, (end - skip - 1) as u16);
+
+        assert_eq!(&z80.memory[destination..end], input);
+    }
+
+    /// Decompress data in the dual-stream format in place, laid out like the forwards format.
+    fn decompress_dual(input: &[u8], skip: usize) {
+        let result = Compressor::new().skip(skip).compress_dual(input);
+
+        let destination = START + skip;
+        let end = (START + input.len() + result.delta).max(destination + result.output.len());
+        let source = end - result.output.len();
+
+        let mut z80 = Z80::new(&DZX0_DUAL);
+        z80.memory[START..destination].copy_from_slice(&input[..skip]);
+        z80.memory[source..end].copy_from_slice(&result.output);
+        z80.call(source as u16, destination as u16);
+
+        assert_eq!(&z80.memory[START..START + input.len()], input);
+    }
+
+    #[test]
+    fn standard() {
+        for input in inputs() {
+            decompress_forwards(&input, 0);
+        }
+    }
+
+    #[test]
+    fn standard_with_prefix() {
+        for input in inputs().iter().filter(|input| input.len() > 1) {
+            for skip in [1, input.len() / 3, input.len() - 1] {
+                decompress_forwards(input, skip);
+            }
+        }
+    }
+
+    #[test]
+    fn standard_backwards() {
+        for input in inputs() {
+            decompress_backwards(&input, 0);
+        }
+    }
+
+    #[test]
+    fn standard_backwards_with_suffix() {
+        for input in inputs().iter().filter(|input| input.len() > 1) {
+            for skip in [1, input.len() / 3, input.len() - 1] {
+                decompress_backwards(input, skip);
+            }
+        }
+    }
+
+    #[test]
+    fn dual() {
+        for input in inputs() {
+            decompress_dual(&input, 0);
+        }
+    }
+
+    #[test]
+    fn dual_with_prefix() {
+        for input in inputs().iter().filter(|input| input.len() > 1) {
+            for skip in [1, input.len() / 3, input.len() - 1] {
+                decompress_dual(input, skip);
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! Decoding of the ZX7 format, the predecessor of ZX0, so that existing assets can be unpacked
+//! while migrating them to the new format.
+//!
+//! A ZX7 stream starts with a literal byte, after which every element is preceded by an indicator
+//! bit. A clear bit is followed by a single literal byte, and a set bit by a match. The length of
+//! a match minus one is stored as an Elias gamma code, and its offset minus one as a single byte
+//! for offsets up to 128, or as a byte with its highest bit set followed by four more bits for
+//! offsets up to 2176. The stream ends with a gamma code of 16 zero bits.
+
+use crate::Error;
+#[cfg(feature = "std")]
+use crate::grow::grow_until_fits;
+
+// The number of zero bits in the gamma code that marks the end of the stream
+const END_MARKER_ZEROS: usize = 16;
+
+struct Reader<'a> {
+    input: &'a [u8],
+    position: usize,
+    bit_mask: u8,
+    bit_value: u8
+}
+
+impl Reader<'_> {
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        let value = *self.input.get(self.position).ok_or(Error::Truncated)?;
+        self.position += 1;
+        Ok(value)
+    }
+
+    fn read_bit(&mut self) -> Result<bool, Error> {
+        if self.bit_mask == 0 {
+            self.bit_value = self.read_byte()?;
+            self.bit_mask = 128;
+        }
+
+        let bit = self.bit_value & self.bit_mask != 0;
+        self.bit_mask >>= 1;
+        Ok(bit)
+    }
+
+    fn read_bits(&mut self, count: usize, mut value: usize) -> Result<usize, Error> {
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()? as usize;
+        }
+
+        Ok(value)
+    }
+}
+
+/// Decompress data in the ZX7 format into the provided buffer, and return the number of
+/// decompressed bytes. This is equivalent to the `dzx7` tool of the original ZX7 implementation.
+///
+/// Returns an error if the compressed data is malformed or ends before its end marker, or if the
+/// decompressed data does not fit in the output buffer.
+pub fn decompress_zx7_into(input: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+    let mut reader = Reader {
+        input,
+        position: 0,
+        bit_mask: 0,
+        bit_value: 0
+    };
+
+    let mut position = 0;
+    let mut length = 1;
+
+    // Streams always start with a literal, so the first indicator bit is omitted
+    let mut is_match = false;
+
+    loop {
+        if length > output.len() - position {
+            return Err(Error::OutputTooSmall { len: output.len() });
+        }
+
+        if is_match {
+            let mut offset = reader.read_byte()? as usize;
+
+            // Larger offsets store their upper four bits after the byte
+            if offset >= 128 {
+                offset = ((reader.read_bits(4, 0)? << 7) | (offset & 127)) + 128;
+            }
+
+            offset += 1;
+
+            if offset > position {
+                return Err(Error::OffsetOutOfRange { offset, position });
+            }
+
+            // Copy byte by byte, since the source and destination may overlap
+            for _ in 0..length {
+                output[position] = output[position - offset];
+                position += 1;
+            }
+        } else {
+            output[position] = reader.read_byte()?;
+            position += 1;
+        }
+
+        is_match = reader.read_bit()?;
+
+        if is_match {
+            let mut zeros = 0;
+
+            while !reader.read_bit()? {
+                zeros += 1;
+
+                if zeros > END_MARKER_ZEROS {
+                    return Err(Error::InvalidData);
+                }
+            }
+
+            if zeros == END_MARKER_ZEROS {
+                return Ok(position);
+            }
+
+            length = reader.read_bits(zeros, 1)? + 1;
+        } else {
+            length = 1;
+        }
+    }
+}
+
+/// Decompress data in the ZX7 format, and return the decompressed data.
+///
+/// Since the decompressed length is not stored in the compressed data, the output buffer is grown
+/// until the data fits. Use [`decompress_zx7_into`] with a buffer of a known size for untrusted
+/// data.
+#[cfg(feature = "std")]
+pub fn decompress_zx7(input: &[u8]) -> Result<Vec<u8>, Error> {
+    grow_until_fits(4 * input.len() + 64, None, |output| decompress_zx7_into(input, output).map(|len| (len, ())))
+        .map(|(output, ())| output)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::{decompress_zx7, decompress_zx7_into};
+    use crate::Error;
+
+    // A greedy ZX7 encoder that is laid out like the original one, which is enough to produce
+    // test data for every kind of element
+    struct Writer {
+        output: Vec<u8>,
+        bit_index: usize,
+        bit_mask: u8
+    }
+
+    impl Writer {
+        fn write_bit(&mut self, bit: bool) {
+            if self.bit_mask == 0 {
+                self.bit_mask = 128;
+                self.bit_index = self.output.len();
+                self.output.push(0);
+            }
+
+            if bit {
+                self.output[self.bit_index] |= self.bit_mask;
+            }
+
+            self.bit_mask >>= 1;
+        }
+
+        fn write_elias_gamma(&mut self, value: usize) {
+            let bits = usize::BITS - value.leading_zeros() - 1;
+
+            for _ in 0..bits {
+                self.write_bit(false);
+            }
+
+            for i in (0..=bits).rev() {
+                self.write_bit(value >> i & 1 != 0);
+            }
+        }
+    }
+
+    fn compress_zx7(input: &[u8]) -> Vec<u8> {
+        let mut writer = Writer { output: vec![input[0]], bit_index: 0, bit_mask: 0 };
+        let mut position = 1;
+
+        while position < input.len() {
+            let (offset, length) = (1..=position.min(2176))
+                .map(|offset| {
+                    let length = (position..input.len().min(position + 65536))
+                        .take_while(|&i| input[i] == input[i - offset])
+                        .count();
+
+                    (offset, length)
+                })
+                .max_by_key(|&(offset, length)| (length, std::cmp::Reverse(offset)))
+                .unwrap_or((1, 0));
+
+            if length < 2 {
+                writer.write_bit(false);
+                writer.output.push(input[position]);
+                position += 1;
+                continue;
+            }
+
+            writer.write_bit(true);
+            writer.write_elias_gamma(length - 1);
+
+            if offset <= 128 {
+                writer.output.push((offset - 1) as u8);
+            } else {
+                let offset = offset - 129;
+                writer.output.push((offset & 127) as u8 | 128);
+
+                for i in (7..11).rev() {
+                    writer.write_bit(offset >> i & 1 != 0);
+                }
+            }
+
+            position += length;
+        }
+
+        // The end marker
+        writer.write_bit(true);
+
+        for _ in 0..16 {
+            writer.write_bit(false);
+        }
+
+        writer.write_bit(true);
+        writer.output
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = std::fs::read("src/lib.rs").unwrap();
+        let long_run = [&[1][..], &[0; 70000]].concat();
+        let distant = [&text[..200], &[0; 2000], &text[..200]].concat();
+
+        for input in [&text[..3000], &long_run, &distant, b"a"] {
+            let compressed = compress_zx7(input);
+            assert_eq!(decompress_zx7(&compressed).as_deref(), Ok(input));
+        }
+    }
+
+    #[test]
+    fn errors() {
+        // A literal, followed by a match of four bytes at offset 1
+        let compressed = [0x61, 0xb8, 0x00, 0x00, 0x04];
+
+        let mut buffer = [0; 8];
+        assert_eq!(decompress_zx7_into(&compressed, &mut buffer), Ok(5));
+        assert_eq!(&buffer[..5], b"aaaaa");
+
+        assert_eq!(decompress_zx7_into(&compressed[..4], &mut buffer), Err(Error::Truncated));
+        assert_eq!(decompress_zx7_into(&compressed, &mut buffer[..4]), Err(Error::OutputTooSmall { len: 4 }));
+        assert_eq!(decompress_zx7_into(&[], &mut buffer), Err(Error::Truncated));
+
+        // A literal, followed by a match of two bytes at offset 2
+        assert_eq!(
+            decompress_zx7_into(&[0x61, 0xc0, 0x01], &mut buffer),
+            Err(Error::OffsetOutOfRange { offset: 2, position: 1 })
+        );
+
+        // More zero bits than the end marker has
+        assert_eq!(decompress_zx7_into(&[0x61, 0x80, 0x00, 0x00, 0x00], &mut buffer), Err(Error::InvalidData));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(1000))]
+
+        #[test]
+        fn malformed_never_panics(input in proptest::collection::vec(any::<u8>(), 0..64), len in 0usize..256) {
+            let mut output = vec![0; len];
+            let _ = decompress_zx7_into(&input, &mut output);
+        }
+    }
+}